@@ -3,7 +3,7 @@ use reqwest::{
     self,
     blocking::{Client, Response},
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, thread::sleep, time::Duration};
 use tokio_tungstenite::tungstenite::{connect, handshake::client::Request};
 use url::Url;
 
@@ -52,6 +52,7 @@ pub fn run(matches: &ArgMatches) -> Result<Response, Error> {
             _ => Err(Error::Usage("ilp-cli help accounts")),
         },
         ("pay", Some(pay_matches)) => client.post_account_payments(pay_matches),
+        ("ping", Some(ping_matches)) => client.post_ping(ping_matches),
         ("rates", Some(rates_matches)) => match rates_matches.subcommand() {
             ("list", Some(submatches)) => client.get_rates(submatches),
             ("set-all", Some(submatches)) => client.put_rates(submatches),
@@ -222,17 +223,90 @@ impl NodeClient<'_> {
     }
 
     // POST /accounts/:username/payments
+    // POST /accounts/:username/quotes (with --quote-only)
     fn post_account_payments(&self, matches: &ArgMatches) -> Result<Response, Error> {
         let (auth, mut args) = extract_args(matches);
         let user = args.remove("sender_username").unwrap(); // infallible unwrap
+        let path = if matches.is_present("quote_only") {
+            "quotes"
+        } else {
+            "payments"
+        };
         self.client
-            .post(&format!("{}/accounts/{}/payments", self.url, user))
+            .post(&format!("{}/accounts/{}/{}", self.url, user, path))
             .bearer_auth(auth)
             .json(&args)
             .send()
             .map_err(Error::Send)
     }
 
+    // POST /ping
+    // Sends `count` echo requests, one every `interval` milliseconds, printing the
+    // outcome of each and returning a summary of the round trip times, much like ICMP ping
+    fn post_ping(&self, matches: &ArgMatches) -> Result<Response, Error> {
+        let (auth, mut args) = extract_args(matches);
+        let count: u32 = args
+            .remove("count")
+            .unwrap() // infallible unwrap
+            .parse()
+            .map_err(|_| Error::Usage("ilp-cli help ping"))?;
+        let interval: u64 = args
+            .remove("interval")
+            .unwrap() // infallible unwrap
+            .parse()
+            .map_err(|_| Error::Usage("ilp-cli help ping"))?;
+
+        let mut rtts = Vec::new();
+        for seq in 1..=count {
+            let response = self
+                .client
+                .post(&format!("{}/ping", self.url))
+                .bearer_auth(auth)
+                .json(&args)
+                .send()?;
+            if !response.status().is_success() {
+                return Ok(response);
+            }
+            let reply: PingReply = response.json()?;
+            match reply.status.as_str() {
+                "fulfilled" => {
+                    println!(
+                        "Reply from {}: seq={} time={:.1} ms",
+                        reply.destination, seq, reply.rtt_ms
+                    );
+                    rtts.push(reply.rtt_ms);
+                }
+                _ => println!(
+                    "Rejected by {}: seq={} code={} message={:?} time={:.1} ms",
+                    reply.triggered_by.as_deref().unwrap_or("unknown"),
+                    seq,
+                    reply.code.as_deref().unwrap_or(""),
+                    reply.message.as_deref().unwrap_or(""),
+                    reply.rtt_ms
+                ),
+            }
+            if seq < count {
+                sleep(Duration::from_millis(interval));
+            }
+        }
+
+        let received = rtts.len();
+        let mut summary = serde_json::json!({
+            "destination": args["destination"],
+            "transmitted": count,
+            "received": received,
+            "packet_loss": if count > 0 { 1.0 - received as f64 / f64::from(count) } else { 0.0 },
+        });
+        if received > 0 {
+            summary["rtt_min_ms"] = rtts.iter().cloned().fold(f64::INFINITY, f64::min).into();
+            summary["rtt_avg_ms"] = (rtts.iter().sum::<f64>() / received as f64).into();
+            summary["rtt_max_ms"] = rtts.iter().cloned().fold(0.0, f64::max).into();
+        }
+        Ok(Response::from(
+            http::Response::builder().body(summary.to_string()).unwrap(), // infallible unwrap
+        ))
+    }
+
     // GET /rates
     fn get_rates(&self, _matches: &ArgMatches) -> Result<Response, Error> {
         self.client
@@ -390,6 +464,16 @@ fn unflatten_pairs<'a>(matches: &'a ArgMatches) -> (&'a str, HashMap<&'a str, &'
     (matches.value_of("authorization_key").unwrap(), pairs)
 }
 
+#[derive(Debug, serde::Deserialize)]
+struct PingReply {
+    destination: String,
+    rtt_ms: f64,
+    status: String,
+    code: Option<String>,
+    message: Option<String>,
+    triggered_by: Option<String>,
+}
+
 #[allow(unused)]
 #[derive(Debug, serde::Deserialize)]
 struct XpringResponse {
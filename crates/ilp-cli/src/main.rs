@@ -137,6 +137,15 @@ mod interface_tests {
     fn pay() {
         should_parse(&[
             "ilp-cli pay alice --auth foo --amount 500 --to bar", // minimal
+            "ilp-cli pay alice --auth foo --amount 500 --to bar --quote-only", // maximal
+        ]);
+    }
+
+    #[test]
+    fn ping() {
+        should_parse(&[
+            "ilp-cli ping example.bob --auth foo --from alice", // minimal
+            "ilp-cli ping example.bob --auth foo --from alice --amount 10 --count 2 --interval 10", // maximal
         ]);
     }
 
@@ -13,6 +13,7 @@ pub fn build<'a, 'b>() -> App<'a, 'b> {
             accounts_update_settings(),
         ]),
         pay(),
+        ping(),
         rates().subcommands(vec![rates_list(), rates_set_all()]),
         routes().subcommands(vec![routes_list(), routes_set(), routes_set_all()]),
         settlement_engines().subcommands(vec![settlement_engines_set_all()]),
@@ -110,6 +111,18 @@ fn accounts_create<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("ilp_over_http_url")
                 .long("ilp-over-http-url")
                 .takes_value(true),
+            Arg::with_name("ilp_over_http_callback_url")
+                .long("ilp-over-http-callback-url")
+                .takes_value(true),
+            Arg::with_name("ilp_over_http_client_cert")
+                .long("ilp-over-http-client-cert")
+                .takes_value(true),
+            Arg::with_name("ilp_over_http_client_key")
+                .long("ilp-over-http-client-key")
+                .takes_value(true),
+            Arg::with_name("ilp_over_http_client_cert_fingerprint")
+                .long("ilp-over-http-client-cert-fingerprint")
+                .takes_value(true),
             Arg::with_name("ilp_over_http_incoming_token")
                 .long("ilp-over-http-incoming-token")
                 .takes_value(true),
@@ -181,6 +194,18 @@ fn accounts_update<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("ilp_over_http_url")
                 .long("ilp-over-http-url")
                 .takes_value(true),
+            Arg::with_name("ilp_over_http_callback_url")
+                .long("ilp-over-http-callback-url")
+                .takes_value(true),
+            Arg::with_name("ilp_over_http_client_cert")
+                .long("ilp-over-http-client-cert")
+                .takes_value(true),
+            Arg::with_name("ilp_over_http_client_key")
+                .long("ilp-over-http-client-key")
+                .takes_value(true),
+            Arg::with_name("ilp_over_http_client_cert_fingerprint")
+                .long("ilp-over-http-client-cert-fingerprint")
+                .takes_value(true),
             Arg::with_name("ilp_over_http_incoming_token")
                 .long("ilp-over-http-incoming-token")
                 .takes_value(true),
@@ -275,6 +300,18 @@ fn accounts_update_settings<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("ilp_over_http_url")
                 .long("ilp-over-http-url")
                 .takes_value(true),
+            Arg::with_name("ilp_over_http_callback_url")
+                .long("ilp-over-http-callback-url")
+                .takes_value(true),
+            Arg::with_name("ilp_over_http_client_cert")
+                .long("ilp-over-http-client-cert")
+                .takes_value(true),
+            Arg::with_name("ilp_over_http_client_key")
+                .long("ilp-over-http-client-key")
+                .takes_value(true),
+            Arg::with_name("ilp_over_http_client_cert_fingerprint")
+                .long("ilp-over-http-client-cert-fingerprint")
+                .takes_value(true),
             Arg::with_name("ilp_over_http_incoming_token")
                 .long("ilp-over-http-incoming-token")
                 .takes_value(true),
@@ -319,6 +356,42 @@ fn pay<'a, 'b>() -> App<'a, 'b> {
                 .takes_value(true)
                 .required(true)
                 .help("The Payment Pointer or SPSP address of the account receiving the payment"),
+            Arg::with_name("quote_only")
+                .long("quote-only")
+                .help("Estimate the exchange rate and the amount the receiver would get without sending the payment"),
+        ])
+}
+
+fn ping<'a, 'b>() -> App<'a, 'b> {
+    AuthorizedSubCommand::with_name("ping")
+        .about("Send echo requests to an ILP address and report the round trip times")
+        .args(&[
+            Arg::with_name("destination")
+                .index(1)
+                .takes_value(true)
+                .required(true)
+                .help("The ILP address to ping"),
+            Arg::with_name("from")
+                .long("from")
+                .takes_value(true)
+                .required(true)
+                .help("The username of the account on this node the echo requests are sent from"),
+            Arg::with_name("amount")
+                .long("amount")
+                .takes_value(true)
+                .help("The amount to send with each echo request, denominated in units of the sender's assets"),
+            Arg::with_name("count")
+                .short("c")
+                .long("count")
+                .takes_value(true)
+                .default_value("4")
+                .help("The number of echo requests to send"),
+            Arg::with_name("interval")
+                .short("i")
+                .long("interval")
+                .takes_value(true)
+                .default_value("1000")
+                .help("The number of milliseconds to wait between sending echo requests"),
         ])
 }
 
@@ -164,6 +164,38 @@ fn cmdline_configuration<'b>(version: &'b str) -> clap::App<'static, 'b> {
             .long("route_broadcast_interval")
             .takes_value(true)
             .help("Interval, defined in milliseconds, on which the node will broadcast routing information to other nodes using CCP. Defaults to 30000ms (30 seconds)."),
+        Arg::with_name("route_hold_down_time")
+            .long("route_hold_down_time")
+            .takes_value(true)
+            .help("Hold down time, defined in milliseconds, advertised to other nodes in our CCP route broadcasts. Defaults to 30000ms (30 seconds)."),
+        Arg::with_name("route_expiry_time")
+            .long("route_expiry_time")
+            .takes_value(true)
+            .help("How long, in milliseconds, to keep the routes learned from a peer after its last CCP route broadcast. Defaults to the hold down time advertised by the peer plus one broadcast interval."),
+        Arg::with_name("route_aggregation")
+            .long("route_aggregation")
+            .takes_value(true)
+            .help("Whether to advertise a single CCP route for a prefix when all of the routes under it go to accounts on this node, instead of one route for each account. Defaults to false."),
+        Arg::with_name("btp_ping_interval")
+            .long("btp_ping_interval")
+            .takes_value(true)
+            .help("Interval, defined in milliseconds, on which the node will send WebSocket Pings on its BTP connections. 0 disables the Pings. Defaults to 30000ms (30 seconds)."),
+        Arg::with_name("btp_max_missed_pongs")
+            .long("btp_max_missed_pongs")
+            .takes_value(true)
+            .help("How many Pings in a row a peer can leave unanswered before the node closes its BTP connection. Outgoing connections are then opened again. 0 never closes the connections. Defaults to 3."),
+        Arg::with_name("http_tls_cert")
+            .long("http_tls_cert")
+            .takes_value(true)
+            .help("Path to the PEM encoded certificate (chain) of the HTTP listener. If it is set along with http_tls_key, the API and ILP over HTTP are served over HTTPS and peers can authenticate with client certificates."),
+        Arg::with_name("http_tls_key")
+            .long("http_tls_key")
+            .takes_value(true)
+            .help("Path to the PEM encoded private key of http_tls_cert."),
+        Arg::with_name("http_tls_client_ca")
+            .long("http_tls_client_ca")
+            .takes_value(true)
+            .help("Path to the PEM encoded CA certificates which must have issued the client certificates. If it is not set, client certificates are only authenticated by the fingerprints configured on the accounts."),
         Arg::with_name("exchange_rate.provider")
             .long("exchange_rate.provider")
             .takes_value(true)
@@ -30,14 +30,23 @@ cfg_if! {
 use interledger::service::OutgoingService;
 
 use bytes::Bytes;
-use futures::TryFutureExt;
+use futures::{future::join_all, TryFutureExt};
 use hex::FromHex;
 use interledger::{
     api::{NodeApi, NodeStore},
-    btp::{btp_service_as_filter, connect_client, BtpOutgoingService, BtpStore},
-    ccp::{CcpRouteManagerBuilder, CcpRoutingAccount, CcpRoutingStore, RoutingRelation},
+    btp::{
+        btp_service_as_filter, connect_to_service_account, BtpOutgoingService, BtpStore,
+        BtpSupervisor,
+    },
+    ccp::{
+        route_auth_anchor, CcpRouteManagerBuilder, CcpRoutingAccount, CcpRoutingStore,
+        RoutingEventsStore, RoutingRelation,
+    },
     errors::*,
-    http::{HttpClientService, HttpServer as IlpOverHttpServer, HttpStore},
+    http::{
+        serve_tls, tls_server_config, HttpCallbacks, HttpClientService,
+        HttpServer as IlpOverHttpServer, HttpStore,
+    },
     ildcp::IldcpService,
     packet::Address,
     packet::{ErrorCode, RejectBuilder},
@@ -63,17 +72,20 @@ use interledger::{
 };
 use num_bigint::BigUint;
 use once_cell::sync::Lazy;
+use ring::hmac;
 use serde::{de::Error as DeserializeError, Deserialize, Deserializer};
 #[cfg(feature = "balance-tracking")]
 use std::num::NonZeroU32;
 use std::{
+    collections::HashMap,
     convert::TryFrom,
     net::SocketAddr,
+    path::PathBuf,
     str::{self, FromStr},
     time::Duration,
 };
 use tokio::spawn;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use url::Url;
 use uuid::Uuid;
 use warp::{self, Filter};
@@ -87,6 +99,7 @@ use interledger::service_util::{start_delayed_settlement, BalanceService};
 pub use interledger::rates::ExchangeRateProvider;
 
 static DEFAULT_ILP_ADDRESS: Lazy<Address> = Lazy::new(|| Address::from_str("local.host").unwrap());
+static ROUTE_AUTH_SECRET_GENERATION_STRING: &str = "ilp_ccp_route_auth_secret";
 
 fn default_settlement_api_bind_address() -> SocketAddr {
     SocketAddr::from(([127, 0, 0, 1], 7771))
@@ -130,6 +143,37 @@ where
     })
 }
 
+fn deserialize_route_auth_anchors<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, [u8; 32]>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(prefix, anchor)| {
+            <[u8; 32]>::from_hex(anchor)
+                .map(|anchor| (prefix, anchor))
+                .map_err(|err| {
+                    DeserializeError::custom(format!(
+                        "Invalid route auth anchor (must be 32 hex-encoded bytes): {:?}",
+                        err
+                    ))
+                })
+        })
+        .collect()
+}
+
+fn generate_route_auth_secret(secret_seed: &[u8; 32]) -> [u8; 32] {
+    let mut route_auth_secret: [u8; 32] = [0; 32];
+    let sig = hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA256, secret_seed),
+        ROUTE_AUTH_SECRET_GENERATION_STRING.as_bytes(),
+    );
+    route_auth_secret.copy_from_slice(sig.as_ref());
+    route_auth_secret
+}
+
 fn deserialize_optional_username<'de, D>(deserializer: D) -> Result<Option<Username>, D::Error>
 where
     D: Deserializer<'de>,
@@ -236,6 +280,38 @@ pub struct InterledgerNode {
     /// Interval, defined in milliseconds, on which the node will broadcast routing
     /// information to other nodes using CCP. Defaults to 30000ms (30 seconds).
     pub route_broadcast_interval: Option<u64>,
+    /// Hold down time, defined in milliseconds, advertised to other nodes in our CCP
+    /// route broadcasts. Defaults to 30000ms (30 seconds).
+    pub route_hold_down_time: Option<u32>,
+    /// How long, in milliseconds, to keep the routes learned from a peer after its last
+    /// CCP route broadcast. Defaults to the hold down time advertised by the peer
+    /// plus one broadcast interval.
+    pub route_expiry_time: Option<u64>,
+    /// Whether to advertise a single CCP route for a prefix when all of the routes under it
+    /// go to accounts on this node, instead of one route for each account. Defaults to false.
+    #[serde(default)]
+    pub route_aggregation: bool,
+    /// Maps ILP address prefixes to the hex-encoded anchors published by their owners.
+    /// CCP routes for these prefixes are only accepted if they were authenticated by the owner.
+    #[serde(default, deserialize_with = "deserialize_route_auth_anchors")]
+    pub protected_route_prefixes: HashMap<String, [u8; 32]>,
+    /// Interval, defined in milliseconds, on which the node will send WebSocket Pings on its
+    /// BTP connections. 0 disables the Pings. Defaults to 30000ms (30 seconds).
+    pub btp_ping_interval: Option<u64>,
+    /// How many Pings in a row a peer can leave unanswered before the node closes its BTP
+    /// connection. Outgoing connections are then opened again. 0 never closes the connections.
+    /// Defaults to 3.
+    pub btp_max_missed_pongs: Option<u32>,
+    /// Path to the PEM encoded certificate (chain) of the HTTP listener. If it is set along with
+    /// `http_tls_key`, the API and ILP over HTTP are served over HTTPS and peers can authenticate
+    /// with client certificates.
+    pub http_tls_cert: Option<PathBuf>,
+    /// Path to the PEM encoded private key of `http_tls_cert`
+    pub http_tls_key: Option<PathBuf>,
+    /// Path to the PEM encoded CA certificates which must have issued the client certificates.
+    /// If it is not set, client certificates are only authenticated by the fingerprints
+    /// configured on the accounts.
+    pub http_tls_client_ca: Option<PathBuf>,
     #[serde(default)]
     /// Configuration for calculating exchange rates between various pairs.
     pub exchange_rate: ExchangeRateConfig,
@@ -331,6 +407,7 @@ impl InterledgerNode {
             + SettlementStore<Account = Account>
             + RouterStore<Account = Account>
             + CcpRoutingStore<Account = Account>
+            + RoutingEventsStore
             + RateLimitStore<Account = Account>
             + LeftoversStore<AccountId = Uuid, AssetType = BigUint>
             + IdempotentStore
@@ -351,6 +428,29 @@ impl InterledgerNode {
         let admin_auth_token = self.admin_auth_token.clone();
         let default_spsp_account = self.default_spsp_account.clone();
         let route_broadcast_interval = self.route_broadcast_interval;
+        let route_hold_down_time = self.route_hold_down_time;
+        let route_expiry_time = self.route_expiry_time;
+        let route_aggregation = self.route_aggregation;
+        let btp_ping_interval = self.btp_ping_interval;
+        let btp_max_missed_pongs = self.btp_max_missed_pongs;
+        let http_tls_config = match (&self.http_tls_cert, &self.http_tls_key) {
+            (Some(cert), Some(key)) => {
+                match tls_server_config(cert, key, self.http_tls_client_ca.as_deref()) {
+                    Ok(config) => Some(config),
+                    Err(err) => {
+                        error!(target: "interledger-node", "Error loading the HTTP TLS configuration: {}", err);
+                        return Err(());
+                    }
+                }
+            }
+            (None, None) => None,
+            _ => {
+                error!(target: "interledger-node", "http_tls_cert and http_tls_key must be configured together");
+                return Err(());
+            }
+        };
+        let route_auth_secret = generate_route_auth_secret(&self.secret_seed);
+        let protected_route_prefixes = self.protected_route_prefixes.clone();
         let exchange_rate_provider = self.exchange_rate.provider.clone();
         let exchange_rate_poll_interval = self.exchange_rate.poll_interval;
         let exchange_rate_poll_failure_tolerance = self.exchange_rate.poll_failure_tolerance;
@@ -389,22 +489,60 @@ impl InterledgerNode {
             }
         });
 
+        let mut btp_client_service = BtpOutgoingService::new(ilp_address.clone(), outgoing_service);
+        if let Some(ms) = btp_ping_interval {
+            btp_client_service.ping_interval(Duration::from_millis(ms));
+        }
+        if let Some(max_missed_pongs) = btp_max_missed_pongs {
+            btp_client_service.max_missed_pongs(max_missed_pongs);
+        }
         // Connect to all of the accounts that have outgoing ilp_over_btp_urls configured
         // but don't fail if we are unable to connect
-        // TODO try reconnecting to those accounts later
-        let btp_client_service =
-            connect_client(ilp_address.clone(), btp_accounts, false, outgoing_service)
-                .map_err(|err| error!("{}", err))
-                .await?;
-        let btp_server_service =
-            BtpOutgoingService::new(ilp_address.clone(), btp_client_service.clone());
+        let connect_btp = btp_accounts.into_iter().map(|account| {
+            let username = account.username().clone();
+            let connect = connect_to_service_account(account, false, btp_client_service.clone());
+            async move { (username, connect.await) }
+        });
+        for (username, result) in join_all(connect_btp).await {
+            if let Err(err) = result {
+                warn!(target: "interledger-node",
+                    "Could not connect to account {} over BTP, retrying in the background: {}",
+                    username, err
+                );
+            }
+        }
+        // The supervisor retries the accounts we could not connect to, reconnects dropped
+        // connections and connects to the accounts added or updated through the API
+        let btp_supervisor = BtpSupervisor::new(store.clone(), btp_client_service.clone());
+        spawn(async move { btp_supervisor.run().await });
+        // Share the client's connection registry so the API lists the connections in both directions
+        let mut btp_server_service = BtpOutgoingService::with_connection_registry(
+            ilp_address.clone(),
+            btp_client_service.clone(),
+            btp_client_service.connection_registry().clone(),
+        );
+        if let Some(ms) = btp_ping_interval {
+            btp_server_service.ping_interval(Duration::from_millis(ms));
+        }
+        if let Some(max_missed_pongs) = btp_max_missed_pongs {
+            btp_server_service.max_missed_pongs(max_missed_pongs);
+        }
+        // Share the sub-protocol handlers too, so they also handle the packets of incoming connections
+        btp_server_service.share_sub_protocols(&btp_client_service);
         let btp_server_service_clone = btp_server_service.clone();
         let btp = btp_client_service.clone();
 
         // The BTP service is both an Incoming and Outgoing one so we pass it first as the Outgoing
         // service to others like the router and then call handle_incoming on it to set up the incoming handler
         let outgoing_service = btp_server_service.clone();
-        let outgoing_service = HttpClientService::new(store.clone(), outgoing_service);
+        // Asynchronous ILP over HTTP responses are received by the HTTP server and matched
+        // with the requests the HTTP client is waiting on
+        let http_callbacks = HttpCallbacks::new();
+        let outgoing_service = HttpClientService::with_callbacks(
+            store.clone(),
+            outgoing_service,
+            http_callbacks.clone(),
+        );
 
         #[cfg(feature = "monitoring")]
         let outgoing_service = outgoing_service.wrap(outgoing_metrics);
@@ -469,9 +607,35 @@ impl InterledgerNode {
         if let Some(ms) = route_broadcast_interval {
             ccp_builder.broadcast_interval(ms);
         }
+        if let Some(ms) = route_hold_down_time {
+            ccp_builder.hold_down_time(ms);
+        }
+        if let Some(ms) = route_expiry_time {
+            ccp_builder.route_expiry_time(ms);
+        }
+        ccp_builder.route_aggregation(route_aggregation);
+        ccp_builder.routing_events(store.clone());
+        // Rank routes by the latency measured on the BTP connections where there is one,
+        // falling back to the round trip times configured for the accounts
+        let connection_registry = btp_client_service.connection_registry().clone();
+        ccp_builder.round_trip_times(move |account_id: Uuid| {
+            connection_registry
+                .ping_latency(&account_id)
+                .map(|latency| latency.as_millis().min(u128::from(u32::MAX)) as u32)
+        });
+        info!(target: "interledger-node",
+            "CCP route auth anchor for {}: {}",
+            ilp_address,
+            hex::encode(route_auth_anchor(&route_auth_secret, &ilp_address))
+        );
+        ccp_builder.route_auth_secret(route_auth_secret);
+        for (prefix, anchor) in protected_route_prefixes {
+            ccp_builder.protected_prefix(prefix, anchor);
+        }
 
         let incoming_service = ccp_builder.to_service();
         let incoming_service = EchoService::new(store.clone(), incoming_service);
+        let echo_client = incoming_service.client();
         let incoming_service = SettlementMessageService::new(incoming_service);
         let incoming_service = IldcpService::new(incoming_service);
         let incoming_service = MaxPacketAmountService::new(store.clone(), incoming_service);
@@ -534,6 +698,8 @@ impl InterledgerNode {
         if let Some(username) = default_spsp_account {
             api.default_spsp_account(username);
         }
+        api.echo_client(echo_client);
+        api.exchange_rate_spread(exchange_rate_spread);
         api.node_version(env!("CARGO_PKG_VERSION").to_string());
 
         cfg_if! {
@@ -552,7 +718,12 @@ impl InterledgerNode {
         // add an API of ILP over HTTP and add rejection handler
         let api = api
             .into_warp_filter()
-            .or(IlpOverHttpServer::new(incoming_service_http, store.clone()).as_filter())
+            .or(IlpOverHttpServer::with_callbacks(
+                incoming_service_http,
+                store.clone(),
+                http_callbacks,
+            )
+            .as_filter())
             .or(btp_service_as_filter(
                 btp_server_service_clone,
                 store.clone(),
@@ -625,8 +796,17 @@ impl InterledgerNode {
             .with(warp::log("interledger-api"))
             .boxed();
 
-        info!(target: "interledger-node", "Interledger.rs node HTTP API listening on: {}", http_bind_address);
-        spawn(warp::serve(api).bind(http_bind_address));
+        if let Some(tls_config) = http_tls_config {
+            info!(target: "interledger-node", "Interledger.rs node HTTPS API listening on: {}", http_bind_address);
+            spawn(async move {
+                if let Err(err) = serve_tls(api, http_bind_address, tls_config).await {
+                    error!(target: "interledger-node", "Error serving the HTTPS API: {}", err);
+                }
+            });
+        } else {
+            info!(target: "interledger-node", "Interledger.rs node HTTP API listening on: {}", http_bind_address);
+            spawn(warp::serve(api).bind(http_bind_address));
+        }
 
         // Settlement API
         let settlement_api = create_settlements_filter(store.clone(), outgoing_service.clone());
@@ -2,16 +2,18 @@
 use async_trait::async_trait;
 use bytes::Bytes;
 use interledger_btp::{BtpAccount, BtpOutgoingService};
-use interledger_ccp::CcpRoutingAccount;
+use interledger_ccp::{CcpRoutingAccount, RoutingEventsStore, RoutingPolicy};
 use interledger_errors::NodeStoreError;
-use interledger_http::{HttpAccount, HttpStore};
+use interledger_http::{HttpAccount, HttpClientSettings, HttpStore};
 use interledger_packet::Address;
 use interledger_rates::ExchangeRateStore;
-use interledger_router::RouterStore;
+use interledger_router::{NextHop, RouterStore};
 use interledger_service::{
     Account, AccountStore, AddressStore, IncomingService, OutgoingService, Username,
 };
-use interledger_service_util::BalanceStore;
+use interledger_service_util::{
+    BalanceStore, EchoClient, MaxPacketAmountAccount, MinBalanceAccount,
+};
 use interledger_settlement::core::types::{SettlementAccount, SettlementStore};
 use interledger_stream::StreamNotificationsStore;
 use secrecy::SecretString;
@@ -135,6 +137,22 @@ pub trait NodeStore: Clone + Send + Sync + 'static {
         account_id: Uuid,
     ) -> Result<(), NodeStoreError>;
 
+    /// Sets the static routes for routing, where each route may have multiple
+    /// next hops with different weights and priorities
+    async fn set_static_multipath_routes<R>(&self, routes: R) -> Result<(), NodeStoreError>
+    where
+        R: IntoIterator<Item = (String, Vec<NextHop>)> + Send + 'async_trait;
+
+    /// Sets a single static route with multiple next hops
+    async fn set_static_multipath_route(
+        &self,
+        prefix: String,
+        next_hops: Vec<NextHop>,
+    ) -> Result<(), NodeStoreError>;
+
+    /// Gets the static routes, including the default route ("") if one is set
+    async fn get_static_routes(&self) -> Result<HashMap<String, Vec<NextHop>>, NodeStoreError>;
+
     /// Sets the default route ("") to be the provided account id
     /// (acts as a catch-all route if all other routes don't match)
     async fn set_default_route(&self, account_id: Uuid) -> Result<(), NodeStoreError>;
@@ -182,6 +200,21 @@ pub struct AccountSettings {
     pub ilp_over_btp_outgoing_token: Option<SecretString>,
     /// The account's ILP over HTTP URL (this is where packets are sent over HTTP from your node)
     pub ilp_over_http_url: Option<String>,
+    /// The URL the account's peer should POST asynchronous ILP over HTTP responses to.
+    /// If set, packets are sent to the account with the `Request-Id` and `Callback-Url` headers
+    pub ilp_over_http_callback_url: Option<String>,
+    /// Path to the PEM encoded client certificate presented to the account's peer on
+    /// ILP over HTTP requests (for peers that require mutual TLS)
+    pub ilp_over_http_client_cert: Option<String>,
+    /// Path to the PEM encoded private key of the ILP over HTTP client certificate
+    pub ilp_over_http_client_key: Option<String>,
+    /// The SHA-256 fingerprint of the client certificate the account's peer presents on
+    /// mutual TLS connections, which authenticates its ILP over HTTP requests
+    pub ilp_over_http_client_cert_fingerprint: Option<String>,
+    /// Settings of the HTTP client used to send ILP over HTTP requests to the account
+    /// (connection pool, keepalive, HTTP/2 and timeouts)
+    #[serde(default)]
+    pub ilp_over_http_client_settings: Option<HttpClientSettings>,
     /// The account's ILP over BTP URL (this is where packets are sent over WebSockets from your node)
     pub ilp_over_btp_url: Option<String>,
     /// The threshold after which the balance service will trigger a settlement
@@ -206,6 +239,11 @@ pub struct EncryptedAccountSettings {
     pub ilp_over_http_outgoing_token: Option<Bytes>,
     pub ilp_over_btp_outgoing_token: Option<Bytes>,
     pub ilp_over_http_url: Option<String>,
+    pub ilp_over_http_callback_url: Option<String>,
+    pub ilp_over_http_client_cert: Option<String>,
+    pub ilp_over_http_client_key: Option<String>,
+    pub ilp_over_http_client_cert_fingerprint: Option<String>,
+    pub ilp_over_http_client_settings: Option<HttpClientSettings>,
     pub ilp_over_btp_url: Option<String>,
     #[serde(default, deserialize_with = "optional_number_or_string")]
     /// The threshold after which the balance service will trigger a settlement
@@ -236,6 +274,21 @@ pub struct AccountDetails {
     pub min_balance: Option<i64>,
     /// The account's ILP over HTTP URL (this is where packets are sent over HTTP from your node)
     pub ilp_over_http_url: Option<String>,
+    /// The URL the account's peer should POST asynchronous ILP over HTTP responses to.
+    /// If set, packets are sent to the account with the `Request-Id` and `Callback-Url` headers
+    pub ilp_over_http_callback_url: Option<String>,
+    /// Path to the PEM encoded client certificate presented to the account's peer on
+    /// ILP over HTTP requests (for peers that require mutual TLS)
+    pub ilp_over_http_client_cert: Option<String>,
+    /// Path to the PEM encoded private key of the ILP over HTTP client certificate
+    pub ilp_over_http_client_key: Option<String>,
+    /// The SHA-256 fingerprint of the client certificate the account's peer presents on
+    /// mutual TLS connections, which authenticates its ILP over HTTP requests
+    pub ilp_over_http_client_cert_fingerprint: Option<String>,
+    /// Settings of the HTTP client used to send ILP over HTTP requests to the account
+    /// (connection pool, keepalive, HTTP/2 and timeouts)
+    #[serde(default)]
+    pub ilp_over_http_client_settings: Option<HttpClientSettings>,
     /// The account's API and incoming ILP over HTTP token.
     /// This must match the ILP over HTTP outgoing token on the peer's node if receiving
     /// packets from that peer
@@ -282,6 +335,9 @@ pub struct AccountDetails {
     /// for the account's asset code,  that will be used instead (even if the account is
     /// configured with a specific one)
     pub settlement_engine_url: Option<String>,
+    /// The policy applied to the routes exchanged with the account over CCP
+    #[serde(default)]
+    pub routing_policy: Option<RoutingPolicy>,
 }
 
 pub struct NodeApi<S, I, O, B, A: Account> {
@@ -290,6 +346,8 @@ pub struct NodeApi<S, I, O, B, A: Account> {
     // TODO: Make this a SecretString
     admin_api_token: String,
     default_spsp_account: Option<Username>,
+    /// Used to originate echo requests (pings) from the node
+    echo_client: Option<EchoClient>,
     incoming_handler: I,
     // The outgoing service is included so that the API can send outgoing
     // requests to specific accounts (namely ILDCP requests)
@@ -300,6 +358,8 @@ pub struct NodeApi<S, I, O, B, A: Account> {
     /// Server secret used to instantiate SPSP/Stream connections
     server_secret: Bytes,
     node_version: Option<String>,
+    /// The spread applied by the node's `ExchangeRateService`, used to explain routes
+    exchange_rate_spread: f64,
 }
 
 impl<S, I, O, B, A> NodeApi<S, I, O, B, A>
@@ -312,6 +372,7 @@ where
         + SettlementStore<Account = A>
         + StreamNotificationsStore<Account = A>
         + RouterStore
+        + RoutingEventsStore
         + ExchangeRateStore,
     I: IncomingService<A> + Clone + Send + Sync + 'static,
     O: OutgoingService<A> + Clone + Send + Sync + 'static,
@@ -320,6 +381,8 @@ where
         + CcpRoutingAccount
         + Account
         + HttpAccount
+        + MaxPacketAmountAccount
+        + MinBalanceAccount
         + SettlementAccount
         + Serialize
         + Send
@@ -338,11 +401,13 @@ where
             store,
             admin_api_token,
             default_spsp_account: None,
+            echo_client: None,
             incoming_handler,
             outgoing_handler,
             btp,
             server_secret,
             node_version: None,
+            exchange_rate_spread: 0.0,
         }
     }
 
@@ -354,18 +419,34 @@ where
         self
     }
 
+    /// Sets the echo client used to ping other nodes. It should be obtained from the
+    /// `EchoService` in the node's incoming service chain, since that service is
+    /// responsible for fulfilling the echo responses routed back to the node.
+    pub fn echo_client(&mut self, echo_client: EchoClient) -> &mut Self {
+        self.echo_client = Some(echo_client);
+        self
+    }
+
     /// Sets the node version
     pub fn node_version(&mut self, version: String) -> &mut Self {
         self.node_version = Some(version);
         self
     }
 
-    /// Returns a Warp Filter which exposes the accounts and admin APIs
+    /// Sets the exchange rate spread the node applies to packets, so that the
+    /// route explanations show the same outgoing amounts the node would send
+    pub fn exchange_rate_spread(&mut self, spread: f64) -> &mut Self {
+        self.exchange_rate_spread = spread;
+        self
+    }
+
+    /// Returns a Warp Filter which exposes the accounts, admin and public receipt APIs
     pub fn into_warp_filter(self) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
         routes::accounts_api(
             self.server_secret,
             self.admin_api_token.clone(),
             self.default_spsp_account,
+            self.echo_client,
             self.incoming_handler,
             self.outgoing_handler,
             self.btp,
@@ -374,8 +455,10 @@ where
         .or(routes::node_settings_api(
             self.admin_api_token,
             self.node_version,
+            self.exchange_rate_spread,
             self.store,
         ))
+        .or(routes::receipts_api())
         .boxed()
     }
 
@@ -453,6 +536,10 @@ mod tests {
             "ilp_over_http_incoming_token": "secret",
             "settle_to": 0,
             "settle_threshold": "1000",
+            "ilp_over_http_client_settings": {
+                "http2_prior_knowledge": true,
+                "connect_timeout": 2000,
+            },
         }))
         .unwrap();
         assert_eq!(settings.settle_threshold, Some(1000));
@@ -462,5 +549,13 @@ mod tests {
             Some("https://example.com/ilp".to_string())
         );
         assert!(settings.ilp_over_btp_url.is_none());
+        assert_eq!(
+            settings.ilp_over_http_client_settings,
+            Some(HttpClientSettings {
+                http2_prior_knowledge: true,
+                connect_timeout: Some(2000),
+                ..Default::default()
+            })
+        );
     }
 }
@@ -1,22 +1,30 @@
-use crate::{number_or_string, AccountDetails, AccountSettings, NodeStore};
+use crate::{
+    number_or_string, optional_number_or_string, AccountDetails, AccountSettings, NodeStore,
+};
 use bytes::Bytes;
 use futures::{Future, FutureExt, StreamExt, TryFutureExt};
-use interledger_btp::{connect_to_service_account, BtpAccount, BtpOutgoingService};
+use interledger_btp::{
+    connect_to_service_account, BtpAccount, BtpConnectionInfo, BtpOutgoingService,
+    ConnectionDirection,
+};
 use interledger_ccp::{CcpRoutingAccount, Mode, RouteControlRequest, RoutingRelation};
 use interledger_errors::*;
 use interledger_http::{deserialize_json, HttpAccount, HttpStore};
 use interledger_ildcp::IldcpRequest;
 use interledger_ildcp::IldcpResponse;
+use interledger_packet::Address;
 use interledger_rates::ExchangeRateStore;
 use interledger_router::RouterStore;
 use interledger_service::{
     Account, AccountStore, AddressStore, IncomingService, OutgoingRequest, OutgoingService,
     Username,
 };
-use interledger_service_util::BalanceStore;
+use interledger_service_util::{BalanceStore, EchoClient, DEFAULT_PING_TIMEOUT};
 use interledger_settlement::core::{types::SettlementAccount, SettlementClient};
-use interledger_spsp::{pay, SpspResponder};
-use interledger_stream::{PaymentNotification, StreamNotificationsStore};
+use interledger_spsp::{parse_receipt_headers, pay, pay_fixed_delivery, quote, SpspResponder};
+use interledger_stream::{
+    CongestionStrategy, PaymentNotification, ReceiptDetails, StreamNotificationsStore,
+};
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
@@ -75,19 +83,81 @@ const fn get_default_max_slippage() -> f64 {
 #[derive(Deserialize, Debug)]
 struct SpspPayRequest {
     receiver: String,
-    #[serde(deserialize_with = "number_or_string")]
-    source_amount: u64,
+    /// The amount to send, unless the payment delivers a fixed destination amount
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    source_amount: Option<u64>,
     #[serde(
         deserialize_with = "number_or_string",
         default = "get_default_max_slippage"
     )]
     slippage: f64,
+    /// The amount the receiver should get, in its asset's units
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    destination_amount: Option<u64>,
+    /// The most a payment delivering a fixed destination amount may send
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    max_source_amount: Option<u64>,
+    /// How the amount in flight and the packet amounts are adjusted (AIMD by default)
+    #[serde(default)]
+    congestion_control: CongestionStrategy,
+}
+
+#[derive(Deserialize, Debug)]
+struct SpspQuoteRequest {
+    receiver: String,
+    #[serde(deserialize_with = "number_or_string")]
+    source_amount: u64,
+    /// Limits the test packets like the packets of the payment (AIMD by default)
+    #[serde(default)]
+    congestion_control: CongestionStrategy,
 }
 
+#[derive(Deserialize, Debug)]
+struct PingRequest {
+    destination: Address,
+    from: Username,
+    #[serde(deserialize_with = "number_or_string", default)]
+    amount: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct BtpConnectionResponse {
+    username: Username,
+    account_id: Uuid,
+    direction: &'static str,
+    connected_since: String,
+    last_activity: String,
+    messages_sent: u64,
+    messages_received: u64,
+    ping_latency_ms: Option<f64>,
+}
+
+impl From<BtpConnectionInfo> for BtpConnectionResponse {
+    fn from(info: BtpConnectionInfo) -> Self {
+        BtpConnectionResponse {
+            username: info.username,
+            account_id: info.account_id,
+            direction: match info.direction {
+                ConnectionDirection::Incoming => "incoming",
+                ConnectionDirection::Outgoing => "outgoing",
+            },
+            connected_since: info.connected_since.to_rfc3339(),
+            last_activity: info.last_activity.to_rfc3339(),
+            messages_sent: info.messages_sent,
+            messages_received: info.messages_received,
+            ping_latency_ms: info
+                .ping_latency
+                .map(|latency| latency.as_secs_f64() * 1000.0),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn accounts_api<I, O, S, A, B>(
     server_secret: Bytes,
     admin_api_token: String,
     default_spsp_account: Option<Username>,
+    echo_client: Option<EchoClient>,
     incoming_handler: I,
     outgoing_handler: O,
     btp: BtpOutgoingService<B, A>,
@@ -320,6 +390,60 @@ where
             }
         });
 
+    // GET /btp/connections
+    let btp_clone = btp.clone();
+    let get_btp_connections = warp::get()
+        .and(warp::path("btp"))
+        .and(warp::path("connections"))
+        .and(warp::path::end())
+        .and(admin_only.clone())
+        .map(move || {
+            let mut connections: Vec<BtpConnectionResponse> = btp_clone
+                .connection_registry()
+                .connections()
+                .into_iter()
+                .map(BtpConnectionResponse::from)
+                .collect();
+            connections.sort_by(|a, b| {
+                a.username
+                    .as_ref()
+                    .cmp(b.username.as_ref())
+                    .then_with(|| a.direction.cmp(b.direction))
+            });
+            warp::reply::json(&connections)
+        });
+
+    // DELETE /btp/connections/:username
+    let btp_clone = btp.clone();
+    let delete_btp_connections = warp::delete()
+        .and(warp::path("btp"))
+        .and(warp::path("connections"))
+        .and(account_username_to_id.clone())
+        .and(warp::path::end())
+        .and(admin_only.clone())
+        .and_then(move |id: Uuid| {
+            let btp = btp_clone.clone();
+            async move {
+                let closed: Vec<BtpConnectionResponse> = btp
+                    .connection_registry()
+                    .connections()
+                    .into_iter()
+                    .filter(|connection| connection.account_id == id)
+                    .map(BtpConnectionResponse::from)
+                    .collect();
+                // Stop sending packets over the connection right away,
+                // then close it (and any incoming connection from the account).
+                // The BtpSupervisor reopens the outgoing connection on its next check
+                btp.close_connection(&id);
+                if !btp.connection_registry().close(&id) {
+                    return Err(Rejection::from(
+                        ApiError::not_found().detail("the account has no open BTP connections"),
+                    ));
+                }
+                Ok::<Json, Rejection>(warp::reply::json(&closed))
+            }
+        });
+
     // PUT /accounts/:username/settings
     let outgoing_handler_clone = outgoing_handler;
     let put_account_settings = warp::put()
@@ -372,7 +496,7 @@ where
 
     // (Websocket) /payments/incoming
     let all_payment_notifications = warp::path("payments")
-        .and(admin_only)
+        .and(admin_only.clone())
         .and(warp::path("incoming"))
         .and(warp::path::end())
         .and(warp::ws())
@@ -388,30 +512,60 @@ where
     // POST /accounts/:username/payments
     let post_payments = warp::post()
         .and(warp::path("accounts"))
-        .and(authorized_user_only)
+        .and(authorized_user_only.clone())
         .and(warp::path("payments"))
         .and(warp::path::end())
         .and(deserialize_json())
-        .and(with_incoming_handler)
+        .and(with_incoming_handler.clone())
         .and(with_store.clone())
         .and_then(
             move |account: A, pay_request: SpspPayRequest, incoming_handler: I, store: S| {
                 async move {
-                    let receipt = pay(
-                        incoming_handler,
-                        account.clone(),
-                        store,
-                        &pay_request.receiver,
+                    pay_request
+                        .congestion_control
+                        .validate()
+                        .map_err(|err| ApiError::bad_request().detail(err.to_string()))?;
+                    let result = match (
                         pay_request.source_amount,
-                        pay_request.slippage,
-                    )
-                    .map_err(|err| {
+                        pay_request.destination_amount,
+                        pay_request.max_source_amount,
+                    ) {
+                        (Some(source_amount), None, None) => {
+                            pay(
+                                incoming_handler,
+                                account.clone(),
+                                store,
+                                &pay_request.receiver,
+                                source_amount,
+                                pay_request.slippage,
+                                pay_request.congestion_control,
+                            )
+                            .await
+                        }
+                        (None, Some(destination_amount), Some(max_source_amount)) => {
+                            pay_fixed_delivery(
+                                incoming_handler,
+                                account.clone(),
+                                store,
+                                &pay_request.receiver,
+                                destination_amount,
+                                max_source_amount,
+                                pay_request.congestion_control,
+                            )
+                            .await
+                        }
+                        _ => {
+                            return Err(Rejection::from(ApiError::bad_request().detail(
+                                "either source_amount or destination_amount and max_source_amount must be set",
+                            )));
+                        }
+                    };
+                    let receipt = result.map_err(|err| {
                         let msg = format!("Error sending SPSP payment: {}", err);
                         error!("{}", msg);
                         // TODO give a different error message depending on what type of error it is
                         Rejection::from(ApiError::internal_server_error().detail(msg))
-                    })
-                    .await?;
+                    })?;
 
                     debug!("Sent SPSP payment, receipt: {:?}", receipt);
                     Ok::<Json, Rejection>(warp::reply::json(&json!(receipt)))
@@ -419,6 +573,95 @@ where
             },
         );
 
+    // POST /accounts/:username/quotes
+    let post_quotes =
+        warp::post()
+            .and(warp::path("accounts"))
+            .and(authorized_user_only)
+            .and(warp::path("quotes"))
+            .and(warp::path::end())
+            .and(deserialize_json())
+            .and(with_incoming_handler.clone())
+            .and(with_store.clone())
+            .and_then(
+                move |account: A,
+                      quote_request: SpspQuoteRequest,
+                      incoming_handler: I,
+                      store: S| {
+                    async move {
+                        quote_request
+                            .congestion_control
+                            .validate()
+                            .map_err(|err| ApiError::bad_request().detail(err.to_string()))?;
+                        let quote = quote(
+                            incoming_handler,
+                            account,
+                            store,
+                            &quote_request.receiver,
+                            quote_request.source_amount,
+                            quote_request.congestion_control,
+                        )
+                        .await
+                        .map_err(|err| {
+                            let msg = format!("Error quoting SPSP payment: {}", err);
+                            error!("{}", msg);
+                            Rejection::from(ApiError::internal_server_error().detail(msg))
+                        })?;
+
+                        debug!("Quoted SPSP payment: {:?}", quote);
+                        Ok::<Json, Rejection>(warp::reply::json(&json!(quote)))
+                    }
+                },
+            );
+
+    // POST /ping
+    // Body: { "destination": ILP Address, "from": Username, "amount": (optional) u64 }
+    let post_ping = warp::post()
+        .and(warp::path("ping"))
+        .and(warp::path::end())
+        .and(admin_only.clone())
+        .and(deserialize_json())
+        .and(with_incoming_handler)
+        .and(with_store.clone())
+        .and_then(
+            move |ping_request: PingRequest, incoming_handler: I, store: S| {
+                let echo_client = echo_client.clone();
+                async move {
+                    let echo_client = echo_client.ok_or_else(|| {
+                        Rejection::from(
+                            ApiError::not_found().detail("the echo client is not enabled"),
+                        )
+                    })?;
+                    let id = store
+                        .get_account_id_from_username(&ping_request.from)
+                        .await?;
+                    let mut accounts = store.get_accounts(vec![id]).await?;
+                    let account = accounts.pop().unwrap();
+
+                    let result = echo_client
+                        .ping(
+                            incoming_handler,
+                            account,
+                            &store.get_ilp_address(),
+                            ping_request.destination,
+                            ping_request.amount,
+                            DEFAULT_PING_TIMEOUT,
+                        )
+                        .await;
+                    debug!("Ping result: {:?}", result);
+                    Ok::<Json, Rejection>(warp::reply::json(&result))
+                }
+            },
+        );
+
+    // The receipt details an SPSP query may include for receiving STREAM receipts
+    let receipt_details = warp::header::optional::<String>("receipt-nonce")
+        .and(warp::header::optional::<String>("receipt-secret"))
+        .and_then(|nonce: Option<String>, secret: Option<String>| async move {
+            parse_receipt_headers(nonce.as_deref(), secret.as_deref())
+                .map_err(|err| Rejection::from(ApiError::bad_request().detail(err)))
+        });
+
     // GET /accounts/:username/spsp
     let server_secret_clone = server_secret.clone();
     let get_spsp = warp::get()
@@ -426,21 +669,24 @@ where
         .and(account_username_to_id)
         .and(warp::path("spsp"))
         .and(warp::path::end())
+        .and(receipt_details)
         .and(with_store.clone())
-        .and_then(move |id: Uuid, store: S| {
-            let server_secret_clone = server_secret_clone.clone();
-            async move {
-                let accounts = store.get_accounts(vec![id]).await?;
-                // TODO return the response without instantiating an SpspResponder (use a simple fn)
-                Ok::<_, Rejection>(
-                    SpspResponder::new(
-                        accounts[0].ilp_address().clone(),
-                        server_secret_clone.clone(),
+        .and_then(
+            move |id: Uuid, receipt_details: Option<ReceiptDetails>, store: S| {
+                let server_secret_clone = server_secret_clone.clone();
+                async move {
+                    let accounts = store.get_accounts(vec![id]).await?;
+                    // TODO return the response without instantiating an SpspResponder (use a simple fn)
+                    Ok::<_, Rejection>(
+                        SpspResponder::new(
+                            accounts[0].ilp_address().clone(),
+                            server_secret_clone.clone(),
+                        )
+                        .generate_http_response_with_receipts(receipt_details.as_ref()),
                     )
-                    .generate_http_response(),
-                )
-            }
-        });
+                }
+            },
+        );
 
     // GET /.well-known/pay
     // This is the endpoint a [Payment Pointer](https://github.com/interledger/rfcs/blob/master/0026-payment-pointers/0026-payment-pointers.md)
@@ -449,8 +695,9 @@ where
         .and(warp::path(".well-known"))
         .and(warp::path("pay"))
         .and(warp::path::end())
+        .and(receipt_details)
         .and(with_store)
-        .and_then(move |store: S| {
+        .and_then(move |receipt_details: Option<ReceiptDetails>, store: S| {
             let default_spsp_account = default_spsp_account.clone();
             let server_secret_clone = server_secret.clone();
             async move {
@@ -467,7 +714,7 @@ where
                             account.ilp_address().clone(),
                             server_secret_clone.clone(),
                         )
-                        .generate_http_response(),
+                        .generate_http_response_with_receipts(receipt_details.as_ref()),
                     )
                 } else {
                     Err(Rejection::from(
@@ -491,13 +738,19 @@ where
         get_account,
         get_account_balance,
         put_account_settings,
+        get_btp_connections,
+        delete_btp_connections,
         incoming_payment_notifications,
         all_payment_notifications,
         post_payments,
+        post_quotes,
+        post_ping,
     )
 }
 
-async fn consume_msg_drain(mut ws_rx: futures::stream::SplitStream<warp::ws::WebSocket>) {
+pub(super) async fn consume_msg_drain(
+    mut ws_rx: futures::stream::SplitStream<warp::ws::WebSocket>,
+) {
     while let Some(result) = ws_rx.next().await {
         if let Err(e) = result {
             debug!("consume msg drain read error: {}", e);
@@ -724,8 +977,35 @@ where
 #[cfg(test)]
 mod tests {
     use crate::routes::test_helpers::*;
+    use interledger_btp::{btp_service_as_filter, BtpOutgoingService};
+    use interledger_packet::{ErrorCode, RejectBuilder};
+    use interledger_service::outgoing_service_fn;
+    use serde_json::Value;
+    use std::time::Duration;
     // TODO: Add test for GET /accounts/:username/spsp and /.well_known
 
+    #[tokio::test]
+    async fn spsp_accepts_receipt_headers() {
+        let api = test_accounts_api();
+        let spsp_query = |nonce: &str| {
+            warp::test::request()
+                .method("GET")
+                .path("/accounts/alice/spsp")
+                .header("Receipt-Nonce", nonce)
+                .header(
+                    "Receipt-Secret",
+                    "AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=",
+                )
+                .reply(&api)
+        };
+
+        let resp = spsp_query("AQEBAQEBAQEBAQEBAQEBAQ==").await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let resp = spsp_query("AQEB").await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+
     #[tokio::test]
     async fn only_admin_can_create_account() {
         let api = test_accounts_api();
@@ -766,6 +1046,63 @@ mod tests {
         assert_eq!(resp.status().as_u16(), 401);
     }
 
+    #[tokio::test]
+    async fn only_admin_can_get_btp_connections() {
+        let api = test_accounts_api();
+        let resp = api_call(&api, "GET", "/btp/connections", "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.body(), "[]");
+
+        let resp = api_call(&api, "GET", "/btp/connections", "wrong", None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[tokio::test]
+    async fn only_admin_can_close_btp_connections() {
+        let api = test_accounts_api();
+        // alice is not connected over BTP
+        let resp = api_call(&api, "DELETE", "/btp/connections/alice", "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 404);
+
+        let resp = api_call(&api, "DELETE", "/btp/connections/alice", "wrong", None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[tokio::test]
+    async fn closes_open_btp_connections() {
+        let btp = BtpOutgoingService::new(
+            EXAMPLE_ADDRESS.clone(),
+            outgoing_service_fn(|_request| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    data: &[],
+                    triggered_by: None,
+                }
+                .build())
+            }),
+        );
+        let (addr, server) = warp::serve(btp_service_as_filter(btp.clone(), TestStore))
+            .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let _peer = connect_btp_peer(addr).await;
+        // The server adds the connection right after answering the auth request
+        for _ in 0..100 {
+            if !btp.connection_registry().connections().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let api = test_accounts_api_with_btp(btp.clone());
+        let resp = api_call(&api, "DELETE", "/btp/connections/alice", "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let closed: Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(closed.as_array().unwrap().len(), 1);
+        assert_eq!(closed[0]["username"], "alice");
+        assert_eq!(closed[0]["direction"], "incoming");
+    }
+
     #[tokio::test]
     async fn only_admin_or_user_can_get_account() {
         let api = test_accounts_api();
@@ -870,4 +1207,142 @@ mod tests {
         .await;
         assert_eq!(resp.status().as_u16(), 401);
     }
+
+    #[tokio::test]
+    async fn only_admin_or_user_can_quote_payment() {
+        let quote: Option<serde_json::Value> = Some(serde_json::json!({
+            "receiver": "some_receiver",
+            "source_amount" : 10,
+        }));
+        let api = test_accounts_api();
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/quotes",
+            "password",
+            quote.clone(),
+        )
+        .await;
+        // Like sending a payment, quoting fails to query the invalid receiver
+        assert_eq!(resp.status().as_u16(), 500);
+
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/quotes",
+            "admin",
+            quote.clone(),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let resp = api_call(&api, "POST", "/accounts/alice/quotes", "wrong", quote).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[tokio::test]
+    async fn validates_payment_amounts() {
+        let api = test_accounts_api();
+        for payment in [
+            serde_json::json!({ "receiver": "some_receiver" }),
+            serde_json::json!({ "receiver": "some_receiver", "destination_amount": 10 }),
+            serde_json::json!({
+                "receiver": "some_receiver",
+                "source_amount": 10,
+                "destination_amount": 10,
+                "max_source_amount": 20,
+            }),
+        ] {
+            let resp = api_call(
+                &api,
+                "POST",
+                "/accounts/alice/payments",
+                "password",
+                Some(payment),
+            )
+            .await;
+            assert_eq!(resp.status().as_u16(), 400);
+        }
+
+        // Fails to query the invalid receiver, like the payments sending a fixed source amount
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/payments",
+            "password",
+            Some(serde_json::json!({
+                "receiver": "some_receiver",
+                "destination_amount": "10",
+                "max_source_amount": "20",
+            })),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 500);
+    }
+
+    #[tokio::test]
+    async fn validates_congestion_control() {
+        let api = test_accounts_api();
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/payments",
+            "password",
+            Some(serde_json::json!({
+                "receiver": "some_receiver",
+                "source_amount": 10,
+                "congestion_control": { "strategy": "aimd", "decrease_factor": 0.5 },
+            })),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 400);
+
+        // Fails to query the invalid receiver
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/payments",
+            "password",
+            Some(serde_json::json!({
+                "receiver": "some_receiver",
+                "source_amount": 10,
+                "congestion_control": { "strategy": "latency", "target_latency_ms": 200 },
+            })),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 500);
+
+        let resp = api_call(
+            &api,
+            "POST",
+            "/accounts/alice/quotes",
+            "password",
+            Some(serde_json::json!({
+                "receiver": "some_receiver",
+                "source_amount": 10,
+                "congestion_control": { "strategy": "latency", "target_latency_ms": 0 },
+            })),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+
+    #[tokio::test]
+    async fn only_admin_can_ping() {
+        let ping: Option<serde_json::Value> = Some(serde_json::json!({
+            "destination": "example.bob",
+            "from": "alice",
+        }));
+        let api = test_accounts_api();
+        let resp = api_call(&api, "POST", "/ping", "admin", ping.clone()).await;
+        // The test incoming handler rejects everything, which is reported in the ping result
+        assert_eq!(resp.status().as_u16(), 200);
+        let result: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(result["status"], "rejected");
+        assert_eq!(result["code"], "F02");
+        assert_eq!(result["destination"], "example.bob");
+
+        let resp = api_call(&api, "POST", "/ping", "password", ping).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
 }
@@ -1,8 +1,10 @@
 mod accounts;
 mod node_settings;
+mod receipts;
 
 pub use accounts::accounts_api;
 pub use node_settings::node_settings_api;
+pub use receipts::receipts_api;
 
 #[cfg(test)]
 pub mod test_helpers;
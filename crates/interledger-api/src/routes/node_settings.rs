@@ -1,20 +1,25 @@
+use super::accounts::consume_msg_drain;
 use crate::{ExchangeRates, NodeStore};
 use bytes::Bytes;
-use futures::TryFutureExt;
+use futures::{Future, FutureExt, StreamExt, TryFutureExt};
+use interledger_ccp::RoutingEventsStore;
 use interledger_errors::*;
 use interledger_http::{deserialize_json, HttpAccount};
-use interledger_packet::Address;
+use interledger_packet::{Address, ErrorCode};
 use interledger_rates::ExchangeRateStore;
-use interledger_router::RouterStore;
+use interledger_router::{NextHop, RouterStore};
 use interledger_service::{Account, AccountStore, AddressStore, Username};
+use interledger_service_util::{
+    convert_amount, exchange_rate, BalanceStore, MaxPacketAmountAccount, MinBalanceAccount,
+};
 use interledger_settlement::core::{types::SettlementAccount, SettlementClient};
 use secrecy::{ExposeSecret, SecretString};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     str::{self, FromStr},
 };
-use tracing::{error, trace};
+use tracing::{error, trace, warn};
 use url::Url;
 use uuid::Uuid;
 use warp::{self, reply::Json, Filter, Rejection};
@@ -28,18 +33,290 @@ struct StatusResponse {
     version: Option<String>,
 }
 
+/// A static route as given in the API: either the username of the only next hop,
+/// or a list of next hops with weights and priorities
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum StaticRoute {
+    Account(String),
+    NextHops(Vec<StaticNextHop>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StaticNextHop {
+    account: String,
+    #[serde(default = "default_weight")]
+    weight: u32,
+    #[serde(default)]
+    priority: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl StaticRoute {
+    /// Looks up the account ids of the route's next hops
+    async fn next_hops<S: AccountStore>(&self, store: &S) -> Result<Vec<NextHop>, Rejection> {
+        match self {
+            StaticRoute::Account(username) => {
+                let account_id = account_id(store, username).await?;
+                Ok(vec![NextHop::from(account_id)])
+            }
+            StaticRoute::NextHops(next_hops) => {
+                let mut resolved = Vec::with_capacity(next_hops.len());
+                for next_hop in next_hops {
+                    let account_id = account_id(store, &next_hop.account).await?;
+                    resolved.push(NextHop::new(account_id, next_hop.weight, next_hop.priority));
+                }
+                Ok(resolved)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ExplainQuery {
+    destination: Address,
+    from: String,
+    #[serde(default)]
+    amount: u64,
+}
+
+/// Where the route used for a destination came from
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RouteSource {
+    /// Configured via the API or the node's config (including the default route)
+    Static,
+    /// Learned from a peer via CCP
+    Ccp,
+    /// The route to one of the node's own accounts
+    Local,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct MatchedRoute {
+    prefix: String,
+    source: RouteSource,
+}
+
+/// The reject a packet would most likely get from the node
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct ExpectedReject {
+    code: String,
+    message: String,
+}
+
+impl ExpectedReject {
+    fn new(code: ErrorCode, message: impl ToString) -> Self {
+        ExpectedReject {
+            code: code.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct NextHopExplanation {
+    account: Option<Username>,
+    account_id: Uuid,
+    weight: u32,
+    priority: u32,
+    /// The exchange rate with the spread applied, not including the difference in asset scales
+    exchange_rate: Option<f64>,
+    outgoing_amount: Option<u64>,
+    reject: Option<ExpectedReject>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct RouteExplanation {
+    destination: Address,
+    from: Username,
+    amount: u64,
+    /// The sending account's max packet amount
+    max_packet_amount: u64,
+    /// The sending account's balance (including any prepaid amount)
+    balance: i64,
+    min_balance: Option<i64>,
+    /// How much the sending account's balance can still go down,
+    /// or `None` if it is not limited
+    balance_headroom: Option<i64>,
+    route: Option<MatchedRoute>,
+    /// The route's next hops, ordered by priority
+    next_hops: Vec<NextHopExplanation>,
+    /// The reject the packet would get from this node, if any
+    reject: Option<ExpectedReject>,
+}
+
+/// Runs a packet through the same checks as the incoming `MaxPacketAmountService`,
+/// the `Router` and the outgoing `ExchangeRateService` and `BalanceService`,
+/// without actually sending or preparing anything
+async fn explain_route<S, A>(
+    store: &S,
+    exchange_rate_spread: f64,
+    query: ExplainQuery,
+) -> Result<RouteExplanation, Rejection>
+where
+    S: NodeStore<Account = A>
+        + AccountStore<Account = A>
+        + BalanceStore
+        + ExchangeRateStore
+        + RouterStore,
+    A: Account + MaxPacketAmountAccount + MinBalanceAccount,
+{
+    let from_id = account_id(store, &query.from).await?;
+    let from = store.get_accounts(vec![from_id]).await?.remove(0);
+    let balance = store.get_balance(from_id).await?;
+    let amount = query.amount;
+    let mut explanation = RouteExplanation {
+        destination: query.destination.clone(),
+        from: from.username().clone(),
+        amount,
+        max_packet_amount: from.max_packet_amount(),
+        balance,
+        min_balance: from.min_balance(),
+        balance_headroom: from
+            .min_balance()
+            .map(|min_balance| balance.saturating_sub(min_balance)),
+        route: None,
+        next_hops: Vec::new(),
+        reject: None,
+    };
+
+    if amount > explanation.max_packet_amount {
+        explanation.reject = Some(ExpectedReject::new(
+            ErrorCode::F08_AMOUNT_TOO_LARGE,
+            format!(
+                "Amount {} exceeds the max packet amount of {}",
+                amount, explanation.max_packet_amount
+            ),
+        ));
+    }
+
+    let routing_table = store.routing_table();
+    let destination: &str = &query.destination;
+    let (prefix, next_hops) = match routing_table.longest_match_next_hops(destination) {
+        Some(route) => route,
+        None => {
+            explanation.reject.get_or_insert_with(|| {
+                ExpectedReject::new(ErrorCode::F02_UNREACHABLE, "No route found")
+            });
+            return Ok(explanation);
+        }
+    };
+
+    let mut accounts = Vec::with_capacity(next_hops.len());
+    for next_hop in next_hops {
+        // Look the accounts up one by one, the router skips the ones that don't exist
+        accounts.push(
+            store
+                .get_accounts(vec![next_hop.account_id])
+                .await
+                .ok()
+                .map(|mut accounts| accounts.remove(0)),
+        );
+    }
+
+    let source = if store.get_static_routes().await?.contains_key(prefix) {
+        RouteSource::Static
+    } else if accounts.iter().flatten().any(|account| {
+        let address: &str = account.ilp_address();
+        address == prefix
+    }) {
+        RouteSource::Local
+    } else {
+        RouteSource::Ccp
+    };
+    explanation.route = Some(MatchedRoute {
+        prefix: prefix.to_string(),
+        source,
+    });
+
+    for (next_hop, to) in next_hops.iter().zip(accounts.iter()) {
+        let mut hop = NextHopExplanation {
+            account: to.as_ref().map(|to| to.username().clone()),
+            account_id: next_hop.account_id,
+            weight: next_hop.weight,
+            priority: next_hop.priority,
+            exchange_rate: None,
+            outgoing_amount: None,
+            reject: None,
+        };
+        match to {
+            None => {
+                hop.reject = Some(ExpectedReject::new(
+                    ErrorCode::F02_UNREACHABLE,
+                    "No account found for the next hop",
+                ))
+            }
+            Some(to) => {
+                hop.exchange_rate = exchange_rate(store, exchange_rate_spread, &from, to).ok();
+                // Zero-amount packets are forwarded without converting the amount
+                let outgoing_amount = if amount > 0 {
+                    convert_amount(store, exchange_rate_spread, &from, to, amount)
+                } else {
+                    Ok(0)
+                };
+                match outgoing_amount {
+                    Ok(outgoing_amount) => hop.outgoing_amount = Some(outgoing_amount),
+                    Err((code, message)) => hop.reject = Some(ExpectedReject::new(code, message)),
+                }
+            }
+        }
+        explanation.next_hops.push(hop);
+    }
+
+    // The packet is only rejected if none of the next hops work
+    if explanation.next_hops.iter().all(|hop| hop.reject.is_some()) {
+        if let Some(reject) = explanation.next_hops[0].reject.clone() {
+            explanation.reject.get_or_insert(reject);
+        }
+    }
+
+    if let Some(headroom) = explanation.balance_headroom {
+        if amount > 0 && (amount as i128) > i128::from(headroom) {
+            explanation.reject.get_or_insert_with(|| {
+                ExpectedReject::new(
+                    ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                    "The packet would bring the sending account under its minimum balance",
+                )
+            });
+        }
+    }
+
+    Ok(explanation)
+}
+
+async fn account_id<S: AccountStore>(store: &S, username: &str) -> Result<Uuid, Rejection> {
+    let username =
+        Username::from_str(username).map_err(|_| Rejection::from(ApiError::bad_request()))?;
+    Ok(store.get_account_id_from_username(&username).await?)
+}
+
 pub fn node_settings_api<S, A>(
     admin_api_token: String,
     node_version: Option<String>,
+    exchange_rate_spread: f64,
     store: S,
 ) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
 where
     S: NodeStore<Account = A>
         + AccountStore<Account = A>
         + AddressStore
+        + BalanceStore
         + ExchangeRateStore
-        + RouterStore,
-    A: Account + HttpAccount + Send + Sync + SettlementAccount + Serialize + 'static,
+        + RouterStore
+        + RoutingEventsStore,
+    A: Account
+        + HttpAccount
+        + MaxPacketAmountAccount
+        + MinBalanceAccount
+        + Send
+        + Sync
+        + SettlementAccount
+        + Serialize
+        + 'static,
 {
     // Helper filters
     let admin_auth_header = format!("Bearer {}", admin_api_token);
@@ -119,8 +396,36 @@ where
             }
         });
 
+    // (Websocket) /routes/events
+    let routing_events = warp::path("routes")
+        .and(warp::path("events"))
+        .and(warp::path::end())
+        .and(admin_only.clone())
+        .and(warp::ws())
+        .and(with_store.clone())
+        .map(|ws: warp::ws::Ws, store: S| {
+            ws.on_upgrade(move |ws: warp::ws::WebSocket| {
+                let (ws_tx, ws_rx) = ws.split();
+                tokio::task::spawn(notify_routing_events(ws_tx, store));
+                consume_msg_drain(ws_rx)
+            })
+        });
+
+    // GET /routes/explain?destination=<address>&from=<username>&amount=<amount>
+    let get_route_explanation = warp::get()
+        .and(warp::path("routes"))
+        .and(warp::path("explain"))
+        .and(warp::path::end())
+        .and(admin_only.clone())
+        .and(warp::query::<ExplainQuery>())
+        .and(with_store.clone())
+        .and_then(move |query: ExplainQuery, store: S| async move {
+            let explanation = explain_route(&store, exchange_rate_spread, query).await?;
+            Ok::<Json, Rejection>(warp::reply::json(&explanation))
+        });
+
     // PUT /routes/static
-    // Body: Map of ILP Address prefix -> Username
+    // Body: Map of ILP Address prefix -> Username or list of next hops
     let put_static_routes = warp::put()
         .and(warp::path("routes"))
         .and(warp::path("static"))
@@ -128,33 +433,21 @@ where
         .and(admin_only.clone())
         .and(deserialize_json())
         .and(with_store.clone())
-        .and_then(move |routes: HashMap<String, String>, store: S| {
+        .and_then(move |routes: HashMap<String, StaticRoute>, store: S| {
             async move {
                 // Convert the usernames to account IDs to set the routes in the store
-                let mut usernames: Vec<Username> = Vec::new();
-                for username in routes.values() {
-                    let user = match Username::from_str(username) {
-                        Ok(u) => u,
-                        Err(_) => return Err(Rejection::from(ApiError::bad_request())),
-                    };
-                    usernames.push(user);
+                let mut next_hops: Vec<(String, Vec<NextHop>)> = Vec::new();
+                for (prefix, route) in routes.iter() {
+                    next_hops.push((prefix.to_string(), route.next_hops(&store).await?));
                 }
 
-                let mut account_ids: Vec<Uuid> = Vec::new();
-                for username in usernames {
-                    account_ids.push(store.get_account_id_from_username(&username).await?);
-                }
-
-                let prefixes = routes.keys().map(|s| s.to_string());
-                store
-                    .set_static_routes(prefixes.zip(account_ids.into_iter()))
-                    .await?;
+                store.set_static_multipath_routes(next_hops).await?;
                 Ok::<Json, Rejection>(warp::reply::json(&routes))
             }
         });
 
     // PUT /routes/static/:prefix
-    // Body: Username
+    // Body: Username or JSON list of next hops
     let put_static_route = warp::put()
         .and(warp::path("routes"))
         .and(warp::path("static"))
@@ -163,17 +456,25 @@ where
         .and(admin_only.clone())
         .and(warp::body::bytes())
         .and(with_store.clone())
-        .and_then(|prefix: String, body: Bytes, store: S| {
-            async move {
-                let username_str =
-                    str::from_utf8(&body).map_err(|_| Rejection::from(ApiError::bad_request()))?;
-                let username = Username::from_str(username_str)
-                    .map_err(|_| Rejection::from(ApiError::bad_request()))?;
-                // Convert the username to an account ID to set it in the store
-                let account_id = store.get_account_id_from_username(&username).await?;
-                store.set_static_route(prefix, account_id).await?;
-                Ok::<String, Rejection>(username.to_string())
-            }
+        .and_then(|prefix: String, body: Bytes, store: S| async move {
+            let body =
+                str::from_utf8(&body).map_err(|_| Rejection::from(ApiError::bad_request()))?;
+            let route = if body.trim_start().starts_with('[') {
+                serde_json::from_str(body)
+                    .map(StaticRoute::NextHops)
+                    .map_err(|err| {
+                        Rejection::from(ApiError::bad_request().detail(err.to_string()))
+                    })?
+            } else {
+                StaticRoute::Account(body.to_string())
+            };
+            let next_hops = route.next_hops(&store).await?;
+            store.set_static_multipath_route(prefix, next_hops).await?;
+            let response = match route {
+                StaticRoute::Account(username) => username,
+                StaticRoute::NextHops(_) => serde_json::to_string(&route).unwrap(),
+            };
+            Ok::<String, Rejection>(response)
         });
 
     // PUT /settlement/engines
@@ -223,14 +524,43 @@ where
         .or(put_rates)
         .or(get_rates)
         .or(get_routes)
+        .or(routing_events)
+        .or(get_route_explanation)
         .or(put_static_routes)
         .or(put_static_route)
         .or(put_settlement_engines)
 }
 
+/// Forwards the store's routing events to the websocket. Events that the
+/// client was too slow to receive are skipped
+fn notify_routing_events(
+    ws_tx: futures::stream::SplitSink<warp::ws::WebSocket, warp::ws::Message>,
+    store: impl RoutingEventsStore,
+) -> impl Future<Output = ()> {
+    let rx = tokio_stream::wrappers::BroadcastStream::new(store.routing_event_subscription());
+    let rx = rx.filter_map(|event| async move {
+        match event {
+            Ok(event) => Some(Ok(warp::ws::Message::text(
+                serde_json::to_string(&event).unwrap(),
+            ))),
+            Err(err) => {
+                warn!("Skipped routing events: {}", err);
+                None
+            }
+        }
+    });
+
+    rx.forward(ws_tx).map(|result| {
+        if let Err(e) = result {
+            error!("websocket send error: {}", e);
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::routes::test_helpers::{api_call, test_node_settings_api};
+    use crate::routes::test_helpers::{api_call, test_node_settings_api, ROUTING_EVENTS, USERNAME};
+    use interledger_ccp::{RouteSource, RoutingEvent, RoutingEventKind};
     use serde_json::{json, Value};
 
     #[tokio::test]
@@ -244,6 +574,49 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn streams_routing_events() {
+        let api = test_node_settings_api();
+        let wrong_auth = warp::test::ws()
+            .path("/routes/events")
+            .header("Authorization", "Bearer wrong")
+            .handshake(api.clone())
+            .await;
+        assert!(wrong_auth.is_err());
+
+        let mut client = warp::test::ws()
+            .path("/routes/events")
+            .header("Authorization", "Bearer admin")
+            .handshake(api)
+            .await
+            .unwrap();
+        // The subscription is made once the connection is upgraded
+        while ROUTING_EVENTS.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+        ROUTING_EVENTS
+            .send(RoutingEvent {
+                kind: RoutingEventKind::Replaced,
+                prefix: "example.remote".to_string(),
+                next_hop: Some(USERNAME.clone()),
+                source: RouteSource::Ccp,
+                epoch: Some(3),
+            })
+            .unwrap();
+
+        let message = client.recv().await.unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(message.to_str().unwrap()).unwrap(),
+            json!({
+                "kind": "replaced",
+                "prefix": "example.remote",
+                "next_hop": "alice",
+                "source": "ccp",
+                "epoch": 3
+            })
+        );
+    }
+
     #[tokio::test]
     async fn gets_rates() {
         let api = test_node_settings_api();
@@ -262,6 +635,52 @@ mod tests {
         assert_eq!(resp.status().as_u16(), 200);
     }
 
+    #[tokio::test]
+    async fn explains_routes() {
+        let api = test_node_settings_api();
+        let explain = |query: &str| {
+            let path = format!("/routes/explain?{}", query);
+            let api = api.clone();
+            async move {
+                let resp = api_call(&api, "GET", &path, "admin", None).await;
+                assert_eq!(resp.status().as_u16(), 200);
+                serde_json::from_slice::<Value>(resp.body()).unwrap()
+            }
+        };
+
+        let explanation = explain("destination=example.bob&from=alice&amount=10").await;
+        assert_eq!(
+            explanation["route"],
+            json!({"prefix": "example.", "source": "ccp"})
+        );
+        assert_eq!(explanation["next_hops"][0]["account"], "alice");
+        assert_eq!(explanation["next_hops"][0]["exchange_rate"], 1.0);
+        assert_eq!(explanation["next_hops"][0]["outgoing_amount"], 10);
+        assert_eq!(explanation["max_packet_amount"], 100);
+        assert_eq!(explanation["balance_headroom"], 11);
+        assert_eq!(explanation["reject"], Value::Null);
+
+        let explanation = explain("destination=example.bob&from=alice&amount=101").await;
+        assert_eq!(explanation["reject"]["code"], "F08");
+
+        let explanation = explain("destination=example.bob&from=alice&amount=12").await;
+        assert_eq!(explanation["reject"]["code"], "T04");
+
+        let explanation = explain("destination=test.bob&from=alice").await;
+        assert_eq!(explanation["route"], Value::Null);
+        assert_eq!(explanation["reject"]["code"], "F02");
+
+        let resp = api_call(
+            &api,
+            "GET",
+            "/routes/explain?destination=example.bob&from=alice",
+            "wrong",
+            None,
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
     #[tokio::test]
     async fn only_admin_can_put_rates() {
         let api = test_node_settings_api();
@@ -307,6 +726,43 @@ mod tests {
         assert_eq!(resp.status().as_u16(), 401);
     }
 
+    #[tokio::test]
+    async fn puts_multipath_static_routes() {
+        let api = test_node_settings_api();
+        let routes = json!({
+            "g.node1": "alice",
+            "example.eu": [
+                {"account": "bob", "weight": 3},
+                {"account": "charlie", "weight": 1},
+                {"account": "dave", "priority": 1}
+            ]
+        });
+        let resp = api_call(&api, "PUT", "/routes/static", "admin", Some(routes)).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(
+            serde_json::from_slice::<Value>(resp.body()).unwrap()["example.eu"][2],
+            json!({"account": "dave", "weight": 1, "priority": 1})
+        );
+
+        let resp = warp::test::request()
+            .method("PUT")
+            .path("/routes/static/g.node1")
+            .body(r#"[{"account": "alice"}, {"account": "bob", "priority": 1}]"#)
+            .header("Authorization", "Bearer admin")
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let resp = warp::test::request()
+            .method("PUT")
+            .path("/routes/static/g.node1")
+            .body(r#"[{"weight": 1}]"#)
+            .header("Authorization", "Bearer admin")
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+
     #[tokio::test]
     async fn only_admin_can_put_engines() {
         let api = test_node_settings_api();
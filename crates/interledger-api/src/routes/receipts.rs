@@ -0,0 +1,104 @@
+use interledger_errors::*;
+use interledger_http::deserialize_json;
+use interledger_stream::Receipt;
+use serde::{Deserialize, Serialize};
+use warp::{self, reply::Json, Filter, Rejection};
+
+/// A STREAM receipt and the secret it should be authenticated with, both base64-encoded
+#[derive(Clone, Debug, Deserialize)]
+struct VerifyReceiptRequest {
+    receipt: String,
+    receipt_secret: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct VerifiedReceipt {
+    /// The base64-encoded nonce of the connection the money was received on
+    nonce: String,
+    stream_id: u64,
+    total_received: u64,
+}
+
+/// Parses the receipt and checks it was issued with the receipt secret
+fn verify_receipt(request: VerifyReceiptRequest) -> Result<VerifiedReceipt, ApiError> {
+    let receipt = base64::decode(&request.receipt)
+        .map_err(|_| ApiError::bad_request().detail("receipt must be base64-encoded"))?;
+    let receipt_secret = base64::decode(&request.receipt_secret)
+        .map_err(|_| ApiError::bad_request().detail("receipt_secret must be base64-encoded"))?;
+    let receipt = Receipt::verify(&receipt, &receipt_secret)
+        .map_err(|err| ApiError::bad_request().detail(err.to_string()))?;
+    Ok(VerifiedReceipt {
+        nonce: base64::encode(receipt.nonce),
+        stream_id: receipt.stream_id,
+        total_received: receipt.total_received,
+    })
+}
+
+/// Returns the public receipt routes. They need no authorization: the caller
+/// brings the receipt secret and the node's own data is never read.
+pub fn receipts_api(
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    // POST /receipts/verify
+    // Body: the base64-encoded receipt and the receipt secret given in the SPSP query
+    warp::post()
+        .and(warp::path("receipts"))
+        .and(warp::path("verify"))
+        .and(warp::path::end())
+        .and(deserialize_json())
+        .and_then(|request: VerifyReceiptRequest| async move {
+            let receipt = verify_receipt(request)?;
+            Ok::<Json, Rejection>(warp::reply::json(&receipt))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::test_helpers::{api_call, test_receipts_api};
+    use interledger_stream::Receipt;
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn verifies_receipts_without_authorization() {
+        let api = test_receipts_api();
+        let receipt = Receipt {
+            nonce: [1; 16],
+            stream_id: 1,
+            total_received: 500,
+        }
+        .to_bytes(&[2; 32]);
+
+        let resp = api_call(
+            &api,
+            "POST",
+            "/receipts/verify",
+            "",
+            Some(json!({
+                "receipt": base64::encode(&receipt),
+                "receipt_secret": base64::encode([2; 32]),
+            })),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(
+            serde_json::from_slice::<Value>(resp.body()).unwrap(),
+            json!({
+                "nonce": base64::encode([1; 16]),
+                "stream_id": 1,
+                "total_received": 500
+            })
+        );
+
+        let resp = api_call(
+            &api,
+            "POST",
+            "/receipts/verify",
+            "",
+            Some(json!({
+                "receipt": base64::encode(&receipt),
+                "receipt_secret": base64::encode([3; 32]),
+            })),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+}
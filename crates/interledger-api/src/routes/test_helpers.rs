@@ -1,22 +1,25 @@
 use crate::{
-    routes::{accounts_api, node_settings_api},
+    routes::{accounts_api, node_settings_api, receipts_api},
     AccountDetails, AccountSettings, NodeStore,
 };
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::channel::mpsc::UnboundedSender;
 use http::Response;
-use interledger_btp::{BtpAccount, BtpOutgoingService};
-use interledger_ccp::{CcpRoutingAccount, RoutingRelation};
+use interledger_btp::{connect_client, BtpAccount, BtpOutgoingService, BtpStore};
+use interledger_ccp::{CcpRoutingAccount, RoutingEvent, RoutingEventsStore, RoutingRelation};
 use interledger_errors::*;
 use interledger_http::{HttpAccount, HttpStore};
 use interledger_packet::{Address, ErrorCode, FulfillBuilder, RejectBuilder};
 use interledger_rates::ExchangeRateStore;
-use interledger_router::RouterStore;
+use interledger_router::{NextHop, RouterStore, RoutingTable};
 use interledger_service::{
-    incoming_service_fn, outgoing_service_fn, Account, AccountStore, AddressStore, Username,
+    incoming_service_fn, outgoing_service_fn, Account, AccountStore, AddressStore, OutgoingService,
+    Username,
+};
+use interledger_service_util::{
+    BalanceStore, EchoClient, MaxPacketAmountAccount, MinBalanceAccount,
 };
-use interledger_service_util::BalanceStore;
 use interledger_settlement::core::types::{SettlementAccount, SettlementEngineDetails};
 use interledger_stream::{PaymentNotification, StreamNotificationsStore};
 use once_cell::sync::Lazy;
@@ -24,6 +27,7 @@ use secrecy::SecretString;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::str::FromStr;
 use std::sync::Arc;
 use tokio::sync::broadcast;
@@ -56,11 +60,36 @@ where
 
 pub fn test_node_settings_api(
 ) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
-    node_settings_api("admin".to_owned(), None, TestStore).recover(default_rejection_handler)
+    node_settings_api("admin".to_owned(), None, 0.0, TestStore).recover(default_rejection_handler)
+}
+
+pub fn test_receipts_api(
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    receipts_api().recover(default_rejection_handler)
 }
 
 pub fn test_accounts_api(
 ) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let outgoing = outgoing_service_fn(move |_request| {
+        Ok(FulfillBuilder {
+            fulfillment: &[0; 32],
+            data: b"hello!",
+        }
+        .build())
+    });
+    test_accounts_api_with_btp(BtpOutgoingService::new(
+        Address::from_str("example.alice").unwrap(),
+        outgoing,
+    ))
+}
+
+/// The accounts API using the given BTP service, so tests can open connections to it
+pub fn test_accounts_api_with_btp<O>(
+    btp: BtpOutgoingService<O, TestAccount>,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+where
+    O: OutgoingService<TestAccount> + Clone + Send + Sync + 'static,
+{
     let incoming = incoming_service_fn(|_request| {
         Err(RejectBuilder {
             code: ErrorCode::F02_UNREACHABLE,
@@ -77,15 +106,12 @@ pub fn test_accounts_api(
         }
         .build())
     });
-    let btp = BtpOutgoingService::new(
-        Address::from_str("example.alice").unwrap(),
-        outgoing.clone(),
-    );
     let store = TestStore;
     accounts_api(
-        Bytes::from("admin"),
+        Bytes::from(&[0; 32][..]),
         "admin".to_owned(),
         None,
+        Some(EchoClient::default()),
         incoming,
         outgoing,
         btp,
@@ -94,16 +120,44 @@ pub fn test_accounts_api(
     .recover(default_rejection_handler)
 }
 
+/// Connects to the BTP server at the given address as alice
+pub async fn connect_btp_peer(
+    addr: SocketAddr,
+) -> BtpOutgoingService<impl OutgoingService<TestPeerAccount> + Clone, TestPeerAccount> {
+    let account = TestPeerAccount {
+        url: Url::parse(&format!("btp+ws://{}/accounts/alice/ilp/btp", addr)).unwrap(),
+    };
+    connect_client(
+        Address::from_str("example.peer").unwrap(),
+        vec![account],
+        true,
+        outgoing_service_fn(|_request| {
+            Err(RejectBuilder {
+                code: ErrorCode::F02_UNREACHABLE,
+                message: &[],
+                data: &[],
+                triggered_by: None,
+            }
+            .build())
+        }),
+    )
+    .await
+    .unwrap()
+}
+
 /*
  * Lots of boilerplate implementations of all necessary traits to launch
  * the crate's APIs in unit tests
  */
 
 #[derive(Clone)]
-struct TestStore;
+pub struct TestStore;
 
 use serde_json::json;
 pub static USERNAME: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
+pub static ACCOUNT_ID: Lazy<Uuid> = Lazy::new(|| Uuid::from_slice(&[1; 16]).unwrap());
+pub static ROUTING_EVENTS: Lazy<broadcast::Sender<RoutingEvent>> =
+    Lazy::new(|| broadcast::channel(16).0);
 pub static EXAMPLE_ADDRESS: Lazy<Address> =
     Lazy::new(|| Address::from_str("example.alice").unwrap());
 pub static DETAILS: Lazy<Option<Value>> = Lazy::new(|| {
@@ -122,7 +176,7 @@ pub struct TestAccount;
 
 impl Account for TestAccount {
     fn id(&self) -> Uuid {
-        Uuid::new_v4()
+        *ACCOUNT_ID
     }
 
     fn username(&self) -> &Username {
@@ -162,18 +216,89 @@ impl BtpAccount for TestAccount {
     }
 }
 
+/// The account of the node's peer on the peer's side of a BTP connection
+#[derive(Clone, Debug)]
+pub struct TestPeerAccount {
+    url: Url,
+}
+
+impl Account for TestPeerAccount {
+    fn id(&self) -> Uuid {
+        *ACCOUNT_ID
+    }
+
+    fn username(&self) -> &Username {
+        &USERNAME
+    }
+
+    fn asset_scale(&self) -> u8 {
+        9
+    }
+
+    fn asset_code(&self) -> &str {
+        "XYZ"
+    }
+
+    fn ilp_address(&self) -> &Address {
+        &EXAMPLE_ADDRESS
+    }
+}
+
+impl BtpAccount for TestPeerAccount {
+    fn get_ilp_over_btp_url(&self) -> Option<&Url> {
+        Some(&self.url)
+    }
+
+    fn get_ilp_over_btp_outgoing_token(&self) -> Option<&[u8]> {
+        Some(AUTH_PASSWORD.as_bytes())
+    }
+}
+
 impl SettlementAccount for TestAccount {
     fn settlement_engine_details(&self) -> Option<SettlementEngineDetails> {
         None
     }
 }
 
+impl MaxPacketAmountAccount for TestAccount {
+    fn max_packet_amount(&self) -> u64 {
+        100
+    }
+}
+
+impl MinBalanceAccount for TestAccount {
+    fn min_balance(&self) -> Option<i64> {
+        Some(-10)
+    }
+}
+
 impl CcpRoutingAccount for TestAccount {
     fn routing_relation(&self) -> RoutingRelation {
         RoutingRelation::NonRoutingAccount
     }
 }
 
+#[async_trait]
+impl BtpStore for TestStore {
+    type Account = TestAccount;
+
+    async fn get_account_from_btp_auth(
+        &self,
+        username: &Username,
+        token: &str,
+    ) -> Result<TestAccount, BtpStoreError> {
+        if token == AUTH_PASSWORD {
+            Ok(TestAccount)
+        } else {
+            Err(BtpStoreError::Unauthorized(username.to_string()))
+        }
+    }
+
+    async fn get_btp_outgoing_accounts(&self) -> Result<Vec<TestAccount>, BtpStoreError> {
+        Ok(Vec::new())
+    }
+}
+
 #[async_trait]
 impl AccountStore for TestStore {
     type Account = TestAccount;
@@ -190,7 +315,7 @@ impl AccountStore for TestStore {
         &self,
         _username: &Username,
     ) -> Result<Uuid, AccountStoreError> {
-        Ok(Uuid::new_v4())
+        Ok(*ACCOUNT_ID)
     }
 }
 
@@ -218,8 +343,12 @@ impl ExchangeRateStore for TestStore {
 }
 
 impl RouterStore for TestStore {
-    fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
-        Arc::new(HashMap::new())
+    fn routing_table(&self) -> Arc<RoutingTable> {
+        Arc::new(
+            vec![("example.".to_string(), Uuid::new_v4())]
+                .into_iter()
+                .collect(),
+        )
     }
 }
 
@@ -273,6 +402,25 @@ impl NodeStore for TestStore {
         Ok(())
     }
 
+    async fn set_static_multipath_routes<R>(&self, _routes: R) -> Result<(), NodeStoreError>
+    where
+        R: IntoIterator<Item = (String, Vec<NextHop>)> + Send + 'async_trait,
+    {
+        Ok(())
+    }
+
+    async fn set_static_multipath_route(
+        &self,
+        _prefix: String,
+        _next_hops: Vec<NextHop>,
+    ) -> Result<(), NodeStoreError> {
+        Ok(())
+    }
+
+    async fn get_static_routes(&self) -> Result<HashMap<String, Vec<NextHop>>, NodeStoreError> {
+        Ok(HashMap::new())
+    }
+
     async fn set_default_route(&self, _account_id: Uuid) -> Result<(), NodeStoreError> {
         unimplemented!()
     }
@@ -309,6 +457,16 @@ impl AddressStore for TestStore {
     }
 }
 
+impl RoutingEventsStore for TestStore {
+    fn publish_routing_event(&self, event: RoutingEvent) {
+        let _ = ROUTING_EVENTS.send(event);
+    }
+
+    fn routing_event_subscription(&self) -> broadcast::Receiver<RoutingEvent> {
+        ROUTING_EVENTS.subscribe()
+    }
+}
+
 impl StreamNotificationsStore for TestStore {
     type Account = TestAccount;
 
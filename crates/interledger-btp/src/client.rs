@@ -1,4 +1,5 @@
 use super::packet::*;
+use super::registry::ConnectionDirection;
 use super::service::BtpOutgoingService;
 use super::BtpAccount;
 use futures::{future::join_all, SinkExt, StreamExt, TryFutureExt};
@@ -139,7 +140,7 @@ where
         Ok(_) => {
             debug!("Connected to account {}'s server", account.id());
             let connection = connection.filter_map(|v| async move { v.ok() });
-            service.add_connection(account, connection);
+            service.add_connection(account, ConnectionDirection::Outgoing, connection);
             Ok(())
         }
         Err(err) => {
@@ -1,5 +1,6 @@
 use interledger_packet::OerError;
 use std::str::Utf8Error;
+use uuid::Uuid;
 
 #[derive(Debug, thiserror::Error)]
 pub enum BtpPacketError {
@@ -22,3 +23,20 @@ pub enum PacketTypeError {
     #[error("Cannot parse Message from packet of type {0}, expected type {1}")]
     Unexpected(u8, u8),
 }
+
+#[derive(Debug, thiserror::Error)]
+pub enum BtpSubProtocolError {
+    #[error("No open BTP connection to account {0}")]
+    NotConnected(Uuid),
+    #[error("Timed out waiting for the BTP response")]
+    Timeout,
+    #[error("The BTP connection was closed before the response was received")]
+    ConnectionClosed,
+    /// The packet was (or should be) answered with a BTP Error
+    #[error("BTP error {code} {name}: {data}")]
+    Rejected {
+        code: String,
+        name: String,
+        data: String,
+    },
+}
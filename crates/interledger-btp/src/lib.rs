@@ -13,13 +13,21 @@ use url::Url;
 mod client;
 mod errors;
 mod packet;
+mod registry;
 mod server;
 mod service;
+mod sub_protocol;
+mod supervisor;
 mod wrapped_ws;
 
 pub use self::client::{connect_client, connect_to_service_account};
+pub use self::errors::BtpSubProtocolError;
+pub use self::packet::{ContentType, ProtocolData};
+pub use self::registry::{BtpConnectionInfo, BtpConnectionRegistry, ConnectionDirection};
 pub use self::server::btp_service_as_filter; // This is consumed only by the node.
 pub use self::service::{BtpOutgoingService, BtpService};
+pub use self::sub_protocol::{BtpSubProtocolHandler, BtpSubProtocolRequest};
+pub use self::supervisor::BtpSupervisor;
 
 use interledger_errors::BtpStoreError;
 
@@ -71,6 +79,7 @@ pub mod fuzzing {
 #[cfg(test)]
 mod client_server {
     use super::*;
+    use futures::SinkExt;
     use interledger_packet::{Address, ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder};
     use interledger_service::*;
     use socket2::{Domain, Socket, Type};
@@ -283,6 +292,23 @@ mod client_server {
             .await;
         assert!(res.is_ok());
 
+        let client_connections = btp_client.connection_registry().connections();
+        assert_eq!(client_connections.len(), 1);
+        assert_eq!(client_connections[0].account_id, account.id);
+        assert_eq!(
+            client_connections[0].direction,
+            ConnectionDirection::Outgoing
+        );
+        assert!(client_connections[0].messages_sent >= 1);
+        assert!(client_connections[0].messages_received >= 1);
+        let server_connections = btp_service.connection_registry().connections();
+        assert_eq!(server_connections.len(), 1);
+        assert_eq!(server_connections[0].account_id, server_acc_id);
+        assert_eq!(
+            server_connections[0].direction,
+            ConnectionDirection::Incoming
+        );
+
         btp_service.close_connection(&server_acc_id);
         // after removing the connection this will fail
         let mut btp_client_clone = btp_client.clone();
@@ -326,4 +352,453 @@ mod client_server {
 
         btp_service.close();
     }
+
+    #[tokio::test]
+    async fn supervisor_reconnects() {
+        let bind_addr = get_open_port();
+        let server_store = TestStore {
+            accounts: Arc::new([TestAccount {
+                id: Uuid::new_v4(),
+                ilp_over_btp_incoming_token: Some("test_auth_token".to_string()),
+                ilp_over_btp_outgoing_token: None,
+                ilp_over_btp_url: None,
+            }]),
+        };
+        let server_address = Address::from_str("example.server").unwrap();
+        let btp_service = BtpOutgoingService::new(
+            server_address.clone(),
+            outgoing_service_fn(move |_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&server_address),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        btp_service
+            .clone()
+            .handle_incoming(incoming_service_fn(|_| {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: b"test data",
+                }
+                .build())
+            }))
+            .await;
+        let filter = btp_service_as_filter(btp_service.clone(), server_store);
+        tokio::spawn(warp::serve(filter).bind(bind_addr));
+
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_over_btp_url: Some(
+                Url::parse(&format!("btp+ws://{}/accounts/alice/ilp/btp", bind_addr)).unwrap(),
+            ),
+            ilp_over_btp_outgoing_token: Some("test_auth_token".to_string()),
+            ilp_over_btp_incoming_token: None,
+        };
+        let client_store = TestStore {
+            accounts: Arc::new([account.clone()]),
+        };
+        let addr = Address::from_str("example.address").unwrap();
+        let btp_client = BtpOutgoingService::new(
+            addr.clone(),
+            outgoing_service_fn(move |_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    data: &[],
+                    triggered_by: Some(&addr),
+                }
+                .build())
+            }),
+        );
+        let mut supervisor = BtpSupervisor::new(client_store, btp_client.clone());
+        supervisor.check_interval(Duration::from_millis(10));
+        tokio::spawn(async move { supervisor.run().await });
+
+        let wait_until_connected = || async {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while !btp_client.is_connected(&account.id) {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .expect("Supervisor should connect to the account")
+        };
+        wait_until_connected().await;
+
+        // The connection is opened again (and authenticated) after it is closed
+        btp_client.close_connection(&account.id);
+        assert!(!btp_client.is_connected(&account.id));
+        wait_until_connected().await;
+
+        let res = btp_client
+            .clone()
+            .send_request(OutgoingRequest {
+                from: account.clone(),
+                to: account.clone(),
+                original_amount: 100,
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.destination").unwrap(),
+                    amount: 100,
+                    execution_condition: &[0; 32],
+                    expires_at: SystemTime::now() + Duration::from_secs(30),
+                    data: b"test data",
+                }
+                .build(),
+            })
+            .await;
+        assert!(res.is_ok());
+
+        btp_service.close();
+    }
+    #[tokio::test]
+    async fn keeps_connections_open_without_pings() {
+        let bind_addr = get_open_port();
+        let server_acc_id = Uuid::new_v4();
+        let server_store = TestStore {
+            accounts: Arc::new([TestAccount {
+                id: server_acc_id,
+                ilp_over_btp_incoming_token: Some("test_auth_token".to_string()),
+                ilp_over_btp_outgoing_token: None,
+                ilp_over_btp_url: None,
+            }]),
+        };
+        let server_address = Address::from_str("example.server").unwrap();
+        let mut btp_service = BtpOutgoingService::new(
+            server_address.clone(),
+            outgoing_service_fn(move |_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&server_address),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        // An interval of 0 disables the Pings instead of panicking
+        btp_service.ping_interval(Duration::from_millis(0));
+        let filter = btp_service_as_filter(btp_service.clone(), server_store);
+        tokio::spawn(warp::serve(filter).bind(bind_addr));
+
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_over_btp_url: Some(
+                Url::parse(&format!("btp+ws://{}/accounts/alice/ilp/btp", bind_addr)).unwrap(),
+            ),
+            ilp_over_btp_outgoing_token: Some("test_auth_token".to_string()),
+            ilp_over_btp_incoming_token: None,
+        };
+        let addr = Address::from_str("example.address").unwrap();
+        let mut btp_client = BtpOutgoingService::new(
+            addr.clone(),
+            outgoing_service_fn(move |_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    data: &[],
+                    triggered_by: Some(&addr),
+                }
+                .build())
+            }),
+        );
+        btp_client.ping_interval(Duration::from_millis(0));
+        connect_to_service_account(account, true, btp_client.clone())
+            .await
+            .unwrap();
+
+        let registry = btp_service.connection_registry();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while registry.connections().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The client should connect");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let connections = registry.connections();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].ping_latency, None);
+        assert!(btp_service.is_connected(&server_acc_id));
+
+        btp_service.close();
+        btp_client.close();
+    }
+
+    #[tokio::test]
+    async fn closes_unresponsive_connections() {
+        let bind_addr = get_open_port();
+        let responsive_id = Uuid::new_v4();
+        let unresponsive_id = Uuid::new_v4();
+        let server_store = TestStore {
+            accounts: Arc::new([
+                TestAccount {
+                    id: responsive_id,
+                    ilp_over_btp_incoming_token: Some("responsive".to_string()),
+                    ilp_over_btp_outgoing_token: None,
+                    ilp_over_btp_url: None,
+                },
+                TestAccount {
+                    id: unresponsive_id,
+                    ilp_over_btp_incoming_token: Some("unresponsive".to_string()),
+                    ilp_over_btp_outgoing_token: None,
+                    ilp_over_btp_url: None,
+                },
+            ]),
+        };
+        let server_address = Address::from_str("example.server").unwrap();
+        let mut btp_service = BtpOutgoingService::new(
+            server_address.clone(),
+            outgoing_service_fn(move |_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&server_address),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        btp_service
+            .ping_interval(Duration::from_millis(100))
+            .max_missed_pongs(2);
+        let filter = btp_service_as_filter(btp_service.clone(), server_store);
+        tokio::spawn(warp::serve(filter).bind(bind_addr));
+
+        // This client answers the server's Pings
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_over_btp_url: Some(
+                Url::parse(&format!("btp+ws://{}/accounts/alice/ilp/btp", bind_addr)).unwrap(),
+            ),
+            ilp_over_btp_outgoing_token: Some("responsive".to_string()),
+            ilp_over_btp_incoming_token: None,
+        };
+        let addr = Address::from_str("example.address").unwrap();
+        let btp_client = BtpOutgoingService::new(
+            addr.clone(),
+            outgoing_service_fn(move |_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    data: &[],
+                    triggered_by: Some(&addr),
+                }
+                .build())
+            }),
+        );
+        connect_to_service_account(account, true, btp_client.clone())
+            .await
+            .unwrap();
+
+        // This one authenticates but never reads from the WebSocket, so it never
+        // answers the server's Pings
+        let url = format!("ws://{}/accounts/alice/ilp/btp", bind_addr);
+        let (mut unresponsive, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let auth = packet::BtpPacket::Message(packet::BtpMessage {
+            request_id: 1,
+            protocol_data: vec![
+                packet::ProtocolData {
+                    protocol_name: "auth".into(),
+                    content_type: packet::ContentType::ApplicationOctetStream,
+                    data: vec![],
+                },
+                packet::ProtocolData {
+                    protocol_name: "auth_token".into(),
+                    content_type: packet::ContentType::TextPlainUtf8,
+                    data: b"unresponsive".to_vec(),
+                },
+            ],
+        });
+        unresponsive
+            .send(tokio_tungstenite::tungstenite::Message::binary(
+                packet::Serializable::to_bytes(&auth),
+            ))
+            .await
+            .unwrap();
+
+        let registry = btp_service.connection_registry();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while registry.connections().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Both clients should connect");
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let connections = registry.connections();
+                if connections.len() == 1 && connections[0].ping_latency.is_some() {
+                    assert_eq!(connections[0].account_id, responsive_id);
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The unresponsive connection should be closed");
+        assert!(btp_service.is_connected(&responsive_id));
+        assert!(!btp_service.is_connected(&unresponsive_id));
+
+        btp_service.close();
+        drop(unresponsive);
+    }
+    struct EchoAmount;
+
+    #[async_trait]
+    impl BtpSubProtocolHandler<TestAccount> for EchoAmount {
+        async fn handle_sub_protocol(
+            &self,
+            request: BtpSubProtocolRequest<TestAccount>,
+        ) -> Result<Vec<ProtocolData>, BtpSubProtocolError> {
+            match request.amount {
+                Some(0) => Err(BtpSubProtocolError::Rejected {
+                    code: "not a code".to_string(),
+                    name: "InvalidFieldsError".to_string(),
+                    data: "the amount must be positive".to_string(),
+                }),
+                Some(amount) => Ok(vec![ProtocolData {
+                    protocol_name: "settlement".into(),
+                    content_type: ContentType::TextPlainUtf8,
+                    data: amount.to_string().into_bytes(),
+                }]),
+                None => Err(BtpSubProtocolError::Rejected {
+                    code: "F01".to_string(),
+                    name: "InvalidFieldsError".to_string(),
+                    data: "expected a Transfer".to_string(),
+                }),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn handles_sub_protocols() {
+        let bind_addr = get_open_port();
+        let server_acc_id = Uuid::new_v4();
+        let server_store = TestStore {
+            accounts: Arc::new([TestAccount {
+                id: server_acc_id,
+                ilp_over_btp_incoming_token: Some("test_auth_token".to_string()),
+                ilp_over_btp_outgoing_token: None,
+                ilp_over_btp_url: None,
+            }]),
+        };
+        let server_address = Address::from_str("example.server").unwrap();
+        let next = outgoing_service_fn(move |_| {
+            Err(RejectBuilder {
+                code: ErrorCode::F02_UNREACHABLE,
+                message: b"No other outgoing handler",
+                triggered_by: Some(&server_address),
+                data: &[],
+            }
+            .build())
+        });
+        // The handler is registered on another service, like the node's client service
+        let handlers =
+            BtpOutgoingService::new(Address::from_str("example.server").unwrap(), next.clone());
+        handlers.register_sub_protocol_handler("settlement", EchoAmount);
+        let mut btp_service =
+            BtpOutgoingService::new(Address::from_str("example.server").unwrap(), next);
+        btp_service.share_sub_protocols(&handlers);
+        let filter = btp_service_as_filter(btp_service.clone(), server_store);
+        tokio::spawn(warp::serve(filter).bind(bind_addr));
+
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_over_btp_url: Some(
+                Url::parse(&format!("btp+ws://{}/accounts/alice/ilp/btp", bind_addr)).unwrap(),
+            ),
+            ilp_over_btp_outgoing_token: Some("test_auth_token".to_string()),
+            ilp_over_btp_incoming_token: None,
+        };
+        let addr = Address::from_str("example.address").unwrap();
+        let btp_client = connect_client(
+            addr.clone(),
+            vec![account.clone()],
+            true,
+            outgoing_service_fn(move |_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    data: &[],
+                    triggered_by: Some(&addr),
+                }
+                .build())
+            }),
+        )
+        .await
+        .unwrap();
+
+        let settlement_data = vec![ProtocolData {
+            protocol_name: "settlement".into(),
+            content_type: ContentType::ApplicationOctetStream,
+            data: vec![1, 2, 3],
+        }];
+        let response = btp_client
+            .send_transfer(&account.id, 1000, settlement_data.clone())
+            .await
+            .unwrap();
+        assert_eq!(response[0].protocol_name, "settlement");
+        assert_eq!(response[0].data, b"1000");
+
+        // Invalid error codes are replaced so the BTP Error can still be parsed
+        match btp_client
+            .send_transfer(&account.id, 0, settlement_data.clone())
+            .await
+        {
+            Err(BtpSubProtocolError::Rejected { code, name, .. }) => {
+                assert_eq!(code, "F00");
+                assert_eq!(name, "InvalidFieldsError");
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // The handler answers with a BTP Error
+        match btp_client
+            .send_sub_protocol_message(&account.id, settlement_data)
+            .await
+        {
+            Err(BtpSubProtocolError::Rejected { code, name, .. }) => {
+                assert_eq!(code, "F01");
+                assert_eq!(name, "InvalidFieldsError");
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // There is no handler for this sub-protocol on either side
+        let unknown_data = vec![ProtocolData {
+            protocol_name: "unknown".into(),
+            content_type: ContentType::ApplicationOctetStream,
+            data: vec![],
+        }];
+        match btp_client
+            .send_sub_protocol_message(&account.id, unknown_data.clone())
+            .await
+        {
+            Err(BtpSubProtocolError::Rejected { code, .. }) => assert_eq!(code, "F00"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match btp_service
+            .send_sub_protocol_message(&server_acc_id, unknown_data.clone())
+            .await
+        {
+            Err(BtpSubProtocolError::Rejected { code, .. }) => assert_eq!(code, "F00"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        match btp_client
+            .send_sub_protocol_message(&Uuid::new_v4(), unknown_data)
+            .await
+        {
+            Err(BtpSubProtocolError::NotConnected(_)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        btp_client.close();
+        btp_service.close();
+    }
 }
@@ -21,6 +21,7 @@ pub trait Serializable<T> {
 #[repr(u8)]
 enum PacketType {
     Message = 6,
+    Transfer = 7,
     Response = 1,
     Error = 2,
     Unknown,
@@ -35,6 +36,7 @@ impl From<u8> for PacketType {
     fn from(type_int: u8) -> Self {
         match type_int {
             6 => PacketType::Message,
+            7 => PacketType::Transfer,
             1 => PacketType::Response,
             2 => PacketType::Error,
             _ => PacketType::Unknown,
@@ -45,6 +47,7 @@ impl From<u8> for PacketType {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum BtpPacket {
     Message(BtpMessage),
+    Transfer(BtpTransfer),
     Response(BtpResponse),
     Error(BtpError),
 }
@@ -56,6 +59,7 @@ impl Serializable<BtpPacket> for BtpPacket {
         }
         match PacketType::from(bytes[0]) {
             PacketType::Message => Ok(BtpPacket::Message(BtpMessage::from_bytes(bytes)?)),
+            PacketType::Transfer => Ok(BtpPacket::Transfer(BtpTransfer::from_bytes(bytes)?)),
             PacketType::Response => Ok(BtpPacket::Response(BtpResponse::from_bytes(bytes)?)),
             PacketType::Error => Ok(BtpPacket::Error(BtpError::from_bytes(bytes)?)),
             PacketType::Unknown => Err(PacketTypeError::Unknown(bytes[0]).into()),
@@ -65,6 +69,7 @@ impl Serializable<BtpPacket> for BtpPacket {
     fn to_bytes(&self) -> Vec<u8> {
         match self {
             BtpPacket::Message(packet) => packet.to_bytes(),
+            BtpPacket::Transfer(packet) => packet.to_bytes(),
             BtpPacket::Response(packet) => packet.to_bytes(),
             BtpPacket::Error(packet) => packet.to_bytes(),
         }
@@ -210,6 +215,59 @@ impl Serializable<BtpMessage> for BtpMessage {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BtpTransfer {
+    pub request_id: u32,
+    pub amount: u64,
+    pub protocol_data: Vec<ProtocolData>,
+}
+
+impl Serializable<BtpTransfer> for BtpTransfer {
+    fn from_bytes(bytes: &[u8]) -> Result<BtpTransfer, BtpPacketError> {
+        let mut reader = bytes;
+
+        const AMOUNT_LEN: usize = 8;
+        const MIN_LEN: usize = PacketType::LEN + REQUEST_ID_LEN + oer::EMPTY_VARLEN_OCTETS_LEN;
+
+        if reader.remaining() < MIN_LEN {
+            return Err(OerError::UnexpectedEof.into());
+        }
+        let packet_type = reader.get_u8();
+        if PacketType::from(packet_type) != PacketType::Transfer {
+            return Err(
+                PacketTypeError::Unexpected(packet_type, PacketType::Transfer as u8).into(),
+            );
+        }
+        let request_id = reader.get_u32();
+        let mut contents = reader.read_var_octet_string()?;
+
+        check_no_trailing_bytes(reader)?;
+
+        if contents.remaining() < AMOUNT_LEN {
+            return Err(OerError::UnexpectedEof.into());
+        }
+        let amount = contents.get_u64();
+        let protocol_data = read_protocol_data(&mut contents)?;
+
+        Ok(BtpTransfer {
+            request_id,
+            amount,
+            protocol_data,
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.put_u8(PacketType::Transfer as u8);
+        buf.put_u32(self.request_id);
+        let mut contents = Vec::new();
+        contents.put_u64(self.amount);
+        put_protocol_data(&mut contents, &self.protocol_data);
+        buf.put_var_octet_string(&*contents);
+        buf
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct BtpResponse {
     pub request_id: u32,
@@ -579,6 +637,40 @@ mod tests {
         }
     }
 
+    mod btp_transfer {
+        use super::*;
+
+        static TRANSFER_1: Lazy<BtpTransfer> = Lazy::new(|| BtpTransfer {
+            request_id: 3,
+            amount: 1000,
+            protocol_data: vec![ProtocolData {
+                protocol_name: "test".into(),
+                content_type: ContentType::ApplicationOctetStream,
+                data: hex_literal::hex!("FFFF")[..].to_vec(),
+            }],
+        });
+        static TRANSFER_1_SERIALIZED: &[u8] =
+            &hex_literal::hex!("07000000031300000000000003e8010104746573740002ffff");
+
+        #[test]
+        fn from_bytes() {
+            assert_eq!(
+                BtpTransfer::from_bytes(TRANSFER_1_SERIALIZED).unwrap(),
+                *TRANSFER_1
+            );
+        }
+
+        #[test]
+        fn to_bytes() {
+            assert_eq!(TRANSFER_1.to_bytes(), *TRANSFER_1_SERIALIZED);
+        }
+
+        #[test]
+        fn fails_without_amount() {
+            assert!(BtpTransfer::from_bytes(&[7, 0, 0, 0, 3, 2, 0, 0]).is_err());
+        }
+    }
+
     mod btp_response {
         use super::*;
 
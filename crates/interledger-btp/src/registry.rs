@@ -0,0 +1,221 @@
+use chrono::{DateTime, TimeZone, Utc};
+use futures::channel::mpsc::UnboundedSender;
+use interledger_service::Username;
+use parking_lot::{Mutex, RwLock};
+use std::sync::{
+    atomic::{AtomicI64, AtomicU64, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+
+/// Which side opened a BTP connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionDirection {
+    /// The peer connected to our BTP server
+    Incoming,
+    /// We connected to the peer's BTP server
+    Outgoing,
+}
+
+/// A snapshot of the details of an open BTP connection
+#[derive(Clone, Debug, PartialEq)]
+pub struct BtpConnectionInfo {
+    pub account_id: Uuid,
+    pub username: Username,
+    pub direction: ConnectionDirection,
+    pub connected_since: DateTime<Utc>,
+    /// When the last WebSocket message was sent or received on the connection
+    pub last_activity: DateTime<Utc>,
+    /// The number of WebSocket messages (including Pings and Pongs) sent on the connection
+    pub messages_sent: u64,
+    /// The number of WebSocket messages (including Pings and Pongs) received on the connection
+    pub messages_received: u64,
+    /// The round trip time of the last WebSocket Ping answered by the peer
+    pub ping_latency: Option<Duration>,
+}
+
+/// An entry of the registry, updated by the tasks reading from and writing to the WebSocket
+pub(crate) struct RegisteredConnection {
+    account_id: Uuid,
+    username: Username,
+    direction: ConnectionDirection,
+    connected_since: DateTime<Utc>,
+    /// Milliseconds since the Unix epoch
+    last_activity: AtomicI64,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    ping_latency: Mutex<Option<Duration>>,
+    /// Closing this channel closes the WebSocket
+    sender: UnboundedSender<Message>,
+}
+
+impl RegisteredConnection {
+    pub(crate) fn record_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.last_activity
+            .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_received(&self) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.last_activity
+            .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_ping_latency(&self, latency: Duration) {
+        *self.ping_latency.lock() = Some(latency);
+    }
+
+    fn info(&self) -> BtpConnectionInfo {
+        BtpConnectionInfo {
+            account_id: self.account_id,
+            username: self.username.clone(),
+            direction: self.direction,
+            connected_since: self.connected_since,
+            last_activity: Utc.timestamp_millis(self.last_activity.load(Ordering::Relaxed)),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            ping_latency: *self.ping_latency.lock(),
+        }
+    }
+}
+
+/// The open BTP connections of one or more [BtpOutgoingService](./struct.BtpOutgoingService.html)s.
+///
+/// Each service has its own registry unless it is created with
+/// [with_connection_registry](./struct.BtpOutgoingService.html#method.with_connection_registry),
+/// so that the services handling the incoming and the outgoing connections of a node can
+/// share one.
+#[derive(Clone, Default)]
+pub struct BtpConnectionRegistry {
+    connections: Arc<RwLock<Vec<Arc<RegisteredConnection>>>>,
+}
+
+impl BtpConnectionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the details of all of the open connections
+    pub fn connections(&self) -> Vec<BtpConnectionInfo> {
+        self.connections
+            .read()
+            .iter()
+            .map(|connection| connection.info())
+            .collect()
+    }
+
+    /// Closes all of the connections of the account, in both directions.
+    /// Returns false if the account had no open connections
+    pub fn close(&self, account_id: &Uuid) -> bool {
+        let connections = self.connections.read();
+        let mut closed = false;
+        for connection in connections
+            .iter()
+            .filter(|connection| connection.account_id == *account_id)
+        {
+            connection.sender.close_channel();
+            closed = true;
+        }
+        closed
+    }
+
+    /// Returns the lowest ping latency measured on the account's open connections,
+    /// or `None` if none of them has answered a Ping yet
+    pub fn ping_latency(&self, account_id: &Uuid) -> Option<Duration> {
+        self.connections
+            .read()
+            .iter()
+            .filter(|connection| connection.account_id == *account_id)
+            .filter_map(|connection| *connection.ping_latency.lock())
+            .min()
+    }
+
+    pub(crate) fn register(
+        &self,
+        account_id: Uuid,
+        username: Username,
+        direction: ConnectionDirection,
+        sender: UnboundedSender<Message>,
+    ) -> Arc<RegisteredConnection> {
+        let now = Utc::now();
+        let connection = Arc::new(RegisteredConnection {
+            account_id,
+            username,
+            direction,
+            connected_since: now,
+            last_activity: AtomicI64::new(now.timestamp_millis()),
+            messages_sent: AtomicU64::new(0),
+            messages_received: AtomicU64::new(0),
+            ping_latency: Mutex::new(None),
+            sender,
+        });
+        self.connections.write().push(connection.clone());
+        connection
+    }
+
+    pub(crate) fn unregister(&self, connection: &Arc<RegisteredConnection>) {
+        self.connections
+            .write()
+            .retain(|registered| !Arc::ptr_eq(registered, connection));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc::unbounded;
+    use std::str::FromStr;
+
+    #[test]
+    fn tracks_and_closes_connections() {
+        let registry = BtpConnectionRegistry::new();
+        let alice = Uuid::new_v4();
+        let bob = Uuid::new_v4();
+        let (alice_tx, _alice_rx) = unbounded();
+        let (bob_tx, _bob_rx) = unbounded();
+        let alice_connection = registry.register(
+            alice,
+            Username::from_str("alice").unwrap(),
+            ConnectionDirection::Outgoing,
+            alice_tx.clone(),
+        );
+        registry.register(
+            bob,
+            Username::from_str("bob").unwrap(),
+            ConnectionDirection::Incoming,
+            bob_tx.clone(),
+        );
+
+        alice_connection.record_sent();
+        alice_connection.record_sent();
+        alice_connection.record_received();
+        alice_connection.record_ping_latency(Duration::from_millis(15));
+        let connections = registry.connections();
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0].account_id, alice);
+        assert_eq!(connections[0].direction, ConnectionDirection::Outgoing);
+        assert_eq!(connections[0].messages_sent, 2);
+        assert_eq!(connections[0].messages_received, 1);
+        assert_eq!(connections[0].ping_latency, Some(Duration::from_millis(15)));
+        assert_eq!(connections[1].messages_sent, 0);
+        assert_eq!(connections[1].ping_latency, None);
+        assert_eq!(
+            registry.ping_latency(&alice),
+            Some(Duration::from_millis(15))
+        );
+        assert_eq!(registry.ping_latency(&bob), None);
+
+        assert!(registry.close(&alice));
+        assert!(alice_tx.is_closed());
+        assert!(!bob_tx.is_closed());
+        assert!(!registry.close(&Uuid::new_v4()));
+
+        registry.unregister(&alice_connection);
+        let connections = registry.connections();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].account_id, bob);
+    }
+}
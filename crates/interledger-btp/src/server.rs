@@ -1,5 +1,5 @@
 use super::{packet::*, BtpAccount, BtpStore};
-use super::{service::BtpOutgoingService, wrapped_ws::WsWrap};
+use super::{registry::ConnectionDirection, service::BtpOutgoingService, wrapped_ws::WsWrap};
 use futures::{FutureExt, Sink, Stream};
 use futures::{SinkExt, StreamExt, TryFutureExt};
 use interledger_service::*;
@@ -86,7 +86,11 @@ where
     // We need to wrap our Warp connection in order to cast the Sink type
     // to tungstenite::Message. This probably can be implemented with SinkExt::with
     // but couldn't figure out how.
-    service.add_connection(account.clone(), WsWrap { connection });
+    service.add_connection(
+        account.clone(),
+        ConnectionDirection::Incoming,
+        WsWrap { connection },
+    );
     debug!(
         "Added connection for account {}: (id: {})",
         account.username(),
@@ -1,4 +1,10 @@
-use super::{packet::*, BtpAccount};
+use super::{
+    errors::BtpSubProtocolError,
+    packet::*,
+    registry::{BtpConnectionRegistry, ConnectionDirection},
+    sub_protocol::{BtpSubProtocolHandler, SubProtocols},
+    BtpAccount,
+};
 use async_trait::async_trait;
 use bytes::BytesMut;
 use futures::{
@@ -6,25 +12,29 @@ use futures::{
         mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
         oneshot,
     },
-    future, FutureExt, Sink, Stream, StreamExt,
+    future::Either,
+    stream, FutureExt, Sink, Stream, StreamExt,
 };
 use interledger_packet::{Address, ErrorCode, Fulfill, Packet, Prepare, Reject, RejectBuilder};
 use interledger_service::*;
-use once_cell::sync::Lazy;
 use parking_lot::{Mutex, RwLock};
 use rand::random;
 use std::collections::HashMap;
-use std::{convert::TryFrom, iter::IntoIterator, marker::PhantomData, sync::Arc, time::Duration};
+use std::{
+    convert::TryFrom,
+    iter::IntoIterator,
+    marker::PhantomData,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use stream_cancel::{Trigger, Valve};
 use tokio::time;
 use tokio_tungstenite::tungstenite::Message;
 use tracing::{debug, error, trace, warn};
 use uuid::Uuid;
 
-const PING_INTERVAL: u64 = 30; // seconds
-
-static PING: Lazy<Message> = Lazy::new(|| Message::Ping(Vec::with_capacity(0)));
-static PONG: Lazy<Message> = Lazy::new(|| Message::Pong(Vec::with_capacity(0)));
+const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_MISSED_PONGS: u32 = 3;
 
 // Return a Reject timeout if the outgoing message future does not complete
 // within this timeout. This will probably happen if the peer closed the websocket
@@ -53,6 +63,42 @@ pub struct BtpOutgoingService<O, A: Account> {
     next: O,
     close_all_connections: Arc<Mutex<Option<Trigger>>>,
     stream_valve: Arc<Valve>,
+    registry: BtpConnectionRegistry,
+    sub_protocols: SubProtocols<A>,
+    ping_interval: Duration,
+    max_missed_pongs: u32,
+}
+
+/// The Ping we are waiting for the peer to answer and the number of Pings
+/// the peer did not answer in a row
+#[derive(Default)]
+struct Keepalive {
+    outstanding: Option<(u64, Instant)>,
+    missed_pongs: u32,
+}
+
+impl Keepalive {
+    /// Returns the payload of the next Ping and the number of Pings that went unanswered
+    fn next_ping(&mut self) -> (u64, u32) {
+        if self.outstanding.is_some() {
+            self.missed_pongs += 1;
+        }
+        let nonce = random();
+        self.outstanding = Some((nonce, Instant::now()));
+        (nonce, self.missed_pongs)
+    }
+
+    /// Returns the round trip time if the Pong answers the outstanding Ping
+    fn pong_received(&mut self, payload: &[u8]) -> Option<Duration> {
+        match self.outstanding {
+            Some((nonce, sent_at)) if payload == nonce.to_be_bytes() => {
+                self.outstanding = None;
+                self.missed_pongs = 0;
+                Some(sent_at.elapsed())
+            }
+            _ => None,
+        }
+    }
 }
 
 /// Handle the packets based on whether they are an incoming request or a response to something we sent.
@@ -63,16 +109,29 @@ pub struct BtpOutgoingService<O, A: Account> {
 /// incoming_sender.unbounded_send basically sends data to the self.incoming_receiver
 /// to be consumed when we setup the incoming handler
 /// Set up a listener to handle incoming packets from the WebSocket connection
+/// BTP packets that don't carry an ILP packet are passed to the sub-protocol handlers.
 #[inline]
-async fn handle_message<A: BtpAccount>(
+async fn handle_message<A: BtpAccount + Send + Sync + 'static>(
     message: Message,
     tx_clone: UnboundedSender<Message>,
     account: A,
     pending_requests: Arc<Mutex<HashMap<u32, IlpResultChannel>>>,
     incoming_sender: UnboundedSender<(A, u32, Prepare)>,
+    sub_protocols: SubProtocols<A>,
 ) {
-    if message.is_binary() {
-        match parse_ilp_packet(message) {
+    if let Message::Binary(data) = message {
+        let packet = match BtpPacket::from_bytes(&data) {
+            Ok(packet) => packet,
+            Err(err) => {
+                error!("Error parsing BTP packet: {:?}", err);
+                return;
+            }
+        };
+        if !carries_ilp_packet(&packet) {
+            sub_protocols.handle_packet(packet, account, tx_clone);
+            return;
+        }
+        match parse_ilp_packet(packet) {
             // Queues up the prepare packet
             Ok((request_id, Packet::Prepare(prepare))) => {
                 trace!(
@@ -108,15 +167,15 @@ async fn handle_message<A: BtpAccount>(
                 }
             }
             Err(_) => {
-                debug!("Unable to parse ILP packet from BTP packet (if this is the first time this appears, the packet was probably the auth response)");
+                debug!("Unable to parse ILP packet from BTP packet");
                 // TODO Send error back
             }
         }
-    } else if message.is_ping() {
+    } else if let Message::Ping(payload) = message {
         trace!("Responding to Ping message from account {}", account.id());
-        // Writes back the PONG to the websocket
+        // Writes back the Pong (with the same payload) to the websocket
         let _ = tx_clone
-            .unbounded_send(PONG.clone())
+            .unbounded_send(Message::Pong(payload))
             .map_err(|err| error!("Error sending Pong message back: {:?}", err));
     }
 }
@@ -127,6 +186,16 @@ where
     A: BtpAccount + Send + Sync + 'static,
 {
     pub fn new(ilp_address: Address, next: O) -> Self {
+        Self::with_connection_registry(ilp_address, next, BtpConnectionRegistry::new())
+    }
+
+    /// Creates a service that adds its connections to the given registry
+    /// instead of one of its own
+    pub fn with_connection_registry(
+        ilp_address: Address,
+        next: O,
+        registry: BtpConnectionRegistry,
+    ) -> Self {
         let (incoming_sender, incoming_receiver) = unbounded();
         let (close_all_connections, stream_valve) = Valve::new();
         BtpOutgoingService {
@@ -138,6 +207,117 @@ where
             next,
             close_all_connections: Arc::new(Mutex::new(Some(close_all_connections))),
             stream_valve: Arc::new(stream_valve),
+            registry,
+            sub_protocols: SubProtocols::new(),
+            ping_interval: DEFAULT_PING_INTERVAL,
+            max_missed_pongs: DEFAULT_MAX_MISSED_PONGS,
+        }
+    }
+
+    /// Sets how often WebSocket Pings are sent on the connections added after this call.
+    /// An interval of 0 disables the Pings, and with them the closing of unresponsive
+    /// connections. Defaults to 30 seconds
+    pub fn ping_interval(&mut self, interval: Duration) -> &mut Self {
+        self.ping_interval = interval;
+        self
+    }
+
+    /// Sets how many Pings in a row the peer can leave unanswered before its connection
+    /// is considered dead and closed, for the connections added after this call.
+    /// Setting this to 0 keeps the connections open regardless. Defaults to 3
+    pub fn max_missed_pongs(&mut self, max_missed_pongs: u32) -> &mut Self {
+        self.max_missed_pongs = max_missed_pongs;
+        self
+    }
+
+    /// Returns the registry of the service's open connections
+    pub fn connection_registry(&self) -> &BtpConnectionRegistry {
+        &self.registry
+    }
+
+    /// Passes the BTP Message and Transfer packets whose primary sub-protocol is named
+    /// `protocol_name` to the handler, replacing the handler previously registered for it.
+    /// Packets for sub-protocols without a handler are answered with a BTP Error
+    pub fn register_sub_protocol_handler<H>(&self, protocol_name: &str, handler: H)
+    where
+        H: BtpSubProtocolHandler<A> + 'static,
+    {
+        self.sub_protocols
+            .register(protocol_name, Arc::new(handler));
+    }
+
+    /// Makes the connections added after this call use the sub-protocol handlers of the other
+    /// service, so that the services handling the incoming and the outgoing connections of a
+    /// node can share one set of handlers, like they share a
+    /// [BtpConnectionRegistry](./struct.BtpConnectionRegistry.html)
+    pub fn share_sub_protocols<P>(&mut self, other: &BtpOutgoingService<P, A>) -> &mut Self {
+        self.sub_protocols = other.sub_protocols.clone();
+        self
+    }
+
+    /// Sends a BTP Message with the given sub-protocol entries to the account and returns the
+    /// entries of its Response
+    pub async fn send_sub_protocol_message(
+        &self,
+        account_id: &Uuid,
+        protocol_data: Vec<ProtocolData>,
+    ) -> Result<Vec<ProtocolData>, BtpSubProtocolError> {
+        self.send_btp_request(account_id, |request_id| {
+            BtpMessage {
+                request_id,
+                protocol_data,
+            }
+            .to_bytes()
+        })
+        .await
+    }
+
+    /// Sends a BTP Transfer of the given amount (for example, for a settlement plugin) with the
+    /// given sub-protocol entries to the account and returns the entries of its Response
+    pub async fn send_transfer(
+        &self,
+        account_id: &Uuid,
+        amount: u64,
+        protocol_data: Vec<ProtocolData>,
+    ) -> Result<Vec<ProtocolData>, BtpSubProtocolError> {
+        self.send_btp_request(account_id, |request_id| {
+            BtpTransfer {
+                request_id,
+                amount,
+                protocol_data,
+            }
+            .to_bytes()
+        })
+        .await
+    }
+
+    async fn send_btp_request(
+        &self,
+        account_id: &Uuid,
+        to_bytes: impl FnOnce(u32) -> Vec<u8>,
+    ) -> Result<Vec<ProtocolData>, BtpSubProtocolError> {
+        let connection = self
+            .connections
+            .read()
+            .get(account_id)
+            .cloned()
+            .ok_or(BtpSubProtocolError::NotConnected(*account_id))?;
+        let request_id = random::<u32>();
+        let receiver = self.sub_protocols.expect_response(request_id);
+        if connection
+            .unbounded_send(Message::binary(to_bytes(request_id)))
+            .is_err()
+        {
+            self.sub_protocols.forget_response(request_id);
+            return Err(BtpSubProtocolError::NotConnected(*account_id));
+        }
+        match time::timeout(SEND_MSG_TIMEOUT, receiver).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(BtpSubProtocolError::ConnectionClosed),
+            Err(_) => {
+                self.sub_protocols.forget_response(request_id);
+                Err(BtpSubProtocolError::Timeout)
+            }
         }
     }
 
@@ -146,6 +326,15 @@ where
         self.connections.write().remove(account_id);
     }
 
+    /// Returns true if there is an open WebSocket connection for the provided `account_id`
+    pub fn is_connected(&self, account_id: &Uuid) -> bool {
+        self.connections
+            .read()
+            .get(account_id)
+            .map(|tx| !tx.is_closed())
+            .unwrap_or(false)
+    }
+
     /// Close all of the open WebSocket connections
     // TODO is there some more automatic way of knowing when we should close the connections?
     // The problem is that the WS client can be a server too, so it's not clear when we are done with it
@@ -161,6 +350,7 @@ where
     pub(crate) fn add_connection(
         &self,
         account: A,
+        direction: ConnectionDirection,
         ws_stream: impl Stream<Item = Message> + Sink<Message> + Send + 'static,
     ) {
         let account_id = account.id();
@@ -168,10 +358,24 @@ where
         let (client_tx, client_rx) = unbounded();
         let (write, read) = ws_stream.split();
         let (close_connection, valve) = Valve::new();
+        // Dropped by the keepalive task when the peer stops answering Pings
+        let (close_dead_connection, keepalive_valve) = Valve::new();
+        let keepalive = Arc::new(Mutex::new(Keepalive::default()));
+        let registered = self.registry.register(
+            account_id,
+            account.username().clone(),
+            direction,
+            client_tx.clone(),
+        );
 
         // tx -> rx -> write -> our peer
         // Responsible mainly for responding to Pings
-        let write_to_ws = client_rx.map(Ok).forward(write).then(move |_| {
+        let registered_clone = registered.clone();
+        let client_rx = client_rx.map(move |message| {
+            registered_clone.record_sent();
+            Ok(message)
+        });
+        let write_to_ws = client_rx.forward(write).then(move |_| {
             async move {
                 debug!(
                     "Finished forwarding to WebSocket stream for account: {}",
@@ -188,45 +392,93 @@ where
         let pending_outgoing = self.pending_outgoing.clone();
         let incoming_sender = self.incoming_sender.clone();
         let client_tx_clone = client_tx.clone();
+        let registered_clone = registered.clone();
+        let keepalive_clone = keepalive.clone();
+        let sub_protocols = self.sub_protocols.clone();
         let handle_message_fn = move |msg: Message| {
+            registered_clone.record_received();
+            if let Message::Pong(ref payload) = msg {
+                if let Some(latency) = keepalive_clone.lock().pong_received(payload) {
+                    trace!("Ping latency to account {}: {:?}", account_id, latency);
+                    registered_clone.record_ping_latency(latency);
+                }
+            }
             handle_message(
                 msg,
                 client_tx_clone.clone(),
                 account.clone(),
                 pending_outgoing.clone(),
                 incoming_sender.clone(),
+                sub_protocols.clone(),
             )
         };
 
         // Close connections trigger
         let read = valve.wrap(read); // close when `write_to_ws` calls `drop(connection)`
+        let read = keepalive_valve.wrap(read);
         let read = self.stream_valve.wrap(read);
+        let connections = self.connections.clone();
+        let client_tx_clone = client_tx.clone();
+        let registry = self.registry.clone();
         let read_from_ws = read.for_each(handle_message_fn).then(move |_| async move {
             debug!(
                 "Finished reading from WebSocket stream for account: {}",
                 account_id
             );
+            // The peer closed the connection, so we stop writing to it and forget it
+            // (unless it was already replaced by a new connection for the same account)
+            let mut connections = connections.write();
+            let is_current = connections
+                .get(&account_id)
+                .map(|tx| tx.same_receiver(&client_tx_clone))
+                .unwrap_or(false);
+            if is_current {
+                connections.remove(&account_id);
+            }
+            client_tx_clone.close_channel();
+            registry.unregister(&registered);
             Ok::<(), ()>(())
         });
         tokio::spawn(read_from_ws);
 
-        // Send pings every PING_INTERVAL until the connection closes (when `drop(close_connection)` is called)
-        // or the Service is dropped (which will implicitly drop `close_all_connections`, closing the stream_valve)
+        // Send pings every ping_interval until the connection closes (when `drop(close_connection)` is called)
+        // or the Service is dropped (which will implicitly drop `close_all_connections`, closing the stream_valve).
+        // If the peer stops answering them (for example because a NAT dropped the connection without
+        // either side noticing), close the connection so that it can be replaced
         let tx_clone = client_tx.clone();
+        let max_missed_pongs = self.max_missed_pongs;
 
-        let ping_interval = time::interval(Duration::from_secs(PING_INTERVAL));
-        let ping_stream = tokio_stream::wrappers::IntervalStream::new(ping_interval);
+        // Without Pings the task only keeps the connection open until it is closed otherwise
+        let ping_stream = if self.ping_interval.is_zero() {
+            Either::Left(stream::pending())
+        } else {
+            let ping_interval = time::interval(self.ping_interval);
+            Either::Right(tokio_stream::wrappers::IntervalStream::new(ping_interval))
+        };
         let repeat_until_service_drops = self.stream_valve.wrap(ping_stream);
-        let send_pings = valve.wrap(repeat_until_service_drops).for_each(move |_| {
-            // For each tick send a ping
-            if let Err(err) = tx_clone.unbounded_send(PING.clone()) {
-                warn!(
-                    "Error sending Ping on connection to account {}: {:?}",
-                    account_id, err
-                );
+        let mut ticks = valve.wrap(repeat_until_service_drops);
+        let send_pings = async move {
+            while ticks.next().await.is_some() {
+                let (nonce, missed_pongs) = keepalive.lock().next_ping();
+                if max_missed_pongs > 0 && missed_pongs >= max_missed_pongs {
+                    warn!(
+                        "Account {} did not answer the last {} Pings, closing its connection",
+                        account_id, missed_pongs
+                    );
+                    tx_clone.close_channel();
+                    drop(close_dead_connection);
+                    return;
+                }
+                if let Err(err) =
+                    tx_clone.unbounded_send(Message::Ping(nonce.to_be_bytes().to_vec()))
+                {
+                    warn!(
+                        "Error sending Ping on connection to account {}: {:?}",
+                        account_id, err
+                    );
+                }
             }
-            future::ready(())
-        });
+        };
         tokio::spawn(send_pings);
 
         // Save the sender side of the channel so we have a way to forward outgoing requests to the WebSocket
@@ -432,6 +684,40 @@ where
     pub fn close_connection(&self, account_id: &Uuid) {
         self.outgoing.close_connection(account_id);
     }
+
+    /// Returns the registry of the service's open connections
+    pub fn connection_registry(&self) -> &BtpConnectionRegistry {
+        self.outgoing.connection_registry()
+    }
+
+    pub fn register_sub_protocol_handler<H>(&self, protocol_name: &str, handler: H)
+    where
+        H: BtpSubProtocolHandler<A> + 'static,
+    {
+        self.outgoing
+            .register_sub_protocol_handler(protocol_name, handler);
+    }
+
+    pub async fn send_sub_protocol_message(
+        &self,
+        account_id: &Uuid,
+        protocol_data: Vec<ProtocolData>,
+    ) -> Result<Vec<ProtocolData>, BtpSubProtocolError> {
+        self.outgoing
+            .send_sub_protocol_message(account_id, protocol_data)
+            .await
+    }
+
+    pub async fn send_transfer(
+        &self,
+        account_id: &Uuid,
+        amount: u64,
+        protocol_data: Vec<ProtocolData>,
+    ) -> Result<Vec<ProtocolData>, BtpSubProtocolError> {
+        self.outgoing
+            .send_transfer(account_id, amount, protocol_data)
+            .await
+    }
 }
 
 #[async_trait]
@@ -450,44 +736,32 @@ where
     }
 }
 
-#[allow(clippy::cognitive_complexity)]
-fn parse_ilp_packet(message: Message) -> Result<(u32, Packet), ()> {
-    if let Message::Binary(data) = message {
-        let (request_id, ilp_data) = match BtpPacket::from_bytes(&data) {
-            Ok(BtpPacket::Message(message)) => {
-                let ilp_data = message
-                    .protocol_data
-                    .into_iter()
-                    .find(|proto| proto.protocol_name == "ilp")
-                    .ok_or(())?
-                    .data;
-                (message.request_id, ilp_data)
-            }
-            Ok(BtpPacket::Response(response)) => {
-                let ilp_data = response
-                    .protocol_data
-                    .into_iter()
-                    .find(|proto| proto.protocol_name == "ilp")
-                    .ok_or(())?
-                    .data;
-                (response.request_id, ilp_data)
-            }
-            Ok(BtpPacket::Error(error)) => {
-                error!("Got BTP error: {:?}", error);
-                return Err(());
-            }
-            Err(err) => {
-                error!("Error parsing BTP packet: {:?}", err);
-                return Err(());
-            }
-        };
-        if let Ok(packet) = Packet::try_from(BytesMut::from(ilp_data.as_slice())) {
-            Ok((request_id, packet))
-        } else {
-            Err(())
-        }
+/// Returns true if the packet is a Message or a Response with an `ilp` entry
+fn carries_ilp_packet(packet: &BtpPacket) -> bool {
+    let protocol_data = match packet {
+        BtpPacket::Message(message) => &message.protocol_data,
+        BtpPacket::Response(response) => &response.protocol_data,
+        BtpPacket::Transfer(_) | BtpPacket::Error(_) => return false,
+    };
+    protocol_data
+        .iter()
+        .any(|proto| proto.protocol_name == "ilp")
+}
+
+fn parse_ilp_packet(packet: BtpPacket) -> Result<(u32, Packet), ()> {
+    let (request_id, protocol_data) = match packet {
+        BtpPacket::Message(message) => (message.request_id, message.protocol_data),
+        BtpPacket::Response(response) => (response.request_id, response.protocol_data),
+        BtpPacket::Transfer(_) | BtpPacket::Error(_) => return Err(()),
+    };
+    let ilp_data = protocol_data
+        .into_iter()
+        .find(|proto| proto.protocol_name == "ilp")
+        .ok_or(())?
+        .data;
+    if let Ok(packet) = Packet::try_from(BytesMut::from(ilp_data.as_slice())) {
+        Ok((request_id, packet))
     } else {
-        error!("Got a non-binary WebSocket message");
         Err(())
     }
 }
@@ -0,0 +1,233 @@
+use super::errors::BtpSubProtocolError;
+use super::packet::*;
+use async_trait::async_trait;
+use chrono::{SecondsFormat, Utc};
+use futures::channel::{mpsc::UnboundedSender, oneshot};
+use interledger_packet::oer::VariableLengthTimestamp;
+use interledger_service::Account;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error, trace, warn};
+
+type SubProtocolResultChannel = oneshot::Sender<Result<Vec<ProtocolData>, BtpSubProtocolError>>;
+type SubProtocolHandlers<A> = HashMap<String, Arc<dyn BtpSubProtocolHandler<A>>>;
+
+/// A BTP Message or Transfer packet whose primary (first) sub-protocol is not `ilp`
+#[derive(Clone, Debug)]
+pub struct BtpSubProtocolRequest<A> {
+    pub from: A,
+    pub request_id: u32,
+    /// The amount of a Transfer packet, or None for a Message packet
+    pub amount: Option<u64>,
+    /// All of the entries of the packet, starting with the primary sub-protocol
+    pub protocol_data: Vec<ProtocolData>,
+}
+
+/// Handles the BTP Message and Transfer packets of a named sub-protocol, such as the
+/// ones used by settlement plugins.
+///
+/// Handlers are registered with
+/// [register_sub_protocol_handler](./struct.BtpOutgoingService.html#method.register_sub_protocol_handler)
+/// and are called for the packets whose primary sub-protocol has the name they were registered with.
+#[async_trait]
+pub trait BtpSubProtocolHandler<A: Account>: Send + Sync {
+    /// Returns the protocol data to send back in a BTP Response. Returning
+    /// `BtpSubProtocolError::Rejected` answers the packet with a BTP Error with the given details
+    async fn handle_sub_protocol(
+        &self,
+        request: BtpSubProtocolRequest<A>,
+    ) -> Result<Vec<ProtocolData>, BtpSubProtocolError>;
+}
+
+/// The registered sub-protocol handlers and the sub-protocol requests we are waiting
+/// for responses to, shared by all of the connections of a service
+pub(crate) struct SubProtocols<A> {
+    handlers: Arc<RwLock<SubProtocolHandlers<A>>>,
+    pending_outgoing: Arc<Mutex<HashMap<u32, SubProtocolResultChannel>>>,
+}
+
+impl<A> Clone for SubProtocols<A> {
+    fn clone(&self) -> Self {
+        SubProtocols {
+            handlers: self.handlers.clone(),
+            pending_outgoing: self.pending_outgoing.clone(),
+        }
+    }
+}
+
+impl<A> SubProtocols<A>
+where
+    A: Account + Send + Sync + 'static,
+{
+    pub(crate) fn new() -> Self {
+        SubProtocols {
+            handlers: Arc::new(RwLock::new(HashMap::new())),
+            pending_outgoing: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub(crate) fn register(&self, protocol_name: &str, handler: Arc<dyn BtpSubProtocolHandler<A>>) {
+        self.handlers
+            .write()
+            .insert(protocol_name.to_string(), handler);
+    }
+
+    /// Returns a receiver for the Response (or Error) to the request with the given ID
+    pub(crate) fn expect_response(
+        &self,
+        request_id: u32,
+    ) -> oneshot::Receiver<Result<Vec<ProtocolData>, BtpSubProtocolError>> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending_outgoing.lock().insert(request_id, sender);
+        receiver
+    }
+
+    pub(crate) fn forget_response(&self, request_id: u32) {
+        self.pending_outgoing.lock().remove(&request_id);
+    }
+
+    /// Handles a BTP packet that does not carry an ILP packet. Requests are passed to
+    /// the handler of their primary sub-protocol (in a separate task, so that slow handlers
+    /// don't hold up the connection) and responses are passed back to the request they answer
+    pub(crate) fn handle_packet(&self, packet: BtpPacket, from: A, tx: UnboundedSender<Message>) {
+        match packet {
+            BtpPacket::Message(message) => {
+                self.handle_request(from, message.request_id, None, message.protocol_data, tx)
+            }
+            BtpPacket::Transfer(transfer) => self.handle_request(
+                from,
+                transfer.request_id,
+                Some(transfer.amount),
+                transfer.protocol_data,
+                tx,
+            ),
+            BtpPacket::Response(response) => {
+                match self.pending_outgoing.lock().remove(&response.request_id) {
+                    Some(channel) => {
+                        let _ = channel.send(Ok(response.protocol_data));
+                    }
+                    None => debug!(
+                        "Got BTP Response {} that does not match a request we sent (if this is the first time this appears, the packet was probably the auth response)",
+                        response.request_id
+                    ),
+                }
+            }
+            BtpPacket::Error(err) => match self.pending_outgoing.lock().remove(&err.request_id) {
+                Some(channel) => {
+                    let _ = channel.send(Err(BtpSubProtocolError::Rejected {
+                        code: err.code,
+                        name: err.name,
+                        data: err.data,
+                    }));
+                }
+                None => error!("Got BTP error: {:?}", err),
+            },
+        }
+    }
+
+    fn handle_request(
+        &self,
+        from: A,
+        request_id: u32,
+        amount: Option<u64>,
+        protocol_data: Vec<ProtocolData>,
+        tx: UnboundedSender<Message>,
+    ) {
+        let protocol_name = protocol_data
+            .first()
+            .map(|data| data.protocol_name.to_string())
+            .unwrap_or_default();
+        let handler = self.handlers.read().get(&protocol_name).cloned();
+        let handler = match handler {
+            Some(handler) => handler,
+            None => {
+                debug!(
+                    "Got BTP request {} for sub-protocol \"{}\" from account {}, which has no handler",
+                    request_id,
+                    protocol_name,
+                    from.id()
+                );
+                send_error(
+                    &tx,
+                    request_id,
+                    "F00",
+                    "NotAcceptedError",
+                    format!("Unsupported sub-protocol: {}", protocol_name),
+                );
+                return;
+            }
+        };
+
+        trace!(
+            "Handling BTP request {} for sub-protocol \"{}\" from account {}",
+            request_id,
+            protocol_name,
+            from.id()
+        );
+        let request = BtpSubProtocolRequest {
+            from,
+            request_id,
+            amount,
+            protocol_data,
+        };
+        tokio::spawn(async move {
+            match handler.handle_sub_protocol(request).await {
+                Ok(protocol_data) => {
+                    let response = BtpResponse {
+                        request_id,
+                        protocol_data,
+                    };
+                    let _ = tx
+                        .unbounded_send(Message::binary(response.to_bytes()))
+                        .map_err(|err| error!("Error sending BTP Response: {:?}", err));
+                }
+                Err(BtpSubProtocolError::Rejected { code, name, data }) => {
+                    send_error(&tx, request_id, &code, &name, data)
+                }
+                Err(err) => send_error(&tx, request_id, "F00", "NotAcceptedError", err.to_string()),
+            }
+        });
+    }
+}
+
+/// Whether the code has the format of a BTP error code, such as `F00` or `T01`
+fn is_btp_error_code(code: &str) -> bool {
+    matches!(code.as_bytes(), [letter, tens, ones]
+        if letter.is_ascii_uppercase() && tens.is_ascii_digit() && ones.is_ascii_digit())
+}
+
+fn send_error(
+    tx: &UnboundedSender<Message>,
+    request_id: u32,
+    code: &str,
+    name: &str,
+    data: String,
+) {
+    // The code is serialized as exactly 3 bytes, so anything else would corrupt the packet
+    let code = if is_btp_error_code(code) {
+        code
+    } else {
+        warn!(
+            "Sub-protocol handler rejected request {} with invalid BTP error code {:?}, sending F00 instead",
+            request_id, code
+        );
+        "F00"
+    };
+    let triggered_at = VariableLengthTimestamp::parse_from_rfc3339(
+        &Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+    )
+    .expect("chrono produces valid RFC3339 timestamps");
+    let error = BtpError {
+        request_id,
+        code: code.to_string(),
+        name: name.to_string(),
+        triggered_at,
+        data,
+        protocol_data: Vec::new(),
+    };
+    let _ = tx
+        .unbounded_send(Message::binary(error.to_bytes()))
+        .map_err(|err| error!("Error sending BTP Error: {:?}", err));
+}
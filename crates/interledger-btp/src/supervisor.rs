@@ -0,0 +1,231 @@
+use super::client::connect_to_service_account;
+use super::service::BtpOutgoingService;
+use super::{BtpAccount, BtpStore};
+use futures::future::join_all;
+use interledger_service::OutgoingService;
+use rand::random;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+use tokio::time;
+use tracing::{debug, trace, warn};
+use url::Url;
+use uuid::Uuid;
+
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_MIN_BACKOFF: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(300);
+// Don't let a peer that never answers hold up the reconnections to the other peers
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The connection details we last connected to an account with
+struct SupervisedAccount {
+    url: Url,
+    token: Vec<u8>,
+    /// The number of reconnection attempts that failed in a row
+    failures: u32,
+    retry_at: Instant,
+}
+
+/// Keeps the outgoing BTP connections of the [BtpOutgoingService](./struct.BtpOutgoingService.html)
+/// open.
+///
+/// On every check, the supervisor loads the accounts with an `ilp_over_btp_url` from the store
+/// and connects to the ones that are not connected (sending the BTP auth again on each new
+/// connection). Failed attempts are retried with exponential backoff and jitter. Accounts
+/// that were added through the API are connected on the next check, accounts whose BTP URL
+/// or outgoing token changed are reconnected with the new details, and the connections to
+/// accounts that no longer have a BTP URL are closed. Connections closed by other means,
+/// for example with [BtpConnectionRegistry::close](./struct.BtpConnectionRegistry.html#method.close),
+/// are reopened like the ones the peer dropped.
+pub struct BtpSupervisor<S, O, A: BtpAccount> {
+    store: S,
+    service: BtpOutgoingService<O, A>,
+    check_interval: Duration,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    account_type: PhantomData<A>,
+}
+
+impl<S, O, A> BtpSupervisor<S, O, A>
+where
+    S: BtpStore<Account = A> + Clone + Send + Sync + 'static,
+    O: OutgoingService<A> + Clone + Send + Sync + 'static,
+    A: BtpAccount + Send + Sync + 'static,
+{
+    pub fn new(store: S, service: BtpOutgoingService<O, A>) -> Self {
+        BtpSupervisor {
+            store,
+            service,
+            check_interval: DEFAULT_CHECK_INTERVAL,
+            min_backoff: DEFAULT_MIN_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            account_type: PhantomData,
+        }
+    }
+
+    /// Sets how often the accounts and their connections are checked. Defaults to 5 seconds
+    pub fn check_interval(&mut self, interval: Duration) -> &mut Self {
+        self.check_interval = interval;
+        self
+    }
+
+    /// Sets the delay before the first retry after a failed attempt to connect, which is
+    /// doubled after each further failure. Defaults to 1 second
+    pub fn min_backoff(&mut self, backoff: Duration) -> &mut Self {
+        self.min_backoff = backoff;
+        self
+    }
+
+    /// Sets the longest delay between the attempts to connect to an account. Defaults to 5 minutes
+    pub fn max_backoff(&mut self, backoff: Duration) -> &mut Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Checks the outgoing BTP connections on the configured interval. This never returns,
+    /// so it should be spawned as a separate task
+    pub async fn run(&self) {
+        let mut accounts = HashMap::new();
+        let mut interval = time::interval(self.check_interval);
+        loop {
+            interval.tick().await;
+            self.check_connections(&mut accounts).await;
+        }
+    }
+
+    async fn check_connections(&self, supervised: &mut HashMap<Uuid, SupervisedAccount>) {
+        let accounts = match self.store.get_btp_outgoing_accounts().await {
+            Ok(accounts) => accounts,
+            Err(err) => {
+                warn!("Error loading the accounts to connect to over BTP: {}", err);
+                return;
+            }
+        };
+        let now = Instant::now();
+
+        // Stop supervising the accounts that were deleted or don't have a BTP URL anymore
+        let account_ids: Vec<Uuid> = accounts.iter().map(|account| account.id()).collect();
+        let removed: Vec<Uuid> = supervised
+            .keys()
+            .filter(|id| !account_ids.contains(id))
+            .cloned()
+            .collect();
+        for account_id in removed {
+            debug!(
+                "Account {} no longer has a BTP URL, closing its connection",
+                account_id
+            );
+            supervised.remove(&account_id);
+            self.service.close_connection(&account_id);
+        }
+
+        let mut to_connect = Vec::new();
+        for account in accounts {
+            let url = match account.get_ilp_over_btp_url() {
+                Some(url) => url.clone(),
+                None => continue,
+            };
+            let token = account
+                .get_ilp_over_btp_outgoing_token()
+                .map(|token| token.to_vec())
+                .unwrap_or_default();
+            let account_id = account.id();
+
+            let state = supervised
+                .entry(account_id)
+                .or_insert_with(|| SupervisedAccount {
+                    url: url.clone(),
+                    token: token.clone(),
+                    failures: 0,
+                    retry_at: now,
+                });
+            if state.url != url || state.token != token {
+                debug!(
+                    "BTP details of account {} changed, reconnecting",
+                    account.username()
+                );
+                self.service.close_connection(&account_id);
+                *state = SupervisedAccount {
+                    url,
+                    token,
+                    failures: 0,
+                    retry_at: now,
+                };
+            } else if self.service.is_connected(&account_id) {
+                state.failures = 0;
+                continue;
+            }
+
+            if state.retry_at <= now {
+                to_connect.push(account);
+            }
+        }
+
+        let attempts = to_connect.into_iter().map(|account| {
+            let account_id = account.id();
+            trace!("Connecting to account {} over BTP", account.username());
+            let connect = connect_to_service_account(account, true, self.service.clone());
+            async move { (account_id, time::timeout(CONNECT_TIMEOUT, connect).await) }
+        });
+        for (account_id, result) in join_all(attempts).await {
+            let state = match supervised.get_mut(&account_id) {
+                Some(state) => state,
+                None => continue,
+            };
+            match result {
+                Ok(Ok(())) => {
+                    debug!("Connected to account {} over BTP", account_id);
+                    state.failures = 0;
+                }
+                Ok(Err(err)) => self.schedule_retry(state, account_id, &err.to_string()),
+                Err(_) => self.schedule_retry(state, account_id, "connection timed out"),
+            }
+        }
+    }
+
+    fn schedule_retry(&self, state: &mut SupervisedAccount, account_id: Uuid, err: &str) {
+        state.failures = state.failures.saturating_add(1);
+        let delay = backoff(
+            self.min_backoff,
+            self.max_backoff,
+            state.failures,
+            random::<f64>(),
+        );
+        warn!(
+            "Error connecting to account {} over BTP (attempt {}), retrying in {:?}: {}",
+            account_id, state.failures, delay, err
+        );
+        state.retry_at = Instant::now() + delay;
+    }
+}
+
+/// Returns the delay before the next attempt after the given number of failures.
+/// The delay doubles with each failure up to `max`, and a random part of up to half
+/// of it (depending on `jitter`, between 0 and 1) is left out so that the nodes that
+/// lost their connections at the same time don't all reconnect at the same time
+fn backoff(min: Duration, max: Duration, failures: u32, jitter: f64) -> Duration {
+    let exponent = failures.saturating_sub(1).min(31);
+    let delay = min
+        .checked_mul(1 << exponent)
+        .map_or(max, |delay| delay.min(max));
+    delay - delay.mul_f64(jitter.clamp(0.0, 1.0) / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_exponentially_with_jitter() {
+        let min = Duration::from_secs(1);
+        let max = Duration::from_secs(60);
+        assert_eq!(backoff(min, max, 1, 0.0), Duration::from_secs(1));
+        assert_eq!(backoff(min, max, 2, 0.0), Duration::from_secs(2));
+        assert_eq!(backoff(min, max, 5, 0.0), Duration::from_secs(16));
+        assert_eq!(backoff(min, max, 10, 0.0), max);
+        assert_eq!(backoff(min, max, u32::MAX, 0.0), max);
+        assert_eq!(backoff(min, max, 5, 1.0), Duration::from_secs(8));
+        assert_eq!(backoff(min, max, 5, 0.5), Duration::from_secs(12));
+    }
+}
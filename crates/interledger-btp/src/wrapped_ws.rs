@@ -54,10 +54,10 @@ where
         let item = match item {
             tungstenite::Message::Binary(data) => Message::binary(data),
             tungstenite::Message::Text(data) => Message::text(data),
+            tungstenite::Message::Ping(data) => Message::ping(data),
+            tungstenite::Message::Pong(data) => Message::pong(data),
             // Ignore other message types because warp's WebSocket type doesn't
             // allow us to send any other types of messages
-            // TODO make sure warp's websocket responds to pings and/or sends them to keep the
-            // connection alive
             _ => return Ok(()),
         };
         this.connection.start_send(item)
@@ -77,6 +77,8 @@ where
 fn convert_msg(message: Message) -> tungstenite::Message {
     if message.is_ping() {
         tungstenite::Message::Ping(message.into_bytes())
+    } else if message.is_pong() {
+        tungstenite::Message::Pong(message.into_bytes())
     } else if message.is_binary() {
         tungstenite::Message::Binary(message.into_bytes())
     } else if message.is_text() {
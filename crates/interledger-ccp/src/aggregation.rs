@@ -0,0 +1,192 @@
+use crate::{
+    auth::{hash, route_auth},
+    packet::Route,
+    policy::prefix_matches,
+};
+use interledger_packet::Address;
+use std::collections::{HashMap, HashSet};
+
+/// Replace the routes that end at our node with a single route for their common prefix,
+/// as long as every route under that prefix ends at our node.
+///
+/// The routes are the ones we would broadcast, so they already have our address at the
+/// start of their paths. The routes that end at our node are the ones for the prefixes
+/// given in `local_prefixes`, which go to accounts on our node rather than through other
+/// connectors. Aggregates can themselves be aggregated further, but never into the global
+/// prefix or into a prefix that covers our own address, which we always advertise separately.
+pub(crate) fn aggregate_routes<A: Clone>(
+    routes: Vec<(A, Route)>,
+    local_prefixes: &HashSet<String>,
+    ilp_address: &Address,
+    route_auth_secret: Option<[u8; 32]>,
+) -> Vec<(A, Route)> {
+    let mut routes: HashMap<String, (A, Route)> = routes
+        .into_iter()
+        .map(|(account, route)| (route.prefix.clone(), (account, route)))
+        .collect();
+    let mut local_prefixes = local_prefixes.clone();
+
+    while let Some(aggregate) = find_aggregate(&routes, &local_prefixes, ilp_address) {
+        let mut covered: Vec<String> = routes
+            .keys()
+            .filter(|prefix| prefix_matches(prefix, &aggregate))
+            .cloned()
+            .collect();
+        covered.sort_unstable();
+        let account = routes[&covered[0]].0.clone();
+        for prefix in covered.iter() {
+            routes.remove(prefix);
+        }
+        local_prefixes.insert(aggregate.clone());
+
+        // We are the origin of the aggregate route, so its auth value
+        // is derived the same way as the one for our own address
+        let auth = route_auth_secret
+            .map(|secret| route_auth(&secret, &aggregate))
+            .unwrap_or([0; 32]);
+        routes.insert(
+            aggregate.clone(),
+            (
+                account,
+                Route {
+                    prefix: aggregate,
+                    path: vec![ilp_address.to_string()],
+                    auth: hash(&auth),
+                    props: Vec::new(),
+                },
+            ),
+        );
+    }
+
+    routes.drain().map(|(_prefix, route)| route).collect()
+}
+
+/// The prefix made of the first two segments of the given one. Routes are never aggregated
+/// into a prefix without a `.`, so only routes under the same root are aggregated together
+pub(crate) fn aggregation_root(prefix: &str) -> &str {
+    match prefix.match_indices('.').nth(1) {
+        Some((index, _)) => &prefix[..index],
+        None => prefix,
+    }
+}
+
+/// Find the longest prefix that covers at least two routes and only routes that end at our node
+fn find_aggregate<A>(
+    routes: &HashMap<String, (A, Route)>,
+    local_prefixes: &HashSet<String>,
+    ilp_address: &Address,
+) -> Option<String> {
+    let mut candidates: HashMap<&str, usize> = HashMap::new();
+    for prefix in routes
+        .keys()
+        .filter(|prefix| local_prefixes.contains(*prefix))
+    {
+        if let Some(index) = prefix.rfind('.') {
+            *candidates.entry(&prefix[..index]).or_insert(0) += 1;
+        }
+    }
+
+    let mut candidates: Vec<&str> = candidates
+        .into_iter()
+        .filter(|(parent, count)| {
+            *count >= 2
+                // Don't aggregate into the global prefix
+                && parent.contains('.')
+                && *parent != ilp_address as &str
+                && !prefix_matches(ilp_address, parent)
+        })
+        .map(|(parent, _count)| parent)
+        .filter(|parent| {
+            routes
+                .keys()
+                .filter(|prefix| prefix_matches(prefix, parent))
+                .all(|prefix| local_prefixes.contains(prefix))
+        })
+        .collect();
+    candidates.sort_unstable_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    candidates.first().map(|parent| parent.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// Takes the prefixes of the routes and whether each one ends at our node
+    fn aggregated_prefixes(routes: Vec<(&str, bool)>) -> Vec<String> {
+        let ilp_address = Address::from_str("example.connector").unwrap();
+        let local_prefixes: HashSet<String> = routes
+            .iter()
+            .filter(|(_, is_local)| *is_local)
+            .map(|(prefix, _)| prefix.to_string())
+            .collect();
+        let routes = routes
+            .into_iter()
+            .map(|(prefix, _)| {
+                (
+                    0,
+                    Route {
+                        prefix: prefix.to_string(),
+                        path: vec![ilp_address.to_string()],
+                        auth: [0; 32],
+                        props: Vec::new(),
+                    },
+                )
+            })
+            .collect();
+        let mut prefixes: Vec<String> =
+            aggregate_routes(routes, &local_prefixes, &ilp_address, None)
+                .into_iter()
+                .map(|(_, route)| route.prefix)
+                .collect();
+        prefixes.sort_unstable();
+        prefixes
+    }
+
+    #[test]
+    fn finds_the_aggregation_root_of_prefixes() {
+        assert_eq!(
+            aggregation_root("example.customers.eu.alice"),
+            "example.customers"
+        );
+        assert_eq!(aggregation_root("example.other"), "example.other");
+        assert_eq!(aggregation_root("example"), "example");
+    }
+
+    #[test]
+    fn aggregates_routes_that_end_at_our_node() {
+        let prefixes = aggregated_prefixes(vec![
+            ("example.customers.eu.alice", true),
+            ("example.customers.eu.bob", true),
+            ("example.customers.us.carol", true),
+            ("example.customers.us.dave", true),
+            ("example.other", true),
+        ]);
+        assert_eq!(prefixes, vec!["example.customers", "example.other"]);
+    }
+
+    #[test]
+    fn does_not_aggregate_routes_through_other_nodes() {
+        let prefixes = aggregated_prefixes(vec![
+            ("example.customers.eu.alice", true),
+            ("example.customers.eu.bob", true),
+            ("example.customers.eu.carol", false),
+            ("example.customers.us.dave", true),
+        ]);
+        assert_eq!(
+            prefixes,
+            vec![
+                "example.customers.eu.alice",
+                "example.customers.eu.bob",
+                "example.customers.eu.carol",
+                "example.customers.us.dave",
+            ]
+        );
+    }
+
+    #[test]
+    fn does_not_aggregate_into_global_prefix() {
+        let prefixes = aggregated_prefixes(vec![("example.alice", true), ("example.bob", true)]);
+        assert_eq!(prefixes, vec!["example.alice", "example.bob"]);
+    }
+}
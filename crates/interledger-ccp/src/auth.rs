@@ -0,0 +1,83 @@
+use ring::{
+    digest::{digest, SHA256},
+    hmac,
+};
+
+/// The maximum path length of authenticated routes
+pub const MAX_ROUTE_AUTH_HOPS: usize = 64;
+
+pub(crate) fn hash(preimage: &[u8; 32]) -> [u8; 32] {
+    let mut out = [0; 32];
+    out.copy_from_slice(digest(&SHA256, preimage).as_ref());
+    out
+}
+
+fn hash_times(preimage: &[u8; 32], times: usize) -> [u8; 32] {
+    (0..times).fold(*preimage, |auth, _| hash(&auth))
+}
+
+/// Returns the auth value the owner of the prefix includes in its own route
+pub fn route_auth(secret: &[u8; 32], prefix: &str) -> [u8; 32] {
+    let mut auth = [0; 32];
+    let sig = hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA256, secret),
+        prefix.as_bytes(),
+    );
+    auth.copy_from_slice(sig.as_ref());
+    auth
+}
+
+/// Returns the public anchor other nodes use to verify the routes for the prefix.
+///
+/// The owner of a prefix includes `HMAC-SHA256(secret, prefix)` as the auth value of its
+/// own route and each node that forwards the route hashes the auth value once. Hashing the
+/// owner's auth value `MAX_ROUTE_AUTH_HOPS` times gives the anchor, so a node can check the
+/// auth value of a route with a path of `n` hops by hashing it `MAX_ROUTE_AUTH_HOPS - n`
+/// more times. Since the hash cannot be reversed, nodes can make the path of a route
+/// longer but cannot shorten it or advertise a prefix they did not learn from its owner.
+pub fn route_auth_anchor(secret: &[u8; 32], prefix: &str) -> [u8; 32] {
+    hash_times(&route_auth(secret, prefix), MAX_ROUTE_AUTH_HOPS)
+}
+
+/// Returns true if the auth value of a route with the given path length
+/// was derived from the secret the anchor belongs to
+pub(crate) fn verify_route_auth(auth: &[u8; 32], path_length: usize, anchor: &[u8; 32]) -> bool {
+    path_length <= MAX_ROUTE_AUTH_HOPS
+        && hash_times(auth, MAX_ROUTE_AUTH_HOPS - path_length) == *anchor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: [u8; 32] = [7; 32];
+
+    #[test]
+    fn verifies_forwarded_auth() {
+        let anchor = route_auth_anchor(&SECRET, "example.alice");
+        let auth = route_auth(&SECRET, "example.alice");
+        assert!(verify_route_auth(&auth, 0, &anchor));
+        assert!(verify_route_auth(&hash(&auth), 1, &anchor));
+        assert!(verify_route_auth(&hash_times(&auth, 5), 5, &anchor));
+    }
+
+    #[test]
+    fn rejects_forged_or_shortened_auth() {
+        let anchor = route_auth_anchor(&SECRET, "example.alice");
+        let auth = route_auth(&SECRET, "example.alice");
+        // A node cannot remove itself from the path
+        assert!(!verify_route_auth(&hash(&auth), 0, &anchor));
+        assert!(!verify_route_auth(&[0; 32], 0, &anchor));
+        assert!(!verify_route_auth(
+            &route_auth(&[8; 32], "example.alice"),
+            0,
+            &anchor
+        ));
+        assert!(!verify_route_auth(
+            &route_auth(&SECRET, "example.bob"),
+            0,
+            &anchor
+        ));
+        assert!(!verify_route_auth(&auth, MAX_ROUTE_AUTH_HOPS + 1, &anchor));
+    }
+}
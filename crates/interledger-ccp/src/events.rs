@@ -0,0 +1,50 @@
+use interledger_service::Username;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// The kind of change made to the route for a prefix
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingEventKind {
+    /// There was no route for the prefix before
+    Added,
+    /// There is no route for the prefix anymore
+    Withdrawn,
+    /// The route for the prefix now goes through a different next hop
+    Replaced,
+}
+
+/// Where the route for a prefix comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteSource {
+    /// The route was learned from a peer over CCP
+    Ccp,
+    /// The route was configured by the node operator
+    Static,
+    /// The route goes to an account on our node
+    Local,
+}
+
+/// A change to the node's routing table
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingEvent {
+    pub kind: RoutingEventKind,
+    pub prefix: String,
+    /// The username of the account the route goes through. For withdrawn
+    /// routes, this is the account the route went through before
+    pub next_hop: Option<Username>,
+    pub source: RouteSource,
+    /// The epoch of the CCP forwarding table the change was made in, if the
+    /// change was made by the `CcpRouteManager`
+    pub epoch: Option<u32>,
+}
+
+/// Store trait for publishing and subscribing to changes to the routing table
+pub trait RoutingEventsStore {
+    /// Publishes the event to all of the store's routing event subscribers
+    fn publish_routing_event(&self, event: RoutingEvent);
+
+    /// Subscribes to the store's routing event publisher
+    fn routing_event_subscription(&self) -> broadcast::Receiver<RoutingEvent>;
+}
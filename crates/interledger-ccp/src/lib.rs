@@ -16,16 +16,29 @@ use std::collections::HashMap;
 use std::{fmt, str::FromStr};
 use uuid::Uuid;
 
+mod aggregation;
+mod auth;
+mod events;
 #[cfg(test)]
 mod fixtures;
 mod packet;
+mod policy;
 mod routing_table;
+mod selection;
 mod server;
+mod state;
 #[cfg(test)]
 mod test_helpers;
 
+pub use auth::{route_auth, route_auth_anchor, MAX_ROUTE_AUTH_HOPS};
+pub use events::{RouteSource, RoutingEvent, RoutingEventKind, RoutingEventsStore};
 pub use packet::{Mode, RouteControlRequest};
+pub use policy::{RouteExportPolicy, RouteImportPolicy, RoutingPolicy};
+pub use selection::{
+    DefaultRouteSelector, RoundTripTimes, RouteCandidate, RouteMetrics, RouteSelector,
+};
 pub use server::{CcpRouteManager, CcpRouteManagerBuilder};
+pub use state::CcpRoutingState;
 
 use serde::{Deserialize, Serialize};
 
@@ -103,6 +116,11 @@ pub trait CcpRoutingAccount: Account {
         self.routing_relation() == RoutingRelation::Parent
             || self.routing_relation() == RoutingRelation::Peer
     }
+
+    /// The policy applied to the routes we exchange with this account, if any
+    fn routing_policy(&self) -> Option<&RoutingPolicy> {
+        None
+    }
 }
 
 // key = Bytes, key should be Address -- TODO
@@ -137,6 +155,28 @@ pub trait CcpRoutingStore: Clone {
         &mut self,
         routes: impl IntoIterator<Item = (String, Self::Account)> + Send + 'async_trait,
     ) -> Result<(), CcpRoutingStoreError>;
+
+    /// Gets the metrics used to rank the routes learned from the given accounts.
+    /// Accounts without metrics may be left out of the result
+    async fn get_route_metrics(
+        &self,
+        _account_ids: Vec<Uuid>,
+    ) -> Result<HashMap<Uuid, RouteMetrics>, CcpRoutingStoreError> {
+        Ok(HashMap::new())
+    }
+
+    /// Saves the routing state so that it can be restored after a restart
+    async fn save_routing_state(
+        &mut self,
+        _state: CcpRoutingState,
+    ) -> Result<(), CcpRoutingStoreError> {
+        Ok(())
+    }
+
+    /// Loads the routing state saved before the last restart, if any
+    async fn load_routing_state(&self) -> Result<Option<CcpRoutingState>, CcpRoutingStoreError> {
+        Ok(None)
+    }
 }
 
 #[cfg(test)]
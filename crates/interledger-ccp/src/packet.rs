@@ -5,6 +5,7 @@ use interledger_packet::{
     Address, AddressError, Fulfill, FulfillBuilder, OerError, Prepare, PrepareBuilder,
 };
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use std::{
     convert::{TryFrom, TryInto},
     fmt::{self, Debug},
@@ -247,7 +248,7 @@ impl From<RouteControlRequest> for Prepare {
     }
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub(crate) struct RouteProp {
     pub(crate) is_optional: bool,
     pub(crate) is_transitive: bool,
@@ -317,7 +318,7 @@ impl RouteProp {
     }
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct Route {
     // TODO switch this to use the Address type so we don't need separate parsing logic when implementing Debug
     pub(crate) prefix: String,
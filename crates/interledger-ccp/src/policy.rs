@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+/// Policies applied to the routes exchanged with a specific account over CCP.
+///
+/// Prefix lists match the given prefix itself and every prefix under it, so
+/// `example.foo` matches `example.foo` and `example.foo.bar` but not `example.foobar`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutingPolicy {
+    /// Policy applied to the routes the account broadcasts to us
+    pub import: RouteImportPolicy,
+    /// Policy applied to the routes we broadcast to the account
+    pub export: RouteExportPolicy,
+}
+
+/// Policy applied to the routes we receive from an account
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RouteImportPolicy {
+    /// If not empty, only routes for prefixes matching one of these are accepted
+    pub allow_prefixes: Vec<String>,
+    /// Routes for prefixes matching one of these are ignored, even if they are also allowed
+    pub deny_prefixes: Vec<String>,
+    /// The maximum number of prefixes we will accept routes for from the account.
+    /// New prefixes beyond this limit are ignored
+    pub max_prefixes: Option<usize>,
+    /// Routes with paths longer than this are ignored
+    pub max_path_length: Option<usize>,
+    /// The cost of the routes learned from the account. When picking the best route
+    /// for a prefix, routes with lower costs are preferred
+    pub cost: u32,
+}
+
+/// Policy applied to the routes we broadcast to an account
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RouteExportPolicy {
+    /// If not empty, only routes for prefixes matching one of these are broadcast
+    pub allow_prefixes: Vec<String>,
+    /// Routes for prefixes matching one of these are broadcast as withdrawn, even if they are also allowed
+    pub deny_prefixes: Vec<String>,
+    /// Routes for prefixes matching one of these are broadcast as a single
+    /// route for the aggregate prefix instead
+    pub aggregate_prefixes: Vec<String>,
+}
+
+impl RouteImportPolicy {
+    /// Returns true if a route for the given prefix and path length should be accepted.
+    /// This does not take the maximum number of prefixes into account
+    pub fn accepts(&self, prefix: &str, path_length: usize) -> bool {
+        allows_prefix(&self.allow_prefixes, &self.deny_prefixes, prefix)
+            && self
+                .max_path_length
+                .map(|max| path_length <= max)
+                .unwrap_or(true)
+    }
+}
+
+impl RouteExportPolicy {
+    /// Returns true if a route for the given prefix may be broadcast
+    pub fn allows(&self, prefix: &str) -> bool {
+        allows_prefix(&self.allow_prefixes, &self.deny_prefixes, prefix)
+    }
+
+    /// Returns the aggregate prefix the given prefix should be broadcast as, if any
+    pub fn aggregate_for(&self, prefix: &str) -> Option<&str> {
+        self.aggregate_prefixes
+            .iter()
+            .find(|aggregate| prefix_matches(prefix, aggregate))
+            .map(|aggregate| aggregate.as_str())
+    }
+}
+
+fn allows_prefix(allow: &[String], deny: &[String], prefix: &str) -> bool {
+    (allow.is_empty() || allow.iter().any(|filter| prefix_matches(prefix, filter)))
+        && !deny.iter().any(|filter| prefix_matches(prefix, filter))
+}
+
+/// Returns true if the prefix is the same as or is under the filter prefix
+pub(crate) fn prefix_matches(prefix: &str, filter: &str) -> bool {
+    if !prefix.starts_with(filter) {
+        return false;
+    }
+    prefix.len() == filter.len() || filter.ends_with('.') || prefix[filter.len()..].starts_with('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_prefixes_on_segment_boundaries() {
+        assert!(prefix_matches("example.foo", "example.foo"));
+        assert!(prefix_matches("example.foo.bar", "example.foo"));
+        assert!(prefix_matches("example.foo.bar", "example."));
+        assert!(!prefix_matches("example.foobar", "example.foo"));
+        assert!(!prefix_matches("example.fo", "example.foo"));
+    }
+
+    #[test]
+    fn import_policy_filters_prefixes_and_path_length() {
+        let policy = RouteImportPolicy {
+            allow_prefixes: vec!["example.allowed".to_string()],
+            deny_prefixes: vec!["example.allowed.denied".to_string()],
+            max_prefixes: None,
+            max_path_length: Some(2),
+            cost: 0,
+        };
+        assert!(policy.accepts("example.allowed.a", 2));
+        assert!(!policy.accepts("example.allowed.a", 3));
+        assert!(!policy.accepts("example.allowed.denied.a", 1));
+        assert!(!policy.accepts("example.other", 1));
+        assert!(RouteImportPolicy::default().accepts("example.other", 100));
+    }
+
+    #[test]
+    fn export_policy_aggregates_prefixes() {
+        let policy = RouteExportPolicy {
+            allow_prefixes: Vec::new(),
+            deny_prefixes: vec!["example.private".to_string()],
+            aggregate_prefixes: vec!["example.region".to_string()],
+        };
+        assert!(policy.allows("example.region.a"));
+        assert!(!policy.allows("example.private.a"));
+        assert_eq!(
+            policy.aggregate_for("example.region.a"),
+            Some("example.region")
+        );
+        assert_eq!(policy.aggregate_for("example.regional"), None);
+    }
+
+    #[test]
+    fn deserializes_partial_policies() {
+        let policy: RoutingPolicy =
+            serde_json::from_str(r#"{"import":{"max_prefixes":10}}"#).unwrap();
+        assert_eq!(policy.import.max_prefixes, Some(10));
+        assert_eq!(policy.export, RouteExportPolicy::default());
+    }
+}
@@ -27,6 +27,10 @@ impl<T> PrefixMap<T> {
         self.map.remove(prefix).is_some()
     }
 
+    pub fn get(&self, prefix: &str) -> Option<&T> {
+        self.map.get(prefix)
+    }
+
     pub fn resolve(&self, prefix: &str) -> Option<&T> {
         // TODO use parallel iterator
         self.map
@@ -66,7 +70,6 @@ where
         self.epoch = 0;
     }
 
-    #[cfg(test)]
     pub(crate) fn set_epoch(&mut self, epoch: u32) {
         self.epoch = epoch;
     }
@@ -107,6 +110,21 @@ where
         self.prefix_map.resolve(prefix)
     }
 
+    /// Get the route for exactly the given prefix
+    pub(crate) fn get_exact_route(&self, prefix: &str) -> Option<&(A, Route)> {
+        self.prefix_map.get(prefix)
+    }
+
+    /// Iterate over all of the routes in this table
+    pub(crate) fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.prefix_map.map.values().map(|(_account, route)| route)
+    }
+
+    /// Iterate over all of the routes in this table along with the account each one goes through
+    pub(crate) fn accounts_and_routes(&self) -> impl Iterator<Item = &(A, Route)> {
+        self.prefix_map.map.values()
+    }
+
     pub(crate) fn get_simplified_table(&self) -> HashMap<String, A> {
         self.prefix_map
             .map
@@ -0,0 +1,127 @@
+use crate::{CcpRoutingAccount, RoutingRelation};
+use interledger_packet::Address;
+use interledger_service::Username;
+use std::cmp::Ordering;
+use uuid::Uuid;
+
+/// Information about an account the store reports for ranking the routes learned from it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RouteMetrics {
+    /// The round trip time expected for packets sent to the account, in milliseconds
+    pub expected_round_trip_time: Option<u32>,
+    /// How much more can be forwarded to the account before its balance reaches its
+    /// settle threshold, in the account's asset, or `None` if it is not limited
+    pub balance_headroom: Option<u64>,
+}
+
+/// Reports the round trip times measured to accounts, for example the latency of the
+/// WebSocket Pings on their BTP connections. Measured round trip times take precedence
+/// over the ones the store reports in the [RouteMetrics](./struct.RouteMetrics.html)
+pub trait RoundTripTimes: Send + Sync {
+    /// Returns the round trip time last measured to the account, in milliseconds
+    fn measured_round_trip_time(&self, account_id: Uuid) -> Option<u32>;
+}
+
+impl<F> RoundTripTimes for F
+where
+    F: Fn(Uuid) -> Option<u32> + Send + Sync,
+{
+    fn measured_round_trip_time(&self, account_id: Uuid) -> Option<u32> {
+        (self)(account_id)
+    }
+}
+
+/// A route learned from a peer that is being considered as the best route for a prefix
+#[derive(Clone, Debug)]
+pub struct RouteCandidate<'a> {
+    /// The ID of the account the route was learned from
+    pub account_id: Uuid,
+    /// The username of the account the route was learned from
+    pub username: &'a Username,
+    /// The ILP address of the account the route was learned from
+    pub ilp_address: &'a Address,
+    /// The routing relation of the account the route was learned from
+    pub relation: RoutingRelation,
+    /// The cost configured in the import policy of the account the route was learned from
+    pub cost: u32,
+    /// The ILP addresses of the nodes the route goes through
+    pub path: &'a [String],
+    /// The metrics the store reported for the account the route was learned from
+    pub metrics: RouteMetrics,
+}
+
+impl<'a> RouteCandidate<'a> {
+    pub(crate) fn new<A: CcpRoutingAccount>(
+        account: &'a A,
+        path: &'a [String],
+        metrics: Option<&RouteMetrics>,
+    ) -> Self {
+        RouteCandidate {
+            account_id: account.id(),
+            username: account.username(),
+            ilp_address: account.ilp_address(),
+            relation: account.routing_relation(),
+            cost: account
+                .routing_policy()
+                .map(|policy| policy.import.cost)
+                .unwrap_or(0),
+            path,
+            metrics: metrics.cloned().unwrap_or_default(),
+        }
+    }
+}
+
+/// Ranks the routes learned from peers when picking the best route for a prefix.
+/// Configured and local routes always take precedence over the routes learned from peers.
+pub trait RouteSelector: Send + Sync {
+    /// Compares two candidate routes for the same prefix.
+    /// Returns `Ordering::Less` if `a` is the better route.
+    fn compare(&self, a: &RouteCandidate, b: &RouteCandidate) -> Ordering;
+}
+
+impl<F> RouteSelector for F
+where
+    F: Fn(&RouteCandidate, &RouteCandidate) -> Ordering + Send + Sync,
+{
+    fn compare(&self, a: &RouteCandidate, b: &RouteCandidate) -> Ordering {
+        (self)(a, b)
+    }
+}
+
+/// The route selection used unless another one is configured.
+///
+/// Routes are ranked by, in order:
+/// 1. The lowest cost configured for the account
+/// 2. The routing relation (child > peer > parent)
+/// 3. The shortest path
+/// 4. The most balance headroom left toward the account (accounts without a limit come first)
+/// 5. The lowest expected round trip time to the account (accounts without one come last)
+/// 6. The account ID, so that the choice is deterministic
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultRouteSelector;
+
+impl RouteSelector for DefaultRouteSelector {
+    fn compare(&self, a: &RouteCandidate, b: &RouteCandidate) -> Ordering {
+        a.cost
+            .cmp(&b.cost)
+            .then_with(|| b.relation.cmp(&a.relation))
+            .then_with(|| a.path.len().cmp(&b.path.len()))
+            .then_with(|| balance_headroom(b).cmp(&balance_headroom(a)))
+            .then_with(|| {
+                match (
+                    a.metrics.expected_round_trip_time,
+                    b.metrics.expected_round_trip_time,
+                ) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                }
+            })
+            .then_with(|| a.account_id.cmp(&b.account_id))
+    }
+}
+
+fn balance_headroom(candidate: &RouteCandidate) -> u64 {
+    candidate.metrics.balance_headroom.unwrap_or(u64::MAX)
+}
@@ -1,10 +1,18 @@
 use crate::{
+    aggregation::{aggregate_routes, aggregation_root},
+    auth::{hash, route_auth, verify_route_auth},
     packet::{
         Mode, Route, RouteControlRequest, RouteUpdateRequest, CCP_CONTROL_DESTINATION,
         CCP_RESPONSE, CCP_UPDATE_DESTINATION,
     },
+    policy::{prefix_matches, RouteExportPolicy, RouteImportPolicy},
     routing_table::RoutingTable,
-    CcpRoutingAccount, CcpRoutingStore, RoutingRelation,
+    selection::{
+        DefaultRouteSelector, RoundTripTimes, RouteCandidate, RouteMetrics, RouteSelector,
+    },
+    state::{CcpRoutingState, RoutingTableState},
+    CcpRoutingAccount, CcpRoutingStore, RouteSource, RoutingEvent, RoutingEventKind,
+    RoutingEventsStore, RoutingRelation,
 };
 use async_trait::async_trait;
 use futures::future::join_all;
@@ -15,18 +23,15 @@ use interledger_service::{
     OutgoingService,
 };
 use parking_lot::{Mutex, RwLock};
-use ring::digest::{digest, SHA256};
-use std::cmp::Ordering as StdOrdering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::{
-    cmp::min,
     convert::TryFrom,
     str,
     sync::{
-        atomic::{AtomicU32, Ordering},
+        atomic::{AtomicBool, AtomicU32, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tracing::{debug, error, trace, warn};
 use uuid::Uuid;
@@ -38,19 +43,16 @@ use futures::TryFutureExt;
 #[cfg(test)]
 use once_cell::sync::Lazy;
 
-// TODO should the route expiry be longer? we use 30 seconds now
+// TODO should the hold down time be longer? we use 30 seconds now
 // because the expiry shortener will lower the expiry to 30 seconds
 // otherwise. we could make it longer and make sure the BTP server
 // comes after the expiry shortener
-const DEFAULT_ROUTE_EXPIRY_TIME: u32 = 30000;
+const DEFAULT_HOLD_DOWN_TIME: u32 = 30000;
 const DEFAULT_BROADCAST_INTERVAL: u64 = 30000;
 const DUMMY_ROUTING_TABLE_ID: [u8; 16] = [0; 16];
-
-fn hash(preimage: &[u8; 32]) -> [u8; 32] {
-    let mut out = [0; 32];
-    out.copy_from_slice(digest(&SHA256, preimage).as_ref());
-    out
-}
+/// How many of the latest forwarding table updates are saved in the routing state.
+/// After a restart, peers which last saw an earlier epoch are sent the whole table
+const MAX_SAVED_FORWARDING_TABLE_UPDATES: usize = 1000;
 
 type NewAndWithdrawnRoutes = (Vec<Route>, Vec<String>);
 
@@ -68,6 +70,23 @@ pub struct CcpRouteManagerBuilder<I, O, S> {
     store: S,
     ilp_address: Address,
     broadcast_interval: u64,
+    /// The hold down time (in milliseconds) we advertise to peers in our Route Update Requests.
+    hold_down_time: u32,
+    /// How long (in milliseconds) we keep the routes learned from a peer after its last
+    /// Route Update Request. If not set, the hold down time advertised by the peer is used.
+    route_expiry_time: Option<u64>,
+    /// Ranks the routes learned from peers when picking the best route for a prefix
+    route_selector: Arc<dyn RouteSelector>,
+    /// The round trip times measured to peers, if any
+    round_trip_times: Option<Arc<dyn RoundTripTimes>>,
+    /// The secret used to authenticate the route we advertise for our own address
+    route_auth_secret: Option<[u8; 32]>,
+    /// Maps protected prefixes to the anchors their routes are verified against
+    protected_prefixes: HashMap<String, [u8; 32]>,
+    /// Whether routes that end at our node are aggregated before being broadcast
+    route_aggregation: bool,
+    /// Where the changes to the routing table are published, if anywhere
+    routing_events: Option<Arc<dyn RoutingEventsStore + Send + Sync>>,
 }
 
 impl<I, O, S, A> CcpRouteManagerBuilder<I, O, S>
@@ -84,6 +103,14 @@ where
             outgoing,
             store,
             broadcast_interval: DEFAULT_BROADCAST_INTERVAL,
+            hold_down_time: DEFAULT_HOLD_DOWN_TIME,
+            route_expiry_time: None,
+            route_selector: Arc::new(DefaultRouteSelector),
+            round_trip_times: None,
+            route_auth_secret: None,
+            protected_prefixes: HashMap::new(),
+            route_aggregation: false,
+            routing_events: None,
         }
     }
 
@@ -98,6 +125,73 @@ where
         self
     }
 
+    /// Set the hold down time (in milliseconds) advertised to peers, which tells
+    /// them how long to keep our routes if they don't hear from us again
+    pub fn hold_down_time(&mut self, ms: u32) -> &mut Self {
+        self.hold_down_time = ms;
+        self
+    }
+
+    /// Set how long (in milliseconds) routes learned from a peer are kept after the
+    /// last Route Update Request we got from it, instead of using the peer's hold down time
+    pub fn route_expiry_time(&mut self, ms: u64) -> &mut Self {
+        self.route_expiry_time = Some(ms);
+        self
+    }
+
+    /// Set how the routes learned from peers are ranked when picking the best route
+    /// for a prefix. Defaults to the [DefaultRouteSelector](./struct.DefaultRouteSelector.html)
+    pub fn route_selector<R>(&mut self, route_selector: R) -> &mut Self
+    where
+        R: RouteSelector + 'static,
+    {
+        self.route_selector = Arc::new(route_selector);
+        self
+    }
+
+    /// Rank routes using the round trip times measured to peers where there are any,
+    /// instead of the round trip times the store reports for them
+    pub fn round_trip_times<R>(&mut self, round_trip_times: R) -> &mut Self
+    where
+        R: RoundTripTimes + 'static,
+    {
+        self.round_trip_times = Some(Arc::new(round_trip_times));
+        self
+    }
+
+    /// Set the secret the auth value of the route for our own address is derived from.
+    /// Peers configured with the matching [anchor](./fn.route_auth_anchor.html) will only
+    /// accept routes for our address that were originally advertised by us
+    pub fn route_auth_secret(&mut self, secret: [u8; 32]) -> &mut Self {
+        self.route_auth_secret = Some(secret);
+        self
+    }
+
+    /// Only accept routes for the given prefix (or any prefix under it) if their auth value
+    /// matches the [anchor](./fn.route_auth_anchor.html) published by the prefix owner
+    pub fn protected_prefix(&mut self, prefix: String, anchor: [u8; 32]) -> &mut Self {
+        self.protected_prefixes.insert(prefix, anchor);
+        self
+    }
+
+    /// Publish the changes the route manager makes to the routing table to the given store.
+    /// Nothing is published by default
+    pub fn routing_events<E>(&mut self, routing_events: E) -> &mut Self
+    where
+        E: RoutingEventsStore + Send + Sync + 'static,
+    {
+        self.routing_events = Some(Arc::new(routing_events));
+        self
+    }
+
+    /// Set whether the routes that end at our node are aggregated before being broadcast.
+    /// When all of the routes under a prefix go to accounts on our node, we advertise a
+    /// single route for that prefix instead of one for each account. Disabled by default
+    pub fn route_aggregation(&mut self, enabled: bool) -> &mut Self {
+        self.route_aggregation = enabled;
+        self
+    }
+
     pub fn to_service(&self) -> CcpRouteManager<I, O, S, A> {
         #[allow(clippy::let_and_return)]
         let service = CcpRouteManager {
@@ -107,10 +201,26 @@ where
             store: self.store.clone(),
             forwarding_table: Arc::new(RwLock::new(RoutingTable::default())),
             forwarding_table_updates: Arc::new(RwLock::new(Vec::new())),
+            forwarding_table_updates_start: Arc::new(AtomicU32::new(0)),
+            saved_forwarding_epoch: Arc::new(AtomicU32::new(0)),
             last_epoch_updates_sent_for: Arc::new(AtomicU32::new(0)),
             local_table: Arc::new(RwLock::new(RoutingTable::default())),
             incoming_tables: Arc::new(RwLock::new(HashMap::new())),
             unavailable_accounts: Arc::new(Mutex::new(HashMap::new())),
+            peer_modes: Arc::new(Mutex::new(HashMap::new())),
+            incoming_table_expiries: Arc::new(Mutex::new(HashMap::new())),
+            stale_incoming_tables: Arc::new(Mutex::new(HashSet::new())),
+            routing_state_changed: Arc::new(AtomicBool::new(false)),
+            local_route_sources: Arc::new(Mutex::new(HashMap::new())),
+            broadcast_interval: self.broadcast_interval,
+            hold_down_time: self.hold_down_time,
+            route_expiry_time: self.route_expiry_time,
+            route_selector: self.route_selector.clone(),
+            round_trip_times: self.round_trip_times.clone(),
+            route_auth_secret: self.route_auth_secret,
+            protected_prefixes: Arc::new(self.protected_prefixes.clone()),
+            route_aggregation: self.route_aggregation,
+            routing_events: self.routing_events.clone(),
         };
 
         #[cfg(not(test))]
@@ -158,8 +268,16 @@ pub struct CcpRouteManager<I, O, S, A: Account> {
     /// It is the same as the local_table with our own address added to the path of each route.
     forwarding_table: Arc<RwLock<RoutingTable<A>>>,
     last_epoch_updates_sent_for: Arc<AtomicU32>,
-    /// These updates are stored such that index 0 is the transition from epoch 0 to epoch 1
+    /// These updates are stored such that index 0 is the transition from
+    /// epoch `forwarding_table_updates_start` to the epoch after it
     forwarding_table_updates: Arc<RwLock<Vec<NewAndWithdrawnRoutes>>>,
+    /// The epoch the first of the forwarding table updates starts from. This is only above 0
+    /// if the forwarding table was restored without the updates of its earliest epochs
+    forwarding_table_updates_start: Arc<AtomicU32>,
+    /// The forwarding table epoch in the routing state that was saved last. Later epochs
+    /// are saved before peers are told about them, so that a restarted node doesn't
+    /// reuse epochs our peers already know for different routes
+    saved_forwarding_epoch: Arc<AtomicU32>,
     /// This is the routing table we have compile from configuration and
     /// broadcasts we have received from our peers. It is saved to the Store so that
     /// the Router services forwards packets according to what it says.
@@ -175,6 +293,29 @@ pub struct CcpRouteManager<I, O, S, A: Account> {
     /// This maps the account ID to the number of route brodcast intervals
     /// we should wait before trying again
     unavailable_accounts: Arc<Mutex<HashMap<Uuid, BackoffParams>>>,
+    /// The mode each peer asked for in its last Route Control Request.
+    /// Peers in Idle mode are skipped in the route update broadcasts.
+    peer_modes: Arc<Mutex<HashMap<Uuid, Mode>>>,
+    /// When the incoming routing table of each peer expires if we don't get
+    /// another Route Update Request from it
+    incoming_table_expiries: Arc<Mutex<HashMap<Uuid, Instant>>>,
+    /// The peers whose incoming routing tables were restored from the store after a restart
+    /// and that have not sent us a Route Update Request since then
+    stale_incoming_tables: Arc<Mutex<HashSet<Uuid>>>,
+    /// Whether the incoming routing tables changed since the routing state was last saved
+    routing_state_changed: Arc<AtomicBool>,
+    /// Where each route in the local table comes from, so that
+    /// the routing events for withdrawn routes can include it
+    local_route_sources: Arc<Mutex<HashMap<String, RouteSource>>>,
+    broadcast_interval: u64,
+    hold_down_time: u32,
+    route_expiry_time: Option<u64>,
+    route_selector: Arc<dyn RouteSelector>,
+    round_trip_times: Option<Arc<dyn RoundTripTimes>>,
+    route_auth_secret: Option<[u8; 32]>,
+    protected_prefixes: Arc<HashMap<String, [u8; 32]>>,
+    route_aggregation: bool,
+    routing_events: Option<Arc<dyn RoutingEventsStore + Send + Sync>>,
 }
 
 impl<I, O, S, A> CcpRouteManager<I, O, S, A>
@@ -187,6 +328,9 @@ where
     /// Returns a future that will trigger this service to update its routes and broadcast
     /// updates to peers on the given interval. `interval` is in milliseconds
     pub async fn start_broadcast_interval(&self, interval: u64) {
+        if let Err(err) = self.restore_routing_state().await {
+            warn!("Error restoring the saved routing state: {:?}", err);
+        }
         self.request_all_routes().await;
         let mut interval = tokio::time::interval(Duration::from_millis(interval));
         loop {
@@ -213,20 +357,262 @@ where
     }
 
     pub async fn broadcast_routes(&self) -> Result<(), CcpRoutingStoreError> {
+        self.expire_incoming_tables().await?;
         self.update_best_routes(None).await?;
+        // Saving once per broadcast interval, rather than on every update we get,
+        // bounds how often the whole state is written to the store
+        let incoming_tables_changed = self.routing_state_changed.swap(false, Ordering::SeqCst);
+        if incoming_tables_changed || self.forwarding_table_unsaved() {
+            self.save_routing_state().await;
+        }
         self.send_route_updates().await
     }
 
+    /// Whether the forwarding table has epochs that were not saved yet
+    fn forwarding_table_unsaved(&self) -> bool {
+        self.forwarding_table.read().epoch() != self.saved_forwarding_epoch.load(Ordering::SeqCst)
+    }
+
+    /// Remove the incoming routing tables of peers we haven't gotten a Route Update Request
+    /// from before their routes expired and withdraw the routes we had learned from them.
+    async fn expire_incoming_tables(&self) -> Result<(), CcpRoutingStoreError> {
+        let expired: Vec<Uuid> = {
+            let now = Instant::now();
+            let mut expiries = self.incoming_table_expiries.lock();
+            let expired: Vec<Uuid> = expiries
+                .iter()
+                .filter(|(_, expires_at)| **expires_at <= now)
+                .map(|(id, _)| *id)
+                .collect();
+            for id in expired.iter() {
+                expiries.remove(id);
+            }
+            expired
+        };
+        if expired.is_empty() {
+            return Ok(());
+        }
+        // Forget everything else we know about these peers too, so the state of
+        // accounts that were deleted or stopped peering doesn't pile up
+        {
+            let mut peer_modes = self.peer_modes.lock();
+            let mut stale_incoming_tables = self.stale_incoming_tables.lock();
+            for id in expired.iter() {
+                peer_modes.remove(id);
+                stale_incoming_tables.remove(id);
+            }
+        }
+
+        let mut prefixes: Vec<String> = {
+            let mut incoming_tables = self.incoming_tables.write();
+            expired
+                .iter()
+                .filter_map(|id| incoming_tables.remove(id))
+                .inspect(|_| self.routing_state_changed.store(true, Ordering::SeqCst))
+                .flat_map(|table| {
+                    table
+                        .routes()
+                        .map(|route| route.prefix.clone())
+                        .collect::<Vec<String>>()
+                })
+                .collect()
+        };
+        prefixes.sort_unstable();
+        prefixes.dedup();
+        debug!(
+            "Routes from accounts {:?} expired, recalculating best routes for prefixes: {}",
+            expired,
+            prefixes.join(", ")
+        );
+
+        if prefixes.is_empty() {
+            Ok(())
+        } else {
+            self.update_best_routes(Some(prefixes)).await
+        }
+    }
+
+    /// Restore the routing state saved before the last restart.
+    ///
+    /// Our forwarding table is restored with its ID, epoch and the saved updates, so our
+    /// peers keep synchronizing it incrementally. Routes through accounts that were deleted
+    /// in the meantime are withdrawn in a new epoch. The forwarding table is only restored
+    /// if it has not changed since we started.
+    ///
+    /// The routing tables restored for peers are marked as stale until the peers send us a
+    /// Route Update Request again. Their routes are still used in the meantime, unless another
+    /// peer has a route for the same prefix, and they expire like any other routes if the
+    /// peers don't refresh them. Peers that already sent us their routes since we started
+    /// keep the tables built from those.
+    async fn restore_routing_state(&self) -> Result<(), CcpRoutingStoreError> {
+        let state = match self.store.load_routing_state().await? {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        let (local_routes, configured_routes) =
+            self.store.get_local_and_configured_routes().await?;
+        let peers = self.store.get_accounts_to_receive_routes_from().await?;
+        let peer_ids: HashSet<Uuid> = peers.iter().map(|account| account.id()).collect();
+        let accounts: HashMap<Uuid, A> = local_routes
+            .into_iter()
+            .chain(configured_routes)
+            .map(|(_prefix, account)| account)
+            .chain(peers)
+            .map(|account| (account.id(), account))
+            .collect();
+
+        if let Some(forwarding_table) = state.forwarding_table {
+            self.restore_forwarding_table(
+                forwarding_table,
+                state.forwarding_table_updates_start,
+                state.forwarding_table_updates,
+                &accounts,
+            );
+        }
+
+        let route_expiry_time = self
+            .route_expiry_time
+            .unwrap_or_else(|| u64::from(DEFAULT_HOLD_DOWN_TIME) + self.broadcast_interval);
+        let expires_at = Instant::now() + Duration::from_millis(route_expiry_time);
+        let mut restored: Vec<Uuid> = Vec::new();
+        let mut prefixes: Vec<String> = Vec::new();
+        {
+            let mut incoming_tables = self.incoming_tables.write();
+            let mut stale_incoming_tables = self.stale_incoming_tables.lock();
+            let mut expiries = self.incoming_table_expiries.lock();
+            for (account_id, table) in state.incoming_tables {
+                if !peer_ids.contains(&account_id) || incoming_tables.contains_key(&account_id) {
+                    continue;
+                }
+                prefixes.extend(table.routes.iter().map(|(_, route)| route.prefix.clone()));
+                incoming_tables.insert(account_id, table.into_table(&accounts));
+                stale_incoming_tables.insert(account_id);
+                expiries.insert(account_id, expires_at);
+                restored.push(account_id);
+            }
+        }
+        debug!("Restored the routing tables of accounts: {:?}", restored);
+        prefixes.sort_unstable();
+        prefixes.dedup();
+
+        if prefixes.is_empty() {
+            Ok(())
+        } else {
+            self.update_best_routes(Some(prefixes)).await
+        }
+    }
+
+    fn restore_forwarding_table(
+        &self,
+        saved: RoutingTableState,
+        mut updates_start: u32,
+        mut updates: Vec<NewAndWithdrawnRoutes>,
+        accounts: &HashMap<Uuid, A>,
+    ) {
+        let mut forwarding_table = self.forwarding_table.write();
+        let mut forwarding_table_updates = self.forwarding_table_updates.write();
+        if forwarding_table.epoch() != 0 {
+            debug!("Forwarding routing table changed since starting, not restoring the saved one");
+            return;
+        }
+
+        let saved_epoch = saved.epoch;
+        if updates_start as usize + updates.len() != saved_epoch as usize {
+            warn!(
+                "Saved forwarding routing table is at epoch {} but has the updates of epochs {} to {}, sending peers that need earlier epochs the whole table",
+                saved_epoch,
+                updates_start,
+                updates_start as usize + updates.len()
+            );
+            updates_start = saved_epoch;
+            updates.clear();
+        }
+        let saved_prefixes: Vec<String> = saved
+            .routes
+            .iter()
+            .map(|(_, route)| route.prefix.clone())
+            .collect();
+        let mut table = saved.into_table(accounts);
+        // Our peers still have the routes through accounts that are gone
+        let withdrawn_routes: Vec<String> = saved_prefixes
+            .into_iter()
+            .filter(|prefix| table.get_exact_route(prefix).is_none())
+            .collect();
+        if !withdrawn_routes.is_empty() {
+            table.increment_epoch();
+            updates.push((Vec::new(), withdrawn_routes));
+        }
+        debug!(
+            "Restored forwarding routing table {:?} at epoch {}",
+            HexString(&table.id()[..]),
+            table.epoch()
+        );
+
+        *forwarding_table = table;
+        *forwarding_table_updates = updates;
+        self.forwarding_table_updates_start
+            .store(updates_start, Ordering::SeqCst);
+        self.saved_forwarding_epoch
+            .store(saved_epoch, Ordering::SeqCst);
+        self.last_epoch_updates_sent_for
+            .store(saved_epoch, Ordering::SeqCst);
+    }
+
+    /// Save the routing tables to the store so they can be restored after a restart.
+    /// Errors are only logged since they don't affect routing until the node restarts
+    async fn save_routing_state(&self) {
+        let state = {
+            let forwarding_table = self.forwarding_table.read();
+            let forwarding_table_updates = self.forwarding_table_updates.read();
+            // Only the latest updates are saved, to bound the size of the state
+            let skip = forwarding_table_updates
+                .len()
+                .saturating_sub(MAX_SAVED_FORWARDING_TABLE_UPDATES);
+            CcpRoutingState {
+                forwarding_table: Some(RoutingTableState::from(&*forwarding_table)),
+                forwarding_table_updates_start: self
+                    .forwarding_table_updates_start
+                    .load(Ordering::SeqCst)
+                    + skip as u32,
+                forwarding_table_updates: forwarding_table_updates[skip..].to_vec(),
+                incoming_tables: self
+                    .incoming_tables
+                    .read()
+                    .iter()
+                    .map(|(account_id, table)| (*account_id, RoutingTableState::from(table)))
+                    .collect(),
+            }
+        };
+        let epoch = state
+            .forwarding_table
+            .as_ref()
+            .map(|table| table.epoch)
+            .unwrap_or(0);
+        match self.store.clone().save_routing_state(state).await {
+            Ok(()) => self.saved_forwarding_epoch.store(epoch, Ordering::SeqCst),
+            Err(err) => warn!("Error saving the routing state: {:?}", err),
+        }
+    }
+
     /// Request routes from all the peers we are willing to receive routes from.
     /// This is mostly intended for when the CCP server starts up and doesn't have any routes from peers.
+    /// If we restored the routing table of a peer, we only ask for the epochs we are missing.
     async fn request_all_routes(&self) {
         let result = self.store.get_accounts_to_receive_routes_from().await;
         let accounts = result.unwrap_or_else(|_| Vec::new());
-        join_all(
-            accounts
-                .into_iter()
-                .map(|account| self.send_route_control_request(account, DUMMY_ROUTING_TABLE_ID, 0)),
-        )
+        let last_known_epochs: HashMap<Uuid, ([u8; 16], u32)> = self
+            .incoming_tables
+            .read()
+            .iter()
+            .map(|(account_id, table)| (*account_id, (table.id(), table.epoch())))
+            .collect();
+        join_all(accounts.into_iter().map(|account| {
+            let (routing_table_id, epoch) = last_known_epochs
+                .get(&account.id())
+                .cloned()
+                .unwrap_or((DUMMY_ROUTING_TABLE_ID, 0));
+            self.send_route_control_request(account, routing_table_id, epoch)
+        }))
         .await;
     }
 
@@ -261,8 +647,19 @@ where
             control
         );
 
-        // TODO stop sending updates if they are in Idle mode
-        if control.mode == Mode::Sync {
+        let previous_mode = self
+            .peer_modes
+            .lock()
+            .insert(request.from.id(), control.mode);
+        if control.mode == Mode::Idle {
+            if previous_mode != Some(Mode::Idle) {
+                debug!(
+                    "Account {} (id: {}) requested Idle mode, will stop broadcasting routes to it",
+                    request.from.username(),
+                    request.from.id()
+                );
+            }
+        } else {
             // Don't skip them in the route update broadcasts anymore since this
             // tells us that they are online
             // TODO what happens if they can send to us but we can't send to them?
@@ -276,15 +673,23 @@ where
                 }
             }
 
+            if self.forwarding_table_unsaved() {
+                self.save_routing_state().await;
+            }
+
             let (from_epoch_index, to_epoch_index) = {
                 let forwarding_table = self.forwarding_table.read();
                 let to_epoch_index = forwarding_table.epoch();
-                let from_epoch_index =
-                    if control.last_known_routing_table_id != forwarding_table.id() {
-                        0
-                    } else {
-                        min(control.last_known_epoch, to_epoch_index)
-                    };
+                // A peer which knows a later epoch of our table than we do saw epochs
+                // we lost in a restart, so it gets the whole table again
+                let from_epoch_index = if control.last_known_routing_table_id
+                    != forwarding_table.id()
+                    || control.last_known_epoch > to_epoch_index
+                {
+                    0
+                } else {
+                    control.last_known_epoch
+                };
                 (from_epoch_index, to_epoch_index)
             };
 
@@ -336,6 +741,12 @@ where
                         route
                     );
                     false
+                } else if !self.is_authenticated(route) {
+                    warn!(
+                        "Got route broadcast for a protected prefix without a valid auth value: {:?}",
+                        route
+                    );
+                    false
                 } else {
                     true
                 }
@@ -344,6 +755,74 @@ where
         update
     }
 
+    /// Returns true if the route is not for a protected prefix or if its auth value matches
+    /// the anchor of the protected prefix. Routes for more specific prefixes under a
+    /// protected prefix cannot be authenticated and are only accepted if those prefixes
+    /// are protected themselves.
+    fn is_authenticated(&self, route: &Route) -> bool {
+        let protected_prefix = self
+            .protected_prefixes
+            .iter()
+            .filter(|(prefix, _)| prefix_matches(&route.prefix, prefix))
+            .max_by_key(|(prefix, _)| prefix.len());
+        match protected_prefix {
+            Some((prefix, anchor)) => {
+                prefix == &route.prefix && verify_route_auth(&route.auth, route.path.len(), anchor)
+            }
+            None => true,
+        }
+    }
+
+    /// Remove the routes that the import policy of the account we got this update from
+    /// does not accept. The prefixes of those routes are treated as withdrawn so that
+    /// any routes we accepted for them before the policy changed are removed as well.
+    fn apply_import_policy(
+        &self,
+        account_id: Uuid,
+        policy: &RouteImportPolicy,
+        mut update: RouteUpdateRequest,
+    ) -> RouteUpdateRequest {
+        let (mut accepted, rejected): (Vec<Route>, Vec<Route>) = update
+            .new_routes
+            .into_iter()
+            .partition(|route| policy.accepts(&route.prefix, route.path.len()));
+        let mut rejected: Vec<String> = rejected.into_iter().map(|route| route.prefix).collect();
+
+        if let Some(max_prefixes) = policy.max_prefixes {
+            // Routes for prefixes we already have from this account are always accepted,
+            // new prefixes only as long as we stay within the limit
+            let mut prefixes: HashSet<String> = self
+                .incoming_tables
+                .read()
+                .get(&account_id)
+                .map(|table| table.routes().map(|route| route.prefix.clone()).collect())
+                .unwrap_or_default();
+            for prefix in update.withdrawn_routes.iter() {
+                prefixes.remove(prefix);
+            }
+            accepted.retain(|route| {
+                if prefixes.contains(&route.prefix) || prefixes.len() < max_prefixes {
+                    prefixes.insert(route.prefix.clone());
+                    true
+                } else {
+                    rejected.push(route.prefix.clone());
+                    false
+                }
+            });
+        }
+
+        if !rejected.is_empty() {
+            debug!(
+                "Ignoring routes from account {} that are not accepted by its import policy: {}",
+                account_id,
+                rejected.join(", ")
+            );
+        }
+        update.new_routes = accepted;
+        update.withdrawn_routes.extend(rejected);
+        update
+    }
+
     /// Check if this Route Update Request is valid and, if so, apply any updates it contains.
     /// If updates are applied to the Incoming Routing Table for this peer, we will
     /// then check whether those routes are better than the current best ones we have in the
@@ -379,6 +858,15 @@ where
 
         // Filter out routes that don't make sense or that we won't accept
         let update = self.filter_routes(update);
+        let update = match request.from.routing_policy() {
+            Some(policy) => self.apply_import_policy(request.from.id(), &policy.import, update),
+            None => update,
+        };
+        let route_expiry_time = self.route_expiry_time.unwrap_or_else(|| {
+            // Give the peer an extra broadcast interval so its routes don't flap
+            // if its heartbeats arrive slightly later than its hold down time
+            u64::from(update.hold_down_time) + self.broadcast_interval
+        });
 
         // Ensure the mutex gets dropped before the async block
         let result = {
@@ -400,10 +888,23 @@ where
         // that were included in this route update.
         match result {
             Ok(prefixes_updated) => {
+                self.incoming_table_expiries.lock().insert(
+                    request.from.id(),
+                    Instant::now() + Duration::from_millis(route_expiry_time),
+                );
+                if self.stale_incoming_tables.lock().remove(&request.from.id()) {
+                    debug!(
+                        "Routing table restored for account {} (id: {}) is up to date again",
+                        request.from.username(),
+                        request.from.id()
+                    );
+                }
+
                 if prefixes_updated.is_empty() {
                     trace!("Route update request did not contain any prefixes we need to update our routes for");
                     return Ok(CCP_RESPONSE.clone());
                 }
+                self.routing_state_changed.store(true, Ordering::SeqCst);
 
                 debug!(
                     "Recalculating best routes for prefixes: {}",
@@ -531,6 +1032,30 @@ where
 
         let (local_routes, configured_routes) =
             self.store.get_local_and_configured_routes().await?;
+        let peer_ids: Vec<Uuid> = incoming_tables.read().keys().cloned().collect();
+        let mut route_metrics = self
+            .store
+            .get_route_metrics(peer_ids.clone())
+            .await
+            .unwrap_or_else(|err| {
+                warn!(
+                    "Error getting route metrics, ranking routes without them: {:?}",
+                    err
+                );
+                HashMap::new()
+            });
+        if let Some(ref round_trip_times) = self.round_trip_times {
+            for id in peer_ids {
+                if let Some(measured) = round_trip_times.measured_round_trip_time(id) {
+                    route_metrics
+                        .entry(id)
+                        .or_default()
+                        .expected_round_trip_time = Some(measured);
+                }
+            }
+        }
+
+        let stale_incoming_tables = self.stale_incoming_tables.lock().clone();
 
         // TODO: Should we extract this to a function and #[inline] it?
         let (better_routes, withdrawn_routes) = {
@@ -559,6 +1084,9 @@ where
                     &configured_routes,
                     &incoming_tables,
                     prefix,
+                    self.route_selector.as_ref(),
+                    &route_metrics,
+                    &stale_incoming_tables,
                 ) {
                     if let Some((ref next_account, ref _route)) = local_table.get_route(prefix) {
                         if next_account.id() == best_next_account.id() {
@@ -583,12 +1111,21 @@ where
 
         // Update the local and forwarding tables
         if !better_routes.is_empty() || !withdrawn_routes.is_empty() {
-            let update_routes = {
+            let (update_routes, events, epoch) = {
                 let mut local_table = local_table.write();
                 let mut forwarding_table = forwarding_table.write();
                 let mut forwarding_table_updates = forwarding_table_updates.write();
+                let mut local_route_sources = self.local_route_sources.lock();
 
                 let mut new_routes: Vec<Route> = Vec::with_capacity(better_routes.len());
+                let changed_prefixes: HashSet<&str> = better_routes
+                    .iter()
+                    .map(|(prefix, _, _)| *prefix)
+                    .chain(withdrawn_routes.iter().copied())
+                    .collect();
+                // The epoch is filled in once the forwarding table has been updated
+                let mut events: Vec<RoutingEvent> =
+                    Vec::with_capacity(better_routes.len() + withdrawn_routes.len());
 
                 for (prefix, account, mut route) in better_routes {
                     debug!(
@@ -597,27 +1134,33 @@ where
                         account.username(),
                         account.id(),
                     );
+                    let kind = if local_table.get_route(prefix).is_some() {
+                        RoutingEventKind::Replaced
+                    } else {
+                        RoutingEventKind::Added
+                    };
+                    let source = route_source(prefix, &local_routes, &configured_routes);
+                    local_route_sources.insert(prefix.to_string(), source);
+                    events.push(RoutingEvent {
+                        kind,
+                        prefix: prefix.to_string(),
+                        next_hop: Some(account.username().clone()),
+                        source,
+                        epoch: None,
+                    });
                     local_table.set_route(prefix.to_string(), account.clone(), route.clone());
 
-                    // Update the forwarding table
+                    // With route aggregation, the forwarding table is rebuilt below
+                    // once the local table is up to date
+                    if self.route_aggregation {
+                        continue;
+                    }
 
-                    // Don't advertise routes that don't start with the global prefix
-                    // or that advertise the whole global prefix
-                    let address_scheme = ilp_address.scheme();
-                    let correct_address_scheme =
-                        route.prefix.starts_with(address_scheme) && route.prefix != address_scheme;
-                    // We do want to advertise our address
-                    let is_our_address = route.prefix == &ilp_address as &str;
-                    // Don't advertise local routes because advertising only our address
-                    // will be enough to ensure the packet gets to us and we can route it
-                    // to the correct account on our node
-                    let is_local_route =
-                        route.prefix.starts_with(&ilp_address as &str) && route.path.is_empty();
-                    let not_local_route = is_our_address || !is_local_route;
+                    // Update the forwarding table
                     // Don't include routes we're also withdrawing
                     let not_withdrawn_route = !withdrawn_routes.contains(&prefix);
 
-                    if correct_address_scheme && not_local_route && not_withdrawn_route {
+                    if should_advertise(&route, &ilp_address) && not_withdrawn_route {
                         let old_route = forwarding_table.get_route(prefix);
                         if old_route.is_none() || old_route.unwrap().0.id() != account.id() {
                             route.path.insert(0, ilp_address.to_string());
@@ -634,29 +1177,75 @@ where
                 }
 
                 for prefix in withdrawn_routes.iter() {
+                    if let Some((account, _route)) = local_table.get_route(prefix) {
+                        events.push(RoutingEvent {
+                            kind: RoutingEventKind::Withdrawn,
+                            prefix: prefix.to_string(),
+                            next_hop: Some(account.username().clone()),
+                            source: local_route_sources
+                                .remove(*prefix)
+                                .unwrap_or(RouteSource::Ccp),
+                            epoch: None,
+                        });
+                    }
                     debug!("Removed route for prefix: {}", prefix);
                     local_table.delete_route(prefix);
-                    forwarding_table.delete_route(prefix);
+                    if !self.route_aggregation {
+                        forwarding_table.delete_route(prefix);
+                    }
                 }
 
-                let epoch = forwarding_table.increment_epoch();
-                forwarding_table_updates.push((
-                    new_routes,
+                let withdrawn_routes = if self.route_aggregation {
+                    let (aggregated_new_routes, aggregated_withdrawn_routes) =
+                        update_aggregated_forwarding_table(
+                            &local_table,
+                            &local_routes,
+                            &changed_prefixes,
+                            &mut forwarding_table,
+                            &ilp_address,
+                            self.route_auth_secret,
+                        );
+                    new_routes = aggregated_new_routes;
+                    aggregated_withdrawn_routes
+                } else {
                     withdrawn_routes
                         .into_iter()
                         .map(|s| s.to_string())
-                        .collect(),
-                ));
-                debug_assert_eq!(epoch as usize + 1, forwarding_table_updates.len());
+                        .collect()
+                };
+
+                // Changes that are only in the local table, for example after the
+                // routing state was restored, don't need a new epoch
+                let epoch = if new_routes.is_empty() && withdrawn_routes.is_empty() {
+                    forwarding_table.epoch()
+                } else {
+                    let epoch = forwarding_table.increment_epoch();
+                    forwarding_table_updates.push((new_routes, withdrawn_routes));
+                    debug_assert_eq!(
+                        epoch as usize + 1,
+                        self.forwarding_table_updates_start.load(Ordering::SeqCst) as usize
+                            + forwarding_table_updates.len()
+                    );
+                    epoch
+                };
 
-                store.set_routes(local_table.get_simplified_table())
+                (
+                    store.set_routes(local_table.get_simplified_table()),
+                    events,
+                    epoch,
+                )
             };
 
-            update_routes.await
-        } else {
-            // The routing table hasn't changed
-            Ok(())
+            update_routes.await?;
+            if let Some(ref routing_events) = self.routing_events {
+                for mut event in events {
+                    event.epoch = Some(epoch);
+                    routing_events.publish_routing_event(event);
+                }
+            }
         }
+
+        Ok(())
     }
 
     /// Send RouteUpdateRequests to all peers that we send routing messages to
@@ -674,6 +1263,14 @@ where
                 }
                 backoff.skip_intervals = backoff.skip_intervals.saturating_sub(1);
             }
+            // Peers in Idle mode don't want any updates until they switch back to Sync
+            skip.extend(
+                self.peer_modes
+                    .lock()
+                    .iter()
+                    .filter(|(_, mode)| **mode == Mode::Idle)
+                    .map(|(id, _)| *id),
+            );
             skip
         };
 
@@ -723,12 +1320,18 @@ where
             let mut outgoing = self_clone.outgoing.clone();
             let mut results = Vec::new();
             for account in accounts.into_iter() {
+                let prepare = match account.routing_policy() {
+                    Some(policy) => self_clone
+                        .apply_export_policy(&policy.export, route_update_request.clone())
+                        .to_prepare(),
+                    None => prepare.clone(),
+                };
                 let res = outgoing
                     .send_request(OutgoingRequest {
                         from: account.clone(),
                         to: account.clone(),
                         original_amount: prepare.amount(),
-                        prepare: prepare.clone(),
+                        prepare,
                     })
                     .await;
                 results.push((account, res));
@@ -788,13 +1391,30 @@ where
         from_epoch_index: u32,
         to_epoch_index: u32,
     ) -> RouteUpdateRequest {
+        let updates_start = self.forwarding_table_updates_start.load(Ordering::SeqCst);
+        // Without the updates of the epochs the peer is missing, we send it the whole table
+        let send_whole_table = from_epoch_index < updates_start;
+        let from_epoch_index = if send_whole_table {
+            0
+        } else {
+            from_epoch_index
+        };
         let (start, end) = (from_epoch_index as usize, to_epoch_index as usize);
-        let (routing_table_id, current_epoch_index) = {
+        let (routing_table_id, current_epoch_index, whole_table) = {
             let table = self.forwarding_table.read();
-            (table.id(), table.epoch())
+            let whole_table: Vec<Route> = if send_whole_table {
+                table.routes().cloned().collect()
+            } else {
+                Vec::new()
+            };
+            (table.id(), table.epoch(), whole_table)
         };
         let forwarding_table_updates = self.forwarding_table_updates.read();
-        let epochs_to_take = end.saturating_sub(start);
+        let epochs_to_take = if send_whole_table {
+            0
+        } else {
+            end.saturating_sub(start)
+        };
 
         // Merge the new routes and withdrawn routes from all of the given epochs
         let mut new_routes: Vec<Route> = Vec::with_capacity(epochs_to_take);
@@ -806,19 +1426,24 @@ where
         // or the updates is that there isn't necessarily an Account that
         // corresponds to this ILP address)
         if start == 0 {
+            let prefix = self.ilp_address.read().to_string();
+            let auth = self
+                .route_auth_secret
+                .map(|secret| route_auth(&secret, &prefix))
+                .unwrap_or([0; 32]);
             new_routes.push(Route {
-                prefix: self.ilp_address.read().to_string(),
+                prefix,
                 path: Vec::new(),
-                // TODO what should we include here?
-                auth: [0; 32],
+                auth,
                 props: Vec::new(),
             });
         }
+        new_routes.extend(whole_table);
 
         // Iterate through each of the given epochs
         for (new, withdrawn) in forwarding_table_updates
             .iter()
-            .skip(start)
+            .skip(start.saturating_sub(updates_start as usize))
             .take(epochs_to_take)
         {
             for new_route in new {
@@ -855,16 +1480,103 @@ where
             new_routes,
             withdrawn_routes,
             speaker: self.ilp_address.read().clone(),
-            hold_down_time: DEFAULT_ROUTE_EXPIRY_TIME,
+            hold_down_time: self.hold_down_time,
+        }
+    }
+
+    /// Remove the routes that the export policy of the account we are sending this update to
+    /// does not allow and replace the routes under its aggregate prefixes with a single
+    /// route for each aggregate prefix.
+    fn apply_export_policy(
+        &self,
+        policy: &RouteExportPolicy,
+        mut update: RouteUpdateRequest,
+    ) -> RouteUpdateRequest {
+        // Withdrawals are always sent and the routes the policy denies are withdrawn
+        // rather than left out, so that a peer which learned a route before the policy
+        // started denying it doesn't keep the stale route
+        let (allowed, denied): (Vec<Route>, Vec<Route>) = update
+            .new_routes
+            .into_iter()
+            .partition(|route| policy.allows(&route.prefix));
+        update.new_routes = allowed;
+        update
+            .withdrawn_routes
+            .extend(denied.into_iter().map(|route| route.prefix));
+        if policy.aggregate_prefixes.is_empty() {
+            return update;
+        }
+
+        let mut aggregates: Vec<&str> = update
+            .new_routes
+            .iter()
+            .map(|route| route.prefix.as_str())
+            .chain(update.withdrawn_routes.iter().map(|prefix| prefix.as_str()))
+            .filter_map(|prefix| policy.aggregate_for(prefix))
+            .collect();
+        aggregates.sort_unstable();
+        aggregates.dedup();
+
+        update
+            .new_routes
+            .retain(|route| policy.aggregate_for(&route.prefix).is_none());
+        update
+            .withdrawn_routes
+            .retain(|prefix| policy.aggregate_for(prefix).is_none());
+        // The aggregate routes reflect the current state of the forwarding table rather
+        // than only this update, so that withdrawing one of the routes under an aggregate
+        // prefix only withdraws the aggregate route if there are no others left
+        for aggregate in aggregates {
+            match self.aggregate_route(policy, aggregate) {
+                Some(route) => update.new_routes.push(route),
+                None => update.withdrawn_routes.push(aggregate.to_string()),
+            }
         }
+        update
+    }
+
+    /// Create the route we advertise for an aggregate prefix of an export policy.
+    /// The path includes every node on the paths of the routes it covers so that
+    /// peers can still detect routing loops. Returns None if there are no routes
+    /// under the aggregate prefix.
+    fn aggregate_route(&self, policy: &RouteExportPolicy, aggregate: &str) -> Option<Route> {
+        let ilp_address = self.ilp_address.read().to_string();
+        let is_covered =
+            |prefix: &str| policy.allows(prefix) && policy.aggregate_for(prefix) == Some(aggregate);
+
+        let forwarding_table = self.forwarding_table.read();
+        let mut covered_routes = forwarding_table
+            .routes()
+            .filter(|route| is_covered(&route.prefix))
+            .peekable();
+        if covered_routes.peek().is_none() && !is_covered(&ilp_address) {
+            return None;
+        }
+
+        let mut path = vec![ilp_address];
+        for hop in covered_routes.flat_map(|route| route.path.iter()) {
+            if !path.contains(hop) {
+                path.push(hop.clone());
+            }
+        }
+        Some(Route {
+            prefix: aggregate.to_string(),
+            path,
+            auth: [0; 32],
+            props: Vec::new(),
+        })
     }
 
     /// Send a Route Update Request to a specific account for the given epoch range.
     /// This is used when the peer has fallen behind and has requested a specific range of updates.
     async fn send_route_update(&self, account: A, from_epoch_index: u32, to_epoch_index: u32) {
-        let prepare = self
-            .create_route_update(from_epoch_index, to_epoch_index)
-            .to_prepare();
+        let update = self.create_route_update(from_epoch_index, to_epoch_index);
+        let prepare = match account.routing_policy() {
+            Some(policy) => self
+                .apply_export_policy(&policy.export, update)
+                .to_prepare(),
+            None => update.to_prepare(),
+        };
         let account_id = account.id();
         debug!(
             "Sending individual route update to account: {} for epochs from: {} to: {}",
@@ -890,11 +1602,130 @@ where
     }
 }
 
+/// Returns true if the given route from the local table should be broadcast to peers
+fn should_advertise(route: &Route, ilp_address: &Address) -> bool {
+    // Don't advertise routes that don't start with the global prefix
+    // or that advertise the whole global prefix
+    let address_scheme = ilp_address.scheme();
+    let correct_address_scheme =
+        route.prefix.starts_with(address_scheme) && route.prefix != address_scheme;
+    // We do want to advertise our address
+    let is_our_address = route.prefix == ilp_address as &str;
+    // Don't advertise local routes because advertising only our address
+    // will be enough to ensure the packet gets to us and we can route it
+    // to the correct account on our node
+    let is_local_route = route.prefix.starts_with(ilp_address as &str) && route.path.is_empty();
+    let not_local_route = is_our_address || !is_local_route;
+    correct_address_scheme && not_local_route
+}
+
+/// Rebuild the part of the forwarding table affected by the changes to the given prefixes
+/// of the local table, aggregating the routes that end at our node.
+/// Returns the routes that need to be advertised and the prefixes that need to be withdrawn
+/// so that peers end up with the new forwarding table.
+fn update_aggregated_forwarding_table<A: CcpRoutingAccount>(
+    local_table: &RoutingTable<A>,
+    local_routes: &HashMap<String, A>,
+    changed_prefixes: &HashSet<&str>,
+    forwarding_table: &mut RoutingTable<A>,
+    ilp_address: &Address,
+    route_auth_secret: Option<[u8; 32]>,
+) -> NewAndWithdrawnRoutes {
+    // Only the routes under the same root as a changed prefix can be aggregated differently
+    let roots: HashSet<&str> = changed_prefixes
+        .iter()
+        .map(|prefix| aggregation_root(prefix))
+        .collect();
+    let is_affected = |prefix: &str| roots.contains(aggregation_root(prefix));
+    let affected: Vec<&(A, Route)> = local_table
+        .accounts_and_routes()
+        .filter(|(_account, route)| is_affected(&route.prefix))
+        .collect();
+
+    // The routes to the accounts on our node end here rather than at another connector
+    let local_prefixes: HashSet<String> = affected
+        .iter()
+        .filter(|(account, route)| {
+            local_routes
+                .get(&route.prefix)
+                .map(|local_account| local_account.id() == account.id())
+                .unwrap_or(false)
+        })
+        .map(|(_account, route)| route.prefix.clone())
+        .collect();
+    let routes = affected
+        .into_iter()
+        .filter(|(_account, route)| should_advertise(route, ilp_address))
+        .map(|(account, route)| {
+            let mut route = route.clone();
+            route.path.insert(0, ilp_address.to_string());
+            // Each hop hashes the auth before forwarding
+            route.auth = hash(&route.auth);
+            (account.clone(), route)
+        })
+        .collect();
+    let mut routes = aggregate_routes(routes, &local_prefixes, ilp_address, route_auth_secret);
+    routes.sort_unstable_by(|(_, a), (_, b)| a.prefix.cmp(&b.prefix));
+    let route_prefixes: HashSet<&str> = routes
+        .iter()
+        .map(|(_, route)| route.prefix.as_str())
+        .collect();
+
+    let mut withdrawn_routes: Vec<String> = forwarding_table
+        .routes()
+        .map(|route| route.prefix.as_str())
+        .filter(|prefix| is_affected(prefix) && !route_prefixes.contains(prefix))
+        .map(|prefix| prefix.to_string())
+        .collect();
+    withdrawn_routes.sort_unstable();
+    for prefix in withdrawn_routes.iter() {
+        forwarding_table.delete_route(prefix);
+    }
+
+    let mut new_routes = Vec::new();
+    for (account, route) in routes {
+        let is_advertised = matches!(
+            forwarding_table.get_exact_route(&route.prefix),
+            Some((_, advertised)) if advertised.path == route.path && advertised.auth == route.auth
+        );
+        if !is_advertised {
+            new_routes.push(route.clone());
+        }
+        // The account may change without changing what we advertise,
+        // for example when the routes covered by an aggregate change
+        forwarding_table.set_route(route.prefix.clone(), account, route);
+    }
+    (new_routes, withdrawn_routes)
+}
+
+/// Returns where the best route for the prefix comes from, following the same order
+/// as `get_best_route_for_prefix`: configured routes (which may be for a shorter prefix)
+/// come first, then the routes to local accounts and last the routes learned over CCP
+fn route_source<A>(
+    prefix: &str,
+    local_routes: &HashMap<String, A>,
+    configured_routes: &HashMap<String, A>,
+) -> RouteSource {
+    if configured_routes
+        .keys()
+        .any(|configured| prefix_matches(prefix, configured))
+    {
+        RouteSource::Static
+    } else if local_routes.contains_key(prefix) {
+        RouteSource::Local
+    } else {
+        RouteSource::Ccp
+    }
+}
+
 fn get_best_route_for_prefix<A: CcpRoutingAccount>(
     local_routes: &HashMap<String, A>,
     configured_routes: &HashMap<String, A>,
     incoming_tables: &HashMap<Uuid, RoutingTable<A>>,
     prefix: &str,
+    route_selector: &dyn RouteSelector,
+    route_metrics: &HashMap<Uuid, RouteMetrics>,
+    stale_incoming_tables: &HashSet<Uuid>,
 ) -> Option<(A, Route)> {
     // Check if we have a configured route for that specific prefix
     // or any shorter prefix ("example.a.b.c" will match "example.a.b" and "example.a")
@@ -929,42 +1760,24 @@ fn get_best_route_for_prefix<A: CcpRoutingAccount>(
         ));
     }
 
-    let mut candidate_routes = incoming_tables
+    incoming_tables
         .values()
-        .filter_map(|incoming_table| incoming_table.get_route(prefix));
-    if let Some((account, route)) = candidate_routes.next() {
-        let (best_account, best_route) = candidate_routes.fold(
-            (account, route),
-            |(best_account, best_route), (account, route)| {
-                // Prioritize child > peer > parent
-                match best_account
-                    .routing_relation()
-                    .cmp(&account.routing_relation())
-                {
-                    StdOrdering::Greater => (best_account, best_route),
-                    StdOrdering::Less => (account, route),
-                    _ => {
-                        // Prioritize shortest path
-                        match best_route.path.len().cmp(&route.path.len()) {
-                            StdOrdering::Less => (best_account, best_route),
-                            StdOrdering::Greater => (account, route),
-                            _ => {
-                                // Finally base it on account ID
-                                if best_account.id().to_string() < account.id().to_string() {
-                                    (best_account, best_route)
-                                } else {
-                                    (account, route)
-                                }
-                            }
-                        }
-                    }
-                }
-            },
-        );
-        Some((best_account.clone(), best_route.clone()))
-    } else {
-        None
-    }
+        .filter_map(|incoming_table| incoming_table.get_route(prefix))
+        .map(|(account, route)| {
+            let candidate =
+                RouteCandidate::new(account, &route.path, route_metrics.get(&account.id()));
+            (account, route, candidate)
+        })
+        // Routes from tables that were restored after a restart and haven't been
+        // refreshed by the peer yet are only used if there is no other route
+        .min_by(|(_, _, a), (_, _, b)| {
+            let a_is_stale = stale_incoming_tables.contains(&a.account_id);
+            let b_is_stale = stale_incoming_tables.contains(&b.account_id);
+            a_is_stale
+                .cmp(&b_is_stale)
+                .then_with(|| route_selector.compare(a, b))
+        })
+        .map(|(account, route, _)| (account.clone(), route.clone()))
 }
 
 #[async_trait]
@@ -993,7 +1806,7 @@ where
 mod ranking_routes {
     use super::*;
     use crate::test_helpers::*;
-    use crate::RoutingRelation;
+    use crate::{RoutingPolicy, RoutingRelation};
     use std::iter::FromIterator;
 
     static LOCAL: Lazy<HashMap<String, TestAccount>> = Lazy::new(|| {
@@ -1085,9 +1898,21 @@ mod ranking_routes {
         ])
     });
 
+    fn best_route(prefix: &str) -> Option<(TestAccount, Route)> {
+        get_best_route_for_prefix(
+            &LOCAL,
+            &CONFIGURED,
+            &INCOMING,
+            prefix,
+            &DefaultRouteSelector,
+            &HashMap::new(),
+            &HashSet::new(),
+        )
+    }
+
     #[test]
     fn prioritizes_configured_routes() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, "example.a");
+        let best_route = best_route("example.a");
         assert_eq!(
             best_route.unwrap().0.id(),
             Uuid::from_slice(&[4; 16]).unwrap()
@@ -1096,8 +1921,7 @@ mod ranking_routes {
 
     #[test]
     fn prioritizes_shorter_configured_routes() {
-        let best_route =
-            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, "example.a.sub-prefix");
+        let best_route = best_route("example.a.sub-prefix");
         assert_eq!(
             best_route.unwrap().0.id(),
             Uuid::from_slice(&[4; 16]).unwrap()
@@ -1106,7 +1930,7 @@ mod ranking_routes {
 
     #[test]
     fn prioritizes_local_routes_over_broadcasted_ones() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, "example.c");
+        let best_route = best_route("example.c");
         assert_eq!(
             best_route.unwrap().0.id(),
             Uuid::from_slice(&[3; 16]).unwrap()
@@ -1115,7 +1939,7 @@ mod ranking_routes {
 
     #[test]
     fn prioritizes_children_over_peers() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, "example.d");
+        let best_route = best_route("example.d");
         assert_eq!(
             best_route.unwrap().0.id(),
             Uuid::from_slice(&[6; 16]).unwrap()
@@ -1124,7 +1948,7 @@ mod ranking_routes {
 
     #[test]
     fn prioritizes_shorter_paths() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, "example.e");
+        let best_route = best_route("example.e");
         assert_eq!(
             best_route.unwrap().0.id(),
             Uuid::from_slice(&[7; 16]).unwrap()
@@ -1133,16 +1957,188 @@ mod ranking_routes {
 
     #[test]
     fn returns_none_for_no_route() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, "example.z");
+        let best_route = best_route("example.z");
         assert!(best_route.is_none());
     }
-}
 
-#[cfg(test)]
-mod handle_route_control_request {
-    use super::*;
-    use crate::fixtures::*;
+    fn peer_tables(
+        accounts: Vec<(TestAccount, usize)>,
+    ) -> HashMap<Uuid, RoutingTable<TestAccount>> {
+        accounts
+            .into_iter()
+            .map(|(account, path_length)| {
+                let mut table = RoutingTable::default();
+                let id = account.id();
+                table.add_route(
+                    account,
+                    Route {
+                        prefix: "example.remote".to_string(),
+                        path: vec!["example.hop".to_string(); path_length],
+                        auth: [0; 32],
+                        props: Vec::new(),
+                    },
+                );
+                (id, table)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn prioritizes_lower_cost() {
+        let cheap_id = Uuid::from_slice(&[9; 16]).unwrap();
+        let mut cheap = TestAccount::new(cheap_id, "example.cheap");
+        cheap.relation = RoutingRelation::Parent;
+        cheap.routing_policy = Some(RoutingPolicy::default());
+        let mut expensive = TestAccount::new(Uuid::from_slice(&[1; 16]).unwrap(), "example.exp");
+        expensive.routing_policy = Some(RoutingPolicy {
+            import: RouteImportPolicy {
+                cost: 10,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let incoming = peer_tables(vec![(cheap, 3), (expensive, 1)]);
+        let best_route = get_best_route_for_prefix(
+            &HashMap::new(),
+            &HashMap::new(),
+            &incoming,
+            "example.remote",
+            &DefaultRouteSelector,
+            &HashMap::new(),
+            &HashSet::new(),
+        );
+        assert_eq!(best_route.unwrap().0.id(), cheap_id);
+    }
+
+    #[test]
+    fn uses_balance_headroom_and_round_trip_time() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let id3 = Uuid::from_slice(&[3; 16]).unwrap();
+        let incoming = peer_tables(vec![
+            (TestAccount::new(id1, "example.one"), 1),
+            (TestAccount::new(id2, "example.two"), 1),
+            (TestAccount::new(id3, "example.three"), 1),
+        ]);
+        let metrics = HashMap::from_iter(vec![
+            (
+                id1,
+                RouteMetrics {
+                    expected_round_trip_time: Some(10),
+                    balance_headroom: Some(0),
+                },
+            ),
+            (
+                id2,
+                RouteMetrics {
+                    expected_round_trip_time: Some(200),
+                    balance_headroom: Some(1000),
+                },
+            ),
+            (
+                id3,
+                RouteMetrics {
+                    expected_round_trip_time: Some(50),
+                    balance_headroom: None,
+                },
+            ),
+        ]);
+        let best_route = get_best_route_for_prefix(
+            &HashMap::new(),
+            &HashMap::new(),
+            &incoming,
+            "example.remote",
+            &DefaultRouteSelector,
+            &metrics,
+            &HashSet::new(),
+        );
+        // The first account has the lowest round trip time but no balance headroom left
+        assert_eq!(best_route.unwrap().0.id(), id3);
+    }
+
+    #[test]
+    fn prefers_more_balance_headroom() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let incoming = peer_tables(vec![
+            (TestAccount::new(id1, "example.one"), 1),
+            (TestAccount::new(id2, "example.two"), 1),
+        ]);
+        let metrics = HashMap::from_iter(vec![
+            (
+                id1,
+                RouteMetrics {
+                    expected_round_trip_time: Some(10),
+                    balance_headroom: Some(10),
+                },
+            ),
+            (
+                id2,
+                RouteMetrics {
+                    expected_round_trip_time: Some(200),
+                    balance_headroom: Some(1000),
+                },
+            ),
+        ]);
+        let best_route = get_best_route_for_prefix(
+            &HashMap::new(),
+            &HashMap::new(),
+            &incoming,
+            "example.remote",
+            &DefaultRouteSelector,
+            &metrics,
+            &HashSet::new(),
+        );
+        assert_eq!(best_route.unwrap().0.id(), id2);
+    }
+
+    #[test]
+    fn prefers_routes_that_are_not_stale() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let incoming = peer_tables(vec![
+            (TestAccount::new(id1, "example.one"), 1),
+            (TestAccount::new(id2, "example.two"), 3),
+        ]);
+        let best_route = get_best_route_for_prefix(
+            &HashMap::new(),
+            &HashMap::new(),
+            &incoming,
+            "example.remote",
+            &DefaultRouteSelector,
+            &HashMap::new(),
+            &HashSet::from_iter(vec![id1]),
+        );
+        // The shorter route was restored after a restart and hasn't been refreshed yet
+        assert_eq!(best_route.unwrap().0.id(), id2);
+    }
+
+    #[test]
+    fn uses_custom_route_selector() {
+        // Prefer the longest path, just to show the default ranking is not used
+        let selector = |a: &RouteCandidate, b: &RouteCandidate| b.path.len().cmp(&a.path.len());
+        let best_route = get_best_route_for_prefix(
+            &LOCAL,
+            &CONFIGURED,
+            &INCOMING,
+            "example.e",
+            &selector,
+            &HashMap::new(),
+            &HashSet::new(),
+        );
+        assert_eq!(
+            best_route.unwrap().0.id(),
+            Uuid::from_slice(&[8; 16]).unwrap()
+        );
+    }
+}
+
+#[cfg(test)]
+mod handle_route_control_request {
+    use super::*;
+    use crate::fixtures::*;
     use crate::test_helpers::*;
+    use crate::RoutingPolicy;
     use interledger_packet::PrepareBuilder;
     use std::time::{Duration, SystemTime};
 
@@ -1250,6 +2246,75 @@ mod handle_route_control_request {
         assert_eq!(update.current_epoch_index, 1);
         assert_eq!(update.new_routes.len(), 3);
     }
+
+    #[tokio::test]
+    async fn stops_and_resumes_broadcasts_for_idle_mode() {
+        let (mut service, outgoing_requests) = test_service_with_routes();
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let control_request = |mode| IncomingRequest {
+            from: TestAccount::new(id1, "example.local.1"),
+            prepare: RouteControlRequest {
+                last_known_routing_table_id: [0; 16],
+                mode,
+                last_known_epoch: 0,
+                features: Vec::new(),
+            }
+            .to_prepare(),
+        };
+
+        service
+            .handle_request(control_request(Mode::Idle))
+            .await
+            .unwrap();
+        assert!(outgoing_requests.lock().is_empty());
+        service.send_route_updates().await.unwrap();
+        let accounts: Vec<Uuid> = outgoing_requests
+            .lock()
+            .iter()
+            .map(|request| request.to.id())
+            .collect();
+        assert_eq!(accounts, vec![id2]);
+
+        *outgoing_requests.lock() = Vec::new();
+        service
+            .handle_request(control_request(Mode::Sync))
+            .await
+            .unwrap();
+        assert_eq!(outgoing_requests.lock().len(), 1);
+        *outgoing_requests.lock() = Vec::new();
+        service.send_route_updates().await.unwrap();
+        assert_eq!(outgoing_requests.lock().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn applies_export_policy_to_individual_updates() {
+        let (mut service, outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).await.unwrap();
+        let mut account = ROUTING_ACCOUNT.clone();
+        account.routing_policy = Some(RoutingPolicy {
+            export: RouteExportPolicy {
+                deny_prefixes: vec!["example.configured".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        service
+            .handle_request(IncomingRequest {
+                from: account,
+                prepare: CONTROL_REQUEST.to_prepare(),
+            })
+            .await
+            .unwrap();
+        let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
+        let prefixes: Vec<&str> = update
+            .new_routes
+            .iter()
+            .map(|route| route.prefix.as_str())
+            .collect();
+        assert!(prefixes.contains(&"example.local.1"));
+        assert!(!prefixes.contains(&"example.configured.1"));
+    }
 }
 
 #[cfg(test)]
@@ -1257,9 +2322,11 @@ mod handle_route_update_request {
     use super::*;
     use crate::fixtures::*;
     use crate::test_helpers::*;
+    use crate::RoutingPolicy;
     use interledger_packet::PrepareBuilder;
     use std::{
         iter::FromIterator,
+        str::FromStr,
         time::{Duration, SystemTime},
     };
 
@@ -1436,6 +2503,43 @@ mod handle_route_update_request {
         assert_eq!(request.new_routes[0].prefix, "example.valid".to_string());
     }
 
+    #[tokio::test]
+    async fn filters_unauthenticated_routes_for_protected_prefixes() {
+        let mut service = test_service();
+        let secret = [9; 32];
+        service.protected_prefixes = Arc::new(HashMap::from_iter(vec![(
+            "example.protected".to_string(),
+            crate::route_auth_anchor(&secret, "example.protected"),
+        )]));
+        let route = |prefix: &str, path: Vec<&str>, auth: [u8; 32]| Route {
+            prefix: prefix.to_string(),
+            path: path.into_iter().map(String::from).collect(),
+            auth,
+            props: Vec::new(),
+        };
+        let owner_auth = route_auth(&secret, "example.protected");
+        let mut request = UPDATE_REQUEST_SIMPLE.clone();
+        request.new_routes = vec![
+            // Forwarded once by the owner's peer
+            route("example.protected", vec!["example.peer"], hash(&owner_auth)),
+            // Forged
+            route("example.protected", vec!["example.peer"], [0; 32]),
+            // The peer removed itself from the path
+            route("example.protected", Vec::new(), hash(&owner_auth)),
+            // More specific than the protected prefix
+            route("example.protected.sub", Vec::new(), owner_auth),
+            route("example.unprotected", Vec::new(), [0; 32]),
+        ];
+        let request = service.filter_routes(request);
+        assert_eq!(
+            request.new_routes,
+            vec![
+                route("example.protected", vec!["example.peer"], hash(&owner_auth)),
+                route("example.unprotected", Vec::new(), [0; 32]),
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn updates_local_routing_table() {
         let mut service = test_service();
@@ -1467,6 +2571,328 @@ mod handle_route_update_request {
         );
     }
 
+    #[tokio::test]
+    async fn restores_saved_routing_state() {
+        let (mut service, _outgoing_requests) = test_service_with_routes();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        // The state is only saved on the next broadcast
+        assert!(service.store.routing_state.lock().is_none());
+        service.broadcast_routes().await.unwrap();
+        let saved = service.store.routing_state.lock().take();
+        assert!(saved.is_some());
+        // Nothing changed since, so it is not saved again
+        service.broadcast_routes().await.unwrap();
+        assert!(service.store.routing_state.lock().is_none());
+
+        // Start another route manager with the same store contents, as if the node restarted
+        let (mut restarted, outgoing_requests) = test_service_with_routes();
+        *restarted.store.routes.lock() = service.store.routes.lock().clone();
+        *restarted.store.routing_state.lock() = saved;
+        restarted.restore_routing_state().await.unwrap();
+
+        assert_eq!(
+            restarted
+                .local_table
+                .read()
+                .get_route("example.prefix1")
+                .unwrap()
+                .0
+                .id(),
+            ROUTING_ACCOUNT.id()
+        );
+        assert!(restarted
+            .stale_incoming_tables
+            .lock()
+            .contains(&ROUTING_ACCOUNT.id()));
+
+        // Only the epochs we are missing are requested from the peer
+        restarted.request_all_routes().await;
+        let control = outgoing_requests
+            .lock()
+            .iter()
+            .find(|request| request.to.id() == ROUTING_ACCOUNT.id())
+            .map(|request| RouteControlRequest::try_from(&request.prepare).unwrap())
+            .unwrap();
+        assert_eq!(
+            control.last_known_routing_table_id,
+            UPDATE_REQUEST_COMPLEX.routing_table_id
+        );
+        assert_eq!(control.last_known_epoch, 1);
+
+        let mut request = UPDATE_REQUEST_SIMPLE.clone();
+        request.routing_table_id = UPDATE_REQUEST_COMPLEX.routing_table_id;
+        request.from_epoch_index = 1;
+        request.to_epoch_index = 2;
+        restarted
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        assert!(restarted.stale_incoming_tables.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn restored_manager_resumes_sending_its_table() {
+        let (mut service, _outgoing_requests) = test_service_with_routes();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        service.broadcast_routes().await.unwrap();
+        let saved = service.store.routing_state.lock().clone();
+        let routing_table_id = service.forwarding_table.read().id();
+        let epoch = service.forwarding_table.read().epoch();
+        assert!(epoch > 1);
+
+        let (mut restarted, outgoing_requests) = test_service_with_routes();
+        *restarted.store.routes.lock() = service.store.routes.lock().clone();
+        *restarted.store.routing_state.lock() = saved;
+        restarted.restore_routing_state().await.unwrap();
+        assert_eq!(restarted.forwarding_table.read().id(), routing_table_id);
+        assert_eq!(restarted.forwarding_table.read().epoch(), epoch);
+
+        // A peer which has all but the last epoch of our table only gets that epoch
+        restarted
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: RouteControlRequest {
+                    last_known_routing_table_id: routing_table_id,
+                    mode: Mode::Sync,
+                    last_known_epoch: epoch - 1,
+                    features: Vec::new(),
+                }
+                .to_prepare(),
+            })
+            .await
+            .unwrap();
+        let update = outgoing_requests
+            .lock()
+            .iter()
+            .filter(|request| request.to.id() == ROUTING_ACCOUNT.id())
+            .filter_map(|request| RouteUpdateRequest::try_from(&request.prepare).ok())
+            .last()
+            .unwrap();
+        assert_eq!(update.routing_table_id, routing_table_id);
+        assert_eq!(update.from_epoch_index, epoch - 1);
+        assert_eq!(update.to_epoch_index, epoch);
+        assert_eq!(
+            update.new_routes,
+            service.forwarding_table_updates.read()[epoch as usize - 1].0
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_whole_table_for_epochs_without_saved_updates() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).await.unwrap();
+        let routing_table_id = service.forwarding_table.read().id();
+        let mut routes: Vec<String> = service
+            .forwarding_table
+            .read()
+            .routes()
+            .map(|route| route.prefix.clone())
+            .collect();
+
+        // Only the updates from epoch 1 on were restored, and a route was withdrawn since
+        let state = CcpRoutingState {
+            forwarding_table: Some(RoutingTableState::from(&*service.forwarding_table.read())),
+            forwarding_table_updates_start: 1,
+            forwarding_table_updates: Vec::new(),
+            incoming_tables: HashMap::new(),
+        };
+        let (mut restarted, outgoing_requests) = test_service_with_routes();
+        *restarted.store.routing_state.lock() = Some(state);
+        restarted.restore_routing_state().await.unwrap();
+        assert_eq!(restarted.forwarding_table.read().epoch(), 1);
+        restarted
+            .forwarding_table
+            .write()
+            .delete_route("example.configured.1");
+        restarted.forwarding_table.write().increment_epoch();
+        restarted
+            .forwarding_table_updates
+            .write()
+            .push((Vec::new(), vec!["example.configured.1".to_string()]));
+
+        restarted
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: RouteControlRequest {
+                    last_known_routing_table_id: routing_table_id,
+                    mode: Mode::Sync,
+                    last_known_epoch: 0,
+                    features: Vec::new(),
+                }
+                .to_prepare(),
+            })
+            .await
+            .unwrap();
+        let update = outgoing_requests
+            .lock()
+            .iter()
+            .filter_map(|request| RouteUpdateRequest::try_from(&request.prepare).ok())
+            .last()
+            .unwrap();
+        assert_eq!(update.routing_table_id, routing_table_id);
+        assert_eq!(update.from_epoch_index, 0);
+        assert_eq!(update.to_epoch_index, 2);
+        let mut new_routes: Vec<String> = update
+            .new_routes
+            .into_iter()
+            .map(|route| route.prefix)
+            .collect();
+        new_routes.sort_unstable();
+        routes.retain(|prefix| prefix != "example.configured.1");
+        routes.push("example.connector".to_string());
+        routes.sort_unstable();
+        assert_eq!(new_routes, routes);
+    }
+
+    #[tokio::test]
+    async fn restoring_keeps_tables_received_since_starting() {
+        let (mut service, _outgoing_requests) = test_service_with_routes();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+
+        // The saved table is older than the one the peer just sent us
+        *service.store.routing_state.lock() = Some(CcpRoutingState {
+            incoming_tables: HashMap::from_iter(vec![(
+                ROUTING_ACCOUNT.id(),
+                RoutingTableState {
+                    id: [9; 16],
+                    epoch: 5,
+                    routes: Vec::new(),
+                },
+            )]),
+            ..Default::default()
+        });
+        service.restore_routing_state().await.unwrap();
+
+        assert_eq!(
+            service.incoming_tables.read()[&ROUTING_ACCOUNT.id()].id(),
+            UPDATE_REQUEST_COMPLEX.routing_table_id
+        );
+        assert!(service.stale_incoming_tables.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn aggregates_and_deaggregates_local_routes() {
+        let local_routes = HashMap::from_iter(vec![
+            (
+                "example.customers.alice".to_string(),
+                TestAccount::new(
+                    Uuid::from_slice(&[1; 16]).unwrap(),
+                    "example.customers.alice",
+                ),
+            ),
+            (
+                "example.customers.bob".to_string(),
+                TestAccount::new(Uuid::from_slice(&[2; 16]).unwrap(), "example.customers.bob"),
+            ),
+        ]);
+        let (mut service, _outgoing_requests) =
+            test_service_with_store(TestStore::with_routes(local_routes, HashMap::new()));
+        service.route_aggregation = true;
+        let last_update = |service: &CcpRouteManager<_, _, _, _>| {
+            let (new_routes, mut withdrawn_routes) = service
+                .forwarding_table_updates
+                .read()
+                .last()
+                .cloned()
+                .unwrap();
+            let mut new_routes: Vec<String> =
+                new_routes.into_iter().map(|route| route.prefix).collect();
+            new_routes.sort_unstable();
+            withdrawn_routes.sort_unstable();
+            (new_routes, withdrawn_routes)
+        };
+
+        service.update_best_routes(None).await.unwrap();
+        assert_eq!(
+            last_update(&service),
+            (vec!["example.customers".to_string()], Vec::new())
+        );
+
+        // A prefix under the aggregate is now reached through a peer
+        let carol = Route {
+            prefix: "example.customers.carol".to_string(),
+            path: Vec::new(),
+            auth: [0; 32],
+            props: Vec::new(),
+        };
+        let mut request = UPDATE_REQUEST_SIMPLE.clone();
+        request.from_epoch_index = 0;
+        request.to_epoch_index = 1;
+        request.new_routes = vec![carol];
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            last_update(&service),
+            (
+                vec![
+                    "example.customers.alice".to_string(),
+                    "example.customers.bob".to_string(),
+                    "example.customers.carol".to_string(),
+                ],
+                vec!["example.customers".to_string()]
+            )
+        );
+
+        // Once the peer withdraws it, the local routes are aggregated again
+        let mut request = UPDATE_REQUEST_SIMPLE.clone();
+        request.from_epoch_index = 1;
+        request.to_epoch_index = 2;
+        request.withdrawn_routes = vec!["example.customers.carol".to_string()];
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            last_update(&service),
+            (
+                vec!["example.customers".to_string()],
+                vec![
+                    "example.customers.alice".to_string(),
+                    "example.customers.bob".to_string(),
+                    "example.customers.carol".to_string(),
+                ]
+            )
+        );
+    }
+
     #[tokio::test]
     async fn writes_local_routing_table_to_store() {
         let mut service = test_service();
@@ -1662,12 +3088,317 @@ mod handle_route_update_request {
         let control = RouteControlRequest::try_from(&request.prepare).unwrap();
         assert_eq!(control.last_known_epoch, 1);
     }
+
+    #[tokio::test]
+    async fn withdraws_expired_routes() {
+        let (mut service, outgoing_requests) = test_service_with_routes();
+        service.route_expiry_time = Some(0);
+        service.update_best_routes(None).await.unwrap();
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: RouteUpdateRequest {
+                    routing_table_id: [0; 16],
+                    current_epoch_index: 1,
+                    from_epoch_index: 0,
+                    to_epoch_index: 1,
+                    hold_down_time: 30000,
+                    speaker: Address::from_str("example.remote").unwrap(),
+                    new_routes: vec![Route {
+                        prefix: "example.remote".to_string(),
+                        path: vec!["example.peer".to_string()],
+                        auth: [0; 32],
+                        props: Vec::new(),
+                    }],
+                    withdrawn_routes: Vec::new(),
+                }
+                .to_prepare(),
+            })
+            .await
+            .unwrap();
+        assert!((*service.local_table.read())
+            .get_route("example.remote")
+            .is_some());
+
+        service
+            .peer_modes
+            .lock()
+            .insert(ROUTING_ACCOUNT.id(), Mode::Sync);
+
+        service.broadcast_routes().await.unwrap();
+        assert!(service.incoming_tables.read().is_empty());
+        assert!(service.incoming_table_expiries.lock().is_empty());
+        assert!(service.peer_modes.lock().is_empty());
+        assert!((*service.local_table.read())
+            .get_route("example.remote")
+            .is_none());
+        let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
+        assert_eq!(update.withdrawn_routes, vec!["example.remote".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn keeps_routes_until_hold_down_time_passes() {
+        let (mut service, _outgoing_requests) = test_service_with_routes();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.from_epoch_index = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        service.broadcast_routes().await.unwrap();
+        assert_eq!(service.incoming_tables.read().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn applies_import_policy() {
+        let (mut service, _outgoing_requests) = test_service_with_routes();
+        let mut account = ROUTING_ACCOUNT.clone();
+        account.routing_policy = Some(RoutingPolicy {
+            import: RouteImportPolicy {
+                allow_prefixes: vec!["example.allowed".to_string()],
+                deny_prefixes: vec!["example.allowed.denied".to_string()],
+                max_prefixes: Some(2),
+                max_path_length: Some(2),
+                cost: 0,
+            },
+            ..Default::default()
+        });
+        let route = |prefix: &str, path_length: usize| Route {
+            prefix: prefix.to_string(),
+            path: vec!["example.peer".to_string(); path_length],
+            auth: [0; 32],
+            props: Vec::new(),
+        };
+        service
+            .handle_request(IncomingRequest {
+                from: account.clone(),
+                prepare: RouteUpdateRequest {
+                    routing_table_id: [0; 16],
+                    current_epoch_index: 1,
+                    from_epoch_index: 0,
+                    to_epoch_index: 1,
+                    hold_down_time: 30000,
+                    speaker: Address::from_str("example.peer").unwrap(),
+                    new_routes: vec![
+                        route("example.allowed.a", 1),
+                        route("example.allowed.too-long", 3),
+                        route("example.allowed.denied.b", 1),
+                        route("example.other", 1),
+                        route("example.allowed.c", 2),
+                        route("example.allowed.over-limit", 1),
+                    ],
+                    withdrawn_routes: Vec::new(),
+                }
+                .to_prepare(),
+            })
+            .await
+            .unwrap();
+
+        let mut prefixes: Vec<String> = service.incoming_tables.read()[&account.id()]
+            .routes()
+            .map(|route| route.prefix.clone())
+            .collect();
+        prefixes.sort();
+        assert_eq!(prefixes, vec!["example.allowed.a", "example.allowed.c"]);
+    }
+
+    #[tokio::test]
+    async fn ranks_routes_using_store_metrics() {
+        let (mut service, _outgoing_requests) = test_service_with_routes();
+        let id1 = Uuid::from_slice(&[10; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[11; 16]).unwrap();
+        service.store.route_metrics.lock().insert(
+            id1,
+            RouteMetrics {
+                expected_round_trip_time: None,
+                balance_headroom: Some(0),
+            },
+        );
+        for id in [id1, id2].iter() {
+            service
+                .handle_request(IncomingRequest {
+                    from: TestAccount::new(*id, "example.peer"),
+                    prepare: RouteUpdateRequest {
+                        routing_table_id: [0; 16],
+                        current_epoch_index: 1,
+                        from_epoch_index: 0,
+                        to_epoch_index: 1,
+                        hold_down_time: 30000,
+                        speaker: Address::from_str("example.peer").unwrap(),
+                        new_routes: vec![Route {
+                            prefix: "example.remote".to_string(),
+                            path: Vec::new(),
+                            auth: [0; 32],
+                            props: Vec::new(),
+                        }],
+                        withdrawn_routes: Vec::new(),
+                    }
+                    .to_prepare(),
+                })
+                .await
+                .unwrap();
+        }
+        // Without the metrics the first account would win the tie
+        let local_table = service.local_table.read();
+        assert_eq!(local_table.get_route("example.remote").unwrap().0.id(), id2);
+    }
+
+    #[tokio::test]
+    async fn ranks_routes_using_measured_round_trip_times() {
+        let (mut service, _outgoing_requests) = test_service_with_routes();
+        let id1 = Uuid::from_slice(&[10; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[11; 16]).unwrap();
+        for (id, round_trip_time) in [(id1, 10), (id2, 100)].iter() {
+            service.store.route_metrics.lock().insert(
+                *id,
+                RouteMetrics {
+                    expected_round_trip_time: Some(*round_trip_time),
+                    balance_headroom: None,
+                },
+            );
+        }
+        // Only the second account's round trip time was measured
+        service.round_trip_times = Some(Arc::new(
+            move |id: Uuid| {
+                if id == id2 {
+                    Some(5)
+                } else {
+                    None
+                }
+            },
+        ));
+        for id in [id1, id2].iter() {
+            service
+                .handle_request(IncomingRequest {
+                    from: TestAccount::new(*id, "example.peer"),
+                    prepare: RouteUpdateRequest {
+                        routing_table_id: [0; 16],
+                        current_epoch_index: 1,
+                        from_epoch_index: 0,
+                        to_epoch_index: 1,
+                        hold_down_time: 30000,
+                        speaker: Address::from_str("example.peer").unwrap(),
+                        new_routes: vec![Route {
+                            prefix: "example.remote".to_string(),
+                            path: Vec::new(),
+                            auth: [0; 32],
+                            props: Vec::new(),
+                        }],
+                        withdrawn_routes: Vec::new(),
+                    }
+                    .to_prepare(),
+                })
+                .await
+                .unwrap();
+        }
+        let local_table = service.local_table.read();
+        assert_eq!(local_table.get_route("example.remote").unwrap().0.id(), id2);
+    }
+
+    #[tokio::test]
+    async fn publishes_routing_events() {
+        let (mut service, _outgoing_requests) = test_service_with_routes();
+        let mut events = service.store.routing_event_subscription();
+        let mut next_events = || {
+            let mut received = Vec::new();
+            while let Ok(event) = events.try_recv() {
+                received.push(event);
+            }
+            received.sort_unstable_by(|a, b| a.prefix.cmp(&b.prefix));
+            received
+        };
+        let event = |kind, prefix: &str, source, epoch| RoutingEvent {
+            kind,
+            prefix: prefix.to_string(),
+            next_hop: Some(ALICE.clone()),
+            source,
+            epoch: Some(epoch),
+        };
+
+        service.update_best_routes(None).await.unwrap();
+        assert_eq!(
+            next_events(),
+            vec![
+                event(
+                    RoutingEventKind::Added,
+                    "example.configured.1",
+                    RouteSource::Static,
+                    0
+                ),
+                event(
+                    RoutingEventKind::Added,
+                    "example.connector.other-local",
+                    RouteSource::Local,
+                    0
+                ),
+                event(
+                    RoutingEventKind::Added,
+                    "example.local.1",
+                    RouteSource::Local,
+                    0
+                ),
+            ]
+        );
+
+        let mut request = UPDATE_REQUEST_SIMPLE.clone();
+        request.from_epoch_index = 0;
+        request.to_epoch_index = 1;
+        request.new_routes = vec![Route {
+            prefix: "example.remote".to_string(),
+            path: Vec::new(),
+            auth: [0; 32],
+            props: Vec::new(),
+        }];
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            next_events(),
+            vec![event(
+                RoutingEventKind::Added,
+                "example.remote",
+                RouteSource::Ccp,
+                1
+            )]
+        );
+
+        let mut request = UPDATE_REQUEST_SIMPLE.clone();
+        request.from_epoch_index = 1;
+        request.to_epoch_index = 2;
+        request.current_epoch_index = 2;
+        request.new_routes = Vec::new();
+        request.withdrawn_routes = vec!["example.remote".to_string()];
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            next_events(),
+            vec![event(
+                RoutingEventKind::Withdrawn,
+                "example.remote",
+                RouteSource::Ccp,
+                2
+            )]
+        );
+    }
 }
 
 #[cfg(test)]
 mod create_route_update {
     use super::*;
     use crate::test_helpers::*;
+    use std::str::FromStr;
 
     #[tokio::test]
     async fn heartbeat_message_for_empty_table() {
@@ -1740,6 +3471,135 @@ mod create_route_update {
         assert!(!new_routes.contains(&"example.m"));
         assert_eq!(update.withdrawn_routes[0], "example.m");
     }
+
+    #[tokio::test]
+    async fn authenticates_own_route() {
+        let mut service = test_service();
+        assert_eq!(
+            service.create_route_update(0, 0).new_routes[0].auth,
+            [0; 32]
+        );
+        service.route_auth_secret = Some([9; 32]);
+        assert_eq!(
+            service.create_route_update(0, 0).new_routes[0].auth,
+            route_auth(&[9; 32], "example.connector")
+        );
+    }
+
+    #[tokio::test]
+    async fn uses_configured_hold_down_time() {
+        let mut service = test_service();
+        assert_eq!(service.create_route_update(0, 0).hold_down_time, 30000);
+        service.hold_down_time = 45000;
+        assert_eq!(service.create_route_update(0, 0).hold_down_time, 45000);
+    }
+
+    #[tokio::test]
+    async fn aggregates_and_filters_routes_for_export() {
+        let (mut service, _outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).await.unwrap();
+        let route_update = |from_epoch_index, new_routes: Vec<&str>, withdrawn: Vec<&str>| {
+            RouteUpdateRequest {
+                routing_table_id: [0; 16],
+                current_epoch_index: from_epoch_index + 1,
+                from_epoch_index,
+                to_epoch_index: from_epoch_index + 1,
+                hold_down_time: 30000,
+                speaker: Address::from_str("example.peer").unwrap(),
+                new_routes: new_routes
+                    .into_iter()
+                    .map(|prefix| Route {
+                        prefix: prefix.to_string(),
+                        path: vec!["example.peer".to_string()],
+                        auth: [0; 32],
+                        props: Vec::new(),
+                    })
+                    .collect(),
+                withdrawn_routes: withdrawn.into_iter().map(|p| p.to_string()).collect(),
+            }
+            .to_prepare()
+        };
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: route_update(
+                    0,
+                    vec!["example.region.a", "example.region.b", "example.private.c"],
+                    Vec::new(),
+                ),
+            })
+            .await
+            .unwrap();
+
+        let policy = RouteExportPolicy {
+            allow_prefixes: Vec::new(),
+            deny_prefixes: vec!["example.private".to_string()],
+            aggregate_prefixes: vec!["example.region".to_string()],
+        };
+        let epoch = service.forwarding_table.read().epoch();
+        let update = service.apply_export_policy(&policy, service.create_route_update(0, epoch));
+        let mut prefixes: Vec<&str> = update
+            .new_routes
+            .iter()
+            .map(|route| route.prefix.as_str())
+            .collect();
+        prefixes.sort_unstable();
+        assert_eq!(
+            prefixes,
+            vec![
+                "example.configured.1",
+                "example.connector",
+                "example.local.1",
+                "example.region"
+            ]
+        );
+        let aggregate = update
+            .new_routes
+            .iter()
+            .find(|route| route.prefix == "example.region")
+            .unwrap();
+        assert_eq!(aggregate.path, vec!["example.connector", "example.peer"]);
+        assert_eq!(update.withdrawn_routes, vec!["example.private.c"]);
+
+        // The aggregate route is only withdrawn once all the routes under it are
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: route_update(1, Vec::new(), vec!["example.region.a"]),
+            })
+            .await
+            .unwrap();
+        let update =
+            service.apply_export_policy(&policy, service.create_route_update(epoch, epoch + 1));
+        assert_eq!(update.new_routes.len(), 1);
+        assert_eq!(update.new_routes[0].prefix, "example.region");
+        assert!(update.withdrawn_routes.is_empty());
+
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: route_update(2, Vec::new(), vec!["example.region.b"]),
+            })
+            .await
+            .unwrap();
+        let update =
+            service.apply_export_policy(&policy, service.create_route_update(epoch + 1, epoch + 2));
+        assert!(update.new_routes.is_empty());
+        assert_eq!(update.withdrawn_routes, vec!["example.region"]);
+
+        // Withdrawals of denied prefixes are still sent
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: route_update(3, Vec::new(), vec!["example.private.c"]),
+            })
+            .await
+            .unwrap();
+        let update =
+            service.apply_export_policy(&policy, service.create_route_update(epoch + 2, epoch + 3));
+        assert!(update.new_routes.is_empty());
+        assert_eq!(update.withdrawn_routes, vec!["example.private.c"]);
+    }
 }
 
 #[cfg(test)]
@@ -1909,6 +3769,7 @@ mod send_route_updates {
                     id: id2,
                     ilp_address: Address::from_str("example.connector.other-local").unwrap(),
                     relation: RoutingRelation::Child,
+                    routing_policy: None,
                 },
             ),
         ]);
@@ -1997,6 +3858,7 @@ mod send_route_updates {
             id: id2,
             ilp_address: Address::from_str("example.connector.other-local").unwrap(),
             relation: RoutingRelation::Child,
+            routing_policy: None,
         };
         let local_routes = HashMap::from_iter(vec![
             (
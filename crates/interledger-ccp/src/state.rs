@@ -0,0 +1,108 @@
+use crate::{packet::Route, routing_table::RoutingTable};
+use interledger_service::Account;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// A snapshot of the routing state of the `CcpRouteManager`.
+///
+/// It is saved through the `CcpRoutingStore` so that, after a restart, the node keeps
+/// forwarding with the routes it had and resumes the incremental synchronization with its
+/// peers, in both directions, instead of starting over with empty routing tables.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CcpRoutingState {
+    /// The routing table we forward to our peers, if it was saved
+    #[serde(default)]
+    pub(crate) forwarding_table: Option<RoutingTableState>,
+    /// The epoch the first of the saved forwarding table updates starts from
+    #[serde(default)]
+    pub(crate) forwarding_table_updates_start: u32,
+    /// The routes added and withdrawn in each of the latest epochs of the forwarding table.
+    /// Peers which last saw an earlier epoch are sent the whole table instead
+    #[serde(default)]
+    pub(crate) forwarding_table_updates: Vec<(Vec<Route>, Vec<String>)>,
+    /// The routing table of each peer we received routes from
+    pub(crate) incoming_tables: HashMap<Uuid, RoutingTableState>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RoutingTableState {
+    pub(crate) id: [u8; 16],
+    pub(crate) epoch: u32,
+    /// The routes in the table, along with the ID of the account each one goes through
+    pub(crate) routes: Vec<(Uuid, Route)>,
+}
+
+impl<A: Account> From<&RoutingTable<A>> for RoutingTableState {
+    fn from(table: &RoutingTable<A>) -> Self {
+        RoutingTableState {
+            id: table.id(),
+            epoch: table.epoch(),
+            routes: table
+                .accounts_and_routes()
+                .map(|(account, route)| (account.id(), route.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl RoutingTableState {
+    /// Rebuild the routing table. Routes through accounts that are not in the
+    /// given map (for example because they were deleted) are left out
+    pub(crate) fn into_table<A: Account>(self, accounts: &HashMap<Uuid, A>) -> RoutingTable<A> {
+        let mut table = RoutingTable::new(self.id);
+        table.set_epoch(self.epoch);
+        for (account_id, route) in self.routes {
+            if let Some(account) = accounts.get(&account_id) {
+                table.add_route(account.clone(), route);
+            }
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use std::iter::FromIterator;
+
+    #[test]
+    fn restores_routing_tables() {
+        let mut table = RoutingTable::new([1; 16]);
+        table.set_epoch(5);
+        let route = |prefix: &str| Route {
+            prefix: prefix.to_string(),
+            path: vec!["example.peer".to_string()],
+            auth: [2; 32],
+            props: Vec::new(),
+        };
+        table.add_route(ROUTING_ACCOUNT.clone(), route("example.a"));
+        table.add_route(CHILD_ACCOUNT.clone(), route("example.b"));
+
+        let state = CcpRoutingState {
+            incoming_tables: HashMap::from_iter(vec![(
+                ROUTING_ACCOUNT.id(),
+                RoutingTableState::from(&table),
+            )]),
+            ..Default::default()
+        };
+        let mut state: CcpRoutingState =
+            serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+
+        let accounts = HashMap::from_iter(vec![(ROUTING_ACCOUNT.id(), ROUTING_ACCOUNT.clone())]);
+        let restored = state
+            .incoming_tables
+            .remove(&ROUTING_ACCOUNT.id())
+            .unwrap()
+            .into_table(&accounts);
+        assert_eq!(restored.id(), [1; 16]);
+        assert_eq!(restored.epoch(), 5);
+        assert_eq!(
+            restored.get_route("example.a").map(|(_, route)| route),
+            Some(&route("example.a"))
+        );
+        // The account the route went through is gone
+        assert!(restored.get_route("example.b").is_none());
+    }
+}
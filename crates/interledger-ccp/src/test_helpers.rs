@@ -13,21 +13,25 @@ use parking_lot::Mutex;
 use std::collections::HashMap;
 use std::str::FromStr;
 use std::{iter::FromIterator, sync::Arc};
+use tokio::sync::broadcast;
 
 pub static ROUTING_ACCOUNT: Lazy<TestAccount> = Lazy::new(|| TestAccount {
     id: Uuid::new_v4(),
     ilp_address: Address::from_str("example.peer").unwrap(),
     relation: RoutingRelation::Peer,
+    routing_policy: None,
 });
 pub static NON_ROUTING_ACCOUNT: Lazy<TestAccount> = Lazy::new(|| TestAccount {
     id: Uuid::new_v4(),
     ilp_address: Address::from_str("example.me.nonroutingaccount").unwrap(),
     relation: RoutingRelation::NonRoutingAccount,
+    routing_policy: None,
 });
 pub static CHILD_ACCOUNT: Lazy<TestAccount> = Lazy::new(|| TestAccount {
     id: Uuid::new_v4(),
     ilp_address: Address::from_str("example.me.child").unwrap(),
     relation: RoutingRelation::Child,
+    routing_policy: None,
 });
 pub static EXAMPLE_CONNECTOR: Lazy<Address> =
     Lazy::new(|| Address::from_str("example.connector").unwrap());
@@ -38,6 +42,7 @@ pub struct TestAccount {
     pub id: Uuid,
     pub ilp_address: Address,
     pub relation: RoutingRelation,
+    pub routing_policy: Option<RoutingPolicy>,
 }
 
 impl TestAccount {
@@ -46,6 +51,7 @@ impl TestAccount {
             id,
             ilp_address: Address::from_str(ilp_address).unwrap(),
             relation: RoutingRelation::Peer,
+            routing_policy: None,
         }
     }
 }
@@ -76,6 +82,10 @@ impl CcpRoutingAccount for TestAccount {
     fn routing_relation(&self) -> RoutingRelation {
         self.relation
     }
+
+    fn routing_policy(&self) -> Option<&RoutingPolicy> {
+        self.routing_policy.as_ref()
+    }
 }
 
 #[derive(Clone)]
@@ -83,6 +93,9 @@ pub struct TestStore {
     pub local: HashMap<String, TestAccount>,
     pub configured: HashMap<String, TestAccount>,
     pub routes: Arc<Mutex<HashMap<String, TestAccount>>>,
+    pub route_metrics: Arc<Mutex<HashMap<Uuid, RouteMetrics>>>,
+    pub routing_state: Arc<Mutex<Option<CcpRoutingState>>>,
+    pub routing_events: broadcast::Sender<RoutingEvent>,
 }
 
 impl TestStore {
@@ -91,6 +104,9 @@ impl TestStore {
             local: HashMap::new(),
             configured: HashMap::new(),
             routes: Arc::new(Mutex::new(HashMap::new())),
+            route_metrics: Arc::new(Mutex::new(HashMap::new())),
+            routing_state: Arc::new(Mutex::new(None)),
+            routing_events: broadcast::channel(16).0,
         }
     }
 
@@ -102,6 +118,9 @@ impl TestStore {
             local,
             configured,
             routes: Arc::new(Mutex::new(HashMap::new())),
+            route_metrics: Arc::new(Mutex::new(HashMap::new())),
+            routing_state: Arc::new(Mutex::new(None)),
+            routing_events: broadcast::channel(16).0,
         }
     }
 }
@@ -175,6 +194,40 @@ impl CcpRoutingStore for TestStore {
         *self.routes.lock() = routes.into_iter().collect();
         Ok(())
     }
+
+    async fn get_route_metrics(
+        &self,
+        account_ids: Vec<Uuid>,
+    ) -> Result<HashMap<Uuid, RouteMetrics>, CcpRoutingStoreError> {
+        let route_metrics = self.route_metrics.lock();
+        Ok(account_ids
+            .into_iter()
+            .filter_map(|id| route_metrics.get(&id).map(|metrics| (id, *metrics)))
+            .collect())
+    }
+
+    async fn save_routing_state(
+        &mut self,
+        state: CcpRoutingState,
+    ) -> Result<(), CcpRoutingStoreError> {
+        *self.routing_state.lock() = Some(state);
+        Ok(())
+    }
+
+    async fn load_routing_state(&self) -> Result<Option<CcpRoutingState>, CcpRoutingStoreError> {
+        Ok(self.routing_state.lock().clone())
+    }
+}
+
+impl RoutingEventsStore for TestStore {
+    fn publish_routing_event(&self, event: RoutingEvent) {
+        // There may not be any subscribers
+        let _ = self.routing_events.send(event);
+    }
+
+    fn routing_event_subscription(&self) -> broadcast::Receiver<RoutingEvent> {
+        self.routing_events.subscribe()
+    }
 }
 
 pub fn test_service() -> CcpRouteManager<
@@ -232,6 +285,7 @@ pub fn test_service_with_routes() -> (
                 id: Uuid::from_slice(&[3; 16]).unwrap(),
                 ilp_address: Address::from_str("example.connector.other-local").unwrap(),
                 relation: RoutingRelation::NonRoutingAccount,
+                routing_policy: None,
             },
         ),
     ]);
@@ -239,7 +293,20 @@ pub fn test_service_with_routes() -> (
         "example.configured.1".to_string(),
         TestAccount::new(Uuid::from_slice(&[2; 16]).unwrap(), "example.configured.1"),
     )]);
-    let store = TestStore::with_routes(local_routes, configured_routes);
+    test_service_with_store(TestStore::with_routes(local_routes, configured_routes))
+}
+
+pub fn test_service_with_store(
+    store: TestStore,
+) -> (
+    CcpRouteManager<
+        impl IncomingService<TestAccount> + Clone,
+        impl OutgoingService<TestAccount> + Clone,
+        TestStore,
+        TestAccount,
+    >,
+    OutgoingRequests,
+) {
     let outgoing_requests: Arc<Mutex<Vec<OutgoingRequest<TestAccount>>>> =
         Arc::new(Mutex::new(Vec::new()));
     let outgoing_requests_clone = outgoing_requests.clone();
@@ -250,7 +317,7 @@ pub fn test_service_with_routes() -> (
     let addr = Address::from_str("example.connector").unwrap();
     let service = CcpRouteManagerBuilder::new(
         addr.clone(),
-        store,
+        store.clone(),
         outgoing,
         incoming_service_fn(|_request| {
             Err(RejectBuilder {
@@ -263,6 +330,7 @@ pub fn test_service_with_routes() -> (
         }),
     )
     .ilp_address(addr)
+    .routing_events(store)
     .to_service();
     (service, outgoing_requests)
 }
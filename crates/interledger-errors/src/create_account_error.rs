@@ -16,6 +16,10 @@ pub enum CreateAccountError {
     InvalidHttpUrl(UrlParseError),
     #[error("the provided btp url is not valid: {0}")]
     InvalidBtpUrl(UrlParseError),
+    #[error("the ILP over HTTP client certificate and key must be provided together")]
+    IncompleteClientIdentity,
+    #[error("the provided client certificate fingerprint is not a hex SHA-256 digest: {0}")]
+    InvalidCertificateFingerprint(String),
     #[error("the provided routing relation is not valid: {0}")]
     InvalidRoutingRelation(String),
     #[error("the provided value for parameter `{0}` was too large")]
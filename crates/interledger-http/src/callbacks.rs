@@ -0,0 +1,80 @@
+use super::client::HttpClients;
+use interledger_service::IlpResult;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+/// The header with the ID that correlates an asynchronous ILP over HTTP response with its request
+pub const REQUEST_ID_HEADER: &str = "request-id";
+/// The header with the URL to POST the asynchronous ILP over HTTP response to
+pub const CALLBACK_URL_HEADER: &str = "callback-url";
+
+struct PendingRequest {
+    /// The account the Prepare was sent to, which is the only one allowed to respond to it
+    account_id: Uuid,
+    sender: oneshot::Sender<IlpResult>,
+}
+
+/// Correlates the responses POSTed back by peers in asynchronous ILP over HTTP mode
+/// with the Prepare packets they answer.
+///
+/// The [HttpClientService](./struct.HttpClientService.html) registers each Prepare it sends
+/// asynchronously and the [HttpServer](./struct.HttpServer.html) passes the responses it
+/// receives back to it, so both must be created with the same `HttpCallbacks`.
+#[derive(Clone)]
+pub struct HttpCallbacks {
+    pending: Arc<Mutex<HashMap<String, PendingRequest>>>,
+    /// Used by the server to POST the responses to the peers' callback URLs
+    clients: HttpClients,
+}
+
+impl HttpCallbacks {
+    pub fn new() -> Self {
+        HttpCallbacks {
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            clients: HttpClients::new(),
+        }
+    }
+
+    pub(crate) fn clients(&self) -> &HttpClients {
+        &self.clients
+    }
+
+    /// Returns a receiver for the response to the request with the given ID
+    pub(crate) fn register(
+        &self,
+        request_id: String,
+        account_id: Uuid,
+    ) -> oneshot::Receiver<IlpResult> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(request_id, PendingRequest { account_id, sender });
+        receiver
+    }
+
+    pub(crate) fn forget(&self, request_id: &str) {
+        self.pending.lock().unwrap().remove(request_id);
+    }
+
+    /// Passes the response back to the request with the given ID. Returns false if
+    /// no request with this ID is waiting for a response from the account
+    pub(crate) fn complete(&self, request_id: &str, account_id: Uuid, result: IlpResult) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.get(request_id) {
+            Some(request) if request.account_id == account_id => {
+                let request = pending.remove(request_id).unwrap();
+                request.sender.send(result).is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for HttpCallbacks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
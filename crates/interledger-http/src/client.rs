@@ -1,4 +1,7 @@
-use super::{HttpAccount, HttpStore};
+use super::{
+    HttpAccount, HttpCallbacks, HttpClientIdentity, HttpStore, CALLBACK_URL_HEADER,
+    REQUEST_ID_HEADER,
+};
 use async_trait::async_trait;
 use bytes::BytesMut;
 use futures::future::TryFutureExt;
@@ -6,11 +9,189 @@ use interledger_packet::{Address, ErrorCode, Packet, RejectBuilder};
 use interledger_service::*;
 use reqwest::{
     header::{HeaderMap, HeaderName, HeaderValue},
-    Client, ClientBuilder, Response as HttpResponse,
+    Client, ClientBuilder, Response as HttpResponse, StatusCode,
+};
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    io,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
-use secrecy::{ExposeSecret, SecretString};
-use std::{convert::TryFrom, marker::PhantomData, sync::Arc, time::Duration};
-use tracing::{error, trace};
+use tokio::sync::oneshot;
+use tracing::{debug, error, trace};
+use uuid::Uuid;
+
+/// How long requests to peers may take when neither the account's settings nor
+/// the Prepare's expiry set a shorter timeout
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Returns a builder for HTTP clients sending ILP packets. The requests' timeouts are set
+/// per request, from the account's settings and the Prepare's expiry
+fn client_builder() -> ClientBuilder {
+    let mut headers = HeaderMap::with_capacity(2);
+    headers.insert(
+        HeaderName::from_static("content-type"),
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    ClientBuilder::new().default_headers(headers)
+}
+
+/// Settings of the HTTP client used to send ILP over HTTP requests to an account.
+/// Durations are in milliseconds; unset values use reqwest's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpClientSettings {
+    /// The maximum number of idle connections to the peer kept in the pool
+    pub max_idle_connections: Option<usize>,
+    /// How long idle connections are kept in the pool
+    pub idle_timeout: Option<u64>,
+    /// The interval of the TCP keepalive probes sent on the connections
+    pub tcp_keepalive: Option<u64>,
+    /// Whether to use HTTP/2 without negotiating it first (the peer must support it)
+    pub http2_prior_knowledge: bool,
+    /// How long connecting to the peer may take
+    pub connect_timeout: Option<u64>,
+    /// How long a request may take (defaults to 30 seconds). Requests also time out
+    /// when the Prepare packet they carry expires.
+    pub request_timeout: Option<u64>,
+}
+
+impl HttpClientSettings {
+    /// The timeout of requests which are not bound by a Prepare's expiry
+    pub fn request_timeout(&self) -> Duration {
+        self.request_timeout
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    fn apply(&self, mut builder: ClientBuilder) -> ClientBuilder {
+        if let Some(max) = self.max_idle_connections {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(ms) = self.idle_timeout {
+            builder = builder.pool_idle_timeout(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.tcp_keepalive {
+            builder = builder.tcp_keepalive(Duration::from_millis(ms));
+        }
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        if let Some(ms) = self.connect_timeout {
+            builder = builder.connect_timeout(Duration::from_millis(ms));
+        }
+        builder
+    }
+}
+
+/// What an account's client was built with
+type ClientConfig = (HttpClientSettings, Option<HttpClientIdentity>);
+
+/// How long an account's client is kept without being used. This evicts the clients of
+/// accounts which were deleted or whose settings went back to the defaults
+const CLIENT_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+struct CachedClient {
+    client: Client,
+    last_used: Instant,
+}
+
+/// The clients of the accounts with their own settings, keyed by the account and the
+/// settings the client was built with
+#[derive(Default)]
+struct ClientCache {
+    clients: HashMap<(Uuid, ClientConfig), CachedClient>,
+    last_sweep: Option<Instant>,
+}
+
+impl ClientCache {
+    /// Returns the client built with the account's current settings, building it if needed
+    fn get<F>(
+        &mut self,
+        account_id: Uuid,
+        config: ClientConfig,
+        now: Instant,
+        build: F,
+    ) -> io::Result<Client>
+    where
+        F: FnOnce(&ClientConfig) -> io::Result<Client>,
+    {
+        self.sweep(now);
+        let key = (account_id, config);
+        if let Some(cached) = self.clients.get_mut(&key) {
+            cached.last_used = now;
+            return Ok(cached.client.clone());
+        }
+        let client = build(&key.1)?;
+        // The account's settings changed, so the client built with the old ones is not used anymore
+        self.clients.retain(|(id, _), _| *id != account_id);
+        self.clients.insert(
+            key,
+            CachedClient {
+                client: client.clone(),
+                last_used: now,
+            },
+        );
+        Ok(client)
+    }
+
+    /// Drops the clients that were not used for a while, at most once per idle timeout
+    fn sweep(&mut self, now: Instant) {
+        if matches!(self.last_sweep, Some(last) if now.duration_since(last) < CLIENT_IDLE_TIMEOUT) {
+            return;
+        }
+        self.last_sweep = Some(now);
+        self.clients
+            .retain(|_, cached| now.duration_since(cached.last_used) < CLIENT_IDLE_TIMEOUT);
+    }
+}
+
+/// The HTTP clients used to send ILP over HTTP requests. Accounts with their own client
+/// settings or a client certificate get their own client (and connection pool).
+#[derive(Clone)]
+pub(crate) struct HttpClients {
+    default: Client,
+    per_account: Arc<Mutex<ClientCache>>,
+}
+
+impl HttpClients {
+    pub(crate) fn new() -> Self {
+        HttpClients {
+            default: client_builder().build().unwrap(),
+            per_account: Arc::new(Mutex::new(ClientCache::default())),
+        }
+    }
+
+    /// Returns the client to send the account's requests with
+    ///
+    /// # Errors
+    /// If the account's client certificate or key cannot be read
+    pub(crate) fn get<A: HttpAccount>(&self, account: &A) -> io::Result<Client> {
+        let config = (
+            account.get_http_client_settings(),
+            account.get_http_client_identity(),
+        );
+        let mut clients = self.per_account.lock().unwrap();
+        if config == ClientConfig::default() {
+            clients.sweep(Instant::now());
+            return Ok(self.default.clone());
+        }
+        clients.get(account.id(), config, Instant::now(), |config| {
+            let mut builder = config.0.apply(client_builder());
+            if let Some(ref identity) = config.1 {
+                // Only the rustls backend can load PEM encoded identities
+                builder = builder.use_rustls_tls().identity(identity.load()?);
+            }
+            builder
+                .build()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        })
+    }
+}
 
 /// The HttpClientService implements [OutgoingService](../../interledger_service/trait.OutgoingService)
 /// for sending ILP Prepare packets over to the HTTP URL associated with the provided account
@@ -18,15 +199,17 @@ use tracing::{error, trace};
 /// the account in the request, then it is forwarded to the next service.
 #[derive(Clone)]
 pub struct HttpClientService<S, O, A> {
-    /// An HTTP client configured with a 30 second timeout by default. It is used to send the
+    /// HTTP clients configured per account. They are used to send the
     /// ILP over HTTP messages to the peer
-    client: Client,
+    clients: HttpClients,
     /// The store used by the client to get the node's ILP Address,
     /// used to populate the `triggered_by` field in Reject packets
     store: Arc<S>,
     /// The next outgoing service to which non ILP-over-HTTP requests should
     /// be forwarded to
     next: O,
+    /// The Prepare packets sent in asynchronous mode that are waiting for their responses
+    callbacks: HttpCallbacks,
     account_type: PhantomData<A>,
 }
 
@@ -38,24 +221,27 @@ where
 {
     /// Constructs the HttpClientService
     pub fn new(store: S, next: O) -> Self {
-        let mut headers = HeaderMap::with_capacity(2);
-        headers.insert(
-            HeaderName::from_static("content-type"),
-            HeaderValue::from_static("application/octet-stream"),
-        );
-        let client = ClientBuilder::new()
-            .default_headers(headers)
-            .timeout(Duration::from_secs(30))
-            .build()
-            .unwrap();
+        Self::with_callbacks(store, next, HttpCallbacks::new())
+    }
 
+    /// Constructs an HttpClientService which waits for the responses to the packets it
+    /// sends in asynchronous mode on the given callbacks. The node's [HttpServer](./struct.HttpServer.html)
+    /// must be created with the same callbacks to receive the responses.
+    /// The service sends its packets with the same HTTP clients as the callbacks
+    pub fn with_callbacks(store: S, next: O, callbacks: HttpCallbacks) -> Self {
         HttpClientService {
-            client,
+            clients: callbacks.clients().clone(),
             store: Arc::new(store),
             next,
+            callbacks,
             account_type: PhantomData,
         }
     }
+
+    /// Returns the callbacks the service waits for the asynchronous responses on
+    pub fn callbacks(&self) -> &HttpCallbacks {
+        &self.callbacks
+    }
 }
 
 #[async_trait]
@@ -76,22 +262,67 @@ where
                 request.to.id(),
                 url.as_str()
             );
-            let token = request
-                .to
-                .get_http_auth_token()
-                .unwrap_or_else(|| SecretString::new("".to_owned()));
-            let header = format!("Bearer {}", token.expose_secret());
             let body = request.prepare.as_ref().to_owned();
-            let resp = self_clone
-                .client
-                .post(url.as_ref())
-                .header("authorization", &header)
-                .body(body)
+            let expires_at = request.prepare.expires_at();
+            // The peer's response is useless once the Prepare expired
+            let timeout = expires_at
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
+                .min(request.to.get_http_client_settings().request_timeout());
+            if timeout == Duration::from_secs(0) {
+                return Err(RejectBuilder {
+                    code: ErrorCode::R00_TRANSFER_TIMED_OUT,
+                    message: b"Prepare expired before it could be sent over HTTP",
+                    triggered_by: Some(&ilp_address),
+                    data: &[],
+                }
+                .build());
+            }
+            let client = self_clone.clients.get(&request.to).map_err(|err| {
+                error!(
+                    "Error creating the HTTP client of account {}: {}",
+                    request.to.id(),
+                    err
+                );
+                RejectBuilder {
+                    code: ErrorCode::T00_INTERNAL_ERROR,
+                    message: b"Error creating the ILP over HTTP client",
+                    triggered_by: Some(&ilp_address),
+                    data: &[],
+                }
+                .build()
+            })?;
+            let mut http_request = client.post(url.as_ref()).timeout(timeout).body(body);
+            // Accounts authenticated by their client certificate don't need a token
+            if let Some(token) = request.to.get_http_auth_token() {
+                http_request = http_request
+                    .header("authorization", format!("Bearer {}", token.expose_secret()));
+            }
+
+            // Ask the peer to POST the response back to us instead of holding the connection open
+            let mut pending = None;
+            if let Some(callback_url) = request.to.get_http_callback_url() {
+                let request_id = Uuid::new_v4().to_string();
+                let receiver = self.callbacks.register(request_id.clone(), request.to.id());
+                http_request = http_request
+                    .header(REQUEST_ID_HEADER, &request_id)
+                    .header(CALLBACK_URL_HEADER, callback_url.as_str());
+                pending = Some((request_id, receiver));
+            }
+
+            let callbacks = self.callbacks.clone();
+            let pending_request_id = pending.as_ref().map(|(request_id, _)| request_id.clone());
+            let resp = http_request
                 .send()
                 .map_err(move |err| {
+                    if let Some(request_id) = pending_request_id {
+                        callbacks.forget(&request_id);
+                    }
                     error!("Error sending HTTP request: {:?}", err);
                     let mut code = ErrorCode::T01_PEER_UNREACHABLE;
-                    if let Some(status) = err.status() {
+                    if err.is_timeout() {
+                        code = ErrorCode::R00_TRANSFER_TIMED_OUT
+                    } else if let Some(status) = err.status() {
                         if status.is_client_error() {
                             code = ErrorCode::F00_BAD_REQUEST
                         }
@@ -107,13 +338,56 @@ where
                     .build()
                 })
                 .await?;
-            parse_packet_from_response(resp, ilp_address_clone).await
+            match pending {
+                Some((request_id, receiver)) if resp.status() == StatusCode::ACCEPTED => {
+                    self.wait_for_callback(request_id, receiver, expires_at, ilp_address_clone)
+                        .await
+                }
+                Some((request_id, _)) => {
+                    // The peer doesn't support the asynchronous mode, so the response is in the body
+                    debug!(
+                        "Account {} answered the asynchronous ILP over HTTP request synchronously",
+                        request.to.id()
+                    );
+                    self.callbacks.forget(&request_id);
+                    parse_packet_from_response(resp, ilp_address_clone).await
+                }
+                None => parse_packet_from_response(resp, ilp_address_clone).await,
+            }
         } else {
             self.next.send_request(request).await
         }
     }
 }
 
+impl<S, O, A> HttpClientService<S, O, A> {
+    /// Waits until the peer POSTs the response to the Prepare back to us or the Prepare expires
+    async fn wait_for_callback(
+        &self,
+        request_id: String,
+        receiver: oneshot::Receiver<IlpResult>,
+        expires_at: SystemTime,
+        ilp_address: Address,
+    ) -> IlpResult {
+        let timeout = expires_at
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(result)) => result,
+            _ => {
+                self.callbacks.forget(&request_id);
+                Err(RejectBuilder {
+                    code: ErrorCode::R00_TRANSFER_TIMED_OUT,
+                    message: b"Timed out waiting for the asynchronous ILP over HTTP response",
+                    triggered_by: Some(&ilp_address),
+                    data: &[],
+                }
+                .build())
+            }
+        }
+    }
+}
+
 /// Parses an ILP over HTTP response.
 ///
 /// # Errors
@@ -149,7 +423,11 @@ async fn parse_packet_from_response(response: HttpResponse, ilp_address: Address
         .map_err(|err| {
             error!("Error getting HTTP response body: {:?}", err);
             RejectBuilder {
-                code: ErrorCode::T01_PEER_UNREACHABLE,
+                code: if err.is_timeout() {
+                    ErrorCode::R00_TRANSFER_TIMED_OUT
+                } else {
+                    ErrorCode::T01_PEER_UNREACHABLE
+                },
                 message: &[],
                 triggered_by: Some(&ilp_address_clone),
                 data: &[],
@@ -171,3 +449,47 @@ async fn parse_packet_from_response(response: HttpResponse, ilp_address: Address
         .build()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caches_clients_by_account_and_settings() {
+        let mut cache = ClientCache::default();
+        let alice = Uuid::new_v4();
+        let bob = Uuid::new_v4();
+        let settings = |max_idle_connections| {
+            (
+                HttpClientSettings {
+                    max_idle_connections: Some(max_idle_connections),
+                    ..Default::default()
+                },
+                None,
+            )
+        };
+        let mut builds = 0;
+        let mut build = |_: &ClientConfig| {
+            builds += 1;
+            Ok(Client::new())
+        };
+        let start = Instant::now();
+
+        cache.get(alice, settings(1), start, &mut build).unwrap();
+        cache.get(alice, settings(1), start, &mut build).unwrap();
+        cache.get(bob, settings(1), start, &mut build).unwrap();
+        assert_eq!(cache.clients.len(), 2);
+
+        // Changing the settings replaces the account's client
+        cache.get(alice, settings(2), start, &mut build).unwrap();
+        assert_eq!(cache.clients.len(), 2);
+        assert!(!cache.clients.contains_key(&(alice, settings(1))));
+
+        // Clients which are not used anymore (for example because the account was deleted) expire
+        let later = start + CLIENT_IDLE_TIMEOUT + Duration::from_secs(1);
+        cache.get(alice, settings(2), later, &mut build).unwrap();
+        assert_eq!(cache.clients.len(), 1);
+        assert!(cache.clients.contains_key(&(alice, settings(2))));
+        assert_eq!(builds, 4);
+    }
+}
@@ -12,13 +12,19 @@ use serde::de::DeserializeOwned;
 use url::Url;
 use warp::{self, Filter, Rejection};
 
+/// Correlation of asynchronous [ILP over HTTP](https://interledger.org/rfcs/0035-ilp-over-http/) responses
+mod callbacks;
 /// [ILP over HTTP](https://interledger.org/rfcs/0035-ilp-over-http/) Outgoing Service
 mod client;
 /// [ILP over HTTP](https://interledger.org/rfcs/0035-ilp-over-http/) API (implemented with [Warp](https://docs.rs/warp/0.2.0/warp/))
 mod server;
+/// Mutual TLS for [ILP over HTTP](https://interledger.org/rfcs/0035-ilp-over-http/)
+mod tls;
 
-pub use self::client::HttpClientService;
+pub use self::callbacks::{HttpCallbacks, CALLBACK_URL_HEADER, REQUEST_ID_HEADER};
+pub use self::client::{HttpClientService, HttpClientSettings};
 pub use self::server::HttpServer;
+pub use self::tls::{serve_tls, tls_server_config, ClientCertificate, HttpClientIdentity};
 
 /// Extension trait for [Account](../interledger_service/trait.Account.html) with [ILP over HTTP](https://interledger.org/rfcs/0035-ilp-over-http/) related information
 pub trait HttpAccount: Account {
@@ -26,6 +32,20 @@ pub trait HttpAccount: Account {
     fn get_http_url(&self) -> Option<&Url>;
     /// Returns the HTTP token which is sent as an HTTP header on each ILP over HTTP request
     fn get_http_auth_token(&self) -> Option<SecretString>;
+    /// Returns the URL the peer should POST its responses to. If this is set, Prepare packets
+    /// are sent to the account in asynchronous mode (unless the peer answers them synchronously)
+    fn get_http_callback_url(&self) -> Option<&Url> {
+        None
+    }
+    /// Returns the client certificate and key presented to the peer on ILP over HTTP
+    /// requests, if the peer requires mutual TLS
+    fn get_http_client_identity(&self) -> Option<HttpClientIdentity> {
+        None
+    }
+    /// Returns the settings of the HTTP client used to send ILP over HTTP requests to the account
+    fn get_http_client_settings(&self) -> HttpClientSettings {
+        HttpClientSettings::default()
+    }
 }
 
 /// The interface for Stores that can be used with the HttpServerService.
@@ -41,6 +61,17 @@ pub trait HttpStore: Clone + Send + Sync + 'static {
         username: &Username,
         token: &str,
     ) -> Result<Self::Account, HttpStoreError>;
+
+    /// Load account details based on the certificate the client presented on a
+    /// mutual TLS connection. Stores which don't map certificates to accounts
+    /// reject every certificate.
+    async fn get_account_from_client_certificate(
+        &self,
+        username: &Username,
+        _certificate: &ClientCertificate,
+    ) -> Result<Self::Account, HttpStoreError> {
+        Err(HttpStoreError::Unauthorized(username.to_string()))
+    }
 }
 
 // TODO: Do we really need this custom deserialization function?
@@ -1,14 +1,19 @@
-use super::HttpStore;
+use super::{
+    ClientCertificate, HttpAccount, HttpCallbacks, HttpStore, CALLBACK_URL_HEADER,
+    REQUEST_ID_HEADER,
+};
 use bytes::{Bytes, BytesMut};
 use interledger_errors::ApiError;
-use interledger_packet::Prepare;
+use interledger_packet::{Packet, Prepare};
 use interledger_service::Username;
-use interledger_service::{IncomingRequest, IncomingService};
+use interledger_service::{Account, IlpResult, IncomingRequest, IncomingService};
 use secrecy::{ExposeSecret, SecretString};
 use std::convert::TryFrom;
 use std::net::SocketAddr;
-use tracing::error;
-use warp::{Filter, Rejection};
+use tracing::{debug, error, trace};
+use url::Url;
+use uuid::Uuid;
+use warp::{http::Response, Filter, Rejection};
 
 /// Max message size that is allowed to transfer from a request or a message.
 pub const MAX_PACKET_SIZE: u64 = 40000;
@@ -24,20 +29,37 @@ pub struct HttpServer<I, S> {
     incoming: I,
     /// A store which implements [`HttpStore`](trait.HttpStore.html)
     store: S,
+    /// Used to pass the asynchronous responses we receive back to the
+    /// [`HttpClientService`](struct.HttpClientService.html) waiting for them
+    callbacks: HttpCallbacks,
 }
 
 #[inline]
 /// Returns the account which matches the provided username/password combination
+/// (or the client certificate, if the request has no authorization header)
 /// from the store, or returns an error if the account was not found or if the
 /// credentials were incorrect
 async fn get_account<S>(
     store: S,
     path_username: &Username,
-    password: &SecretString,
+    password: Option<SecretString>,
+    certificate: Option<ClientCertificate>,
 ) -> Result<S::Account, ApiError>
 where
     S: HttpStore,
 {
+    let password = match (password, certificate) {
+        (Some(password), _) => password,
+        (None, Some(certificate)) => {
+            return Ok(store
+                .get_account_from_client_certificate(path_username, &certificate)
+                .await?)
+        }
+        (None, None) => {
+            return Err(ApiError::unauthorized()
+                .detail("request has neither an authorization header nor a client certificate"))
+        }
+    };
     if password.expose_secret().len() < BEARER_TOKEN_START {
         return Err(ApiError::unauthorized().detail("provided token was not a bearer token"));
     }
@@ -49,62 +71,206 @@ where
         .await?)
 }
 
+fn packet_response(status: u16, body: Bytes) -> Response<Bytes> {
+    Response::builder()
+        .header("Content-Type", "application/octet-stream")
+        .status(status)
+        .body(body)
+        .unwrap()
+}
+
+/// Only POST responses to the same origin as the account's ILP over HTTP URL, so that
+/// peers can't make us send requests to arbitrary URLs
+fn accepts_callback_url<A: HttpAccount>(account: &A, callback_url: &Url) -> bool {
+    account
+        .get_http_url()
+        .map(|url| url.origin() == callback_url.origin())
+        .unwrap_or(false)
+}
+
 #[inline]
+#[allow(clippy::too_many_arguments)]
 /// Implements ILP over HTTP. If account authentication is valid
 /// and the provided packet can be parsed as a
 /// [Prepare](../../interledger_packet/struct.Prepare.html) packet,
 /// then it is forwarded to the next incoming service which will return
 /// an Ok result if the response is a [Fulfill](../../interledger_packet/struct.Fulfill.html).
 ///
+/// If the request has `Request-Id` and `Callback-Url` headers, it is answered right away
+/// and the response to the Prepare is POSTed to the callback URL (asynchronous mode).
+/// Fulfill and Reject packets with a `Request-Id` header are the asynchronous responses
+/// to the Prepare packets we sent.
+///
 /// # Errors
-/// 1. Unauthorized account if invalid credentials are provided
-/// 1. The provided `body` could not be parsed as a Prepare packet
-/// 1. A Reject packet was returned by the next incoming service
+/// 1. Unauthorized account if invalid credentials (or an unknown client certificate) are provided
+/// 1. The provided `body` could not be parsed as an ILP packet
+/// 1. A Fulfill or Reject packet does not answer a Prepare we sent to the account
 async fn ilp_over_http<S, I>(
     path_username: Username,
-    password: SecretString,
+    password: Option<SecretString>,
+    certificate: Option<ClientCertificate>,
+    request_id: Option<String>,
+    callback_url: Option<Url>,
     body: Bytes,
     store: S,
-    mut incoming: I,
+    incoming: I,
+    callbacks: HttpCallbacks,
 ) -> Result<impl warp::Reply, warp::Rejection>
 where
     S: HttpStore,
-    I: IncomingService<S::Account> + Clone,
+    I: IncomingService<S::Account> + Clone + Send + 'static,
 {
-    let account = get_account(store, &path_username, &password).await?;
+    let account = get_account(store, &path_username, password, certificate).await?;
 
     let buffer = bytes::BytesMut::from(body.as_ref());
-    if let Ok(prepare) = Prepare::try_from(buffer) {
-        let result = incoming
-            .handle_request(IncomingRequest {
-                from: account,
-                prepare,
-            })
-            .await;
-
-        let bytes: BytesMut = match result {
-            Ok(fulfill) => fulfill.into(),
-            Err(reject) => reject.into(),
-        };
+    match Packet::try_from(buffer) {
+        Ok(Packet::Prepare(prepare)) => match (request_id, callback_url) {
+            (Some(request_id), Some(callback_url))
+                if accepts_callback_url(&account, &callback_url) =>
+            {
+                trace!(
+                    "Handling asynchronous ILP over HTTP request {} from account {}",
+                    request_id,
+                    account.id()
+                );
+                tokio::spawn(respond_to_callback_url(
+                    account,
+                    prepare,
+                    request_id,
+                    callback_url,
+                    incoming,
+                    callbacks,
+                ));
+                Ok(packet_response(202, Bytes::new()))
+            }
+            (request_id, callback_url) => {
+                if request_id.is_some() || callback_url.is_some() {
+                    debug!(
+                        "Answering asynchronous ILP over HTTP request from account {} synchronously",
+                        account.id()
+                    );
+                }
+                let bytes = handle_prepare(account, prepare, incoming).await;
+                Ok(packet_response(200, bytes.freeze()))
+            }
+        },
+        Ok(Packet::Fulfill(fulfill)) => {
+            complete_callback(&callbacks, request_id, account.id(), Ok(fulfill))
+        }
+        Ok(Packet::Reject(reject)) => {
+            complete_callback(&callbacks, request_id, account.id(), Err(reject))
+        }
+        Err(_) => {
+            error!("Body was not a valid ILP packet");
+            Err(Rejection::from(ApiError::invalid_ilp_packet()))
+        }
+    }
+}
 
-        Ok(warp::http::Response::builder()
-            .header("Content-Type", "application/octet-stream")
-            .status(200)
-            .body(bytes.freeze()) // TODO: bring this back
-            .unwrap())
+/// Passes an asynchronous response from the account back to the Prepare it answers
+fn complete_callback(
+    callbacks: &HttpCallbacks,
+    request_id: Option<String>,
+    account_id: Uuid,
+    result: IlpResult,
+) -> Result<Response<Bytes>, Rejection> {
+    let request_id = request_id.ok_or_else(|| {
+        Rejection::from(
+            ApiError::bad_request()
+                .detail("Fulfill and Reject packets must have a Request-Id header"),
+        )
+    })?;
+    if callbacks.complete(&request_id, account_id, result) {
+        Ok(packet_response(200, Bytes::new()))
     } else {
-        error!("Body was not a valid Prepare packet");
-        Err(Rejection::from(ApiError::invalid_ilp_packet()))
+        Err(Rejection::from(ApiError::not_found().detail(
+            "No request with this Request-Id is waiting for a response from the account",
+        )))
+    }
+}
+
+async fn handle_prepare<A, I>(account: A, prepare: Prepare, mut incoming: I) -> BytesMut
+where
+    A: Account,
+    I: IncomingService<A>,
+{
+    let result = incoming
+        .handle_request(IncomingRequest {
+            from: account,
+            prepare,
+        })
+        .await;
+    match result {
+        Ok(fulfill) => fulfill.into(),
+        Err(reject) => reject.into(),
+    }
+}
+
+/// Handles the Prepare and POSTs the response to the callback URL, authenticated
+/// with the account's outgoing token (or client certificate)
+async fn respond_to_callback_url<A, I>(
+    account: A,
+    prepare: Prepare,
+    request_id: String,
+    callback_url: Url,
+    incoming: I,
+    callbacks: HttpCallbacks,
+) where
+    A: HttpAccount,
+    I: IncomingService<A>,
+{
+    let account_id = account.id();
+    let token = account.get_http_auth_token();
+    let timeout = account.get_http_client_settings().request_timeout();
+    let client = match callbacks.clients().get(&account) {
+        Ok(client) => client,
+        Err(err) => {
+            error!(
+                "Error creating the HTTP client of account {}: {}",
+                account_id, err
+            );
+            return;
+        }
+    };
+    let bytes = handle_prepare(account, prepare, incoming).await;
+    let mut http_request = client
+        .post(callback_url.as_ref())
+        .timeout(timeout)
+        .header(REQUEST_ID_HEADER, &request_id)
+        .body(bytes.freeze());
+    if let Some(token) = token {
+        http_request =
+            http_request.header("authorization", format!("Bearer {}", token.expose_secret()));
+    }
+    let result = http_request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    if let Err(err) = result {
+        error!(
+            "Error sending the response to asynchronous ILP over HTTP request {} to account {}: {:?}",
+            request_id, account_id, err
+        );
     }
 }
 
 impl<I, S> HttpServer<I, S>
 where
-    I: IncomingService<S::Account> + Clone + Send + Sync,
+    I: IncomingService<S::Account> + Clone + Send + Sync + 'static,
     S: HttpStore + Clone,
 {
     pub fn new(incoming: I, store: S) -> Self {
-        HttpServer { incoming, store }
+        Self::with_callbacks(incoming, store, HttpCallbacks::new())
+    }
+
+    /// Creates a server which passes the asynchronous responses it receives to the
+    /// [`HttpClientService`](struct.HttpClientService.html) created with the same callbacks
+    pub fn with_callbacks(incoming: I, store: S, callbacks: HttpCallbacks) -> Self {
+        HttpServer {
+            incoming,
+            store,
+            callbacks,
+        }
     }
 
     /// Returns a Warp filter which exposes per-account endpoints for [ILP over HTTP](https://interledger.org/rfcs/0035-ilp-over-http/).
@@ -114,18 +280,24 @@ where
     ) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
         let store = self.store.clone();
         let incoming = self.incoming.clone();
+        let callbacks = self.callbacks.clone();
         let with_store = warp::any().map(move || store.clone());
         let with_incoming = warp::any().map(move || incoming.clone());
+        let with_callbacks = warp::any().map(move || callbacks.clone());
         warp::post()
             .and(warp::path("accounts"))
             .and(warp::path::param::<Username>())
             .and(warp::path("ilp"))
             .and(warp::path::end())
-            .and(warp::header::<SecretString>("authorization"))
+            .and(warp::header::optional::<SecretString>("authorization"))
+            .and(warp::ext::optional::<ClientCertificate>())
+            .and(warp::header::optional::<String>(REQUEST_ID_HEADER))
+            .and(warp::header::optional::<Url>(CALLBACK_URL_HEADER))
             .and(warp::body::content_length_limit(MAX_PACKET_SIZE))
             .and(warp::body::bytes())
             .and(with_store)
             .and(with_incoming)
+            .and(with_callbacks)
             .and_then(ilp_over_http)
     }
 
@@ -140,17 +312,21 @@ where
 mod tests {
     use super::*;
     use crate::HttpAccount;
+    use crate::HttpClientService;
     use async_trait::async_trait;
     use bytes::BytesMut;
     use http::Response;
-    use interledger_errors::{default_rejection_handler, HttpStoreError};
-    use interledger_packet::{Address, ErrorCode, PrepareBuilder, RejectBuilder};
-    use interledger_service::{incoming_service_fn, Account};
+    use interledger_errors::{default_rejection_handler, AddressStoreError, HttpStoreError};
+    use interledger_packet::{Address, ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder};
+    use interledger_service::{
+        incoming_service_fn, outgoing_service_fn, Account, AddressStore, OutgoingRequest,
+        OutgoingService,
+    };
     use once_cell::sync::Lazy;
     use secrecy::SecretString;
     use std::convert::TryInto;
     use std::str::FromStr;
-    use std::time::SystemTime;
+    use std::time::{Duration, SystemTime};
     use url::Url;
     use uuid::Uuid;
 
@@ -271,4 +447,295 @@ mod tests {
             }
         }
     }
+
+    #[derive(Debug, Clone)]
+    struct AsyncTestAccount {
+        id: Uuid,
+        url: Url,
+        callback_url: Option<Url>,
+    }
+
+    impl Account for AsyncTestAccount {
+        fn id(&self) -> Uuid {
+            self.id
+        }
+
+        fn username(&self) -> &Username {
+            &USERNAME
+        }
+        fn ilp_address(&self) -> &Address {
+            &ILP_ADDRESS
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+    }
+
+    impl HttpAccount for AsyncTestAccount {
+        fn get_http_auth_token(&self) -> Option<SecretString> {
+            Some(SecretString::new(AUTH_PASSWORD.to_string()))
+        }
+
+        fn get_http_url(&self) -> Option<&Url> {
+            Some(&self.url)
+        }
+
+        fn get_http_callback_url(&self) -> Option<&Url> {
+            self.callback_url.as_ref()
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct AsyncTestStore {
+        account: AsyncTestAccount,
+    }
+
+    impl AsyncTestStore {
+        fn new(url: &str) -> Self {
+            let url = Url::parse(url).unwrap();
+            AsyncTestStore {
+                account: AsyncTestAccount {
+                    id: Uuid::new_v4(),
+                    callback_url: Some(url.clone()),
+                    url,
+                },
+            }
+        }
+    }
+
+    #[async_trait]
+    impl HttpStore for AsyncTestStore {
+        type Account = AsyncTestAccount;
+
+        async fn get_account_from_http_auth(
+            &self,
+            username: &Username,
+            token: &str,
+        ) -> Result<Self::Account, HttpStoreError> {
+            if username == &*USERNAME && token == AUTH_PASSWORD {
+                Ok(self.account.clone())
+            } else {
+                Err(HttpStoreError::Unauthorized(username.to_string()))
+            }
+        }
+
+        async fn get_account_from_client_certificate(
+            &self,
+            username: &Username,
+            certificate: &ClientCertificate,
+        ) -> Result<Self::Account, HttpStoreError> {
+            if username == &*USERNAME && certificate == &*CLIENT_CERTIFICATE {
+                Ok(self.account.clone())
+            } else {
+                Err(HttpStoreError::Unauthorized(username.to_string()))
+            }
+        }
+    }
+
+    #[async_trait]
+    impl AddressStore for AsyncTestStore {
+        async fn set_ilp_address(&self, _ilp_address: Address) -> Result<(), AddressStoreError> {
+            Ok(())
+        }
+
+        async fn clear_ilp_address(&self) -> Result<(), AddressStoreError> {
+            Ok(())
+        }
+
+        fn get_ilp_address(&self) -> Address {
+            ILP_ADDRESS.clone()
+        }
+    }
+
+    fn fulfill_incoming() -> impl IncomingService<AsyncTestAccount> + Clone + Send + Sync {
+        incoming_service_fn(|_request| {
+            Ok(FulfillBuilder {
+                fulfillment: &[0; 32],
+                data: b"async",
+            }
+            .build())
+        })
+    }
+
+    #[tokio::test]
+    async fn answers_asynchronous_requests_later() {
+        let store = AsyncTestStore::new("http://127.0.0.1:1/accounts/alice/ilp");
+        let api = HttpServer::new(fulfill_incoming(), store)
+            .as_filter()
+            .recover(default_rejection_handler);
+        let request = || {
+            warp::test::request()
+                .method("POST")
+                .path("/accounts/alice/ilp")
+                .header("Authorization", format!("Bearer {}", AUTH_PASSWORD))
+                .header("Content-length", 1000)
+                .header("Request-Id", "1")
+                .body(PREPARE_BYTES.clone())
+        };
+
+        let resp = request()
+            .header("Callback-Url", "http://127.0.0.1:1/accounts/alice/ilp")
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status().as_u16(), 202);
+        assert!(resp.body().is_empty());
+
+        // The response is not POSTed to other origins than the account's URL
+        let resp = request()
+            .header("Callback-Url", "http://example.com/accounts/alice/ilp")
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert!(!resp.body().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_unexpected_asynchronous_responses() {
+        let store = AsyncTestStore::new("http://127.0.0.1:1/accounts/alice/ilp");
+        let api = HttpServer::new(fulfill_incoming(), store)
+            .as_filter()
+            .recover(default_rejection_handler);
+        let reject: BytesMut = RejectBuilder {
+            code: ErrorCode::F02_UNREACHABLE,
+            message: &[],
+            data: &[],
+            triggered_by: Some(&ILP_ADDRESS),
+        }
+        .build()
+        .into();
+        let request = || {
+            warp::test::request()
+                .method("POST")
+                .path("/accounts/alice/ilp")
+                .header("Authorization", format!("Bearer {}", AUTH_PASSWORD))
+                .header("Content-length", 1000)
+                .body(reject.clone())
+        };
+
+        let resp = request().header("Request-Id", "unknown").reply(&api).await;
+        assert_eq!(resp.status().as_u16(), 404);
+
+        let resp = request().reply(&api).await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+
+    #[tokio::test]
+    async fn sends_and_receives_asynchronous_responses() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        // The node sends the packets to itself, so the response is POSTed back to the same server
+        let store = AsyncTestStore::new(&format!("http://127.0.0.1:{}/accounts/alice/ilp", port));
+        let callbacks = HttpCallbacks::new();
+        let server =
+            HttpServer::with_callbacks(fulfill_incoming(), store.clone(), callbacks.clone());
+        tokio::spawn(warp::serve(server.as_filter()).bind(([127, 0, 0, 1], port)));
+
+        let mut client = HttpClientService::with_callbacks(
+            store.clone(),
+            outgoing_service_fn(|_request| panic!("The packet should be sent over HTTP")),
+            callbacks,
+        );
+        let fulfill = client
+            .send_request(OutgoingRequest {
+                from: store.account.clone(),
+                to: store.account.clone(),
+                original_amount: 0,
+                prepare: PrepareBuilder {
+                    amount: 0,
+                    destination: ILP_ADDRESS.clone(),
+                    expires_at: SystemTime::now() + Duration::from_secs(5),
+                    execution_condition: &[0; 32],
+                    data: &[],
+                }
+                .build(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(fulfill.data(), b"async");
+    }
+
+    static CLIENT_CERTIFICATE: Lazy<ClientCertificate> =
+        Lazy::new(|| ClientCertificate::from_der(b"certificate"));
+
+    #[tokio::test]
+    async fn authenticates_client_certificates() {
+        let store = AsyncTestStore::new("http://127.0.0.1:1/accounts/alice/ilp");
+        let api = HttpServer::new(fulfill_incoming(), store)
+            .as_filter()
+            .recover(default_rejection_handler);
+        let request = || {
+            warp::test::request()
+                .method("POST")
+                .path("/accounts/alice/ilp")
+                .header("Content-length", 1000)
+                .body(PREPARE_BYTES.clone())
+        };
+
+        let resp = request()
+            .extension(CLIENT_CERTIFICATE.clone())
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let resp = request()
+            .extension(ClientCertificate::from_der(b"other certificate"))
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let resp = request().reply(&api).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[tokio::test]
+    async fn times_out_when_the_prepare_expires() {
+        // The peer accepts the connection but never responds
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+        let mut store =
+            AsyncTestStore::new(&format!("http://127.0.0.1:{}/accounts/alice/ilp", port));
+        store.account.callback_url = None;
+        let mut client = HttpClientService::new(
+            store.clone(),
+            outgoing_service_fn(|_request| panic!("The packet should be sent over HTTP")),
+        );
+        let request = |expires_at| OutgoingRequest {
+            from: store.account.clone(),
+            to: store.account.clone(),
+            original_amount: 0,
+            prepare: PrepareBuilder {
+                amount: 0,
+                destination: ILP_ADDRESS.clone(),
+                expires_at,
+                execution_condition: &[0; 32],
+                data: &[],
+            }
+            .build(),
+        };
+
+        let reject = client
+            .send_request(request(SystemTime::now() + Duration::from_millis(200)))
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::R00_TRANSFER_TIMED_OUT);
+
+        let reject = client
+            .send_request(request(SystemTime::now() - Duration::from_secs(1)))
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::R00_TRANSFER_TIMED_OUT);
+    }
 }
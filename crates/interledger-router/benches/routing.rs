@@ -0,0 +1,81 @@
+//! Benchmark routing table lookups: the prefix trie used by the `Router`
+//! against a linear scan over every route in the table.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use interledger_router::RoutingTable;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Builds a routing table that looks like one learned via CCP:
+/// `size` routes spread over a few peers, each with their own children,
+/// plus a catch-all route.
+fn routes(size: usize) -> HashMap<String, Uuid> {
+    let mut routes: HashMap<String, Uuid> = (0..size)
+        .map(|i| (format!("g.peer{}.child{}", i % 16, i / 16), Uuid::new_v4()))
+        .collect();
+    routes.insert(String::new(), Uuid::new_v4());
+    routes
+}
+
+/// The lookup the `Router` used to do for every packet: an exact match
+/// followed by a scan over the whole routing table.
+fn linear_scan<'a>(routes: &'a HashMap<String, Uuid>, destination: &str) -> Option<&'a Uuid> {
+    if let Some(account_id) = routes.get(destination) {
+        return Some(account_id);
+    }
+    let mut next_hop = None;
+    let mut matching_prefix = "";
+    for (prefix, account) in routes.iter() {
+        if (prefix.is_empty() || destination.starts_with(prefix.as_str()))
+            && prefix.len() >= matching_prefix.len()
+        {
+            next_hop = Some(account);
+            matching_prefix = prefix.as_str();
+        }
+    }
+    next_hop
+}
+
+fn benchmark_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("route lookup");
+    for size in [10, 1_000, 10_000].iter() {
+        let routes = routes(*size);
+        let table = RoutingTable::from(routes.clone());
+        // Prefix match against one of the last children, and a miss that falls back to
+        // the catch-all route
+        let destinations = [
+            format!("g.peer3.child{}.receiver.xyz", size / 32),
+            "test.unknown.receiver".to_string(),
+        ];
+
+        group.bench_with_input(BenchmarkId::new("trie", size), &destinations, |b, dests| {
+            b.iter(|| {
+                for destination in dests.iter() {
+                    assert!(table.longest_match(destination).is_some());
+                }
+            })
+        });
+        group.bench_with_input(
+            BenchmarkId::new("linear scan", size),
+            &destinations,
+            |b, dests| {
+                b.iter(|| {
+                    for destination in dests.iter() {
+                        assert!(linear_scan(&routes, destination).is_some());
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn benchmark_build(c: &mut Criterion) {
+    let routes = routes(10_000);
+    c.bench_function("build routing table (10000 routes)", |b| {
+        b.iter(|| RoutingTable::from(routes.clone()))
+    });
+}
+
+criterion_group!(benches, benchmark_lookup, benchmark_build);
+criterion_main!(benches);
@@ -13,12 +13,13 @@
 //! (see the `interledger-ccp` crate for more details).
 
 use interledger_service::AccountStore;
-use std::{collections::HashMap, sync::Arc};
-use uuid::Uuid;
+use std::sync::Arc;
 
 mod router;
+mod routing_table;
 
 pub use self::router::Router;
+pub use self::routing_table::{NextHop, RoutingTable, RoutingTableIter};
 
 /// A trait for Store implmentations that have ILP routing tables.
 pub trait RouterStore: AccountStore + Clone + Send + Sync + 'static {
@@ -27,5 +28,5 @@ pub trait RouterStore: AccountStore + Clone + Send + Sync + 'static {
     /// keep the routing table in memory and use PubSub or polling to keep it updated.
     /// This ensures that individual packets can be routed without hitting the underlying store.
     /// An Arc is returned to avoid copying the underlying data while processing each packet.
-    fn routing_table(&self) -> Arc<HashMap<String, Uuid>>;
+    fn routing_table(&self) -> Arc<RoutingTable>;
 }
@@ -1,9 +1,13 @@
 use super::RouterStore;
+use crate::NextHop;
 use async_trait::async_trait;
-use interledger_packet::{ErrorCode, RejectBuilder};
+use interledger_packet::{ErrorClass, ErrorCode, RejectBuilder};
 use interledger_service::*;
+use rand::Rng;
 use std::str;
-use tracing::{error, trace};
+use std::time::SystemTime;
+use tracing::{debug, error, trace};
+use uuid::Uuid;
 
 /// # Interledger Router
 ///
@@ -38,6 +42,46 @@ where
     }
 }
 
+/// Orders the next hops of a route in the order they should be tried: by priority and,
+/// within each priority, randomly in proportion to the next hops' weights. Next hops with
+/// a weight of zero are only used if all other next hops with the same priority fail.
+fn order_next_hops<R: Rng>(next_hops: &[NextHop], rng: &mut R) -> Vec<Uuid> {
+    let mut ordered = Vec::with_capacity(next_hops.len());
+    // The routing table keeps the next hops sorted by priority
+    let mut start = 0;
+    while start < next_hops.len() {
+        let priority = next_hops[start].priority;
+        let end = next_hops[start..]
+            .iter()
+            .position(|hop| hop.priority != priority)
+            .map_or(next_hops.len(), |len| start + len);
+        let mut remaining: Vec<&NextHop> = next_hops[start..end].iter().collect();
+        loop {
+            let total: u64 = remaining.iter().map(|hop| u64::from(hop.weight)).sum();
+            if total == 0 {
+                break;
+            }
+            let mut pick = rng.gen_range(0, total);
+            let index = remaining
+                .iter()
+                .position(|hop| {
+                    let weight = u64::from(hop.weight);
+                    if pick < weight {
+                        true
+                    } else {
+                        pick -= weight;
+                        false
+                    }
+                })
+                .expect("pick is less than the total weight");
+            ordered.push(remaining.remove(index).account_id);
+        }
+        ordered.extend(remaining.into_iter().map(|hop| hop.account_id));
+        start = end;
+    }
+    ordered
+}
+
 #[async_trait]
 impl<S, O> IncomingService<S::Account> for Router<S, O>
 where
@@ -46,67 +90,79 @@ where
 {
     /// Figures out the next node to pass the received Prepare packet to.
     ///
-    /// Firstly, it checks if there is a direct path for that account and uses that.
-    /// If not it scans through the routing table and checks if the route prefix matches
-    /// the prepare packet's destination or if it's a catch-all address (i.e. empty prefix)
+    /// It looks up the route with the longest prefix matching the prepare packet's
+    /// destination in the routing table, falling back to the catch-all route
+    /// (i.e. the empty prefix) if there is one.
+    ///
+    /// If the route has multiple next hops, the packet is sent to one of the hops with
+    /// the best priority, picked randomly in proportion to their weights. If that
+    /// account rejects the packet with a temporary (T-family) error and the packet has
+    /// not expired yet, the packet is retried via the route's other next hops.
     async fn handle_request(&mut self, request: IncomingRequest<S::Account>) -> IlpResult {
         let destination = request.prepare.destination();
-        let mut next_hop = None;
         let routing_table = self.store.routing_table();
         let ilp_address = self.store.get_ilp_address();
 
-        // Check if we have a direct path for that account or if we need to scan
-        // through the routing table
         let dest: &str = &destination;
-        if let Some(account_id) = routing_table.get(dest) {
-            trace!(
-                "Found direct route for address: \"{}\". Account: {}",
-                destination,
-                account_id
-            );
-            next_hop = Some(*account_id);
-        } else if !routing_table.is_empty() {
-            let mut matching_prefix = "";
-            let routing_table = self.store.routing_table();
-            for (prefix, account) in (*routing_table).iter() {
-                // Check if the route prefix matches or is empty (meaning it's a catch-all address)
-                if (prefix.is_empty() || dest.starts_with(prefix.as_str()))
-                    && prefix.len() >= matching_prefix.len()
-                {
-                    next_hop.replace(*account);
-                    matching_prefix = prefix.as_str();
-                }
-            }
-            if let Some(account_id) = next_hop {
+        let candidates = routing_table
+            .longest_match_next_hops(dest)
+            .map(|(prefix, next_hops)| {
                 trace!(
-                    "Found matching route for address: \"{}\". Prefix: \"{}\", account: {}",
+                    "Found matching route for address: \"{}\". Prefix: \"{}\", next hops: {:?}",
                     destination,
-                    matching_prefix,
-                    account_id,
+                    prefix,
+                    next_hops,
                 );
-            }
-        } else {
+                order_next_hops(next_hops, &mut rand::thread_rng())
+            });
+        if routing_table.is_empty() {
             error!("Unable to route request because routing table is empty");
         }
 
-        if let Some(account_id) = next_hop {
-            let mut next = self.next.clone();
-            match self.store.get_accounts(vec![account_id]).await {
-                Ok(mut accounts) => {
-                    let request = request.into_outgoing(accounts.remove(0));
-                    next.send_request(request).await
+        if let Some(candidates) = candidates {
+            let unreachable = || {
+                RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    triggered_by: Some(&ilp_address),
+                    data: &[],
                 }
-                Err(_) => {
-                    error!("No record found for account: {}", account_id);
-                    Err(RejectBuilder {
-                        code: ErrorCode::F02_UNREACHABLE,
-                        message: &[],
-                        triggered_by: Some(&ilp_address),
-                        data: &[],
+                .build()
+            };
+            let mut last_reject = None;
+            for (index, account_id) in candidates.iter().enumerate() {
+                let is_last = index + 1 == candidates.len();
+                let account = match self.store.get_accounts(vec![*account_id]).await {
+                    Ok(mut accounts) => accounts.remove(0),
+                    Err(_) => {
+                        error!("No record found for account: {}", account_id);
+                        continue;
                     }
-                    .build())
+                };
+
+                let mut next = self.next.clone();
+                if is_last {
+                    return next.send_request(request.into_outgoing(account)).await;
+                }
+                match next
+                    .send_request(request.clone().into_outgoing(account))
+                    .await
+                {
+                    Err(reject)
+                        if reject.code().class() == ErrorClass::Temporary
+                            && request.prepare.expires_at() > SystemTime::now() =>
+                    {
+                        debug!(
+                            "Account {} rejected the packet with a temporary error ({}), trying the next hop",
+                            account_id,
+                            reject.code(),
+                        );
+                        last_reject = Some(reject);
+                    }
+                    result => return result,
                 }
             }
+            Err(last_reject.unwrap_or_else(unreachable))
         } else {
             error!(
                 "No route found for request {}: {:?}",
@@ -140,15 +196,15 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::RoutingTable;
     use interledger_errors::*;
     use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
     use interledger_service::outgoing_service_fn;
     use once_cell::sync::Lazy;
     use parking_lot::Mutex;
-    use std::collections::HashMap;
     use std::str::FromStr;
     use std::sync::Arc;
-    use std::time::UNIX_EPOCH;
+    use std::time::{Duration, UNIX_EPOCH};
     use uuid::Uuid;
 
     #[derive(Debug, Clone)]
@@ -182,7 +238,7 @@ mod tests {
 
     #[derive(Clone)]
     struct TestStore {
-        routes: HashMap<String, Uuid>,
+        routes: RoutingTable,
     }
 
     #[async_trait]
@@ -223,7 +279,7 @@ mod tests {
     }
 
     impl RouterStore for TestStore {
-        fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
+        fn routing_table(&self) -> Arc<RoutingTable> {
             Arc::new(self.routes.clone())
         }
     }
@@ -232,7 +288,7 @@ mod tests {
     async fn empty_routing_table() {
         let mut router = Router::new(
             TestStore {
-                routes: HashMap::new(),
+                routes: RoutingTable::new(),
             },
             outgoing_service_fn(|_| {
                 Ok(FulfillBuilder {
@@ -433,4 +489,147 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(to.lock().take().unwrap().0, id2);
     }
+
+    fn multipath_router(
+        next_hops: Vec<NextHop>,
+        reject_code: ErrorCode,
+        rejecting: Uuid,
+        tried: Arc<Mutex<Vec<Uuid>>>,
+    ) -> Router<TestStore, impl OutgoingService<TestAccount> + Clone> {
+        let mut routes = RoutingTable::new();
+        routes.insert_next_hops("example.".to_string(), next_hops);
+        Router::new(
+            TestStore { routes },
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                tried.lock().push(request.to.0);
+                if request.to.0 == rejecting {
+                    Err(RejectBuilder {
+                        code: reject_code,
+                        message: &[],
+                        triggered_by: None,
+                        data: &[],
+                    }
+                    .build())
+                } else {
+                    Ok(FulfillBuilder {
+                        fulfillment: &[0; 32],
+                        data: &[],
+                    }
+                    .build())
+                }
+            }),
+        )
+    }
+
+    fn prepare_expiring_at(expires_at: SystemTime) -> IncomingRequest<TestAccount> {
+        IncomingRequest {
+            from: TestAccount(Uuid::new_v4()),
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount: 100,
+                execution_condition: &[1; 32],
+                expires_at,
+                data: &[],
+            }
+            .build(),
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_over_on_temporary_errors() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let tried = Arc::new(Mutex::new(Vec::new()));
+        let mut router = multipath_router(
+            vec![NextHop::new(id1, 1, 0), NextHop::new(id2, 1, 1)],
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+            id1,
+            tried.clone(),
+        );
+
+        let result = router
+            .handle_request(prepare_expiring_at(
+                SystemTime::now() + Duration::from_secs(30),
+            ))
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(*tried.lock(), vec![id1, id2]);
+
+        // Expired packets are not retried
+        tried.lock().clear();
+        let result = router.handle_request(prepare_expiring_at(UNIX_EPOCH)).await;
+        assert_eq!(
+            result.unwrap_err().code(),
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY
+        );
+        assert_eq!(*tried.lock(), vec![id1]);
+    }
+
+    #[tokio::test]
+    async fn does_not_fail_over_on_final_errors() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let tried = Arc::new(Mutex::new(Vec::new()));
+        let mut router = multipath_router(
+            vec![NextHop::new(id1, 1, 0), NextHop::new(id2, 1, 1)],
+            ErrorCode::F99_APPLICATION_ERROR,
+            id1,
+            tried.clone(),
+        );
+
+        let result = router
+            .handle_request(prepare_expiring_at(
+                SystemTime::now() + Duration::from_secs(30),
+            ))
+            .await;
+        assert_eq!(result.unwrap_err().code(), ErrorCode::F99_APPLICATION_ERROR);
+        assert_eq!(*tried.lock(), vec![id1]);
+    }
+
+    #[tokio::test]
+    async fn returns_last_reject_if_all_next_hops_fail() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let tried = Arc::new(Mutex::new(Vec::new()));
+        let mut router = multipath_router(
+            vec![NextHop::new(id1, 1, 0), NextHop::new(id1, 1, 1)],
+            ErrorCode::T01_PEER_UNREACHABLE,
+            id1,
+            tried.clone(),
+        );
+
+        let result = router
+            .handle_request(prepare_expiring_at(
+                SystemTime::now() + Duration::from_secs(30),
+            ))
+            .await;
+        assert_eq!(result.unwrap_err().code(), ErrorCode::T01_PEER_UNREACHABLE);
+        assert_eq!(tried.lock().len(), 2);
+    }
+
+    #[test]
+    fn orders_next_hops_by_priority_and_weight() {
+        let id1 = Uuid::from_slice(&[1; 16]).unwrap();
+        let id2 = Uuid::from_slice(&[2; 16]).unwrap();
+        let id3 = Uuid::from_slice(&[3; 16]).unwrap();
+        let id4 = Uuid::from_slice(&[4; 16]).unwrap();
+        let next_hops = [
+            NextHop::new(id1, 3, 0),
+            NextHop::new(id2, 1, 0),
+            NextHop::new(id3, 0, 0),
+            NextHop::new(id4, 1, 1),
+        ];
+        let mut rng = rand::thread_rng();
+        let mut first_id1 = 0;
+        for _ in 0..1000 {
+            let ordered = order_next_hops(&next_hops, &mut rng);
+            assert_eq!(ordered.len(), 4);
+            assert_eq!(ordered[2], id3);
+            assert_eq!(ordered[3], id4);
+            if ordered[0] == id1 {
+                first_id1 += 1;
+            }
+        }
+        // id1 should be picked first about 75% of the time
+        assert!(first_id1 > 650 && first_id1 < 850, "{}", first_id1);
+    }
 }
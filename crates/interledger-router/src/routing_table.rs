@@ -0,0 +1,537 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map, HashMap},
+    iter::FromIterator,
+    ops::Index,
+};
+use uuid::Uuid;
+
+/// One of the next hops of a route.
+///
+/// Packets are only sent to the next hops with the best (i.e. lowest) priority of
+/// the route, spread across them in proportion to their weights. The next hops with
+/// worse priorities are only tried if the better ones reject a packet with a
+/// temporary (T-family) error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NextHop {
+    pub account_id: Uuid,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    #[serde(default)]
+    pub priority: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl NextHop {
+    pub fn new(account_id: Uuid, weight: u32, priority: u32) -> Self {
+        NextHop {
+            account_id,
+            weight,
+            priority,
+        }
+    }
+}
+
+impl From<Uuid> for NextHop {
+    fn from(account_id: Uuid) -> Self {
+        NextHop::new(account_id, default_weight(), 0)
+    }
+}
+
+/// A node in the prefix trie. Edges are labeled with (possibly multi-byte) slices
+/// of the route prefixes and each node's children are kept sorted by the first
+/// byte of their label, so that no two children start with the same byte.
+#[derive(Debug, Clone, Default)]
+struct Node {
+    next_hops: Option<Vec<NextHop>>,
+    children: Vec<(Vec<u8>, Node)>,
+}
+
+impl Node {
+    fn child_index(&self, byte: u8) -> Result<usize, usize> {
+        self.children
+            .binary_search_by_key(&byte, |(label, _)| label[0])
+    }
+
+    fn insert(&mut self, key: &[u8], next_hops: Vec<NextHop>) {
+        if key.is_empty() {
+            self.next_hops = Some(next_hops);
+            return;
+        }
+        match self.child_index(key[0]) {
+            Err(index) => {
+                let child = Node {
+                    next_hops: Some(next_hops),
+                    children: Vec::new(),
+                };
+                self.children.insert(index, (key.to_vec(), child));
+            }
+            Ok(index) => {
+                let (label, child) = &mut self.children[index];
+                let common = common_prefix_len(label, key);
+                if common < label.len() {
+                    // Split the edge so that the common part gets its own node
+                    let suffix = label.split_off(common);
+                    let old_child = std::mem::take(child);
+                    child.children.push((suffix, old_child));
+                }
+                child.insert(&key[common..], next_hops);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Vec<NextHop>> {
+        if key.is_empty() {
+            return self.next_hops.take();
+        }
+        let index = self.child_index(key[0]).ok()?;
+        let (label, child) = &mut self.children[index];
+        if !key.starts_with(label) {
+            return None;
+        }
+        let removed = child.remove(&key[label.len()..]);
+        if removed.is_some() && child.next_hops.is_none() {
+            match child.children.len() {
+                // Prune nodes that no longer lead to any route
+                0 => {
+                    self.children.remove(index);
+                }
+                // Merge nodes that only have a single child into their parent's edge
+                1 => {
+                    let (suffix, grandchild) = child.children.pop().unwrap();
+                    label.extend_from_slice(&suffix);
+                    *child = grandchild;
+                }
+                _ => {}
+            }
+        }
+        removed
+    }
+
+    /// Returns the length of the longest prefix of `key` which has a route, along with its next hops
+    fn longest_match(&self, key: &[u8]) -> Option<(usize, &[NextHop])> {
+        let mut best = self.next_hops.as_deref().map(|next_hops| (0, next_hops));
+        let mut node = self;
+        let mut matched = 0;
+        while matched < key.len() {
+            let remaining = &key[matched..];
+            let (label, child) = match node.child_index(remaining[0]) {
+                Ok(index) => &node.children[index],
+                Err(_) => break,
+            };
+            if !remaining.starts_with(label) {
+                break;
+            }
+            matched += label.len();
+            node = child;
+            if let Some(next_hops) = node.next_hops.as_deref() {
+                best = Some((matched, next_hops));
+            }
+        }
+        best
+    }
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// A routing table mapping ILP address prefixes to the ids of the next hop accounts.
+///
+/// Next to the exact prefix lookups, the table keeps the prefixes in a trie so that
+/// the route for a destination address can be found with a longest-prefix match
+/// without scanning the whole table. As in the rest of the codebase, a prefix matches
+/// an address if the address starts with it, and the empty prefix ("") matches all
+/// addresses (i.e. it acts as the catch-all or default route).
+///
+/// Each prefix may have multiple [`NextHop`](./struct.NextHop.html)s. The methods
+/// which return a single account id per prefix (`get`, `longest_match`, `iter`, etc.)
+/// return the route's primary next hop, which is the first one with the best priority.
+#[derive(Debug, Clone, Default)]
+pub struct RoutingTable {
+    routes: HashMap<String, Vec<NextHop>>,
+    trie: Node,
+}
+
+impl RoutingTable {
+    /// Creates an empty routing table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a route for the given prefix, returning the previous primary next hop for it (if any)
+    pub fn insert(&mut self, prefix: String, account_id: Uuid) -> Option<Uuid> {
+        self.insert_next_hops(prefix, vec![account_id.into()])
+            .map(|next_hops| next_hops[0].account_id)
+    }
+
+    /// Adds a route with multiple next hops for the given prefix, returning the previous
+    /// next hops for it (if any). Inserting an empty list of next hops removes the route.
+    pub fn insert_next_hops(
+        &mut self,
+        prefix: String,
+        mut next_hops: Vec<NextHop>,
+    ) -> Option<Vec<NextHop>> {
+        if next_hops.is_empty() {
+            let removed = self.routes.remove(&prefix);
+            if removed.is_some() {
+                self.trie.remove(prefix.as_bytes());
+            }
+            return removed;
+        }
+        // The sort is stable so the order is kept for next hops with the same priority
+        next_hops.sort_by_key(|next_hop| next_hop.priority);
+        self.trie.insert(prefix.as_bytes(), next_hops.clone());
+        self.routes.insert(prefix, next_hops)
+    }
+
+    /// Removes the route for the given prefix, returning its primary next hop (if any)
+    pub fn remove(&mut self, prefix: &str) -> Option<Uuid> {
+        let removed = self.routes.remove(prefix);
+        if removed.is_some() {
+            self.trie.remove(prefix.as_bytes());
+        }
+        removed.map(|next_hops| next_hops[0].account_id)
+    }
+
+    /// Returns the primary next hop for the exact prefix
+    pub fn get(&self, prefix: &str) -> Option<&Uuid> {
+        self.routes
+            .get(prefix)
+            .map(|next_hops| &next_hops[0].account_id)
+    }
+
+    /// Returns all of the next hops for the exact prefix, ordered by priority
+    pub fn next_hops(&self, prefix: &str) -> Option<&[NextHop]> {
+        self.routes.get(prefix).map(Vec::as_slice)
+    }
+
+    /// Finds the route with the longest prefix matching the destination address.
+    /// Returns the matching prefix and the primary next hop's account id.
+    pub fn longest_match<'a>(&self, destination: &'a str) -> Option<(&'a str, Uuid)> {
+        self.longest_match_next_hops(destination)
+            .map(|(prefix, next_hops)| (prefix, next_hops[0].account_id))
+    }
+
+    /// Finds the route with the longest prefix matching the destination address.
+    /// Returns the matching prefix and all of the route's next hops, ordered by priority.
+    pub fn longest_match_next_hops<'a>(
+        &self,
+        destination: &'a str,
+    ) -> Option<(&'a str, &[NextHop])> {
+        self.trie
+            .longest_match(destination.as_bytes())
+            .map(|(len, next_hops)| (&destination[..len], next_hops))
+    }
+
+    /// The number of routes in the table
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    /// Returns true if the table contains no routes
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Iterates over all (prefix, primary next hop) pairs in arbitrary order
+    pub fn iter(&self) -> RoutingTableIter<'_> {
+        RoutingTableIter(self.routes.iter())
+    }
+
+    /// Iterates over all (prefix, next hops) pairs in arbitrary order
+    pub fn iter_next_hops(&self) -> hash_map::Iter<'_, String, Vec<NextHop>> {
+        self.routes.iter()
+    }
+
+    /// Iterates over all of the prefixes in the table in arbitrary order
+    pub fn keys(&self) -> hash_map::Keys<'_, String, Vec<NextHop>> {
+        self.routes.keys()
+    }
+
+    /// Iterates over the primary next hops of all routes in the table in arbitrary order
+    pub fn values(&self) -> impl Iterator<Item = &Uuid> {
+        self.iter().map(|(_, account_id)| account_id)
+    }
+}
+
+/// An iterator over the (prefix, primary next hop) pairs of a `RoutingTable`
+#[derive(Debug, Clone)]
+pub struct RoutingTableIter<'a>(hash_map::Iter<'a, String, Vec<NextHop>>);
+
+impl<'a> Iterator for RoutingTableIter<'a> {
+    type Item = (&'a String, &'a Uuid);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0
+            .next()
+            .map(|(prefix, next_hops)| (prefix, &next_hops[0].account_id))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl PartialEq for RoutingTable {
+    fn eq(&self, other: &Self) -> bool {
+        self.routes == other.routes
+    }
+}
+
+impl Eq for RoutingTable {}
+
+impl FromIterator<(String, Uuid)> for RoutingTable {
+    fn from_iter<T: IntoIterator<Item = (String, Uuid)>>(iter: T) -> Self {
+        let mut table = RoutingTable::new();
+        for (prefix, account_id) in iter {
+            table.insert(prefix, account_id);
+        }
+        table
+    }
+}
+
+impl FromIterator<(String, Vec<NextHop>)> for RoutingTable {
+    fn from_iter<T: IntoIterator<Item = (String, Vec<NextHop>)>>(iter: T) -> Self {
+        let mut table = RoutingTable::new();
+        for (prefix, next_hops) in iter {
+            table.insert_next_hops(prefix, next_hops);
+        }
+        table
+    }
+}
+
+impl From<HashMap<String, Uuid>> for RoutingTable {
+    fn from(routes: HashMap<String, Uuid>) -> Self {
+        routes.into_iter().collect()
+    }
+}
+
+impl From<HashMap<String, Vec<NextHop>>> for RoutingTable {
+    fn from(routes: HashMap<String, Vec<NextHop>>) -> Self {
+        routes.into_iter().collect()
+    }
+}
+
+impl<'a> IntoIterator for &'a RoutingTable {
+    type Item = (&'a String, &'a Uuid);
+    type IntoIter = RoutingTableIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl Index<&str> for RoutingTable {
+    type Output = Uuid;
+
+    fn index(&self, prefix: &str) -> &Uuid {
+        &self.routes[prefix][0].account_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u8) -> Uuid {
+        Uuid::from_slice(&[n; 16]).unwrap()
+    }
+
+    fn table(routes: &[(&str, u8)]) -> RoutingTable {
+        routes
+            .iter()
+            .map(|(prefix, n)| (prefix.to_string(), id(*n)))
+            .collect()
+    }
+
+    #[test]
+    fn empty_table_matches_nothing() {
+        let table = RoutingTable::new();
+        assert!(table.is_empty());
+        assert_eq!(table.longest_match("example.alice"), None);
+        assert_eq!(table.longest_match(""), None);
+    }
+
+    #[test]
+    fn catch_all_route() {
+        let table = table(&[("", 0), ("example.bob", 1)]);
+        assert_eq!(table.longest_match("example.alice"), Some(("", id(0))));
+        assert_eq!(table.longest_match("test.bob"), Some(("", id(0))));
+        assert_eq!(
+            table.longest_match("example.bob"),
+            Some(("example.bob", id(1)))
+        );
+    }
+
+    #[test]
+    fn finds_longest_matching_prefix() {
+        let table = table(&[
+            ("", 0),
+            ("example.", 1),
+            ("example.destination", 2),
+            ("example.dest", 3),
+        ]);
+        assert_eq!(
+            table.longest_match("example.destination.alice"),
+            Some(("example.destination", id(2)))
+        );
+        assert_eq!(
+            table.longest_match("example.destroyer"),
+            Some(("example.dest", id(3)))
+        );
+        assert_eq!(table.longest_match("example.de"), Some(("example.", id(1))));
+        assert_eq!(table.longest_match("example"), Some(("", id(0))));
+    }
+
+    #[test]
+    fn matches_are_not_limited_to_address_segments() {
+        // Same semantics as `str::starts_with`
+        let table = table(&[("example.a", 1)]);
+        assert_eq!(
+            table.longest_match("example.alice"),
+            Some(("example.a", id(1)))
+        );
+        assert_eq!(table.longest_match("example.bob"), None);
+    }
+
+    #[test]
+    fn insert_overwrites_and_splits_edges() {
+        let mut table = table(&[("example.alice", 1)]);
+        assert_eq!(table.insert("example.bob".to_string(), id(2)), None);
+        assert_eq!(
+            table.insert("example.alice".to_string(), id(3)),
+            Some(id(1))
+        );
+        assert_eq!(table.len(), 2);
+        assert_eq!(
+            table.longest_match("example.alice"),
+            Some(("example.alice", id(3)))
+        );
+        assert_eq!(
+            table.longest_match("example.bob.x"),
+            Some(("example.bob", id(2)))
+        );
+        assert_eq!(table.longest_match("example.b"), None);
+        assert_eq!(table["example.bob"], id(2));
+    }
+
+    #[test]
+    fn remove_prunes_and_merges_nodes() {
+        let mut table = table(&[
+            ("example.", 0),
+            ("example.alice", 1),
+            ("example.alice.phone", 2),
+            ("example.bob", 3),
+        ]);
+        assert_eq!(table.remove("example.alice"), Some(id(1)));
+        assert_eq!(table.remove("example.alice"), None);
+        assert_eq!(table.remove("example.al"), None);
+        assert_eq!(
+            table.longest_match("example.alice"),
+            Some(("example.", id(0)))
+        );
+        assert_eq!(
+            table.longest_match("example.alice.phone.1"),
+            Some(("example.alice.phone", id(2)))
+        );
+
+        assert_eq!(table.remove("example."), Some(id(0)));
+        assert_eq!(table.longest_match("example.alice"), None);
+        assert_eq!(
+            table.longest_match("example.bob"),
+            Some(("example.bob", id(3)))
+        );
+
+        assert_eq!(table.remove("example.alice.phone"), Some(id(2)));
+        assert_eq!(table.remove("example.bob"), Some(id(3)));
+        assert!(table.is_empty());
+        assert!(table.trie.children.is_empty());
+    }
+
+    #[test]
+    fn matches_linear_scan() {
+        let prefixes = [
+            "", "g.", "g.a", "g.ab", "g.abc", "g.b", "g.b.c", "test.", "test.x.y", "private",
+        ];
+        let table: RoutingTable = prefixes
+            .iter()
+            .enumerate()
+            .map(|(n, prefix)| (prefix.to_string(), id(n as u8)))
+            .collect();
+        let destinations = [
+            "g.abcd",
+            "g.ab",
+            "g.b.c.d",
+            "g.bx",
+            "g.c",
+            "test.x",
+            "test.x.y.z",
+            "private.a",
+            "h",
+        ];
+        for destination in destinations.iter() {
+            let expected = table
+                .iter()
+                .filter(|(prefix, _)| destination.starts_with(prefix.as_str()))
+                .max_by_key(|(prefix, _)| prefix.len())
+                .map(|(prefix, account_id)| (prefix.as_str(), *account_id));
+            assert_eq!(table.longest_match(destination), expected);
+        }
+    }
+
+    #[test]
+    fn multiple_next_hops() {
+        let mut table = table(&[("", 0)]);
+        let next_hops = vec![
+            NextHop::new(id(1), 1, 1),
+            NextHop::new(id(2), 3, 0),
+            NextHop::new(id(3), 1, 0),
+        ];
+        assert_eq!(
+            table.insert_next_hops("example.".to_string(), next_hops),
+            None
+        );
+
+        // Ordered by priority, keeping the given order within the same priority
+        let expected = [
+            NextHop::new(id(2), 3, 0),
+            NextHop::new(id(3), 1, 0),
+            NextHop::new(id(1), 1, 1),
+        ];
+        assert_eq!(table.next_hops("example."), Some(&expected[..]));
+        assert_eq!(
+            table.longest_match_next_hops("example.alice"),
+            Some(("example.", &expected[..]))
+        );
+        assert_eq!(
+            table.longest_match("example.alice"),
+            Some(("example.", id(2)))
+        );
+        assert_eq!(table.get("example."), Some(&id(2)));
+        assert_eq!(table.next_hops(""), Some(&[NextHop::from(id(0))][..]));
+
+        assert_eq!(table.insert("example.".to_string(), id(4)), Some(id(2)));
+        assert_eq!(
+            table.next_hops("example."),
+            Some(&[NextHop::from(id(4))][..])
+        );
+        assert_eq!(
+            table.insert_next_hops("example.".to_string(), Vec::new()),
+            Some(vec![NextHop::from(id(4))])
+        );
+        assert_eq!(table.longest_match("example.alice"), Some(("", id(0))));
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn deserializes_next_hops_with_defaults() {
+        let next_hop: NextHop =
+            serde_json::from_str(&format!("{{\"account_id\":\"{}\",\"priority\":2}}", id(1)))
+                .unwrap();
+        assert_eq!(next_hop, NextHop::new(id(1), 1, 2));
+    }
+}
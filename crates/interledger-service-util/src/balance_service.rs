@@ -14,6 +14,14 @@ use tokio::sync::mpsc::error::TrySendError;
 use tracing::{debug, error, info, trace, warn};
 use uuid::Uuid;
 
+/// Extension trait for [`Account`](../interledger_service/trait.Account.html) with the minimum
+/// balance the account must keep. Prepare packets that would bring the account's balance under
+/// this amount are rejected by the `BalanceStore`.
+pub trait MinBalanceAccount: Account {
+    /// The minimum balance, or `None` if the account's balance is not limited
+    fn min_balance(&self) -> Option<i64>;
+}
+
 // TODO: Remove AccountStore dependency, use `AccountId: ToString` as associated type
 /// Trait responsible for managing an account's balance in the store
 /// as ILP Packets get routed
@@ -2,13 +2,19 @@ use async_trait::async_trait;
 use bytes::{Buf, BufMut, BytesMut};
 use core::borrow::Borrow;
 use interledger_packet::{
-    oer::BufOerExt, Address, ErrorCode, Prepare, PrepareBuilder, RejectBuilder,
+    oer::{self, BufOerExt, MutBufOerExt},
+    Address, ErrorCode, FulfillBuilder, Prepare, PrepareBuilder, RejectBuilder,
 };
 use interledger_service::*;
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::marker::PhantomData;
 use std::str;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 use tracing::debug;
 
 /// The prefix that echo packets should have in its data section
@@ -29,6 +35,7 @@ enum EchoPacketType {
 pub struct EchoService<I, S, A> {
     store: S,
     next: I,
+    client: EchoClient,
     account_type: PhantomData<A>,
 }
 
@@ -43,9 +50,16 @@ where
         EchoService {
             store,
             next,
+            client: EchoClient::default(),
             account_type: PhantomData,
         }
     }
+
+    /// Returns an [`EchoClient`] which can originate echo requests whose responses
+    /// will be fulfilled by this service when they are routed back to the node.
+    pub fn client(&self) -> EchoClient {
+        self.client.clone()
+    }
 }
 
 #[async_trait]
@@ -84,7 +98,20 @@ where
         }
         let echo_packet_type = reader.get_u8();
         if echo_packet_type == EchoPacketType::Response as u8 {
-            // if the echo packet type is Response, just pass it to the next service
+            // if we initiated the echo request, fulfill the response with the
+            // fulfillment we generated for it
+            if let Some(fulfillment) = self
+                .client
+                .fulfillment_for(request.prepare.execution_condition())
+            {
+                debug!("Fulfilling response to our echo request");
+                return Ok(FulfillBuilder {
+                    fulfillment: &fulfillment,
+                    data: &[],
+                }
+                .build());
+            }
+            // otherwise, just pass it to the next service
             // so that the initiator could handle this packet
             return self.next.handle_request(request).await;
         }
@@ -148,10 +175,6 @@ where
     }
 }
 
-#[cfg(test)]
-use interledger_packet::{oer, oer::MutBufOerExt};
-// This should be exported when we have a use for it outside of the tests
-#[cfg(test)]
 pub struct EchoRequestBuilder<'a> {
     pub amount: u64,
     pub expires_at: SystemTime,
@@ -162,7 +185,6 @@ pub struct EchoRequestBuilder<'a> {
     pub source_address: &'a Address,
 }
 
-#[cfg(test)]
 impl<'a> EchoRequestBuilder<'a> {
     pub fn build(&self) -> Prepare {
         let source_address_len = oer::predict_var_octet_string(self.source_address.len());
@@ -205,6 +227,119 @@ impl<'a> EchoResponseBuilder<'a> {
     }
 }
 
+/// The default amount of time an echo request is given before it expires
+pub const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The outcome of a single echo request
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PingOutcome {
+    /// The echo response made it back to us and was fulfilled
+    Fulfilled,
+    /// The echo request (or its response) was rejected somewhere along the path
+    Rejected {
+        code: String,
+        message: String,
+        triggered_by: Option<String>,
+    },
+}
+
+/// Details about a single echo request originated by an [`EchoClient`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PingResult {
+    /// The ILP address that was pinged
+    pub destination: String,
+    /// The amount sent with the echo request
+    pub amount: u64,
+    /// The time between sending the echo request and getting its
+    /// Fulfill or Reject back, in milliseconds
+    pub rtt_ms: f64,
+    #[serde(flatten)]
+    pub outcome: PingOutcome,
+}
+
+/// Originates Echo Protocol requests (i.e. pings) from this node.
+///
+/// The destination echoes the request back to the source address with the
+/// same execution condition. The client remembers the fulfillment of every
+/// request in flight so that the [`EchoService`] it was obtained from can fulfill
+/// the echo response once it gets routed back to us.
+#[derive(Clone, Default)]
+pub struct EchoClient {
+    pending: Arc<Mutex<HashMap<[u8; 32], [u8; 32]>>>,
+}
+
+impl EchoClient {
+    /// Sends an echo request to `destination` through the `next` service, as if
+    /// it was sent by the `from` account, and waits for the outcome.
+    /// `source_address` should be this node's ILP address, since that is
+    /// where the echo response will be sent.
+    pub async fn ping<I, A>(
+        &self,
+        mut next: I,
+        from: A,
+        source_address: &Address,
+        destination: Address,
+        amount: u64,
+        timeout: Duration,
+    ) -> PingResult
+    where
+        I: IncomingService<A>,
+        A: Account,
+    {
+        let mut fulfillment = [0; 32];
+        SystemRandom::new()
+            .fill(&mut fulfillment)
+            .expect("Failed to securely generate random fulfillment!");
+        let mut execution_condition = [0; 32];
+        execution_condition.copy_from_slice(digest(&SHA256, &fulfillment).as_ref());
+
+        let prepare = EchoRequestBuilder {
+            amount,
+            expires_at: SystemTime::now() + timeout,
+            execution_condition: &execution_condition,
+            destination: &destination,
+            source_address,
+        }
+        .build();
+
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(execution_condition, fulfillment);
+        debug!("Sending echo request to {}", destination);
+        let start = Instant::now();
+        let result = next.handle_request(IncomingRequest { from, prepare }).await;
+        let rtt = start.elapsed();
+        self.pending.lock().unwrap().remove(&execution_condition);
+
+        let outcome = match result {
+            Ok(_) => PingOutcome::Fulfilled,
+            Err(reject) => PingOutcome::Rejected {
+                code: reject.code().to_string(),
+                message: String::from_utf8_lossy(reject.message()).to_string(),
+                triggered_by: reject.triggered_by().map(|address| address.to_string()),
+            },
+        };
+        PingResult {
+            destination: destination.to_string(),
+            amount,
+            rtt_ms: rtt.as_secs_f64() * 1000.0,
+            outcome,
+        }
+    }
+
+    /// Returns the fulfillment of the pending echo request with the given condition, if any
+    fn fulfillment_for(&self, execution_condition: &[u8]) -> Option<[u8; 32]> {
+        let execution_condition = <[u8; 32]>::try_from(execution_condition).ok()?;
+        self.pending
+            .lock()
+            .unwrap()
+            .get(&execution_condition)
+            .cloned()
+    }
+}
+
 #[cfg(test)]
 mod echo_tests {
     use super::*;
@@ -481,6 +616,74 @@ mod echo_tests {
         assert!(result.is_err());
     }
 
+    /// The echo request is echoed back by the destination and the response is fulfilled
+    /// by the service the client was obtained from.
+    #[tokio::test]
+    async fn test_ping_fulfilled() {
+        let source_address = Address::from_str("example.initiator").unwrap();
+        let destination = Address::from_str("example.recipient").unwrap();
+
+        // the initiator's node, which should handle the echo response itself
+        let handler = incoming_service_fn(|_| {
+            panic!("Echo response should not be passed to the next service");
+        });
+        let initiator = EchoService::new(TestStore(source_address.clone()), handler);
+        let client = initiator.client();
+        // the recipient's node, which echoes the request back to the initiator
+        let recipient = EchoService::new(TestStore(destination.clone()), initiator);
+
+        let result = client
+            .ping(
+                recipient,
+                TestAccount(Uuid::new_v4()),
+                &source_address,
+                destination,
+                0,
+                DEFAULT_PING_TIMEOUT,
+            )
+            .await;
+        assert_eq!(result.outcome, PingOutcome::Fulfilled);
+        assert_eq!(result.destination, "example.recipient");
+        assert!(client.pending.lock().unwrap().is_empty());
+    }
+
+    /// Rejects along the path are reported in the ping result.
+    #[tokio::test]
+    async fn test_ping_rejected() {
+        let source_address = Address::from_str("example.initiator").unwrap();
+        let connector = Address::from_str("example.connector").unwrap();
+        let handler = incoming_service_fn(move |_| {
+            Err(RejectBuilder {
+                code: ErrorCode::F02_UNREACHABLE,
+                message: b"no route",
+                triggered_by: Some(&connector),
+                data: &[],
+            }
+            .build())
+        });
+        let client = EchoService::new(TestStore(source_address.clone()), handler.clone()).client();
+
+        let result = client
+            .ping(
+                handler,
+                TestAccount(Uuid::new_v4()),
+                &source_address,
+                Address::from_str("example.unreachable").unwrap(),
+                0,
+                DEFAULT_PING_TIMEOUT,
+            )
+            .await;
+        assert_eq!(
+            result.outcome,
+            PingOutcome::Rejected {
+                code: "F02".to_string(),
+                message: "no route".to_string(),
+                triggered_by: Some("example.connector".to_string()),
+            }
+        );
+        assert!(client.pending.lock().unwrap().is_empty());
+    }
+
     fn get_random_fulfillment() -> [u8; 32] {
         let mut bytes: [u8; 32] = [0; 32];
         SystemRandom::new().fill(&mut bytes).unwrap();
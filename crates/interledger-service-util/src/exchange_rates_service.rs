@@ -51,84 +51,20 @@ where
     async fn send_request(&mut self, mut request: OutgoingRequest<A>) -> IlpResult {
         let ilp_address = self.store.get_ilp_address();
         if request.prepare.amount() > 0 {
-            let rates: (f64, f64) = if request.from.asset_code() == request.to.asset_code() {
-                (1f64, 1f64)
-            } else if let Ok(rates) = self
-                .store
-                .get_exchange_rates(&[request.from.asset_code(), request.to.asset_code()])
-            {
-                // Exchange rates are expressed as `base asset / asset`. To calculate the outgoing amount,
-                // we multiply by the incoming asset's rate and divide by the outgoing asset's rate. For example,
-                // if an incoming packet is denominated in an asset worth 1 USD and the outgoing asset is worth
-                // 10 USD, the outgoing amount will be 1/10th of the source amount.
-                (rates[0], rates[1])
-            } else {
-                error!(
-                    "No exchange rates available for assets: {}, {}",
-                    request.from.asset_code(),
-                    request.to.asset_code()
-                );
-                return Err(RejectBuilder {
-                    code: ErrorCode::T00_INTERNAL_ERROR,
-                    message: format!(
-                        "No exchange rate available from asset: {} to: {}",
-                        request.from.asset_code(),
-                        request.to.asset_code()
-                    )
-                    .as_bytes(),
-                    triggered_by: Some(&ilp_address),
-                    data: &[],
-                }
-                .build());
-            };
-
-            // Can we overflow here?
-            let outgoing_amount = calculate_outgoing_amount(
-                request.prepare.amount(),
+            match convert_amount(
+                &self.store,
                 self.spread,
-                rates,
-                (request.from.asset_scale(), request.to.asset_scale()),
-            );
-
-            match outgoing_amount {
+                &request.from,
+                &request.to,
+                request.prepare.amount(),
+            ) {
                 Ok(outgoing_amount) => {
-                    request.prepare.set_amount(outgoing_amount as u64);
+                    request.prepare.set_amount(outgoing_amount);
                     trace!("Converted incoming amount of: {} {} (scale {}) from account {} to outgoing amount of: {} {} (scale {}) for account {}",
                         request.original_amount, request.from.asset_code(), request.from.asset_scale(), request.from.id(),
                         outgoing_amount, request.to.asset_code(), request.to.asset_scale(), request.to.id());
                 }
-                Err(outgoing_amount_error) => {
-                    let (code, message) = match outgoing_amount_error {
-                        // Amount was too small to be converted to a non-zero u64, i.e. smaller
-                        // than 1.0.
-                        OutgoingAmountError::LessThanOne(outgoing_amount) => (
-                            ErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT,
-                            format!(
-                                "Could not cast to f64, amount too small: {}",
-                                outgoing_amount
-                            ),
-                        ),
-                        // Amount was too large to be converted to u64 from f64, i.e. greater
-                        // than u64::MAX as f64.
-                        OutgoingAmountError::ToU64ConvertOverflow(outgoing_amount) => (
-                            ErrorCode::F08_AMOUNT_TOO_LARGE,
-                            format!(
-                                "Could not cast to f64, amount too large: {}",
-                                outgoing_amount
-                            ),
-                        ),
-                        OutgoingAmountError::FloatOverflow => (
-                            ErrorCode::F08_AMOUNT_TOO_LARGE,
-                            format!(
-                                "Could not convert exchange rate from {}:{} to: {}:{}. Got incoming amount: {}",
-                                request.from.asset_code(),
-                                request.from.asset_scale(),
-                                request.to.asset_code(),
-                                request.to.asset_scale(),
-                                request.prepare.amount(),
-                            ),
-                        ),
-                    };
+                Err((code, message)) => {
                     return Err(RejectBuilder {
                         code,
                         message: message.as_bytes(),
@@ -137,13 +73,120 @@ where
                     }
                     .build());
                 }
-            };
+            }
         }
 
         self.next.send_request(request).await
     }
 }
 
+/// Returns the exchange rates of the two accounts' assets, expressed as `base asset / asset`
+fn get_rates<S, A>(store: &S, from: &A, to: &A) -> Result<(f64, f64), (ErrorCode, String)>
+where
+    S: ExchangeRateStore,
+    A: Account,
+{
+    if from.asset_code() == to.asset_code() {
+        Ok((1f64, 1f64))
+    } else if let Ok(rates) = store.get_exchange_rates(&[from.asset_code(), to.asset_code()]) {
+        // Exchange rates are expressed as `base asset / asset`. To calculate the outgoing amount,
+        // we multiply by the incoming asset's rate and divide by the outgoing asset's rate. For example,
+        // if an incoming packet is denominated in an asset worth 1 USD and the outgoing asset is worth
+        // 10 USD, the outgoing amount will be 1/10th of the source amount.
+        Ok((rates[0], rates[1]))
+    } else {
+        error!(
+            "No exchange rates available for assets: {}, {}",
+            from.asset_code(),
+            to.asset_code()
+        );
+        Err((
+            ErrorCode::T00_INTERNAL_ERROR,
+            format!(
+                "No exchange rate available from asset: {} to: {}",
+                from.asset_code(),
+                to.asset_code()
+            ),
+        ))
+    }
+}
+
+/// Returns the exchange rate (with the spread applied) that the `ExchangeRateService`
+/// uses for packets going from one account to the other. Note that the rate does not
+/// include the difference between the accounts' asset scales.
+///
+/// Returns the code and message of the reject the service would respond with if the
+/// rate is not available.
+pub fn exchange_rate<S, A>(
+    store: &S,
+    spread: f64,
+    from: &A,
+    to: &A,
+) -> Result<f64, (ErrorCode, String)>
+where
+    S: ExchangeRateStore,
+    A: Account,
+{
+    get_rates(store, from, to).map(|rates| apply_spread(rates, spread))
+}
+
+/// Converts the amount of a packet going from one account to the other in the same
+/// way as the `ExchangeRateService` does.
+///
+/// Returns the code and message of the reject the service would respond with if the
+/// amount cannot be converted.
+pub fn convert_amount<S, A>(
+    store: &S,
+    spread: f64,
+    from: &A,
+    to: &A,
+    amount: u64,
+) -> Result<u64, (ErrorCode, String)>
+where
+    S: ExchangeRateStore,
+    A: Account,
+{
+    let rates = get_rates(store, from, to)?;
+    // Can we overflow here?
+    calculate_outgoing_amount(
+        amount,
+        spread,
+        rates,
+        (from.asset_scale(), to.asset_scale()),
+    )
+    .map_err(|outgoing_amount_error| match outgoing_amount_error {
+        // Amount was too small to be converted to a non-zero u64, i.e. smaller
+        // than 1.0.
+        OutgoingAmountError::LessThanOne(outgoing_amount) => (
+            ErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT,
+            format!(
+                "Could not cast to f64, amount too small: {}",
+                outgoing_amount
+            ),
+        ),
+        // Amount was too large to be converted to u64 from f64, i.e. greater
+        // than u64::MAX as f64.
+        OutgoingAmountError::ToU64ConvertOverflow(outgoing_amount) => (
+            ErrorCode::F08_AMOUNT_TOO_LARGE,
+            format!(
+                "Could not cast to f64, amount too large: {}",
+                outgoing_amount
+            ),
+        ),
+        OutgoingAmountError::FloatOverflow => (
+            ErrorCode::F08_AMOUNT_TOO_LARGE,
+            format!(
+                "Could not convert exchange rate from {}:{} to: {}:{}. Got incoming amount: {}",
+                from.asset_code(),
+                from.asset_scale(),
+                to.asset_code(),
+                to.asset_scale(),
+                amount,
+            ),
+        ),
+    })
+}
+
 #[derive(PartialEq, Debug)]
 enum OutgoingAmountError {
     ToU64ConvertOverflow(f64),
@@ -151,17 +194,12 @@ enum OutgoingAmountError {
     LessThanOne(f64),
 }
 
-fn calculate_outgoing_amount(
-    input: u64,
-    spread: f64,
-    (rate_src, rate_dest): (f64, f64),
-    (asset_scale_src, asset_scale_dest): (u8, u8),
-) -> Result<u64, OutgoingAmountError> {
+fn apply_spread((rate_src, rate_dest): (f64, f64), spread: f64) -> f64 {
     let rate = rate_src / rate_dest;
     // Apply spread
     // TODO should this be applied differently for "local" or same-currency packets?
     let rate = rate * (1.0 - spread);
-    let rate = if rate.is_finite() && rate.is_sign_positive() {
+    if rate.is_finite() && rate.is_sign_positive() {
         rate
     } else {
         warn!(
@@ -169,7 +207,16 @@ fn calculate_outgoing_amount(
             rate
         );
         0.0
-    };
+    }
+}
+
+fn calculate_outgoing_amount(
+    input: u64,
+    spread: f64,
+    (rate_src, rate_dest): (f64, f64),
+    (asset_scale_src, asset_scale_dest): (u8, u8),
+) -> Result<u64, OutgoingAmountError> {
+    let rate = apply_spread((rate_src, rate_dest), spread);
     // Can we overflow here?
     let outgoing_amount = 1.0f64
         .normalize_scale(ConvertDetails {
@@ -19,9 +19,13 @@ mod rate_limit_service;
 /// match the fulfillment inside the incoming fulfills
 mod validator_service;
 
-pub use self::balance_service::{start_delayed_settlement, BalanceService, BalanceStore};
-pub use self::echo_service::EchoService;
-pub use self::exchange_rates_service::ExchangeRateService;
+pub use self::balance_service::{
+    start_delayed_settlement, BalanceService, BalanceStore, MinBalanceAccount,
+};
+pub use self::echo_service::{
+    EchoClient, EchoService, PingOutcome, PingResult, DEFAULT_PING_TIMEOUT,
+};
+pub use self::exchange_rates_service::{convert_amount, exchange_rate, ExchangeRateService};
 pub use self::expiry_shortener_service::{
     ExpiryShortenerService, RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
 };
@@ -2,7 +2,10 @@ use super::{Error, SpspResponse};
 use futures::TryFutureExt;
 use interledger_rates::ExchangeRateStore;
 use interledger_service::{Account, IncomingService};
-use interledger_stream::{send_money, StreamDelivery};
+use interledger_stream::{
+    quote as quote_stream, send_money, send_money_fixed_delivery, CongestionStrategy,
+    StreamDelivery, StreamQuote,
+};
 use reqwest::Client;
 use tracing::{debug, error, trace};
 
@@ -38,6 +41,7 @@ pub async fn pay<I, A, S>(
     receiver: &str,
     source_amount: u64,
     slippage: f64,
+    congestion_strategy: CongestionStrategy,
 ) -> Result<StreamDelivery, Error>
 where
     I: IncomingService<A> + Clone + Send + Sync + 'static,
@@ -57,6 +61,7 @@ where
         shared_secret,
         source_amount,
         slippage,
+        congestion_strategy,
     )
     .map_err(move |err| {
         error!("Error sending payment: {:?}", err);
@@ -68,6 +73,92 @@ where
     Ok(receipt)
 }
 
+/// Query the details of the given Payment Pointer and send a payment using the STREAM protocol
+/// which delivers the given amount, in the receiver's asset's units, without sending more than
+/// the maximum source amount.
+///
+/// The receipt's `source_amount` is the maximum source amount and its `sent_amount` is the amount sent.
+pub async fn pay_fixed_delivery<I, A, S>(
+    service: I,
+    from_account: A,
+    store: S,
+    receiver: &str,
+    destination_amount: u64,
+    max_source_amount: u64,
+    congestion_strategy: CongestionStrategy,
+) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    let spsp = query(receiver).await?;
+    let shared_secret = spsp.shared_secret;
+    let addr = spsp.destination_account;
+    debug!(
+        "Sending SPSP payment delivering {} to address: {}",
+        destination_amount, addr
+    );
+
+    let receipt = send_money_fixed_delivery(
+        service,
+        &from_account,
+        store,
+        addr,
+        shared_secret,
+        destination_amount,
+        max_source_amount,
+        congestion_strategy,
+    )
+    .map_err(|err| {
+        error!("Error sending payment: {:?}", err);
+        Error::StreamError(err)
+    })
+    .await?;
+
+    debug!("Sent SPSP payment. StreamDelivery: {:?}", receipt);
+    Ok(receipt)
+}
+
+/// Query the details of the given Payment Pointer and quote a payment of the given source amount
+/// using unfulfillable STREAM packets, without sending any money.
+pub async fn quote<I, A, S>(
+    service: I,
+    from_account: A,
+    store: S,
+    receiver: &str,
+    source_amount: u64,
+    congestion_strategy: CongestionStrategy,
+) -> Result<StreamQuote, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    let spsp = query(receiver).await?;
+    let shared_secret = spsp.shared_secret;
+    let addr = spsp.destination_account;
+    debug!("Quoting SPSP payment to address: {}", addr);
+
+    let quote = quote_stream(
+        service,
+        &from_account,
+        store,
+        addr,
+        shared_secret,
+        source_amount,
+        congestion_strategy,
+    )
+    .map_err(|err| {
+        error!("Error quoting payment: {:?}", err);
+        Error::StreamError(err)
+    })
+    .await?;
+
+    debug!("Quoted SPSP payment: {:?}", quote);
+    Ok(quote)
+}
+
 fn payment_pointer_to_url(payment_pointer: &str) -> String {
     let mut url: String = if let Some(suffix) = payment_pointer.strip_prefix('$') {
         let prefix = "https://";
@@ -14,8 +14,8 @@ mod client;
 /// An SPSP Server implementing an HTTP Service which generates ILP Addresses and Shared Secrets
 mod server;
 
-pub use client::{pay, query};
-pub use server::SpspResponder;
+pub use client::{pay, pay_fixed_delivery, query, quote};
+pub use server::{parse_receipt_headers, SpspResponder};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
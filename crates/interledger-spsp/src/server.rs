@@ -1,8 +1,8 @@
 use super::SpspResponse;
 use bytes::Bytes;
-use hyper::{service::Service as HttpService, Body, Error, Request, Response};
+use hyper::{service::Service as HttpService, Body, Error, HeaderMap, Request, Response};
 use interledger_packet::Address;
-use interledger_stream::ConnectionGenerator;
+use interledger_stream::{ConnectionGenerator, ReceiptDetails};
 use std::error::Error as StdError;
 use std::{
     fmt, str,
@@ -32,9 +32,24 @@ impl SpspResponder {
     /// and shared secret for this connection
     /// These fields are generated via [Stream's `ConnectionGenerator`](../interledger_stream/struct.ConnectionGenerator.html#method.generate_address_and_secret)
     pub fn generate_http_response(&self) -> Response<Body> {
-        let (destination_account, shared_secret) = self
-            .connection_generator
-            .generate_address_and_secret(&self.ilp_address);
+        self.generate_http_response_with_receipts(None)
+    }
+
+    /// Like [`generate_http_response`](#method.generate_http_response), but if receipt
+    /// details are given the receiver sends [STREAM receipts](../interledger_stream/struct.Receipt.html)
+    /// for the money it receives on the connection
+    pub fn generate_http_response_with_receipts(
+        &self,
+        receipt_details: Option<&ReceiptDetails>,
+    ) -> Response<Body> {
+        let (destination_account, shared_secret) = match receipt_details {
+            Some(receipt_details) => self
+                .connection_generator
+                .generate_address_and_secret_with_receipts(&self.ilp_address, receipt_details),
+            None => self
+                .connection_generator
+                .generate_address_and_secret(&self.ilp_address),
+        };
         debug!(
             "Generated address and secret for: {:?}",
             destination_account
@@ -62,9 +77,59 @@ impl HttpService<Request<Body>> for SpspResponder {
         Ok(()).into()
     }
 
-    fn call(&mut self, _request: Request<Body>) -> Self::Future {
-        futures::future::ok(self.generate_http_response())
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let response = match receipt_details_from_headers(request.headers()) {
+            Ok(receipt_details) => {
+                self.generate_http_response_with_receipts(receipt_details.as_ref())
+            }
+            Err(err) => Response::builder()
+                .status(400)
+                .body(Body::from(err))
+                .unwrap(),
+        };
+        futures::future::ok(response)
+    }
+}
+
+/// Parses the base64-encoded values of the `Receipt-Nonce` and `Receipt-Secret` headers
+/// of an SPSP query, [as specified in the RFC](https://interledger.org/rfcs/0039-stream-receipts/#spsp).
+///
+/// Returns `None` if neither is given and an error if only one of them is given
+/// or if they are not a 16 byte nonce and a 32 byte secret.
+pub fn parse_receipt_headers(
+    receipt_nonce: Option<&str>,
+    receipt_secret: Option<&str>,
+) -> Result<Option<ReceiptDetails>, &'static str> {
+    let (receipt_nonce, receipt_secret) = match (receipt_nonce, receipt_secret) {
+        (None, None) => return Ok(None),
+        (Some(nonce), Some(secret)) => (nonce, secret),
+        _ => return Err("Receipt-Nonce and Receipt-Secret must be given together"),
+    };
+
+    let mut nonce = [0; 16];
+    match base64::decode(receipt_nonce) {
+        Ok(bytes) if bytes.len() == nonce.len() => nonce.copy_from_slice(&bytes),
+        _ => return Err("Receipt-Nonce must be 16 bytes encoded as base64"),
+    }
+    let mut secret = [0; 32];
+    match base64::decode(receipt_secret) {
+        Ok(bytes) if bytes.len() == secret.len() => secret.copy_from_slice(&bytes),
+        _ => return Err("Receipt-Secret must be 32 bytes encoded as base64"),
     }
+    Ok(Some(ReceiptDetails { nonce, secret }))
+}
+
+fn receipt_details_from_headers(
+    headers: &HeaderMap,
+) -> Result<Option<ReceiptDetails>, &'static str> {
+    let header = |name: &str| -> Result<Option<&str>, &'static str> {
+        headers
+            .get(name)
+            .map(|value| value.to_str())
+            .transpose()
+            .map_err(|_| "Receipt headers must be valid base64")
+    };
+    parse_receipt_headers(header("Receipt-Nonce")?, header("Receipt-Secret")?)
 }
 
 // copied from https://github.com/hyperium/hyper/blob/master/src/common/never.rs
@@ -112,4 +177,49 @@ mod spsp_server_test {
             "max-age=60"
         );
     }
+
+    #[tokio::test]
+    async fn enables_receipts_with_receipt_headers() {
+        let addr = Address::from_str("example.receiver").unwrap();
+        let server_secret = Bytes::from(&[0; 32][..]);
+        let mut responder = SpspResponder::new(addr, server_secret.clone());
+        let query = |nonce: &str, secret: &str| {
+            Request::builder()
+                .method("GET")
+                .uri("http://example.com")
+                .header("Accept", "application/spsp4+json")
+                .header("Receipt-Nonce", nonce)
+                .header("Receipt-Secret", secret)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = responder
+            .call(query(&base64::encode([1; 16]), &base64::encode([2; 32])))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let spsp_response: crate::SpspResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            ConnectionGenerator::new(server_secret)
+                .rederive_receipt_details(&spsp_response.destination_account),
+            Some(ReceiptDetails {
+                nonce: [1; 16],
+                secret: [2; 32],
+            })
+        );
+
+        let response = responder
+            .call(query(&base64::encode([1; 15]), &base64::encode([2; 32])))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[test]
+    fn receipt_headers_must_be_given_together() {
+        assert!(parse_receipt_headers(None, None).unwrap().is_none());
+        assert!(parse_receipt_headers(Some("AQEBAQEBAQEBAQEBAQEBAQ=="), None).is_err());
+    }
 }
@@ -1,13 +1,14 @@
 use super::crypto::{decrypt_token, encrypt_token};
 use interledger_api::AccountDetails;
 use interledger_btp::BtpAccount;
-use interledger_ccp::{CcpRoutingAccount, RoutingRelation};
+use interledger_ccp::{CcpRoutingAccount, RoutingPolicy, RoutingRelation};
 use interledger_errors::CreateAccountError;
-use interledger_http::HttpAccount;
+use interledger_http::{HttpAccount, HttpClientIdentity, HttpClientSettings};
 use interledger_packet::Address;
 use interledger_service::{Account as AccountTrait, Username};
 use interledger_service_util::{
-    MaxPacketAmountAccount, RateLimitAccount, RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
+    MaxPacketAmountAccount, MinBalanceAccount, RateLimitAccount, RoundTripTimeAccount,
+    DEFAULT_ROUND_TRIP_TIME,
 };
 use interledger_settlement::core::types::{SettlementAccount, SettlementEngineDetails};
 use ring::aead;
@@ -42,6 +43,16 @@ pub struct Account {
     pub(crate) min_balance: Option<i64>,
     /// The account's ILP over HTTP URL (this is where packets are sent over HTTP from your node)
     pub(crate) ilp_over_http_url: Option<Url>,
+    /// The URL the account's peer should POST asynchronous ILP over HTTP responses to
+    pub(crate) ilp_over_http_callback_url: Option<Url>,
+    /// Path to the PEM encoded client certificate presented to the account's peer
+    pub(crate) ilp_over_http_client_cert: Option<String>,
+    /// Path to the PEM encoded private key of the client certificate
+    pub(crate) ilp_over_http_client_key: Option<String>,
+    /// The SHA-256 fingerprint of the client certificate the account's peer presents
+    pub(crate) ilp_over_http_client_cert_fingerprint: Option<String>,
+    /// Settings of the HTTP client used to send ILP over HTTP requests to the account
+    pub(crate) ilp_over_http_client_settings: Option<HttpClientSettings>,
     #[serde(serialize_with = "optional_secret_bytes_to_utf8")]
     /// The account's API and incoming ILP over HTTP token.
     /// This must match the ILP over HTTP outgoing token on the peer's node if receiving
@@ -83,6 +94,21 @@ pub struct Account {
     /// for the account's asset code,  that will be used instead (even if the account is
     /// configured with a specific one)
     pub(crate) settlement_engine_url: Option<Url>,
+    /// The policy applied to the routes exchanged with the account over CCP
+    pub(crate) routing_policy: Option<RoutingPolicy>,
+}
+
+/// Accepts fingerprints in lowercase or uppercase hex, optionally separated by colons
+/// (as printed by `openssl x509 -fingerprint -sha256`), and returns them in lowercase hex
+pub(crate) fn normalize_fingerprint(fingerprint: &str) -> Result<String, CreateAccountError> {
+    let normalized = fingerprint.replace(':', "").to_lowercase();
+    if normalized.len() == 64 && normalized.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(normalized)
+    } else {
+        Err(CreateAccountError::InvalidCertificateFingerprint(
+            fingerprint.to_string(),
+        ))
+    }
 }
 
 fn address_to_string<S>(address: &Address, serializer: S) -> Result<S::Ok, S::Error>
@@ -125,6 +151,23 @@ impl Account {
             None
         };
 
+        let ilp_over_http_callback_url = if let Some(ref url) = details.ilp_over_http_callback_url {
+            Some(Url::parse(url).map_err(CreateAccountError::InvalidHttpUrl)?)
+        } else {
+            None
+        };
+
+        if details.ilp_over_http_client_cert.is_some() != details.ilp_over_http_client_key.is_some()
+        {
+            return Err(CreateAccountError::IncompleteClientIdentity);
+        }
+
+        let ilp_over_http_client_cert_fingerprint = details
+            .ilp_over_http_client_cert_fingerprint
+            .as_deref()
+            .map(normalize_fingerprint)
+            .transpose()?;
+
         let ilp_over_btp_url = if let Some(ref url) = details.ilp_over_btp_url {
             Some(Url::parse(url).map_err(CreateAccountError::InvalidBtpUrl)?)
         } else {
@@ -153,6 +196,11 @@ impl Account {
             max_packet_amount: details.max_packet_amount,
             min_balance: details.min_balance,
             ilp_over_http_url,
+            ilp_over_http_callback_url,
+            ilp_over_http_client_cert: details.ilp_over_http_client_cert,
+            ilp_over_http_client_key: details.ilp_over_http_client_key,
+            ilp_over_http_client_cert_fingerprint,
+            ilp_over_http_client_settings: details.ilp_over_http_client_settings,
             ilp_over_http_incoming_token: details
                 .ilp_over_http_incoming_token
                 .map(|token| SecretBytesMut::new(token.expose_secret().as_str())),
@@ -173,6 +221,7 @@ impl Account {
             packets_per_minute_limit: details.packets_per_minute_limit,
             amount_per_minute_limit: details.amount_per_minute_limit,
             settlement_engine_url,
+            routing_policy: details.routing_policy,
         })
     }
 
@@ -296,6 +345,26 @@ impl HttpAccount for Account {
         self.ilp_over_http_url.as_ref()
     }
 
+    fn get_http_callback_url(&self) -> Option<&Url> {
+        self.ilp_over_http_callback_url.as_ref()
+    }
+
+    fn get_http_client_settings(&self) -> HttpClientSettings {
+        self.ilp_over_http_client_settings
+            .clone()
+            .unwrap_or_default()
+    }
+
+    fn get_http_client_identity(&self) -> Option<HttpClientIdentity> {
+        match (
+            &self.ilp_over_http_client_cert,
+            &self.ilp_over_http_client_key,
+        ) {
+            (Some(cert), Some(key)) => Some(HttpClientIdentity::new(cert, key)),
+            _ => None,
+        }
+    }
+
     fn get_http_auth_token(&self) -> Option<SecretString> {
         self.ilp_over_http_outgoing_token.as_ref().map(|s| {
             SecretString::new(
@@ -325,10 +394,20 @@ impl MaxPacketAmountAccount for Account {
     }
 }
 
+impl MinBalanceAccount for Account {
+    fn min_balance(&self) -> Option<i64> {
+        self.min_balance
+    }
+}
+
 impl CcpRoutingAccount for Account {
     fn routing_relation(&self) -> RoutingRelation {
         self.routing_relation
     }
+
+    fn routing_policy(&self) -> Option<&RoutingPolicy> {
+        self.routing_policy.as_ref()
+    }
 }
 
 impl RoundTripTimeAccount for Account {
@@ -370,6 +449,11 @@ mod test {
         min_balance: Some(-1000),
         // we are Bob and we're using this account to peer with Alice
         ilp_over_http_url: Some("http://example.com/accounts/bob/ilp".to_string()),
+        ilp_over_http_callback_url: None,
+        ilp_over_http_client_cert: None,
+        ilp_over_http_client_key: None,
+        ilp_over_http_client_cert_fingerprint: None,
+        ilp_over_http_client_settings: None,
         ilp_over_http_incoming_token: Some(SecretString::new("incoming_auth_token".to_string())),
         ilp_over_http_outgoing_token: Some(SecretString::new("outgoing_auth_token".to_string())),
         ilp_over_btp_url: Some("btp+ws://example.com/accounts/bob/ilp/btp".to_string()),
@@ -382,6 +466,7 @@ mod test {
         amount_per_minute_limit: None,
         packets_per_minute_limit: None,
         settlement_engine_url: None,
+        routing_policy: None,
     });
 
     #[test]
@@ -413,4 +498,41 @@ mod test {
         );
         assert_eq!(account.routing_relation(), RoutingRelation::Peer);
     }
+
+    #[test]
+    fn client_certificates() {
+        let mut details = ACCOUNT_DETAILS.clone();
+        details.ilp_over_http_client_cert = Some("/etc/ilp/alice.crt".to_string());
+        details.ilp_over_http_client_cert_fingerprint = Some(
+            "03:D6:6D:D0:88:35:C1:CA:3F:12:8C:CE:AC:D1:F3:1A:C9:41:63:09:6B:20:F4:45:AE:84:28:5B:C0:83:2D:72"
+                .to_string(),
+        );
+        let node_address = Address::from_str("example.account").unwrap();
+        // the key is missing
+        assert!(matches!(
+            Account::try_from(Uuid::new_v4(), details.clone(), node_address.clone()),
+            Err(CreateAccountError::IncompleteClientIdentity)
+        ));
+
+        details.ilp_over_http_client_key = Some("/etc/ilp/alice.key".to_string());
+        let account =
+            Account::try_from(Uuid::new_v4(), details.clone(), node_address.clone()).unwrap();
+        assert_eq!(
+            account.get_http_client_identity(),
+            Some(HttpClientIdentity::new(
+                "/etc/ilp/alice.crt",
+                "/etc/ilp/alice.key"
+            ))
+        );
+        assert_eq!(
+            account.ilp_over_http_client_cert_fingerprint.unwrap(),
+            "03d66dd08835c1ca3f128cceacd1f31ac94163096b20f445ae84285bc0832d72"
+        );
+
+        details.ilp_over_http_client_cert_fingerprint = Some("not a fingerprint".to_string());
+        assert!(matches!(
+            Account::try_from(Uuid::new_v4(), details, node_address),
+            Err(CreateAccountError::InvalidCertificateFingerprint(_))
+        ));
+    }
 }
@@ -19,7 +19,7 @@
 mod reconnect;
 use reconnect::RedisReconnect;
 
-use super::account::{Account, AccountWithEncryptedTokens};
+use super::account::{normalize_fingerprint, Account, AccountWithEncryptedTokens};
 use super::crypto::{encrypt_token, generate_keys, DecryptionKey, EncryptionKey};
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
@@ -27,12 +27,15 @@ use futures::channel::mpsc::UnboundedSender;
 use http::StatusCode;
 use interledger_api::{AccountDetails, AccountSettings, EncryptedAccountSettings, NodeStore};
 use interledger_btp::BtpStore;
-use interledger_ccp::{CcpRoutingAccount, CcpRoutingStore, RoutingRelation};
+use interledger_ccp::{
+    CcpRoutingAccount, CcpRoutingState, CcpRoutingStore, RouteMetrics, RouteSource, RoutingEvent,
+    RoutingEventKind, RoutingEventsStore, RoutingRelation,
+};
 use interledger_errors::*;
-use interledger_http::HttpStore;
+use interledger_http::{ClientCertificate, HttpStore};
 use interledger_packet::Address;
 use interledger_rates::ExchangeRateStore;
-use interledger_router::RouterStore;
+use interledger_router::{NextHop, RouterStore};
 use interledger_service::{Account as AccountTrait, AccountStore, AddressStore, Username};
 use interledger_service_util::{
     BalanceStore, RateLimitError, RateLimitStore, DEFAULT_ROUND_TRIP_TIME,
@@ -62,13 +65,14 @@ use uuid::Uuid;
 use zeroize::Zeroize;
 
 const DEFAULT_POLL_INTERVAL: u64 = 30000; // 30 seconds
-const ACCOUNT_DETAILS_FIELDS: usize = 21;
+const ACCOUNT_DETAILS_FIELDS: usize = 27;
 const DEFAULT_DB_PREFIX: &str = "";
 
 static PARENT_ILP_KEY: &str = "parent_node_account_address";
 static ROUTES_KEY: &str = "routes:current";
 static STATIC_ROUTES_KEY: &str = "routes:static";
 static DEFAULT_ROUTE_KEY: &str = "routes:default";
+static CCP_ROUTING_STATE_KEY: &str = "routes:ccp_state";
 static STREAM_NOTIFICATIONS_PREFIX: &str = "stream_notifications:";
 static SETTLEMENT_ENGINES_KEY: &str = "settlement_engines";
 static USERNAMES_KEY: &str = "usernames";
@@ -239,14 +243,18 @@ impl RedisStoreBuilder {
         };
 
         let (all_payment_publisher, _) = broadcast::channel::<PaymentNotification>(256);
+        let (routing_event_publisher, _) = broadcast::channel::<RoutingEvent>(256);
 
         let store = RedisStore {
             ilp_address: Arc::new(RwLock::new(node_ilp_address)),
             connection,
             subscriptions: Arc::new(Mutex::new(HashMap::new())),
             payment_publisher: all_payment_publisher,
+            routing_event_publisher,
             exchange_rates: Arc::new(RwLock::new(HashMap::new())),
-            routes: Arc::new(RwLock::new(Arc::new(HashMap::new()))),
+            routes: Arc::new(RwLock::new(Arc::new(
+                interledger_router::RoutingTable::new(),
+            ))),
             encryption_key: Arc::new(encryption_key),
             decryption_key: Arc::new(decryption_key),
             db_prefix: self.db_prefix.clone(),
@@ -368,14 +376,16 @@ pub struct RedisStore {
     subscriptions: Arc<Mutex<HashMap<Uuid, Vec<UnboundedSender<PaymentNotification>>>>>,
     /// A subscriber to all payment notifications, exposed via a WebSocket
     payment_publisher: broadcast::Sender<PaymentNotification>,
+    /// A subscriber to all changes to the routing table, exposed via a WebSocket
+    routing_event_publisher: broadcast::Sender<RoutingEvent>,
     exchange_rates: Arc<RwLock<HashMap<String, f64>>>,
     /// The store keeps the routing table in memory so that it can be returned
     /// synchronously while the Router is processing packets.
     /// The outer `Arc<RwLock>` is used so that we can update the stored routing
     /// table after polling the store for updates.
-    /// The inner `Arc<HashMap>` is used so that the `routing_table` method can
+    /// The inner `Arc<RoutingTable>` is used so that the `routing_table` method can
     /// return a reference to the routing table without cloning the underlying data.
-    routes: Arc<RwLock<Arc<HashMap<String, Uuid>>>>,
+    routes: Arc<RwLock<Arc<interledger_router::RoutingTable>>>,
     /// Encryption Key so that the no cleartext data are stored
     encryption_key: Arc<Secret<EncryptionKey>>,
     /// Decryption Key to provide cleartext data to users
@@ -536,6 +546,19 @@ impl RedisStore {
             .arg(encrypted)
             .ignore();
 
+        // Remove the routing policy if the update cleared it
+        if account.routing_policy.is_none() {
+            pipe.hdel(accounts_key(&self.db_prefix, account.id), "routing_policy")
+                .ignore();
+        }
+        if account.ilp_over_http_client_settings.is_none() {
+            pipe.hdel(
+                accounts_key(&self.db_prefix, account.id),
+                "ilp_over_http_client_settings",
+            )
+            .ignore();
+        }
+
         if account.should_send_routes() {
             pipe.sadd(
                 &*prefixed_key(&self.db_prefix, SEND_ROUTES_KEY),
@@ -596,6 +619,44 @@ impl RedisStore {
             pipe.hset(&accounts_key, "ilp_over_http_url", endpoint);
         }
 
+        if let Some(ref endpoint) = settings.ilp_over_http_callback_url {
+            pipe.hset(&accounts_key, "ilp_over_http_callback_url", endpoint);
+        }
+
+        match (
+            &settings.ilp_over_http_client_cert,
+            &settings.ilp_over_http_client_key,
+        ) {
+            (Some(cert), Some(key)) => {
+                pipe.hset(&accounts_key, "ilp_over_http_client_cert", cert);
+                pipe.hset(&accounts_key, "ilp_over_http_client_key", key);
+            }
+            (None, None) => {}
+            _ => {
+                return Err(NodeStoreError::InvalidAccount(
+                    CreateAccountError::IncompleteClientIdentity,
+                ))
+            }
+        }
+
+        if let Some(ref client_settings) = settings.ilp_over_http_client_settings {
+            pipe.hset(
+                &accounts_key,
+                "ilp_over_http_client_settings",
+                serde_json::to_string(client_settings).unwrap_or_default(),
+            );
+        }
+
+        if let Some(ref fingerprint) = settings.ilp_over_http_client_cert_fingerprint {
+            let fingerprint =
+                normalize_fingerprint(fingerprint).map_err(NodeStoreError::InvalidAccount)?;
+            pipe.hset(
+                &accounts_key,
+                "ilp_over_http_client_cert_fingerprint",
+                fingerprint,
+            );
+        }
+
         if let Some(ref token) = settings.ilp_over_btp_outgoing_token {
             pipe.hset(&accounts_key, "ilp_over_btp_outgoing_token", token.as_ref());
         }
@@ -718,6 +779,86 @@ impl RedisStore {
         debug!("Deleted account {}", account.id);
         Ok(encrypted)
     }
+
+    /// Gets the static routes, without the default route
+    async fn get_static_routes_without_default(
+        &self,
+    ) -> Result<HashMap<String, Vec<NextHop>>, NodeStoreError> {
+        let static_routes: Vec<(String, RedisNextHops)> = self
+            .connection
+            .clone()
+            .hgetall(&*prefixed_key(&self.db_prefix, STATIC_ROUTES_KEY))
+            .await?;
+        Ok(static_routes
+            .into_iter()
+            .map(|(prefix, next_hops)| (prefix, next_hops.0))
+            .collect())
+    }
+
+    /// Publishes the routing events for the changes from the old to the new static routes.
+    /// The next hop of each event is the first of the route's next hops with the best priority
+    async fn publish_static_route_events(
+        &self,
+        old_routes: HashMap<String, Vec<NextHop>>,
+        new_routes: HashMap<String, Vec<NextHop>>,
+    ) {
+        let preferred = |next_hops: &[NextHop]| {
+            next_hops
+                .iter()
+                .min_by_key(|next_hop| next_hop.priority)
+                .map(|next_hop| next_hop.account_id)
+        };
+        let mut changes: Vec<(RoutingEventKind, String, Uuid)> = Vec::new();
+        for (prefix, next_hops) in new_routes.iter() {
+            let next_hop = match preferred(next_hops) {
+                Some(next_hop) => next_hop,
+                None => continue,
+            };
+            match old_routes.get(prefix) {
+                Some(old_next_hops) if old_next_hops == next_hops => {}
+                Some(old_next_hops) if !old_next_hops.is_empty() => {
+                    changes.push((RoutingEventKind::Replaced, prefix.clone(), next_hop))
+                }
+                _ => changes.push((RoutingEventKind::Added, prefix.clone(), next_hop)),
+            }
+        }
+        for (prefix, next_hops) in old_routes.iter() {
+            let is_withdrawn = new_routes
+                .get(prefix)
+                .map(|next_hops| next_hops.is_empty())
+                .unwrap_or(true);
+            if let (true, Some(next_hop)) = (is_withdrawn, preferred(next_hops)) {
+                changes.push((RoutingEventKind::Withdrawn, prefix.clone(), next_hop));
+            }
+        }
+        if changes.is_empty() {
+            return;
+        }
+
+        let mut account_ids: Vec<Uuid> = changes.iter().map(|(_, _, id)| *id).collect();
+        account_ids.sort_unstable();
+        account_ids.dedup();
+        // The accounts of withdrawn routes may have been deleted, in which
+        // case the events are published without their next hops
+        let usernames: HashMap<Uuid, Username> = match self.get_accounts(account_ids).await {
+            Ok(accounts) => accounts
+                .into_iter()
+                .map(|account| (account.id, account.username))
+                .collect(),
+            Err(_) => HashMap::new(),
+        };
+
+        changes.sort_unstable_by(|a, b| a.1.cmp(&b.1));
+        for (kind, prefix, account_id) in changes {
+            self.publish_routing_event(RoutingEvent {
+                kind,
+                prefix,
+                next_hop: usernames.get(&account_id).cloned(),
+                source: RouteSource::Static,
+                epoch: None,
+            });
+        }
+    }
 }
 
 #[async_trait]
@@ -839,6 +980,18 @@ impl StreamNotificationsStore for RedisStore {
     }
 }
 
+impl RoutingEventsStore for RedisStore {
+    fn publish_routing_event(&self, event: RoutingEvent) {
+        trace!("Publishing routing event {:?}", event);
+        // An error only means that there are no subscribers
+        let _ = self.routing_event_publisher.send(event);
+    }
+
+    fn routing_event_subscription(&self) -> broadcast::Receiver<RoutingEvent> {
+        self.routing_event_publisher.subscribe()
+    }
+}
+
 #[async_trait]
 impl BalanceStore for RedisStore {
     /// Returns the balance **from the account holder's perspective**, meaning the sum of
@@ -1078,10 +1231,36 @@ impl HttpStore for RedisStore {
             Err(HttpStoreError::AccountNotFound(username.to_string()))
         }
     }
+
+    /// Checks if the fingerprint configured for the provided account matches the
+    /// certificate the client presented, and if so, returns the account
+    async fn get_account_from_client_certificate(
+        &self,
+        username: &Username,
+        certificate: &ClientCertificate,
+    ) -> Result<Self::Account, HttpStoreError> {
+        let account: Option<AccountWithEncryptedTokens> = ACCOUNT_FROM_USERNAME
+            .arg(&*prefixed_key(&self.db_prefix, USERNAMES_KEY))
+            .arg(&*prefixed_key(&self.db_prefix, ACCOUNTS_KEY))
+            .arg(username.as_ref())
+            .invoke_async(&mut self.connection.clone())
+            .await?;
+
+        if let Some(account) = account {
+            let account = account.decrypt_tokens(&self.decryption_key.expose_secret().0);
+            match account.ilp_over_http_client_cert_fingerprint {
+                Some(ref fingerprint) if fingerprint == certificate.fingerprint() => Ok(account),
+                _ => Err(HttpStoreError::Unauthorized(username.to_string())),
+            }
+        } else {
+            warn!("No account found with given client certificate");
+            Err(HttpStoreError::AccountNotFound(username.to_string()))
+        }
+    }
 }
 
 impl RouterStore for RedisStore {
-    fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
+    fn routing_table(&self) -> Arc<interledger_router::RoutingTable> {
         self.routes.read().clone()
     }
 }
@@ -1144,6 +1323,11 @@ impl NodeStore for RedisStore {
             settle_threshold: settings.settle_threshold,
             ilp_over_btp_url: settings.ilp_over_btp_url,
             ilp_over_http_url: settings.ilp_over_http_url,
+            ilp_over_http_callback_url: settings.ilp_over_http_callback_url,
+            ilp_over_http_client_cert: settings.ilp_over_http_client_cert,
+            ilp_over_http_client_key: settings.ilp_over_http_client_key,
+            ilp_over_http_client_cert_fingerprint: settings.ilp_over_http_client_cert_fingerprint,
+            ilp_over_http_client_settings: settings.ilp_over_http_client_settings,
             ilp_over_btp_incoming_token: settings.ilp_over_btp_incoming_token.map(|token| {
                 encrypt_token(
                     &self.encryption_key.expose_secret().0,
@@ -1206,16 +1390,39 @@ impl NodeStore for RedisStore {
     async fn set_static_routes<R>(&self, routes: R) -> Result<(), NodeStoreError>
     where
         R: IntoIterator<Item = (String, Uuid)> + Send + 'async_trait,
+    {
+        let routes: Vec<(String, Vec<NextHop>)> = routes
+            .into_iter()
+            .map(|(prefix, account_id)| (prefix, vec![NextHop::from(account_id)]))
+            .collect();
+        self.set_static_multipath_routes(routes).await
+    }
+
+    async fn set_static_route(
+        &self,
+        prefix: String,
+        account_id: Uuid,
+    ) -> Result<(), NodeStoreError> {
+        self.set_static_multipath_route(prefix, vec![NextHop::from(account_id)])
+            .await
+    }
+
+    async fn set_static_multipath_routes<R>(&self, routes: R) -> Result<(), NodeStoreError>
+    where
+        R: IntoIterator<Item = (String, Vec<NextHop>)> + Send + 'async_trait,
     {
         let mut connection = self.connection.clone();
-        let routes: Vec<(String, RedisAccountId)> = routes
+        let routes: Vec<(String, RedisNextHops)> = routes
             .into_iter()
-            .map(|(s, id)| (s, RedisAccountId(id)))
+            .map(|(prefix, next_hops)| (prefix, RedisNextHops(next_hops)))
             .collect();
-        let accounts = routes.iter().map(|(_prefix, account_id)| account_id);
+        let accounts = routes
+            .iter()
+            .flat_map(|(_prefix, next_hops)| next_hops.0.iter())
+            .map(|next_hop| next_hop.account_id);
         let mut pipe = redis_crate::pipe();
         for account_id in accounts {
-            pipe.exists(accounts_key(&self.db_prefix, (*account_id).0));
+            pipe.exists(accounts_key(&self.db_prefix, account_id));
         }
 
         let routing_table = self.routes.clone();
@@ -1228,51 +1435,105 @@ impl NodeStore for RedisStore {
             return Err(NodeStoreError::MissingAccounts);
         }
 
+        let old_routes = self.get_static_routes_without_default().await?;
+
         let mut pipe = redis_crate::pipe();
         pipe.atomic()
             .del(&*prefixed_key(&self.db_prefix, STATIC_ROUTES_KEY))
-            .ignore()
-            .hset_multiple(&*prefixed_key(&self.db_prefix, STATIC_ROUTES_KEY), &routes)
             .ignore();
+        // Routes without any next hops are simply left out
+        let routes: Vec<_> = routes
+            .into_iter()
+            .filter(|(_prefix, next_hops)| !next_hops.0.is_empty())
+            .collect();
+        if !routes.is_empty() {
+            pipe.hset_multiple(&*prefixed_key(&self.db_prefix, STATIC_ROUTES_KEY), &routes)
+                .ignore();
+        }
 
         pipe.query_async(&mut connection).await?;
 
         update_routes(connection, routing_table, &self.db_prefix).await?;
+        let new_routes = routes
+            .into_iter()
+            .map(|(prefix, next_hops)| (prefix, next_hops.0))
+            .collect();
+        self.publish_static_route_events(old_routes, new_routes)
+            .await;
         Ok(())
     }
 
-    async fn set_static_route(
+    async fn set_static_multipath_route(
         &self,
         prefix: String,
-        account_id: Uuid,
+        next_hops: Vec<NextHop>,
     ) -> Result<(), NodeStoreError> {
         let routing_table = self.routes.clone();
         let mut connection = self.connection.clone();
 
-        let exists: bool = connection
-            .exists(accounts_key(&self.db_prefix, account_id))
-            .await?;
-        if !exists {
-            error!(
-                "Cannot set static route for prefix: {} because account {} does not exist",
-                prefix, account_id
-            );
-            return Err(NodeStoreError::AccountNotFound(account_id.to_string()));
+        for next_hop in next_hops.iter() {
+            let exists: bool = connection
+                .exists(accounts_key(&self.db_prefix, next_hop.account_id))
+                .await?;
+            if !exists {
+                error!(
+                    "Cannot set static route for prefix: {} because account {} does not exist",
+                    prefix, next_hop.account_id
+                );
+                return Err(NodeStoreError::AccountNotFound(
+                    next_hop.account_id.to_string(),
+                ));
+            }
         }
 
-        connection
-            .hset(
-                &*prefixed_key(&self.db_prefix, STATIC_ROUTES_KEY),
-                prefix,
-                RedisAccountId(account_id),
-            )
+        let old_next_hops: Option<RedisNextHops> = connection
+            .hget(&*prefixed_key(&self.db_prefix, STATIC_ROUTES_KEY), &prefix)
             .await?;
 
+        if next_hops.is_empty() {
+            connection
+                .hdel(&*prefixed_key(&self.db_prefix, STATIC_ROUTES_KEY), &prefix)
+                .await?;
+        } else {
+            connection
+                .hset(
+                    &*prefixed_key(&self.db_prefix, STATIC_ROUTES_KEY),
+                    &prefix,
+                    RedisNextHops(next_hops.clone()),
+                )
+                .await?;
+        }
+
         update_routes(connection, routing_table, &self.db_prefix).await?;
+        let old_routes = old_next_hops
+            .map(|old_next_hops| (prefix.clone(), old_next_hops.0))
+            .into_iter()
+            .collect();
+        let new_routes = std::iter::once((prefix, next_hops)).collect();
+        self.publish_static_route_events(old_routes, new_routes)
+            .await;
 
         Ok(())
     }
 
+    async fn get_static_routes(&self) -> Result<HashMap<String, Vec<NextHop>>, NodeStoreError> {
+        let mut pipe = redis_crate::pipe();
+        pipe.hgetall(&*prefixed_key(&self.db_prefix, STATIC_ROUTES_KEY))
+            .get(&*prefixed_key(&self.db_prefix, DEFAULT_ROUTE_KEY));
+        let (static_routes, default_route): (Vec<(String, RedisNextHops)>, Option<RedisAccountId>) =
+            pipe.query_async(&mut self.connection.clone()).await?;
+        // Static routes override the default route, same as in the routing table
+        Ok(default_route
+            .map(|rid| (String::new(), vec![NextHop::from(rid.0)]))
+            .into_iter()
+            .chain(
+                static_routes
+                    .into_iter()
+                    .map(|(prefix, next_hops)| (prefix, next_hops.0)),
+            )
+            .collect())
+    }
+
     async fn set_default_route(&self, account_id: Uuid) -> Result<(), NodeStoreError> {
         let routing_table = self.routes.clone();
         // TODO replace this with a lua script to do both calls at once
@@ -1288,14 +1549,22 @@ impl NodeStore for RedisStore {
             return Err(NodeStoreError::AccountNotFound(account_id.to_string()));
         }
 
-        connection
-            .set(
+        let old_account_id: Option<RedisAccountId> = connection
+            .getset(
                 &*prefixed_key(&self.db_prefix, DEFAULT_ROUTE_KEY),
                 RedisAccountId(account_id),
             )
             .await?;
         debug!("Set default route to account id: {}", account_id);
         update_routes(connection, routing_table, &self.db_prefix).await?;
+        let old_routes = old_account_id
+            .map(|rid| (String::new(), vec![NextHop::from(rid.0)]))
+            .into_iter()
+            .collect();
+        let new_routes =
+            std::iter::once((String::new(), vec![NextHop::from(account_id)])).collect();
+        self.publish_static_route_events(old_routes, new_routes)
+            .await;
         Ok(())
     }
 
@@ -1492,7 +1761,7 @@ impl CcpRoutingStore for RedisStore {
     async fn get_local_and_configured_routes(
         &self,
     ) -> Result<(RoutingTable<Account>, RoutingTable<Account>), CcpRoutingStoreError> {
-        let static_routes: Vec<(String, RedisAccountId)> = self
+        let static_routes: Vec<(String, RedisNextHops)> = self
             .connection
             .clone()
             .hgetall(&*prefixed_key(&self.db_prefix, STATIC_ROUTES_KEY))
@@ -1511,8 +1780,10 @@ impl CcpRoutingStore for RedisStore {
             .collect();
         let configured_table: HashMap<String, Account> = static_routes
             .into_iter()
-            .filter_map(|(prefix, account_id)| {
-                if let Some(account) = account_map.get(&account_id.0) {
+            .filter_map(|(prefix, next_hops)| {
+                // Only the primary next hop of multipath routes is advertised to peers
+                let account_id = next_hops.0.first()?.account_id;
+                if let Some(account) = account_map.get(&account_id) {
                     Some((prefix, (*account).clone()))
                 } else {
                     warn!(
@@ -1552,6 +1823,79 @@ impl CcpRoutingStore for RedisStore {
         update_routes(connection, self.routes.clone(), &self.db_prefix).await?;
         Ok(())
     }
+
+    /// Reports the round trip time configured for each account and how far its balance
+    /// is below its settle threshold, which is how much more can be forwarded to it before
+    /// it has to be settled. Accounts without a settle threshold have no headroom limit
+    async fn get_route_metrics(
+        &self,
+        account_ids: Vec<Uuid>,
+    ) -> Result<HashMap<Uuid, RouteMetrics>, CcpRoutingStoreError> {
+        if account_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut pipe = redis_crate::pipe();
+        for id in account_ids.iter() {
+            pipe.hget(
+                accounts_key(&self.db_prefix, *id),
+                &["balance", "settle_threshold", "round_trip_time"],
+            );
+        }
+        let values: Vec<(Option<i64>, Option<i64>, Option<u32>)> =
+            pipe.query_async(&mut self.connection.clone()).await?;
+
+        Ok(account_ids
+            .into_iter()
+            .zip(values)
+            .map(|(id, (balance, settle_threshold, round_trip_time))| {
+                // Forwarding packets to the account raises its balance, and the settlement
+                // is triggered on the balance without the prepaid amount
+                let balance = balance.unwrap_or(0);
+                let balance_headroom = settle_threshold
+                    .map(|settle_threshold| settle_threshold.saturating_sub(balance).max(0) as u64);
+                (
+                    id,
+                    RouteMetrics {
+                        expected_round_trip_time: Some(
+                            round_trip_time.unwrap_or(DEFAULT_ROUND_TRIP_TIME),
+                        ),
+                        balance_headroom,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    /// The routing state is saved as a JSON string
+    async fn save_routing_state(
+        &mut self,
+        state: CcpRoutingState,
+    ) -> Result<(), CcpRoutingStoreError> {
+        let state = serde_json::to_string(&state)
+            .map_err(|err| CcpRoutingStoreError::Other(Box::new(err)))?;
+        self.connection
+            .clone()
+            .set::<_, _, ()>(
+                &*prefixed_key(&self.db_prefix, CCP_ROUTING_STATE_KEY),
+                state,
+            )
+            .await?;
+        trace!("Saved CCP routing state to Redis");
+        Ok(())
+    }
+
+    async fn load_routing_state(&self) -> Result<Option<CcpRoutingState>, CcpRoutingStoreError> {
+        let state: Option<String> = self
+            .connection
+            .clone()
+            .get(&*prefixed_key(&self.db_prefix, CCP_ROUTING_STATE_KEY))
+            .await?;
+        state
+            .map(|state| serde_json::from_str(&state))
+            .transpose()
+            .map_err(|err| CcpRoutingStoreError::Other(Box::new(err)))
+    }
 }
 
 #[async_trait]
@@ -1966,15 +2310,18 @@ use futures::future::TryFutureExt;
 // TODO replace this with pubsub when async pubsub is added upstream: https://github.com/mitsuhiko/redis-rs/issues/183
 async fn update_routes(
     mut connection: RedisReconnect,
-    routing_table: Arc<RwLock<Arc<HashMap<String, Uuid>>>>,
+    routing_table: Arc<RwLock<Arc<interledger_router::RoutingTable>>>,
     db_prefix: &str,
 ) -> Result<(), RedisError> {
     let mut pipe = redis_crate::pipe();
     pipe.hgetall(&*prefixed_key(db_prefix, ROUTES_KEY))
         .hgetall(&*prefixed_key(db_prefix, STATIC_ROUTES_KEY))
         .get(&*prefixed_key(db_prefix, DEFAULT_ROUTE_KEY));
-    let (routes, static_routes, default_route): (RouteVec, RouteVec, Option<RedisAccountId>) =
-        pipe.query_async(&mut connection).await?;
+    let (routes, static_routes, default_route): (
+        RouteVec,
+        Vec<(String, RedisNextHops)>,
+        Option<RedisAccountId>,
+    ) = pipe.query_async(&mut connection).await?;
     trace!(
         "Loaded routes from redis. Static routes: {:?}, default route: {:?}, other routes: {:?}",
         static_routes,
@@ -1985,12 +2332,16 @@ async fn update_routes(
     // set the entry for "" in the routing table to route to that account
     let routes = routes
         .into_iter()
-        .map(|(s, rid)| (s, rid.0))
+        .map(|(s, rid)| (s, vec![NextHop::from(rid.0)]))
         // Include the default route if there is one
-        .chain(default_route.map(|rid| (String::new(), rid.0)))
+        .chain(default_route.map(|rid| (String::new(), vec![NextHop::from(rid.0)])))
         // Having the static_routes inserted after ensures that they will overwrite
         // any routes with the same prefix from the first set
-        .chain(static_routes.into_iter().map(|(s, rid)| (s, rid.0)))
+        .chain(
+            static_routes
+                .into_iter()
+                .map(|(s, next_hops)| (s, next_hops.0)),
+        )
         .collect();
     // TODO we may not want to print this because the routing table will be very big
     // if the node has a lot of local accounts
@@ -2036,6 +2387,42 @@ impl FromRedisValue for RedisAccountId {
     }
 }
 
+/// The next hops of a static route. Routes with a single next hop with the default
+/// weight and priority are stored as a plain account id (which is how all static
+/// routes used to be stored), other routes as a JSON array of next hops.
+#[derive(Debug, Clone)]
+struct RedisNextHops(Vec<NextHop>);
+
+impl ToRedisArgs for RedisNextHops {
+    fn write_redis_args<W: RedisWrite + ?Sized>(&self, out: &mut W) {
+        match self.0.as_slice() {
+            [next_hop] if *next_hop == NextHop::from(next_hop.account_id) => {
+                RedisAccountId(next_hop.account_id).write_redis_args(out)
+            }
+            next_hops => out.write_arg(
+                serde_json::to_string(next_hops)
+                    .expect("next hops can always be serialized")
+                    .as_bytes(),
+            ),
+        }
+    }
+}
+
+impl FromRedisValue for RedisNextHops {
+    fn from_redis_value(v: &Value) -> Result<Self, RedisError> {
+        let next_hops = String::from_redis_value(v)?;
+        if next_hops.starts_with('[') {
+            let next_hops = serde_json::from_str(&next_hops).map_err(|_| {
+                RedisError::from((ErrorKind::TypeError, "Invalid next hops string"))
+            })?;
+            Ok(RedisNextHops(next_hops))
+        } else {
+            let account_id = RedisAccountId::from_redis_value(v)?;
+            Ok(RedisNextHops(vec![NextHop::from(account_id.0)]))
+        }
+    }
+}
+
 impl ToRedisArgs for &AccountWithEncryptedTokens {
     fn write_redis_args<W: RedisWrite + ?Sized>(&self, out: &mut W) {
         let mut rv = Vec::with_capacity(ACCOUNT_DETAILS_FIELDS * 2);
@@ -2074,6 +2461,24 @@ impl ToRedisArgs for &AccountWithEncryptedTokens {
             "ilp_over_http_url".write_redis_args(&mut rv);
             ilp_over_http_url.as_str().write_redis_args(&mut rv);
         }
+        if let Some(ilp_over_http_callback_url) = account.ilp_over_http_callback_url.as_ref() {
+            "ilp_over_http_callback_url".write_redis_args(&mut rv);
+            ilp_over_http_callback_url
+                .as_str()
+                .write_redis_args(&mut rv);
+        }
+        if let Some(ilp_over_http_client_cert) = account.ilp_over_http_client_cert.as_ref() {
+            "ilp_over_http_client_cert".write_redis_args(&mut rv);
+            ilp_over_http_client_cert.write_redis_args(&mut rv);
+        }
+        if let Some(ilp_over_http_client_key) = account.ilp_over_http_client_key.as_ref() {
+            "ilp_over_http_client_key".write_redis_args(&mut rv);
+            ilp_over_http_client_key.write_redis_args(&mut rv);
+        }
+        if let Some(fingerprint) = account.ilp_over_http_client_cert_fingerprint.as_ref() {
+            "ilp_over_http_client_cert_fingerprint".write_redis_args(&mut rv);
+            fingerprint.write_redis_args(&mut rv);
+        }
         if let Some(ilp_over_http_incoming_token) = account.ilp_over_http_incoming_token.as_ref() {
             "ilp_over_http_incoming_token".write_redis_args(&mut rv);
             ilp_over_http_incoming_token
@@ -2130,6 +2535,18 @@ impl ToRedisArgs for &AccountWithEncryptedTokens {
             "settlement_engine_url".write_redis_args(&mut rv);
             settlement_engine_url.as_str().write_redis_args(&mut rv);
         }
+        if let Some(client_settings) = &account.ilp_over_http_client_settings {
+            "ilp_over_http_client_settings".write_redis_args(&mut rv);
+            serde_json::to_string(client_settings)
+                .unwrap_or_default()
+                .write_redis_args(&mut rv);
+        }
+        if let Some(routing_policy) = &account.routing_policy {
+            "routing_policy".write_redis_args(&mut rv);
+            serde_json::to_string(routing_policy)
+                .unwrap_or_default()
+                .write_redis_args(&mut rv);
+        }
 
         debug_assert!(rv.len() <= ACCOUNT_DETAILS_FIELDS * 2);
         debug_assert!((rv.len() % 2) == 0);
@@ -2156,6 +2573,25 @@ impl FromRedisValue for AccountWithEncryptedTokens {
         };
         let round_trip_time: Option<u32> = get_value_option("round_trip_time", &hash)?;
         let round_trip_time: u32 = round_trip_time.unwrap_or(DEFAULT_ROUND_TRIP_TIME);
+        let routing_policy: Option<String> = get_value_option("routing_policy", &hash)?;
+        let routing_policy =
+            if let Some(policy) = routing_policy {
+                Some(serde_json::from_str(&policy).map_err(|_| {
+                    RedisError::from((ErrorKind::TypeError, "Invalid Routing Policy"))
+                })?)
+            } else {
+                None
+            };
+
+        let client_settings: Option<String> =
+            get_value_option("ilp_over_http_client_settings", &hash)?;
+        let ilp_over_http_client_settings = if let Some(client_settings) = client_settings {
+            Some(serde_json::from_str(&client_settings).map_err(|_| {
+                RedisError::from((ErrorKind::TypeError, "Invalid HTTP client settings"))
+            })?)
+        } else {
+            None
+        };
 
         let rid: RedisAccountId = get_value("id", &hash)?;
 
@@ -2167,6 +2603,14 @@ impl FromRedisValue for AccountWithEncryptedTokens {
                 asset_code: get_value("asset_code", &hash)?,
                 asset_scale: get_value("asset_scale", &hash)?,
                 ilp_over_http_url: get_url_option("ilp_over_http_url", &hash)?,
+                ilp_over_http_callback_url: get_url_option("ilp_over_http_callback_url", &hash)?,
+                ilp_over_http_client_cert: get_value_option("ilp_over_http_client_cert", &hash)?,
+                ilp_over_http_client_key: get_value_option("ilp_over_http_client_key", &hash)?,
+                ilp_over_http_client_cert_fingerprint: get_value_option(
+                    "ilp_over_http_client_cert_fingerprint",
+                    &hash,
+                )?,
+                ilp_over_http_client_settings,
                 ilp_over_http_incoming_token: get_bytes_option(
                     "ilp_over_http_incoming_token",
                     &hash,
@@ -2197,6 +2641,7 @@ impl FromRedisValue for AccountWithEncryptedTokens {
                 packets_per_minute_limit: get_value_option("packets_per_minute_limit", &hash)?,
                 amount_per_minute_limit: get_value_option("amount_per_minute_limit", &hash)?,
                 settlement_engine_url: get_url_option("settlement_engine_url", &hash)?,
+                routing_policy,
             },
         })
     }
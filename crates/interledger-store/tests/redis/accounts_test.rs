@@ -1,7 +1,7 @@
 use super::{fixtures::*, redis_helpers::*, store_helpers::*};
 use interledger_api::{AccountSettings, NodeStore};
 use interledger_btp::BtpAccount;
-use interledger_ccp::{CcpRoutingAccount, RoutingRelation};
+use interledger_ccp::{CcpRoutingAccount, RouteImportPolicy, RoutingPolicy, RoutingRelation};
 use interledger_http::HttpAccount;
 use interledger_packet::Address;
 use interledger_service::Account as AccountTrait;
@@ -196,6 +196,30 @@ async fn update_accounts() {
     assert_eq!(err.to_string(), format!("account `{}` was not found", id));
 }
 
+#[tokio::test]
+async fn updates_routing_policy() {
+    let (store, _context, accounts) = test_store().await.unwrap();
+    let id = accounts[0].id();
+    let policy = RoutingPolicy {
+        import: RouteImportPolicy {
+            deny_prefixes: vec!["example.denied".to_string()],
+            max_prefixes: Some(10),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut new = ACCOUNT_DETAILS_0.clone();
+    new.routing_policy = Some(policy.clone());
+    store.update_account(id, new.clone()).await.unwrap();
+    let account = store.get_accounts(vec![id]).await.unwrap().pop().unwrap();
+    assert_eq!(account.routing_policy(), Some(&policy));
+
+    new.routing_policy = None;
+    store.update_account(id, new).await.unwrap();
+    let account = store.get_accounts(vec![id]).await.unwrap().pop().unwrap();
+    assert_eq!(account.routing_policy(), None);
+}
+
 #[tokio::test]
 async fn modify_account_settings_settle_to_overflow() {
     let (store, _context, accounts) = test_store().await.unwrap();
@@ -244,6 +268,11 @@ async fn modify_account_settings() {
         ilp_over_btp_outgoing_token: Some(SecretString::new("dylan:test".to_owned())),
         ilp_over_btp_incoming_token: Some(SecretString::new("btp_in_new".to_owned())),
         ilp_over_http_url: Some("http://example.com/accounts/dylan/ilp".to_owned()),
+        ilp_over_http_callback_url: None,
+        ilp_over_http_client_cert: None,
+        ilp_over_http_client_key: None,
+        ilp_over_http_client_cert_fingerprint: None,
+        ilp_over_http_client_settings: None,
         ilp_over_btp_url: Some("http://example.com/accounts/dylan/ilp/btp".to_owned()),
         settle_threshold: Some(-50),
         settle_to: Some(100),
@@ -26,6 +26,11 @@ mod fixtures {
         max_packet_amount: 1000,
         min_balance: Some(-1000),
         ilp_over_http_url: Some("http://example.com/accounts/dylan/ilp".to_string()),
+        ilp_over_http_callback_url: None,
+        ilp_over_http_client_cert: None,
+        ilp_over_http_client_key: None,
+        ilp_over_http_client_cert_fingerprint: None,
+        ilp_over_http_client_settings: None,
         ilp_over_http_incoming_token: Some(SecretString::new("incoming_auth_token".to_string())),
         ilp_over_http_outgoing_token: Some(SecretString::new("outgoing_auth_token".to_string())),
         ilp_over_btp_url: Some("btp+ws://example.com/accounts/dylan/ilp/btp".to_string()),
@@ -38,6 +43,7 @@ mod fixtures {
         amount_per_minute_limit: Some(1000),
         packets_per_minute_limit: Some(2),
         settlement_engine_url: Some("http://settlement.example".to_string()),
+        routing_policy: None,
     });
     pub static ACCOUNT_DETAILS_1: Lazy<AccountDetails> = Lazy::new(|| AccountDetails {
         ilp_address: None,
@@ -47,6 +53,11 @@ mod fixtures {
         max_packet_amount: 1_000_000,
         min_balance: Some(0),
         ilp_over_http_url: Some("http://example.com/accounts/dylan/ilp".to_string()),
+        ilp_over_http_callback_url: None,
+        ilp_over_http_client_cert: None,
+        ilp_over_http_client_key: None,
+        ilp_over_http_client_cert_fingerprint: None,
+        ilp_over_http_client_settings: None,
         // incoming token has is the account's username concatenated wiht the password
         ilp_over_http_incoming_token: Some(SecretString::new("incoming_auth_token".to_string())),
         ilp_over_http_outgoing_token: Some(SecretString::new("outgoing_auth_token".to_string())),
@@ -60,6 +71,7 @@ mod fixtures {
         amount_per_minute_limit: Some(1000),
         packets_per_minute_limit: Some(20),
         settlement_engine_url: None,
+        routing_policy: None,
     });
     pub static ACCOUNT_DETAILS_2: Lazy<AccountDetails> = Lazy::new(|| AccountDetails {
         ilp_address: None,
@@ -69,6 +81,11 @@ mod fixtures {
         max_packet_amount: 1000,
         min_balance: Some(0),
         ilp_over_http_url: None,
+        ilp_over_http_callback_url: None,
+        ilp_over_http_client_cert: None,
+        ilp_over_http_client_key: None,
+        ilp_over_http_client_cert_fingerprint: None,
+        ilp_over_http_client_settings: None,
         ilp_over_http_incoming_token: None,
         ilp_over_http_outgoing_token: None,
         ilp_over_btp_url: None,
@@ -81,6 +98,7 @@ mod fixtures {
         amount_per_minute_limit: None,
         packets_per_minute_limit: None,
         settlement_engine_url: None,
+        routing_policy: None,
     });
 }
 
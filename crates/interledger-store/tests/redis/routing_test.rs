@@ -1,10 +1,14 @@
 use super::{fixtures::*, redis_helpers::*, store_helpers::*};
 
 use interledger_api::{AccountDetails, NodeStore};
-use interledger_ccp::CcpRoutingStore;
+use interledger_ccp::{
+    CcpRoutingState, CcpRoutingStore, RouteMetrics, RouteSource, RoutingEvent, RoutingEventKind,
+    RoutingEventsStore,
+};
 use interledger_packet::Address;
-use interledger_router::RouterStore;
+use interledger_router::{NextHop, RouterStore};
 use interledger_service::{Account as AccountTrait, AddressStore, Username};
+use interledger_service_util::{BalanceStore, DEFAULT_ROUND_TRIP_TIME};
 use interledger_store::{account::Account, redis::RedisStoreBuilder};
 use std::str::FromStr;
 use std::{collections::HashMap, time::Duration};
@@ -40,6 +44,11 @@ async fn polls_for_route_updates() {
             max_packet_amount: 1000,
             min_balance: Some(-1000),
             ilp_over_http_url: None,
+            ilp_over_http_callback_url: None,
+            ilp_over_http_client_cert: None,
+            ilp_over_http_client_key: None,
+            ilp_over_http_client_cert_fingerprint: None,
+            ilp_over_http_client_settings: None,
             ilp_over_http_incoming_token: None,
             ilp_over_http_outgoing_token: None,
             ilp_over_btp_url: None,
@@ -52,6 +61,7 @@ async fn polls_for_route_updates() {
             amount_per_minute_limit: None,
             packets_per_minute_limit: None,
             settlement_engine_url: None,
+            routing_policy: None,
         })
         .await
         .unwrap();
@@ -93,6 +103,41 @@ async fn gets_accounts_to_send_routes_to() {
     assert_eq!(accounts.len(), 1);
 }
 
+#[tokio::test]
+async fn gets_route_metrics() {
+    let (store, _context, accounts) = test_store().await.unwrap();
+    // The account's balance goes down to -100 and its settle threshold is 0
+    store
+        .update_balances_for_prepare(accounts[0].id(), 100)
+        .await
+        .unwrap();
+    let metrics = store
+        .get_route_metrics(vec![accounts[0].id()])
+        .await
+        .unwrap();
+    assert_eq!(
+        metrics[&accounts[0].id()],
+        RouteMetrics {
+            expected_round_trip_time: Some(DEFAULT_ROUND_TRIP_TIME),
+            balance_headroom: Some(100),
+        }
+    );
+}
+
+#[tokio::test]
+async fn saves_and_loads_routing_state() {
+    let (mut store, _context, _accounts) = test_store().await.unwrap();
+    assert_eq!(store.load_routing_state().await.unwrap(), None);
+    store
+        .save_routing_state(CcpRoutingState::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        store.load_routing_state().await.unwrap(),
+        Some(CcpRoutingState::default())
+    );
+}
+
 #[tokio::test]
 async fn gets_accounts_to_send_routes_to_and_skips_ignored() {
     let (store, _context, accs) = test_store().await.unwrap();
@@ -194,6 +239,118 @@ async fn adds_static_routes_to_redis() {
     assert_eq!(routes.len(), 3);
 }
 
+#[tokio::test]
+async fn adds_multipath_static_routes() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    let next_hops = vec![
+        NextHop::new(accs[0].id(), 3, 0),
+        NextHop::new(accs[1].id(), 1, 1),
+    ];
+    store
+        .set_static_multipath_routes(vec![
+            ("example.a".to_string(), next_hops.clone()),
+            ("example.b".to_string(), vec![NextHop::from(accs[1].id())]),
+        ])
+        .await
+        .unwrap();
+    store
+        .set_static_multipath_route(
+            "example.c".to_string(),
+            vec![
+                NextHop::new(accs[1].id(), 1, 0),
+                NextHop::new(accs[0].id(), 1, 0),
+            ],
+        )
+        .await
+        .unwrap();
+
+    let routes = store.routing_table();
+    assert_eq!(routes.next_hops("example.a"), Some(&next_hops[..]));
+    assert_eq!(routes["example.a"], accs[0].id());
+    assert_eq!(routes["example.b"], accs[1].id());
+    assert_eq!(routes.next_hops("example.c").unwrap().len(), 2);
+    assert_eq!(routes.len(), 3);
+
+    // The route manager only gets the primary next hop
+    let (_, configured) = store.get_local_and_configured_routes().await.unwrap();
+    assert_eq!(configured["example.a"].id(), accs[0].id());
+    assert_eq!(configured["example.c"].id(), accs[1].id());
+}
+
+#[tokio::test]
+async fn publishes_static_route_events() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    let mut events = store.routing_event_subscription();
+    let event = |kind, prefix: &str, next_hop: &Account| RoutingEvent {
+        kind,
+        prefix: prefix.to_string(),
+        next_hop: Some(next_hop.username().clone()),
+        source: RouteSource::Static,
+        epoch: None,
+    };
+
+    store
+        .set_static_routes(vec![
+            ("example.a".to_string(), accs[0].id()),
+            ("example.b".to_string(), accs[0].id()),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(
+        events.recv().await.unwrap(),
+        event(RoutingEventKind::Added, "example.a", &accs[0])
+    );
+    assert_eq!(
+        events.recv().await.unwrap(),
+        event(RoutingEventKind::Added, "example.b", &accs[0])
+    );
+
+    store
+        .set_static_route("example.a".to_string(), accs[1].id())
+        .await
+        .unwrap();
+    assert_eq!(
+        events.recv().await.unwrap(),
+        event(RoutingEventKind::Replaced, "example.a", &accs[1])
+    );
+
+    store
+        .set_static_routes(vec![("example.a".to_string(), accs[1].id())])
+        .await
+        .unwrap();
+    assert_eq!(
+        events.recv().await.unwrap(),
+        event(RoutingEventKind::Withdrawn, "example.b", &accs[0])
+    );
+
+    store.set_default_route(accs[0].id()).await.unwrap();
+    assert_eq!(
+        events.recv().await.unwrap(),
+        event(RoutingEventKind::Added, "", &accs[0])
+    );
+}
+
+#[tokio::test]
+async fn gets_static_routes_with_default_route() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    store.set_default_route(accs[1].id()).await.unwrap();
+    store
+        .set_static_multipath_route(
+            "example.a".to_string(),
+            vec![NextHop::new(accs[0].id(), 2, 0)],
+        )
+        .await
+        .unwrap();
+
+    let static_routes = store.get_static_routes().await.unwrap();
+    assert_eq!(static_routes.len(), 2);
+    assert_eq!(static_routes[""], vec![NextHop::from(accs[1].id())]);
+    assert_eq!(
+        static_routes["example.a"],
+        vec![NextHop::new(accs[0].id(), 2, 0)]
+    );
+}
+
 #[tokio::test]
 async fn static_routes_override_others() {
     let (store, _context, accs) = test_store().await.unwrap();
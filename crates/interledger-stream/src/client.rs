@@ -1,4 +1,4 @@
-use super::congestion::CongestionController;
+use super::congestion::{CongestionController, CongestionStrategy};
 use super::crypto::*;
 use super::error::Error;
 use super::packet::*;
@@ -39,6 +39,12 @@ const FAIL_FAST_MINIMUM_PACKET_ATTEMPTS: u64 = 200;
 /// Minimum rate of rejected packets in order to terminate the payment
 const FAIL_FAST_MINIMUM_FAILURE_RATE: f64 = 0.99;
 
+/// Maximum number of unfulfillable packets sent to determine the exchange rate of a fixed delivery payment
+const MAX_RATE_PROBE_ATTEMPTS: usize = 10;
+
+/// Maximum number of unfulfillable packets sent to quote a payment
+const MAX_QUOTE_PROBE_ATTEMPTS: usize = 30;
+
 /// Receipt for STREAM payment to account for how much and what assets were sent & delivered
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct StreamDelivery {
@@ -51,6 +57,7 @@ pub struct StreamDelivery {
     /// Asset code of sender
     pub source_asset_code: String,
     /// Total amount *intended* to be sent, in source units
+    /// (or the maximum amount, for fixed delivery payments)
     pub source_amount: u64,
     /// Amount fulfilled or currently in-flight, in source units
     pub sent_amount: u64,
@@ -83,10 +90,51 @@ impl StreamDelivery {
     }
 }
 
+/// Estimate of what a STREAM payment would deliver, from the amounts the receiver got from
+/// unfulfillable test packets
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StreamQuote {
+    /// Sender's ILP Address
+    pub from: Address,
+    /// Receiver's ILP Address
+    pub to: Address,
+    /// Asset scale of sender
+    pub source_asset_scale: u8,
+    /// Asset code of sender
+    pub source_asset_code: String,
+    /// Amount the quote is for, in source units
+    pub source_amount: u64,
+    /// Destination units received per source unit by the largest test packet
+    pub exchange_rate: f64,
+    /// Largest amount a packet may have on the path to the receiver, in source units.
+    /// None if all the test packets up to the source amount got through
+    pub max_packet_amount: Option<u64>,
+    /// Amount the receiver should get at least when sending the source amount, in destination units.
+    /// Accounts for the rounding of each packet of the path's maximum packet amount
+    pub min_destination_amount: u64,
+    /// Receiver's asset scale, if it sent a `ConnectionAssetDetails` frame
+    pub destination_asset_scale: Option<u8>,
+    /// Receiver's asset code, if it sent a `ConnectionAssetDetails` frame
+    pub destination_asset_code: Option<String>,
+}
+
+/// State of payments which deliver a fixed destination amount
+struct FixedDelivery {
+    /// The amount the receiver should get, in destination units
+    destination_amount: u64,
+    /// The lowest exchange rate (destination units per source unit) that delivers the
+    /// destination amount without exceeding the maximum source amount
+    min_rate: BigRational,
+    /// The exchange rate realized by the last packet the receiver told us about
+    rate: Option<BigRational>,
+    /// Sum of the minimum destination amounts of the packets in flight
+    in_flight_destination_amount: u64,
+}
+
 /// Stream payment mutable state: amounts & assets sent and received, sequence, packet counts, and flow control parameters
 struct StreamPayment {
-    /// The [congestion controller](./../congestion/struct.CongestionController.html) to adjust flow control and the in-flight amount
-    congestion_controller: CongestionController,
+    /// The [congestion controller](./../congestion/trait.CongestionController.html) to adjust flow control and the in-flight amount
+    congestion_controller: Box<dyn CongestionController>,
     /// The [StreamDelivery](./struct.StreamDelivery.html) receipt to account for the delivered amounts
     receipt: StreamDelivery,
     /// Do we need to send our source account information to the recipient?
@@ -101,6 +149,8 @@ struct StreamPayment {
     fail_fast_rejects: u64,
     /// Timestamp when a packet was last fulfilled for this payment
     last_fulfill_time: Instant,
+    /// Set for payments which deliver a fixed destination amount rather than send a fixed source amount
+    fixed_delivery: Option<FixedDelivery>,
 }
 
 impl StreamPayment {
@@ -108,6 +158,10 @@ impl StreamPayment {
     /// Return the source packet amount and minimum destination amount
     #[inline]
     fn apply_prepare<S: ExchangeRateStore>(&mut self, store: &S, slippage: f64) -> (u64, u64) {
+        if self.fixed_delivery.is_some() {
+            return self.apply_fixed_delivery_prepare();
+        }
+
         // Determine scaled rate with slippage used for enforcing minimum destination amount
         // and computing its corresponding minimum source amount,
         // where source_amount * scaled_rate = dest_amount.
@@ -174,20 +228,97 @@ impl StreamPayment {
         // (1) Amount available to send, subtracting fulfilled and in-fligth amounts
         source_amount = min(source_amount, self.get_amount_available_to_send());
 
-        // Account for the prepare
-        self.congestion_controller.prepare(source_amount);
-        self.receipt.sent_amount = self.receipt.sent_amount.saturating_add(source_amount);
-        self.receipt.in_flight_amount = self.receipt.in_flight_amount.saturating_add(source_amount);
+        self.account_for_prepare(source_amount);
 
         // Compute the minimum destination amount using the same rate
         let min_destination_amount = convert(source_amount, rate).unwrap_or(0);
         (source_amount, min_destination_amount)
     }
 
+    /// Determine the amount of the next Prepare of a fixed delivery payment and account for it.
+    ///
+    /// The packets are sized with the last realized exchange rate so they deliver the remaining
+    /// destination amount, and the receiver must get at least what that rate would deliver.
+    /// If the rate got worse, the receiver rejects the packet and its response updates the rate,
+    /// which adjusts the amounts of the following packets.
+    /// Return the source packet amount and minimum destination amount
+    fn apply_fixed_delivery_prepare(&mut self) -> (u64, u64) {
+        let fixed_delivery = self.fixed_delivery.as_ref().unwrap();
+        let rate = fixed_delivery
+            .rate
+            .clone()
+            .unwrap_or_else(BigRational::zero);
+
+        // (4) Amount available to send, which is limited to the amount that delivers
+        // the rest of the destination amount
+        let mut source_amount = self.get_amount_available_to_send();
+
+        // (3) Amount left in window for congestion, but enough to deliver at least one destination unit
+        let min_source_amount = BigRational::one()
+            .checked_div(&rate)
+            .map(|amount| amount.ceil().to_integer().to_u64().unwrap_or(0))
+            .unwrap_or(0);
+        source_amount = min(
+            source_amount,
+            max(
+                self.congestion_controller.get_amount_left_in_window(),
+                min_source_amount,
+            ),
+        );
+
+        // (2) Max packet amount allowed by nodes in path
+        source_amount = min(
+            source_amount,
+            self.congestion_controller.get_max_packet_amount(),
+        );
+
+        let expected_amount = floor(source_amount, &rate).unwrap_or(0);
+        let min_destination_amount = max(
+            expected_amount,
+            convert(source_amount, fixed_delivery.min_rate.clone()).unwrap_or(0),
+        );
+
+        self.account_for_prepare(source_amount);
+        let fixed_delivery = self.fixed_delivery.as_mut().unwrap();
+        fixed_delivery.in_flight_destination_amount = fixed_delivery
+            .in_flight_destination_amount
+            .saturating_add(min_destination_amount);
+        (source_amount, min_destination_amount)
+    }
+
+    /// Account for a Prepare with the given source amount
+    #[inline]
+    fn account_for_prepare(&mut self, source_amount: u64) {
+        self.congestion_controller.prepare(source_amount);
+        self.receipt.sent_amount = self.receipt.sent_amount.saturating_add(source_amount);
+        self.receipt.in_flight_amount = self.receipt.in_flight_amount.saturating_add(source_amount);
+    }
+
+    /// Save the exchange rate realized by a packet, as reported by the receiver
+    #[inline]
+    fn apply_realized_rate(&mut self, source_amount: u64, received_amount: u64) {
+        if let Some(ref mut fixed_delivery) = self.fixed_delivery {
+            if source_amount > 0 {
+                fixed_delivery.rate = Some(BigRational::new(
+                    BigInt::from(received_amount),
+                    BigInt::from(source_amount),
+                ));
+            }
+        }
+    }
+
     /// Account for a fulfilled packet and update flow control
     #[inline]
-    fn apply_fulfill(&mut self, source_amount: u64, destination_amount: u64) {
-        self.congestion_controller.fulfill(source_amount);
+    fn apply_fulfill(
+        &mut self,
+        source_amount: u64,
+        min_destination_amount: u64,
+        destination_amount: u64,
+        round_trip_time: Duration,
+    ) {
+        self.congestion_controller
+            .fulfill(source_amount, round_trip_time);
+        self.apply_packet_settled(min_destination_amount);
 
         self.receipt.in_flight_amount = self.receipt.in_flight_amount.saturating_sub(source_amount);
         self.receipt.delivered_amount = self
@@ -201,8 +332,16 @@ impl StreamPayment {
 
     /// Account for a rejected packet and update flow control
     #[inline]
-    fn apply_reject(&mut self, amount: u64, reject: &Reject) {
-        self.congestion_controller.reject(amount, reject);
+    fn apply_reject(
+        &mut self,
+        amount: u64,
+        min_destination_amount: u64,
+        round_trip_time: Duration,
+        reject: &Reject,
+    ) {
+        self.congestion_controller
+            .reject(amount, round_trip_time, reject);
+        self.apply_packet_settled(min_destination_amount);
 
         self.receipt.sent_amount = self.receipt.sent_amount.saturating_sub(amount);
         self.receipt.in_flight_amount = self.receipt.in_flight_amount.saturating_sub(amount);
@@ -222,6 +361,16 @@ impl StreamPayment {
         }
     }
 
+    /// A packet with the given minimum destination amount is no longer in flight
+    #[inline]
+    fn apply_packet_settled(&mut self, min_destination_amount: u64) {
+        if let Some(ref mut fixed_delivery) = self.fixed_delivery {
+            fixed_delivery.in_flight_destination_amount = fixed_delivery
+                .in_flight_destination_amount
+                .saturating_sub(min_destination_amount);
+        }
+    }
+
     /// Save the recipient's destination asset details for calculating minimum exchange rates
     #[inline]
     fn set_destination_asset_details(&mut self, asset_code: String, asset_scale: u8) {
@@ -254,18 +403,68 @@ impl StreamPayment {
     }
 
     /// Has the entire intended source amount been fulfilled by the recipient?
+    /// For fixed delivery payments, has the recipient received the entire destination amount?
     #[inline]
     fn is_complete(&self) -> bool {
-        self.get_remaining_amount() == 0
+        match self.fixed_delivery {
+            Some(ref fixed_delivery) => {
+                self.receipt.delivered_amount >= fixed_delivery.destination_amount
+            }
+            None => self.get_remaining_amount() == 0,
+        }
     }
 
     /// Return the amount of money available to be sent in the payment (amount remaining minus in-flight)
     #[inline]
     fn get_amount_available_to_send(&self) -> u64 {
         // Sent amount also includes the amount in-flight, which should be subtracted from the amount available
-        self.receipt
+        let available = self
+            .receipt
+            .source_amount
+            .saturating_sub(self.receipt.sent_amount);
+        if self.fixed_delivery.is_some() {
+            min(
+                available,
+                self.get_source_amount_to_deliver_remaining().unwrap_or(0),
+            )
+        } else {
+            available
+        }
+    }
+
+    /// For fixed delivery payments, the source amount which delivers the part of the destination
+    /// amount that was neither delivered nor is in flight at the last realized exchange rate.
+    /// None if the payment doesn't deliver a fixed amount or the rate is unknown or zero
+    #[inline]
+    fn get_source_amount_to_deliver_remaining(&self) -> Option<u64> {
+        let fixed_delivery = self.fixed_delivery.as_ref()?;
+        let remaining = fixed_delivery
+            .destination_amount
+            .saturating_sub(self.receipt.delivered_amount)
+            .saturating_sub(fixed_delivery.in_flight_destination_amount);
+        BigRational::from_u64(remaining)?
+            .checked_div(fixed_delivery.rate.as_ref()?)?
+            .ceil()
+            .to_integer()
+            .to_u64()
+    }
+
+    /// Would delivering the rest of a fixed delivery payment exceed the maximum source amount?
+    /// Only known once no packets are in flight
+    #[inline]
+    fn exceeds_max_source_amount(&self) -> bool {
+        if self.fixed_delivery.is_none() || self.receipt.in_flight_amount > 0 || self.is_complete()
+        {
+            return false;
+        }
+        let available = self
+            .receipt
             .source_amount
-            .saturating_sub(self.receipt.sent_amount)
+            .saturating_sub(self.receipt.sent_amount);
+        match self.get_source_amount_to_deliver_remaining() {
+            Some(needed) => needed > available,
+            None => true,
+        }
     }
 
     /// Is as much money as possible in-flight?
@@ -277,6 +476,17 @@ impl StreamPayment {
             || self.get_amount_available_to_send() == 0
     }
 
+    /// Is a packet in flight while we don't know the recipient's asset details yet?
+    /// Packets sent before we know them are unfulfillable, since there's no minimum destination
+    /// amount to enforce, so we only send one until the recipient responds with its details.
+    /// Fixed delivery payments use the realized exchange rate instead
+    #[inline]
+    fn is_awaiting_asset_details(&self) -> bool {
+        self.fixed_delivery.is_none()
+            && self.receipt.destination_asset_scale.is_none()
+            && self.receipt.in_flight_amount > 0
+    }
+
     /// Given we've attempted sending enough packets, does the rate of rejects
     /// that count towards fail-fast indicate the payment is failing?
     #[inline]
@@ -289,6 +499,10 @@ impl StreamPayment {
 
 /// Send the given source amount with packetized Interledger payments using the STREAM transport protocol
 /// Returns the receipt with sent & delivered amounts, asset & account details
+///
+/// The congestion strategy determines how much money is in flight at once and how the
+/// packet amounts adapt to the errors and latency of the path.
+#[allow(clippy::too_many_arguments)]
 pub async fn send_money<I, A, S>(
     service: I,
     from_account: &A,
@@ -297,53 +511,195 @@ pub async fn send_money<I, A, S>(
     shared_secret: Vec<u8>,
     source_amount: u64,
     slippage: f64,
+    congestion_strategy: CongestionStrategy,
 ) -> Result<StreamDelivery, Error>
 where
     I: IncomingService<A> + Clone + Send + Sync + 'static,
     A: Account + Send + Sync + 'static,
     S: ExchangeRateStore + Send + Sync + 'static,
 {
-    let shared_secret = Bytes::from(shared_secret);
-
-    let from = from_account.ilp_address();
-    if from.scheme() != destination_account.scheme() {
-        warn!(
-            "Destination ILP address starts with a different scheme prefix (\"{}\') than ours (\"{}\'), this probably won't work",
-            destination_account.scheme(),
-            from.scheme()
-        );
+    congestion_strategy.validate()?;
+    let sender = StreamSender::new(
+        service,
+        from_account,
+        store,
+        destination_account,
+        shared_secret,
+        source_amount,
+        slippage,
+        congestion_strategy.build(source_amount),
+    );
+    run_payment(sender).await
+}
+
+/// Send packetized Interledger payments using the STREAM transport protocol until the recipient
+/// received the given destination amount, without sending more than the maximum source amount.
+///
+/// The exchange rate is first probed with an unfulfillable packet. The packets are sized to
+/// deliver the rest of the destination amount at the realized exchange rate, so the recipient
+/// receives the destination amount and at most the value of one more source unit.
+/// Returns an error, without sending any more money, as soon as the destination amount cannot
+/// be delivered within the maximum source amount.
+#[allow(clippy::too_many_arguments)]
+pub async fn send_money_fixed_delivery<I, A, S>(
+    service: I,
+    from_account: &A,
+    store: S,
+    destination_account: Address,
+    shared_secret: Vec<u8>,
+    destination_amount: u64,
+    max_source_amount: u64,
+    congestion_strategy: CongestionStrategy,
+) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    congestion_strategy.validate()?;
+    let mut sender = StreamSender::new(
+        service,
+        from_account,
+        store,
+        destination_account,
+        shared_secret,
+        max_source_amount,
+        0.0,
+        congestion_strategy.build(max_source_amount),
+    );
+    {
+        let mut payment = sender.payment.lock().await;
+        payment.fixed_delivery = Some(FixedDelivery {
+            destination_amount,
+            min_rate: BigRational::new(
+                BigInt::from(destination_amount),
+                BigInt::from(max(max_source_amount, 1)),
+            ),
+            rate: None,
+            in_flight_destination_amount: 0,
+        });
+        if payment.is_complete() {
+            return Ok(payment.receipt.clone());
+        }
     }
 
-    let mut sender = StreamSender {
-        next: service,
-        from_account: from_account.clone(),
+    sender.probe_rate().await?;
+    run_payment(sender).await
+}
+
+/// Quote a STREAM payment of the given source amount without sending any money.
+///
+/// Sends unfulfillable test packets, starting with 1 source unit and growing tenfold up to the
+/// source amount. Packets rejected with F08 (Amount Too Large) reveal the path's maximum packet
+/// amount. The exchange rate realized by the largest packet the receiver got is used to
+/// estimate the minimum amount the payment delivers. Probing stops at the first final
+/// error other than F08, and the quote fails if the estimate does not fit in a `u64`.
+///
+/// The test packets are limited by a congestion controller of the given strategy, like the
+/// packets of the payment.
+pub async fn quote<I, A, S>(
+    service: I,
+    from_account: &A,
+    store: S,
+    destination_account: Address,
+    shared_secret: Vec<u8>,
+    source_amount: u64,
+    congestion_strategy: CongestionStrategy,
+) -> Result<StreamQuote, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    congestion_strategy.validate()?;
+    quote_with_congestion_controller(
+        service,
+        from_account,
+        store,
+        destination_account,
         shared_secret,
+        source_amount,
+        congestion_strategy.build(source_amount),
+    )
+    .await
+}
+
+/// Quote a STREAM payment like [quote](./fn.quote.html), limiting the test packets with the
+/// given congestion controller
+pub async fn quote_with_congestion_controller<I, A, S>(
+    service: I,
+    from_account: &A,
+    store: S,
+    destination_account: Address,
+    shared_secret: Vec<u8>,
+    source_amount: u64,
+    congestion_controller: Box<dyn CongestionController>,
+) -> Result<StreamQuote, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    let mut sender = StreamSender::new(
+        service,
+        from_account,
         store,
-        slippage,
-        payment: Arc::new(Mutex::new(StreamPayment {
-            // TODO Make configurable to get money flowing ASAP vs as much as possible per-packet
-            congestion_controller: CongestionController::new(
-                source_amount,
-                source_amount / 10,
-                2.0,
-            ),
-            receipt: StreamDelivery::new(from_account, destination_account, source_amount),
-            should_send_source_account: true,
-            sequence: 1,
-            fulfilled_packets: 0,
-            rejected_packets: 0,
-            fail_fast_rejects: 0,
-            last_fulfill_time: Instant::now(),
-        })),
+        destination_account,
+        shared_secret,
+        source_amount,
+        0.0,
+        congestion_controller,
+    );
+    let probed = sender.probe_path().await;
+    sender.try_send_connection_close().await;
+    let (probe_amount, received_amount) = probed?;
+
+    let payment = sender.payment.lock().await;
+    let max_packet_amount = payment.congestion_controller.get_max_packet_amount();
+    let rate = BigRational::new(BigInt::from(received_amount), BigInt::from(probe_amount));
+
+    // The payment is split into packets of the maximum packet amount, each rounded down
+    let packet_amount = max(min(source_amount, max_packet_amount), 1);
+    let min_destination_amount = floor(packet_amount, &rate)
+        .and_then(|amount| amount.checked_mul(source_amount / packet_amount))
+        .and_then(|amount| amount.checked_add(floor(source_amount % packet_amount, &rate)?))
+        .ok_or(Error::QuoteOverflow)?;
+
+    let quote = StreamQuote {
+        from: payment.receipt.from.clone(),
+        to: payment.receipt.to.clone(),
+        source_asset_scale: payment.receipt.source_asset_scale,
+        source_asset_code: payment.receipt.source_asset_code.clone(),
+        source_amount,
+        exchange_rate: received_amount as f64 / probe_amount as f64,
+        max_packet_amount: if max_packet_amount < u64::MAX {
+            Some(max_packet_amount)
+        } else {
+            None
+        },
+        min_destination_amount,
+        destination_asset_scale: payment.receipt.destination_asset_scale,
+        destination_asset_code: payment.receipt.destination_asset_code.clone(),
     };
+    debug!("Quoted STREAM payment: {:?}", quote);
+    Ok(quote)
+}
 
+/// Send packets until the payment completes or fails
+async fn run_payment<I, A, S>(mut sender: StreamSender<I, A, S>) -> Result<StreamDelivery, Error>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
     let mut pending_requests = FuturesUnordered::new();
 
     /// Actions corresponding to the state of the payment
     enum PaymentEvent {
         /// Send more money: send a packet with the given source amount and minimum destination amount
         SendMoney((u64, u64)),
-        /// Congestion controller limited in-flight amount: wait for pending requests until given deadline
+        /// Congestion controller limited in-flight amount, or the packet which gets the recipient's
+        /// asset details is in flight: wait for pending requests until given deadline
         MaxInFlight(Instant),
         /// Sent full source amount: close the connection and return success
         CloseConnection,
@@ -351,6 +707,8 @@ where
         Timeout,
         /// Too many packets are rejected, such as if the exchange rate is too low: terminate the payment
         FailFast,
+        /// The rest of the fixed destination amount cannot be delivered without exceeding the maximum source amount
+        MaxSourceAmountExceeded,
     }
 
     loop {
@@ -363,7 +721,9 @@ where
                 PaymentEvent::FailFast
             } else if payment.is_complete() {
                 PaymentEvent::CloseConnection
-            } else if payment.is_max_in_flight() {
+            } else if payment.exceeds_max_source_amount() {
+                PaymentEvent::MaxSourceAmountExceeded
+            } else if payment.is_max_in_flight() || payment.is_awaiting_asset_details() {
                 let deadline = payment
                     .last_fulfill_time
                     .checked_add(MAX_TIME_SINCE_LAST_FULFILL)
@@ -419,6 +779,14 @@ where
                     payment.rejected_packets,
                 ));
             }
+            PaymentEvent::MaxSourceAmountExceeded => {
+                sender.try_send_connection_close().await;
+                let payment = sender.payment.lock().await;
+                return Err(Error::MaxSourceAmountExceeded(
+                    payment.receipt.delivered_amount,
+                    payment.get_fulfilled_amount(),
+                ));
+            }
         }
     }
 }
@@ -446,13 +814,164 @@ where
     A: Account,
     S: ExchangeRateStore,
 {
-    /// Send a Prepare for the given source amount and apply the resulting Fulfill or Reject
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        service: I,
+        from_account: &A,
+        store: S,
+        destination_account: Address,
+        shared_secret: Vec<u8>,
+        source_amount: u64,
+        slippage: f64,
+        congestion_controller: Box<dyn CongestionController>,
+    ) -> Self {
+        let shared_secret = Bytes::from(shared_secret);
+
+        let from = from_account.ilp_address();
+        if from.scheme() != destination_account.scheme() {
+            warn!(
+                "Destination ILP address starts with a different scheme prefix (\"{}\') than ours (\"{}\'), this probably won't work",
+                destination_account.scheme(),
+                from.scheme()
+            );
+        }
+
+        StreamSender {
+            next: service,
+            from_account: from_account.clone(),
+            shared_secret,
+            store,
+            slippage,
+            payment: Arc::new(Mutex::new(StreamPayment {
+                congestion_controller,
+                receipt: StreamDelivery::new(from_account, destination_account, source_amount),
+                should_send_source_account: true,
+                sequence: 1,
+                fulfilled_packets: 0,
+                rejected_packets: 0,
+                fail_fast_rejects: 0,
+                last_fulfill_time: Instant::now(),
+                fixed_delivery: None,
+            })),
+        }
+    }
+
+    /// Send unfulfillable packets until the recipient tells us the amount it received,
+    /// which gives the exchange rate to fixed delivery payments. The amount of each
+    /// packet is halved until it gets through
+    async fn probe_rate(&mut self) -> Result<(), Error> {
+        let mut amount = self.payment.lock().await.receipt.source_amount;
+        for _ in 0..MAX_RATE_PROBE_ATTEMPTS {
+            {
+                let mut payment = self.payment.lock().await;
+                amount = max(
+                    min(
+                        amount,
+                        payment.congestion_controller.get_max_packet_amount(),
+                    ),
+                    1,
+                );
+                payment.account_for_prepare(amount);
+            }
+            // The packet is unfulfillable since it has no minimum destination amount
+            self.send_money_packet(amount, 0).await?;
+
+            let payment = self.payment.lock().await;
+            if let Some(ref rate) = payment.fixed_delivery.as_ref().unwrap().rate {
+                debug!("Probed exchange rate: {}", rate);
+                return Ok(());
+            }
+            amount /= 2;
+        }
+        Err(Error::RateProbeFailed)
+    }
+
+    /// Send unfulfillable packets growing tenfold up to the source amount and the path's maximum
+    /// packet amount. Returns the amount of the largest packet the recipient got and the amount it
+    /// received
+    async fn probe_path(&mut self) -> Result<(u64, u64), Error> {
+        let source_amount = self.payment.lock().await.receipt.source_amount;
+        let mut next_amount = 1;
+        let mut largest_probe: Option<(u64, u64)> = None;
+        for _ in 0..MAX_QUOTE_PROBE_ATTEMPTS {
+            let (amount, max_amount) = {
+                let mut payment = self.payment.lock().await;
+                let max_amount = max(
+                    min(
+                        source_amount,
+                        payment.congestion_controller.get_max_packet_amount(),
+                    ),
+                    1,
+                );
+                let amount = min(next_amount, max_amount);
+                payment.account_for_prepare(amount);
+                (amount, max_amount)
+            };
+            // The packet is unfulfillable since it has no minimum destination amount
+            let (received_amount, reject) = self.send_prepare(amount, 0).await;
+            if let Some(received_amount) = received_amount {
+                match largest_probe {
+                    Some((largest_amount, _)) if largest_amount >= amount => {}
+                    _ => largest_probe = Some((amount, received_amount)),
+                }
+                if amount >= max_amount {
+                    break;
+                }
+                next_amount = amount.saturating_mul(10);
+                continue;
+            }
+            match reject {
+                // The next packet is limited to the path's maximum packet amount
+                Some(reject) if reject.code() == IlpErrorCode::F08_AMOUNT_TOO_LARGE => {}
+                Some(reject) if reject.code().class() == ErrorClass::Final => {
+                    return Err(unexpected_rejection(&reject))
+                }
+                // Connectors reject packets which round down to 0 with R01
+                Some(_) if amount < max_amount => next_amount = amount.saturating_mul(10),
+                Some(reject) if reject.code().class() == ErrorClass::Temporary => {}
+                Some(reject) => return Err(unexpected_rejection(&reject)),
+                // Unfulfillable packets cannot be fulfilled, so the receiver is not a STREAM receiver
+                None => break,
+            }
+        }
+        largest_probe.ok_or(Error::RateProbeFailed)
+    }
+
+    /// Send a Prepare for the given source amount and apply the resulting Fulfill or Reject.
+    /// Returns the amount the recipient says it received, if it replied with a valid STREAM packet
     #[inline]
     pub async fn send_money_packet(
         &mut self,
         source_amount: u64,
         min_destination_amount: u64,
-    ) -> Result<(), Error> {
+    ) -> Result<Option<u64>, Error> {
+        let (received_amount, reject) = self
+            .send_prepare(source_amount, min_destination_amount)
+            .await;
+        let reject = match reject {
+            Some(reject) => reject,
+            None => return Ok(received_amount),
+        };
+        match (reject.code().class(), reject.code()) {
+            (ErrorClass::Temporary, _) => Ok(received_amount),
+            (_, IlpErrorCode::F08_AMOUNT_TOO_LARGE) => Ok(received_amount),
+            (_, IlpErrorCode::F99_APPLICATION_ERROR) => Ok(received_amount),
+            // R01 is triggered by connector when the amount rounds to 0, so keep retrying
+            // Other Rxx errors such as timeouts are likely terminal
+            (_, IlpErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT) => Ok(received_amount),
+            // Any other error will stop the rest of the payment
+            _ => Err(unexpected_rejection(&reject)),
+        }
+    }
+
+    /// Send a Prepare for the given source amount and apply the resulting Fulfill or Reject.
+    /// Returns the amount the recipient says it received, if it replied with a valid STREAM
+    /// packet, and the Reject if the Prepare was rejected
+    async fn send_prepare(
+        &mut self,
+        source_amount: u64,
+        min_destination_amount: u64,
+    ) -> (Option<u64>, Option<Reject>) {
         let (prepare, sequence) = {
             let mut payment = self.payment.lock().await;
 
@@ -505,6 +1024,7 @@ where
         };
 
         // Send it!
+        let sent_at = Instant::now();
         let reply = self
             .next
             .handle_request(IncomingRequest {
@@ -512,6 +1032,7 @@ where
                 prepare,
             })
             .await;
+        let round_trip_time = sent_at.elapsed();
 
         let (packet_type, reply_data) = match &reply {
             Ok(fulfill) => (IlpPacketType::Fulfill, fulfill.data()),
@@ -524,7 +1045,7 @@ where
         let mut payment = self.payment.lock().await;
 
         // Parse the stream packet and determine the amount the recipient claims they received
-        let claimed_amount: u64 = match stream_reply_packet {
+        let received_amount: Option<u64> = match stream_reply_packet {
             Ok(stream_reply_packet) => {
                 if stream_reply_packet.sequence() != sequence {
                     warn!(
@@ -532,14 +1053,14 @@ where
                         sequence,
                         stream_reply_packet.sequence()
                     );
-                    0
+                    None
                 } else if stream_reply_packet.ilp_packet_type() == IlpPacketType::Reject
                     && packet_type == IlpPacketType::Fulfill
                 {
                     // If receiver claimed they sent a Reject but we got a Fulfill, they lied!
                     // If receiver said they sent a Fulfill but we got a Reject, that's possible
                     warn!("Discarding STREAM packet (received Fulfill, but recipient said they sent a Reject)");
-                    0
+                    None
                 } else {
                     // Since we decrypted the response, the recipient read the request packet and knows our account
                     payment.should_send_source_account = false;
@@ -560,7 +1081,11 @@ where
                         }
                     }
 
-                    stream_reply_packet.prepare_amount()
+                    // The amount the recipient claims they received shows the realized exchange rate
+                    payment
+                        .apply_realized_rate(source_amount, stream_reply_packet.prepare_amount());
+
+                    Some(stream_reply_packet.prepare_amount())
                 }
             }
             Err(_) => {
@@ -568,7 +1093,7 @@ where
                     "Unable to parse STREAM packet from response data for sequence {}",
                     sequence
                 );
-                0
+                None
             }
         };
 
@@ -577,9 +1102,14 @@ where
             Ok(_) => {
                 // Delivered amount must be *at least* the minimum acceptable amount we told the receiver
                 // Even if the data was invalid, since it was fulfilled, we must assume they got at least the minimum
-                let delivered_amount = max(min_destination_amount, claimed_amount);
+                let delivered_amount = max(min_destination_amount, received_amount.unwrap_or(0));
 
-                payment.apply_fulfill(source_amount, delivered_amount);
+                payment.apply_fulfill(
+                    source_amount,
+                    min_destination_amount,
+                    delivered_amount,
+                    round_trip_time,
+                );
 
                 debug!(
                     "Prepare {} with amount {} was fulfilled ({} left to send)",
@@ -588,11 +1118,16 @@ where
                     payment.get_remaining_amount()
                 );
 
-                Ok(())
+                (received_amount, None)
             }
             // Handle ILP Reject
             Err(reject) => {
-                payment.apply_reject(source_amount, &reject);
+                payment.apply_reject(
+                    source_amount,
+                    min_destination_amount,
+                    round_trip_time,
+                    &reject,
+                );
 
                 debug!(
                     "Prepare {} with amount {} was rejected with code: {} ({} left to send)",
@@ -602,19 +1137,7 @@ where
                     payment.get_remaining_amount()
                 );
 
-                match (reject.code().class(), reject.code()) {
-                    (ErrorClass::Temporary, _) => Ok(()),
-                    (_, IlpErrorCode::F08_AMOUNT_TOO_LARGE) => Ok(()),
-                    (_, IlpErrorCode::F99_APPLICATION_ERROR) => Ok(()),
-                    // R01 is triggered by connector when the amount rounds to 0, so keep retrying
-                    // Other Rxx errors such as timeouts are likely terminal
-                    (_, IlpErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT) => Ok(()),
-                    // Any other error will stop the rest of the payment
-                    _ => Err(Error::UnexpectedRejection(
-                        reject.code(),
-                        String::from_utf8_lossy(reject.message()).into_owned(),
-                    )),
-                }
+                (received_amount, Some(reject))
             }
         }
     }
@@ -670,7 +1193,7 @@ where
 /// fetching from the provider's exchange rates, subtracting slippage, and adjusting scales.
 /// Returns None if destination asset details are unknown or rate cannot be calculated.
 #[inline]
-fn get_rate<S: ExchangeRateStore>(
+pub(crate) fn get_rate<S: ExchangeRateStore>(
     store: &S,
     source_scale: u8,
     source_code: &str,
@@ -708,7 +1231,7 @@ fn get_rate<S: ExchangeRateStore>(
 ///
 /// Returns `Some` when the value can after conversion be represented with an u64.
 #[inline]
-fn convert(source_amount: u64, rate: BigRational) -> Option<u64> {
+pub(crate) fn convert(source_amount: u64, rate: BigRational) -> Option<u64> {
     // First, convert scaled source amount to base unit
     let source_amount = BigRational::from_u64(source_amount)?;
 
@@ -719,10 +1242,29 @@ fn convert(source_amount: u64, rate: BigRational) -> Option<u64> {
     dest_amount.ceil().to_integer().to_u64()
 }
 
+fn unexpected_rejection(reject: &Reject) -> Error {
+    Error::UnexpectedRejection(
+        reject.code(),
+        String::from_utf8_lossy(reject.message()).into_owned(),
+    )
+}
+
+/// Convert the given source amount into the destination amount the
+/// receiver gets at the provided (realized) rate, which is rounded down
+#[inline]
+fn floor(source_amount: u64, rate: &BigRational) -> Option<u64> {
+    (BigRational::from_u64(source_amount)? * rate)
+        .floor()
+        .to_integer()
+        .to_u64()
+}
+
 #[cfg(test)]
 mod send_money_tests {
     use super::*;
-    use crate::test_helpers::{TestAccount, TestStore, EXAMPLE_CONNECTOR};
+    use crate::test_helpers::{
+        test_receiver, TestAccount, TestStore, EXAMPLE_CONNECTOR, EXAMPLE_RECEIVER,
+    };
     use async_trait::async_trait;
     use interledger_packet::{ErrorCode as IlpErrorCode, RejectBuilder};
     use interledger_service::incoming_service_fn;
@@ -766,6 +1308,7 @@ mod send_money_tests {
             vec![0; 32],
             100,
             0.0,
+            CongestionStrategy::default(),
         )
         .await;
         assert!(result.is_err());
@@ -821,6 +1364,7 @@ mod send_money_tests {
             vec![0; 32],
             50,
             0.0,
+            CongestionStrategy::default(),
         )
         .await;
 
@@ -864,7 +1408,39 @@ mod send_money_tests {
         where
             A: Account + 'static,
         {
-            async fn handle_request(&mut self, _: IncomingRequest<A>) -> IlpResult {
+            async fn handle_request(&mut self, request: IncomingRequest<A>) -> IlpResult {
+                let stream_packet =
+                    StreamPacket::from_encrypted(&[0; 32], BytesMut::from(request.prepare.data()))
+                        .unwrap();
+                // The sender only has one packet in flight until the receiver tells it its asset
+                // details, so that the min destination amounts of the following packets can be
+                // enforced. This service therefore has to answer the first packet (the one which
+                // carries our address) like a receiver would, or the concurrent packets this test
+                // counts would never be sent
+                let has_source_account = stream_packet
+                    .frames()
+                    .any(|frame| matches!(frame, Frame::ConnectionNewAddress(_)));
+                if has_source_account {
+                    let data = StreamPacketBuilder {
+                        ilp_packet_type: IlpPacketType::Reject,
+                        prepare_amount: 0,
+                        sequence: stream_packet.sequence(),
+                        frames: &[Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
+                            source_asset_code: "XYZ",
+                            source_asset_scale: 9,
+                        })],
+                    }
+                    .build()
+                    .into_encrypted(&[0; 32]);
+                    return Err(RejectBuilder {
+                        code: IlpErrorCode::F99_APPLICATION_ERROR,
+                        message: &[],
+                        triggered_by: Some(&EXAMPLE_CONNECTOR),
+                        data: &data,
+                    }
+                    .build());
+                }
+
                 self.num_requests_in_flight.fetch_add(1, Ordering::Relaxed);
 
                 // Wait for 100ms while all requests are received, then reject with final error to terminate stream
@@ -906,6 +1482,7 @@ mod send_money_tests {
             vec![0; 32],
             50,
             0.0,
+            CongestionStrategy::default(),
         )
         .await;
 
@@ -913,6 +1490,282 @@ mod send_money_tests {
         assert_eq!(num_requests_in_flight.load(Ordering::Relaxed), 5);
     }
 
+    async fn send_fixed_delivery(
+        rate: u64,
+        destination_amount: u64,
+        max_source_amount: u64,
+    ) -> Result<StreamDelivery, Error> {
+        let receiver = test_receiver(rate, None, None);
+        send_money_fixed_delivery(
+            receiver.service,
+            &receiver.account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            receiver.destination_account,
+            receiver.shared_secret,
+            destination_amount,
+            max_source_amount,
+            CongestionStrategy::default(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn delivers_fixed_destination_amount() {
+        let receipt = send_fixed_delivery(500, 1000, 2500).await.unwrap();
+        assert_eq!(receipt.delivered_amount, 1000);
+        assert_eq!(receipt.sent_amount, 2000);
+        assert_eq!(receipt.in_flight_amount, 0);
+
+        // 3 source units deliver 1 destination unit, which the last packets have to adjust for
+        let receipt = send_fixed_delivery(333, 1000, 3100).await.unwrap();
+        assert!(receipt.delivered_amount >= 1000 && receipt.delivered_amount <= 1001);
+        assert!(receipt.sent_amount <= 3100);
+    }
+
+    #[tokio::test]
+    async fn fails_if_fixed_destination_amount_exceeds_max_source_amount() {
+        let result = send_fixed_delivery(500, 1000, 1500).await;
+        assert!(matches!(result, Err(Error::MaxSourceAmountExceeded(0, 0))));
+    }
+
+    #[tokio::test]
+    async fn enforces_min_destination_amount_with_receivers_asset_details() {
+        let receiver = test_receiver(1000, Some(100), None);
+        let store = TestStore {
+            route: None,
+            price_1: None,
+            price_2: None,
+        };
+        let receipt = send_money(
+            MaxPacketAmountService::new(store.clone(), receiver.service),
+            &receiver.account,
+            store,
+            receiver.destination_account,
+            receiver.shared_secret,
+            1000,
+            0.0,
+            CongestionStrategy::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(receipt.destination_asset_code, Some("XYZ".to_string()));
+        assert_eq!(receipt.destination_asset_scale, Some(9));
+        assert_eq!(receipt.delivered_amount, 1000);
+        assert_eq!(receipt.in_flight_amount, 0);
+    }
+
+    #[tokio::test]
+    async fn sends_money_with_each_congestion_strategy() {
+        let strategies = vec![
+            CongestionStrategy::Aimd {
+                increase_amount: Some(10),
+                decrease_factor: 3.0,
+            },
+            CongestionStrategy::Latency {
+                target_latency_ms: 100,
+                increase_amount: None,
+                decrease_factor: 2.0,
+            },
+            CongestionStrategy::MaxPacketFirst,
+        ];
+        for congestion_strategy in strategies {
+            let receiver = test_receiver(500, Some(100), None);
+            let store = TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            };
+            let receipt = send_money(
+                MaxPacketAmountService::new(store.clone(), receiver.service),
+                &receiver.account,
+                store,
+                receiver.destination_account,
+                receiver.shared_secret,
+                1000,
+                0.6,
+                congestion_strategy,
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(receipt.sent_amount, 1000);
+            assert_eq!(receipt.delivered_amount, 500);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_congestion_strategy() {
+        let result = send_money(
+            incoming_service_fn(|_| -> IlpResult { panic!("shouldn't send packets") }),
+            &TestAccount {
+                id: Uuid::new_v4(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+                ilp_address: Address::from_str("example.sender").unwrap(),
+                max_packet_amount: None,
+            },
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            Address::from_str("example.receiver").unwrap(),
+            vec![0; 32],
+            1000,
+            0.0,
+            CongestionStrategy::Aimd {
+                increase_amount: None,
+                decrease_factor: 0.5,
+            },
+        )
+        .await;
+        assert!(matches!(result, Err(Error::InvalidCongestionStrategy(_))));
+    }
+
+    async fn quote_payment(
+        rate: u64,
+        max_packet_amount: Option<u64>,
+        source_amount: u64,
+    ) -> Result<StreamQuote, Error> {
+        let receiver = test_receiver(rate, max_packet_amount, None);
+        let store = TestStore {
+            route: None,
+            price_1: None,
+            price_2: None,
+        };
+        quote(
+            MaxPacketAmountService::new(store.clone(), receiver.service),
+            &receiver.account,
+            store,
+            receiver.destination_account,
+            receiver.shared_secret,
+            source_amount,
+            CongestionStrategy::default(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn quotes_payment() {
+        let quote = quote_payment(500, None, 1000).await.unwrap();
+        assert_eq!(quote.source_amount, 1000);
+        assert_eq!(quote.exchange_rate, 0.5);
+        assert_eq!(quote.max_packet_amount, None);
+        assert_eq!(quote.min_destination_amount, 500);
+    }
+
+    #[tokio::test]
+    async fn quotes_payment_with_max_packet_amount() {
+        // 1000 source units are rejected, so 250 is the largest test packet
+        let quote = quote_payment(333, Some(250), 1000).await.unwrap();
+        assert_eq!(quote.exchange_rate, 83.0 / 250.0);
+        assert_eq!(quote.max_packet_amount, Some(250));
+        assert_eq!(quote.min_destination_amount, 4 * 83);
+
+        let quote = quote_payment(333, Some(250), 1100).await.unwrap();
+        assert_eq!(quote.min_destination_amount, 4 * 83 + 33);
+    }
+
+    #[tokio::test]
+    async fn stops_quoting_at_final_errors() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests_clone = requests.clone();
+        let result = quote(
+            incoming_service_fn(move |request: IncomingRequest<TestAccount>| -> IlpResult {
+                // Not counting the packet which closes the connection
+                if request.prepare.amount() > 0 {
+                    requests_clone.fetch_add(1, Ordering::Relaxed);
+                }
+                // Not from the receiver, since it has no STREAM packet
+                Err(RejectBuilder {
+                    code: IlpErrorCode::F99_APPLICATION_ERROR,
+                    message: b"not a STREAM receiver",
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                    data: &[],
+                }
+                .build())
+            }),
+            &TestAccount {
+                id: Uuid::new_v4(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+                ilp_address: Address::from_str("example.sender").unwrap(),
+                max_packet_amount: None,
+            },
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            Address::from_str("example.receiver").unwrap(),
+            vec![0; 32],
+            1000,
+            CongestionStrategy::default(),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(Error::UnexpectedRejection(
+                IlpErrorCode::F99_APPLICATION_ERROR,
+                _
+            ))
+        ));
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn fails_to_quote_amounts_which_overflow() {
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: Address::from_str("example.sender").unwrap(),
+            max_packet_amount: Some(10),
+        };
+        let store = TestStore {
+            route: None,
+            price_1: None,
+            price_2: None,
+        };
+        // The receiver claims every packet delivered the largest possible amount
+        let receiver = incoming_service_fn(|request: IncomingRequest<TestAccount>| -> IlpResult {
+            let stream_packet =
+                StreamPacket::from_encrypted(&[0; 32], BytesMut::from(request.prepare.data()))
+                    .unwrap();
+            let data = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Reject,
+                prepare_amount: u64::MAX,
+                sequence: stream_packet.sequence(),
+                frames: &[],
+            }
+            .build()
+            .into_encrypted(&[0; 32]);
+            Err(RejectBuilder {
+                code: IlpErrorCode::F99_APPLICATION_ERROR,
+                message: &[],
+                triggered_by: Some(&EXAMPLE_RECEIVER),
+                data: &data,
+            }
+            .build())
+        });
+        let result = quote(
+            MaxPacketAmountService::new(store.clone(), receiver),
+            &account,
+            store,
+            Address::from_str("example.receiver").unwrap(),
+            vec![0; 32],
+            20,
+            CongestionStrategy::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::QuoteOverflow)));
+    }
+
     #[tokio::test]
     async fn computes_min_destination_amount() {
         struct TestData<'a> {
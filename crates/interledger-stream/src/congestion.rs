@@ -1,15 +1,166 @@
+use crate::error::Error;
 use interledger_packet::{ErrorCode, MaxPacketAmountDetails, Reject};
 #[cfg(test)]
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use std::cmp::{max, min};
+use std::time::Duration;
 use tracing::{debug, warn};
 
+/// Limits the amount a STREAM sender has in flight and the amount of each packet.
+///
+/// The sender calls `prepare` for every packet it sends and `fulfill` or `reject`
+/// with the packet's round trip time once it gets the response.
+pub trait CongestionController: Send + Sync {
+    /// Maximium allowed packet amount allowed to send in a packet per F08s
+    fn get_max_packet_amount(&self) -> u64;
+
+    /// The amount of the packets currently in flight
+    fn get_amount_in_flight(&self) -> u64;
+
+    /// The maximum amount availble to be sent is the maximum amount in flight minus the current amount in flight
+    fn get_amount_left_in_window(&self) -> u64;
+
+    /// Increments the amount in flight by the provided amount
+    fn prepare(&mut self, amount: u64);
+
+    /// Decrements the amount in flight by the provided amount
+    /// and adjusts the allowed max in flight amount cap
+    fn fulfill(&mut self, prepare_amount: u64, round_trip_time: Duration);
+
+    /// Decrements the amount in flight by the provided amount
+    /// and adjusts the allowed max in flight amount cap
+    fn reject(&mut self, prepare_amount: u64, round_trip_time: Duration, reject: &Reject);
+}
+
+/// The congestion control strategy of a payment and its parameters
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum CongestionStrategy {
+    /// [Additive Increase, Multiplicative Decrease](./struct.AimdCongestionController.html)
+    Aimd {
+        /// Amount added to the max in flight amount per fulfill, once packets were rejected
+        /// for insufficient liquidity. Defaults to a tenth of the source amount
+        #[serde(default)]
+        increase_amount: Option<u64>,
+        /// Factor the max in flight amount is divided by per insufficient liquidity error
+        #[serde(default = "default_decrease_factor")]
+        decrease_factor: f64,
+    },
+    /// [Latency-based](./struct.LatencyCongestionController.html)
+    Latency {
+        /// Round trip time above which the max in flight amount is decreased
+        #[serde(default = "default_target_latency_ms")]
+        target_latency_ms: u64,
+        /// Amount added to the max in flight amount per packet fulfilled within the
+        /// target latency. Defaults to a tenth of the source amount
+        #[serde(default)]
+        increase_amount: Option<u64>,
+        /// Factor the max in flight amount is divided by per slow packet or insufficient liquidity error
+        #[serde(default = "default_decrease_factor")]
+        decrease_factor: f64,
+    },
+    /// [Max-packet-first](./struct.MaxPacketCongestionController.html)
+    MaxPacketFirst,
+}
+
+const fn default_decrease_factor() -> f64 {
+    2.0
+}
+
+const fn default_target_latency_ms() -> u64 {
+    500
+}
+
+impl Default for CongestionStrategy {
+    fn default() -> Self {
+        CongestionStrategy::Aimd {
+            increase_amount: None,
+            decrease_factor: default_decrease_factor(),
+        }
+    }
+}
+
+impl CongestionStrategy {
+    /// Checks the parameters can be used to build a congestion controller
+    pub fn validate(&self) -> Result<(), Error> {
+        match self {
+            CongestionStrategy::Aimd {
+                decrease_factor, ..
+            }
+            | CongestionStrategy::Latency {
+                decrease_factor, ..
+            } if decrease_factor.is_nan() || *decrease_factor <= 1.0 => Err(
+                Error::InvalidCongestionStrategy("decrease_factor must be greater than 1"),
+            ),
+            CongestionStrategy::Latency {
+                target_latency_ms: 0,
+                ..
+            } => Err(Error::InvalidCongestionStrategy(
+                "target_latency_ms must be greater than 0",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Builds the congestion controller for a payment of the given source amount
+    pub fn build(&self, source_amount: u64) -> Box<dyn CongestionController> {
+        match *self {
+            CongestionStrategy::Aimd {
+                increase_amount,
+                decrease_factor,
+            } => Box::new(AimdCongestionController::new(
+                source_amount,
+                increase_amount.unwrap_or(source_amount / 10),
+                decrease_factor,
+            )),
+            CongestionStrategy::Latency {
+                target_latency_ms,
+                increase_amount,
+                decrease_factor,
+            } => Box::new(LatencyCongestionController::new(
+                source_amount,
+                Duration::from_millis(target_latency_ms),
+                increase_amount.unwrap_or(source_amount / 10),
+                decrease_factor,
+            )),
+            CongestionStrategy::MaxPacketFirst => Box::new(MaxPacketCongestionController::new()),
+        }
+    }
+}
+
+/// Lowers the max packet amount after a `F08_AMOUNT_TOO_LARGE` error: to the amount the
+/// connector told us or, if it didn't, by the decrease factor
+fn apply_amount_too_large(
+    max_packet_amount: &mut Option<u64>,
+    prepare_amount: u64,
+    reject: &Reject,
+    decrease_factor: f64,
+) {
+    if let Ok(details) = MaxPacketAmountDetails::from_bytes(reject.data()) {
+        let new_max_packet_amount: u64 =
+            prepare_amount * details.max_amount() / details.amount_received();
+        if let Some(max_packet_amount) = max_packet_amount {
+            *max_packet_amount = min(*max_packet_amount, new_max_packet_amount);
+        } else {
+            *max_packet_amount = Some(new_max_packet_amount);
+        }
+    } else {
+        warn!("Got F08: Amount Too Large Error without max packet amount details attached");
+        if let Some(max_packet_amount) = max_packet_amount {
+            *max_packet_amount = (*max_packet_amount as f64 / decrease_factor) as u64;
+        }
+    }
+}
+
+/// Divides the max in flight amount by the decrease factor, but keeps at least 1
+fn decrease(max_in_flight: u64, decrease_factor: f64) -> u64 {
+    max((max_in_flight as f64 / decrease_factor).floor() as u64, 1)
+}
+
 /// A basic congestion controller that implements an
 /// Additive Increase, Multiplicative Decrease (AIMD) algorithm.
-///
-/// Future implementations of this will use more advanced congestion
-/// control algorithms.
-pub struct CongestionController {
+pub struct AimdCongestionController {
     state: CongestionState,
     /// Amount which is added to `max_in_flight` per fulfill
     increase_amount: u64,
@@ -31,10 +182,10 @@ enum CongestionState {
     AvoidCongestion,
 }
 
-impl CongestionController {
+impl AimdCongestionController {
     /// Constructs a new congestion controller
     pub fn new(start_amount: u64, increase_amount: u64, decrease_factor: f64) -> Self {
-        CongestionController {
+        AimdCongestionController {
             state: CongestionState::SlowStart,
             increase_amount,
             decrease_factor,
@@ -44,18 +195,26 @@ impl CongestionController {
         }
     }
 
-    /// Maximium allowed packet amount allowed to send in a packet per F08s
-    pub fn get_max_packet_amount(&self) -> u64 {
+    #[cfg(test)]
+    fn set_max_packet_amount(&mut self, max_packet_amount: u64) {
+        self.max_packet_amount = Some(max_packet_amount)
+    }
+}
+
+impl CongestionController for AimdCongestionController {
+    fn get_max_packet_amount(&self) -> u64 {
         self.max_packet_amount.unwrap_or(u64::max_value())
     }
 
-    /// The maximum amount availble to be sent is the maximum amount in flight minus the current amount in flight
-    pub fn get_amount_left_in_window(&self) -> u64 {
+    fn get_amount_in_flight(&self) -> u64 {
+        self.amount_in_flight
+    }
+
+    fn get_amount_left_in_window(&self) -> u64 {
         self.max_in_flight.saturating_sub(self.amount_in_flight)
     }
 
-    /// Increments the amount in flight by the provided amount
-    pub fn prepare(&mut self, amount: u64) {
+    fn prepare(&mut self, amount: u64) {
         if amount > 0 {
             self.amount_in_flight += amount;
             debug!(
@@ -65,9 +224,7 @@ impl CongestionController {
         }
     }
 
-    /// Decrements the amount in flight by the provided amount
-    /// Increases the allowed max in flight amount cap
-    pub fn fulfill(&mut self, prepare_amount: u64) {
+    fn fulfill(&mut self, prepare_amount: u64, _round_trip_time: Duration) {
         self.amount_in_flight -= prepare_amount;
 
         // Before we know how much we should be sending at a time,
@@ -99,80 +256,246 @@ impl CongestionController {
         }
     }
 
-    /// Decrements the amount in flight by the provided amount
-    /// Decreases the allowed max in flight amount cap
-    pub fn reject(&mut self, prepare_amount: u64, reject: &Reject) {
+    fn reject(&mut self, prepare_amount: u64, _round_trip_time: Duration, reject: &Reject) {
         self.amount_in_flight -= prepare_amount;
 
         match reject.code() {
             ErrorCode::T04_INSUFFICIENT_LIQUIDITY => {
                 self.state = CongestionState::AvoidCongestion;
-                self.max_in_flight = max(
-                    (self.max_in_flight as f64 / self.decrease_factor).floor() as u64,
-                    1,
-                );
+                self.max_in_flight = decrease(self.max_in_flight, self.decrease_factor);
                 debug!("Rejected packet with T04 error. Amount in flight was: {}, decreasing max in flight to: {}", self.amount_in_flight + prepare_amount, self.max_in_flight);
             }
             ErrorCode::F08_AMOUNT_TOO_LARGE => {
-                if let Ok(details) = MaxPacketAmountDetails::from_bytes(reject.data()) {
-                    let new_max_packet_amount: u64 =
-                        prepare_amount * details.max_amount() / details.amount_received();
-                    if let Some(max_packet_amount) = self.max_packet_amount {
-                        self.max_packet_amount =
-                            Some(min(max_packet_amount, new_max_packet_amount));
-                    } else {
-                        self.max_packet_amount = Some(new_max_packet_amount);
-                    }
-                } else {
-                    warn!("Got F08: Amount Too Large Error without max packet amount details attached");
-                    if let Some(max_packet_amount) = self.max_packet_amount {
-                        self.max_packet_amount =
-                            Some((max_packet_amount as f64 / self.decrease_factor) as u64);
-                    }
-                }
+                apply_amount_too_large(
+                    &mut self.max_packet_amount,
+                    prepare_amount,
+                    reject,
+                    self.decrease_factor,
+                );
             }
             _ => {
                 // No special treatment for other errors
             }
         }
     }
+}
 
-    #[cfg(test)]
-    fn set_max_packet_amount(&mut self, max_packet_amount: u64) {
-        self.max_packet_amount = Some(max_packet_amount)
+/// A congestion controller which adjusts the amount in flight to the round trip time of
+/// the packets. Like the AIMD controller, it adds to the max in flight amount for every
+/// fulfilled packet, but it divides it as soon as packets take longer than the target
+/// latency, which usually happens before connectors run out of liquidity.
+pub struct LatencyCongestionController {
+    /// Round trip time above which `max_in_flight` is decreased
+    target_latency: Duration,
+    /// Amount which is added to `max_in_flight` per packet fulfilled within the target latency
+    increase_amount: u64,
+    /// Divide `max_in_flight` by this factor per slow packet or insufficient liquidity error
+    decrease_factor: f64,
+    /// The maximum amount we are allowed to add in a packet, set by `F08_AMOUNT_TOO_LARGE` errors
+    max_packet_amount: Option<u64>,
+    /// The current amount in flight
+    amount_in_flight: u64,
+    /// The maximum allowed amount to be in flight
+    max_in_flight: u64,
+}
+
+impl LatencyCongestionController {
+    /// Constructs a new congestion controller
+    pub fn new(
+        start_amount: u64,
+        target_latency: Duration,
+        increase_amount: u64,
+        decrease_factor: f64,
+    ) -> Self {
+        LatencyCongestionController {
+            target_latency,
+            increase_amount,
+            decrease_factor,
+            max_packet_amount: None,
+            amount_in_flight: 0,
+            max_in_flight: start_amount,
+        }
+    }
+}
+
+impl CongestionController for LatencyCongestionController {
+    fn get_max_packet_amount(&self) -> u64 {
+        self.max_packet_amount.unwrap_or(u64::MAX)
+    }
+
+    fn get_amount_in_flight(&self) -> u64 {
+        self.amount_in_flight
+    }
+
+    fn get_amount_left_in_window(&self) -> u64 {
+        self.max_in_flight.saturating_sub(self.amount_in_flight)
+    }
+
+    fn prepare(&mut self, amount: u64) {
+        self.amount_in_flight += amount;
+    }
+
+    fn fulfill(&mut self, prepare_amount: u64, round_trip_time: Duration) {
+        self.amount_in_flight -= prepare_amount;
+
+        if round_trip_time > self.target_latency {
+            self.max_in_flight = decrease(self.max_in_flight, self.decrease_factor);
+            debug!(
+                "Fulfilled packet of {} after {:?}, decreasing max in flight to: {}",
+                prepare_amount, round_trip_time, self.max_in_flight
+            );
+        } else {
+            self.max_in_flight = self.max_in_flight.saturating_add(self.increase_amount);
+            debug!(
+                "Fulfilled packet of {} after {:?}, increasing max in flight to: {}",
+                prepare_amount, round_trip_time, self.max_in_flight
+            );
+        }
+    }
+
+    fn reject(&mut self, prepare_amount: u64, round_trip_time: Duration, reject: &Reject) {
+        self.amount_in_flight -= prepare_amount;
+
+        match reject.code() {
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY => {
+                self.max_in_flight = decrease(self.max_in_flight, self.decrease_factor);
+                debug!(
+                    "Rejected packet with T04 error, decreasing max in flight to: {}",
+                    self.max_in_flight
+                );
+            }
+            ErrorCode::F08_AMOUNT_TOO_LARGE => {
+                apply_amount_too_large(
+                    &mut self.max_packet_amount,
+                    prepare_amount,
+                    reject,
+                    self.decrease_factor,
+                );
+            }
+            _ if round_trip_time > self.target_latency => {
+                self.max_in_flight = decrease(self.max_in_flight, self.decrease_factor);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A congestion controller for fast small payments: nothing limits the amount in flight,
+/// so the first packet tries to send the whole amount and, once the path's max packet
+/// amount is known, all the packets are sent at once. If connectors run out of liquidity,
+/// the max in flight amount is halved, starting from the amount that was in flight, and
+/// it's doubled again per fulfilled packet.
+pub struct MaxPacketCongestionController {
+    /// The maximum amount we are allowed to add in a packet, set by `F08_AMOUNT_TOO_LARGE` errors
+    max_packet_amount: Option<u64>,
+    /// The current amount in flight
+    amount_in_flight: u64,
+    /// The maximum allowed amount to be in flight
+    max_in_flight: u64,
+}
+
+impl MaxPacketCongestionController {
+    /// Constructs a new congestion controller
+    pub fn new() -> Self {
+        MaxPacketCongestionController {
+            max_packet_amount: None,
+            amount_in_flight: 0,
+            max_in_flight: u64::MAX,
+        }
+    }
+}
+
+impl Default for MaxPacketCongestionController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CongestionController for MaxPacketCongestionController {
+    fn get_max_packet_amount(&self) -> u64 {
+        self.max_packet_amount.unwrap_or(u64::MAX)
+    }
+
+    fn get_amount_in_flight(&self) -> u64 {
+        self.amount_in_flight
+    }
+
+    fn get_amount_left_in_window(&self) -> u64 {
+        self.max_in_flight.saturating_sub(self.amount_in_flight)
+    }
+
+    fn prepare(&mut self, amount: u64) {
+        self.amount_in_flight += amount;
+    }
+
+    fn fulfill(&mut self, prepare_amount: u64, _round_trip_time: Duration) {
+        self.amount_in_flight -= prepare_amount;
+        self.max_in_flight = self.max_in_flight.saturating_mul(2);
+    }
+
+    fn reject(&mut self, prepare_amount: u64, _round_trip_time: Duration, reject: &Reject) {
+        let amount_in_flight = self.amount_in_flight;
+        self.amount_in_flight -= prepare_amount;
+
+        match reject.code() {
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY => {
+                self.max_in_flight = decrease(min(self.max_in_flight, amount_in_flight), 2.0);
+                debug!(
+                    "Rejected packet with T04 error, decreasing max in flight to: {}",
+                    self.max_in_flight
+                );
+            }
+            ErrorCode::F08_AMOUNT_TOO_LARGE => {
+                apply_amount_too_large(&mut self.max_packet_amount, prepare_amount, reject, 2.0);
+            }
+            _ => {}
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use interledger_packet::RejectBuilder;
+
+    const RTT: Duration = Duration::from_millis(100);
+
+    static INSUFFICIENT_LIQUIDITY_ERROR: Lazy<Reject> = Lazy::new(|| {
+        RejectBuilder {
+            code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+            message: &[],
+            triggered_by: None,
+            data: &[],
+        }
+        .build()
+    });
 
     mod slow_start {
         use super::*;
 
         #[test]
         fn doubles_max_amount_on_fulfill() {
-            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            let mut controller = AimdCongestionController::new(1000, 1000, 2.0);
 
             let amount = controller.get_amount_left_in_window();
             controller.prepare(amount);
-            controller.fulfill(amount);
+            controller.fulfill(amount, RTT);
             assert_eq!(controller.get_amount_left_in_window(), 2000);
 
             let amount = controller.get_amount_left_in_window();
             controller.prepare(amount);
-            controller.fulfill(amount);
+            controller.fulfill(amount, RTT);
             assert_eq!(controller.get_amount_left_in_window(), 4000);
 
             let amount = controller.get_amount_left_in_window();
             controller.prepare(amount);
-            controller.fulfill(amount);
+            controller.fulfill(amount, RTT);
             assert_eq!(controller.get_amount_left_in_window(), 8000);
         }
 
         #[test]
         fn doesnt_overflow_u64() {
-            let mut controller = CongestionController {
+            let mut controller = AimdCongestionController {
                 state: CongestionState::SlowStart,
                 increase_amount: 1000,
                 decrease_factor: 2.0,
@@ -183,87 +506,77 @@ mod tests {
 
             let amount = controller.get_amount_left_in_window();
             controller.prepare(amount);
-            controller.fulfill(amount);
+            controller.fulfill(amount, RTT);
             assert_eq!(controller.get_amount_left_in_window(), u64::max_value());
         }
     }
 
     mod congestion_avoidance {
         use super::*;
-        use interledger_packet::RejectBuilder;
-
-        static INSUFFICIENT_LIQUIDITY_ERROR: Lazy<Reject> = Lazy::new(|| {
-            RejectBuilder {
-                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
-                message: &[],
-                triggered_by: None,
-                data: &[],
-            }
-            .build()
-        });
 
         #[test]
         fn additive_increase() {
-            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            let mut controller = AimdCongestionController::new(1000, 1000, 2.0);
             controller.state = CongestionState::AvoidCongestion;
             for i in 1..5 {
                 let amount = i * 1000;
                 controller.prepare(amount);
-                controller.fulfill(amount);
+                controller.fulfill(amount, RTT);
                 assert_eq!(controller.get_amount_left_in_window(), 1000 + i * 1000);
             }
         }
 
         #[test]
         fn multiplicative_decrease() {
-            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            let mut controller = AimdCongestionController::new(1000, 1000, 2.0);
             controller.state = CongestionState::AvoidCongestion;
 
             let amount = controller.get_amount_left_in_window();
             controller.prepare(amount);
-            controller.reject(amount, &*INSUFFICIENT_LIQUIDITY_ERROR);
+            controller.reject(amount, RTT, &INSUFFICIENT_LIQUIDITY_ERROR);
             assert_eq!(controller.get_amount_left_in_window(), 500);
 
             let amount = controller.get_amount_left_in_window();
             controller.prepare(amount);
-            controller.reject(amount, &*INSUFFICIENT_LIQUIDITY_ERROR);
+            controller.reject(amount, RTT, &INSUFFICIENT_LIQUIDITY_ERROR);
             assert_eq!(controller.get_amount_left_in_window(), 250);
         }
 
         #[test]
         fn aimd_combined() {
-            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            let mut controller = AimdCongestionController::new(1000, 1000, 2.0);
             controller.state = CongestionState::AvoidCongestion;
 
             let amount = controller.get_amount_left_in_window();
             controller.prepare(amount);
-            controller.fulfill(amount);
+            controller.fulfill(amount, RTT);
             assert_eq!(controller.get_amount_left_in_window(), 2000);
 
             let amount = controller.get_amount_left_in_window();
             controller.prepare(amount);
-            controller.fulfill(amount);
+            controller.fulfill(amount, RTT);
             assert_eq!(controller.get_amount_left_in_window(), 3000);
 
             let amount = controller.get_amount_left_in_window();
             controller.prepare(amount);
-            controller.reject(amount, &*INSUFFICIENT_LIQUIDITY_ERROR);
+            controller.reject(amount, RTT, &INSUFFICIENT_LIQUIDITY_ERROR);
             assert_eq!(controller.get_amount_left_in_window(), 1500);
 
             let amount = controller.get_amount_left_in_window();
             controller.prepare(amount);
-            controller.fulfill(amount);
+            controller.fulfill(amount, RTT);
             assert_eq!(controller.get_amount_left_in_window(), 2500);
         }
 
         #[test]
         fn max_packet_amount() {
-            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            let mut controller = AimdCongestionController::new(1000, 1000, 2.0);
             assert_eq!(controller.get_amount_left_in_window(), 1000);
 
             controller.prepare(1000);
             controller.reject(
                 1000,
+                RTT,
                 &RejectBuilder {
                     code: ErrorCode::F08_AMOUNT_TOO_LARGE,
                     message: &[],
@@ -281,6 +594,7 @@ mod tests {
             controller.prepare(amount);
             controller.reject(
                 amount,
+                RTT,
                 &RejectBuilder {
                     code: ErrorCode::F08_AMOUNT_TOO_LARGE,
                     message: &[],
@@ -297,7 +611,7 @@ mod tests {
                 controller.get_amount_left_in_window(),
             );
             controller.prepare(amount);
-            controller.fulfill(amount);
+            controller.fulfill(amount, RTT);
 
             amount = min(
                 controller.get_max_packet_amount(),
@@ -308,18 +622,18 @@ mod tests {
 
         #[test]
         fn max_packet_amount_doesnt_overflow_u64() {
-            let mut controller = CongestionController::new(1000, 1000, 5.0);
+            let mut controller = AimdCongestionController::new(1000, 1000, 5.0);
 
             controller.prepare(500);
             controller.prepare(500);
-            controller.reject(500, &*INSUFFICIENT_LIQUIDITY_ERROR);
+            controller.reject(500, RTT, &INSUFFICIENT_LIQUIDITY_ERROR);
 
             assert_eq!(controller.get_amount_left_in_window(), 0);
         }
 
         #[test]
         fn doesnt_overflow_u64() {
-            let mut controller = CongestionController {
+            let mut controller = AimdCongestionController {
                 state: CongestionState::AvoidCongestion,
                 increase_amount: 1000,
                 decrease_factor: 2.0,
@@ -330,7 +644,7 @@ mod tests {
 
             let amount = controller.get_amount_left_in_window();
             controller.prepare(amount);
-            controller.fulfill(amount);
+            controller.fulfill(amount, RTT);
             assert_eq!(controller.get_amount_left_in_window(), u64::max_value());
         }
     }
@@ -340,7 +654,7 @@ mod tests {
 
         #[test]
         fn tracking_amount_in_flight() {
-            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            let mut controller = AimdCongestionController::new(1000, 1000, 2.0);
             controller.set_max_packet_amount(600);
             assert_eq!(controller.get_max_packet_amount(), 600);
 
@@ -359,4 +673,131 @@ mod tests {
             assert_eq!(max_amount, 1000 - 600 - 100);
         }
     }
+
+    mod latency {
+        use super::*;
+
+        #[test]
+        fn decreases_max_in_flight_when_packets_are_slow() {
+            let mut controller =
+                LatencyCongestionController::new(1000, Duration::from_millis(200), 1000, 2.0);
+
+            let amount = controller.get_amount_left_in_window();
+            controller.prepare(amount);
+            controller.fulfill(amount, RTT);
+            assert_eq!(controller.get_amount_left_in_window(), 2000);
+
+            let amount = controller.get_amount_left_in_window();
+            controller.prepare(amount);
+            controller.fulfill(amount, Duration::from_millis(300));
+            assert_eq!(controller.get_amount_left_in_window(), 1000);
+
+            controller.prepare(1000);
+            controller.reject(
+                1000,
+                Duration::from_millis(300),
+                &RejectBuilder {
+                    code: ErrorCode::T00_INTERNAL_ERROR,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build(),
+            );
+            assert_eq!(controller.get_amount_left_in_window(), 500);
+        }
+    }
+
+    mod max_packet_first {
+        use super::*;
+
+        #[test]
+        fn sends_everything_in_max_sized_packets() {
+            let mut controller = MaxPacketCongestionController::new();
+            assert_eq!(controller.get_amount_left_in_window(), u64::MAX);
+
+            controller.prepare(1000);
+            controller.reject(
+                1000,
+                RTT,
+                &RejectBuilder {
+                    code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                    message: &[],
+                    triggered_by: None,
+                    data: &MaxPacketAmountDetails::new(100, 10).to_bytes(),
+                }
+                .build(),
+            );
+            assert_eq!(controller.get_max_packet_amount(), 100);
+
+            for _ in 0..10 {
+                controller.prepare(100);
+            }
+            assert_eq!(controller.get_amount_in_flight(), 1000);
+            assert_eq!(controller.get_amount_left_in_window(), u64::MAX - 1000);
+        }
+
+        #[test]
+        fn halves_amount_in_flight_on_insufficient_liquidity() {
+            let mut controller = MaxPacketCongestionController::new();
+            for _ in 0..10 {
+                controller.prepare(100);
+            }
+            controller.reject(100, RTT, &INSUFFICIENT_LIQUIDITY_ERROR);
+            assert_eq!(controller.get_amount_in_flight(), 900);
+            // Half of the 1000 that were in flight
+            assert_eq!(controller.get_amount_left_in_window(), 0);
+
+            for _ in 0..9 {
+                controller.fulfill(100, RTT);
+            }
+            assert_eq!(controller.get_amount_left_in_window(), 500 << 9);
+        }
+    }
+
+    mod strategy {
+        use super::*;
+
+        #[test]
+        fn deserializes_strategies() {
+            let strategy: CongestionStrategy =
+                serde_json::from_str(r#"{"strategy":"aimd"}"#).unwrap();
+            assert_eq!(strategy, CongestionStrategy::default());
+
+            let strategy: CongestionStrategy = serde_json::from_str(
+                r#"{"strategy":"latency","target_latency_ms":100,"increase_amount":10}"#,
+            )
+            .unwrap();
+            assert_eq!(
+                strategy,
+                CongestionStrategy::Latency {
+                    target_latency_ms: 100,
+                    increase_amount: Some(10),
+                    decrease_factor: 2.0,
+                }
+            );
+
+            let strategy: CongestionStrategy =
+                serde_json::from_str(r#"{"strategy":"max_packet_first"}"#).unwrap();
+            assert_eq!(strategy, CongestionStrategy::MaxPacketFirst);
+        }
+
+        #[test]
+        fn validates_parameters() {
+            assert!(CongestionStrategy::default().validate().is_ok());
+            assert!(CongestionStrategy::Aimd {
+                increase_amount: None,
+                decrease_factor: 1.0,
+            }
+            .validate()
+            .is_err());
+            assert!(CongestionStrategy::Latency {
+                target_latency_ms: 0,
+                increase_amount: None,
+                decrease_factor: 2.0,
+            }
+            .validate()
+            .is_err());
+        }
+    }
 }
@@ -0,0 +1,1024 @@
+use super::client::{convert, get_rate};
+use super::congestion::{CongestionController, CongestionStrategy};
+use super::crypto::*;
+use super::data::{IncomingData, DEFAULT_MAX_BUFFERED_DATA};
+use super::error::Error;
+use super::packet::*;
+use bytes::{Bytes, BytesMut};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use interledger_packet::{
+    Address, ErrorClass, ErrorCode as IlpErrorCode, PacketType as IlpPacketType, PrepareBuilder,
+    Reject,
+};
+use interledger_rates::ExchangeRateStore;
+use interledger_service::*;
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{debug, warn};
+
+/// How long the Prepares the connection sends are valid for
+const PACKET_EXPIRY: Duration = Duration::from_secs(30);
+/// Maximum time an operation keeps sending packets without making progress
+const MAX_TIME_WITHOUT_PROGRESS: Duration = Duration::from_secs(30);
+/// How often streams which are waiting to receive data, or for the receiver to allow
+/// them to send more, send a packet to the receiver. The receiver can only send data
+/// back in the responses to our packets
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// The most data sent in a single Prepare, which leaves enough room for the other
+/// frames and the encryption overhead in the 32767 bytes of ILP packet data
+const MAX_DATA_PER_PACKET: usize = 16_384;
+
+/// Something that happened on a [StreamConnection](./struct.StreamConnection.html)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The receiver fulfilled a packet carrying money sent on the stream
+    MoneyReceived {
+        stream_id: u64,
+        /// The amount of the packet, in source units
+        source_amount: u64,
+        /// The amount the receiver got, in destination units
+        amount: u64,
+    },
+    /// The receiver closed the stream
+    StreamClosed { stream_id: u64 },
+    /// The receiver closed the connection
+    ConnectionClosed,
+}
+
+struct StreamState {
+    incoming: IncomingData,
+    /// Data which was received but not read yet
+    readable: BytesMut,
+    /// The offset of the next data sent on the stream
+    write_offset: u64,
+    /// The most data the receiver is willing to receive on this stream, if it told us
+    remote_max_offset: Option<u64>,
+    /// Money fulfilled by the receiver, in source units
+    sent_amount: u64,
+    /// Money the receiver got, in destination units
+    delivered_amount: u64,
+    closed_locally: bool,
+    closed_remotely: bool,
+}
+
+impl StreamState {
+    fn new() -> Self {
+        StreamState {
+            incoming: IncomingData::default(),
+            readable: BytesMut::new(),
+            write_offset: 0,
+            remote_max_offset: None,
+            sent_amount: 0,
+            delivered_amount: 0,
+            closed_locally: false,
+            closed_remotely: false,
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed_locally || self.closed_remotely
+    }
+
+    /// The receive window ends this many bytes after the data the application has read
+    fn max_offset(&self) -> u64 {
+        self.incoming.read_offset() - self.readable.len() as u64 + DEFAULT_MAX_BUFFERED_DATA
+    }
+}
+
+struct ConnectionState {
+    sequence: u64,
+    next_stream_id: u64,
+    streams: BTreeMap<u64, StreamState>,
+    /// Do we need to send our source account information to the receiver?
+    should_send_source_account: bool,
+    destination_asset_details: Option<(String, u8)>,
+    /// Shared by the streams sending money
+    congestion_controller: Box<dyn CongestionController>,
+    closed: bool,
+    subscribers: Vec<UnboundedSender<ConnectionEvent>>,
+}
+
+impl ConnectionState {
+    fn publish(&mut self, event: ConnectionEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+    }
+
+    /// Returns the stream if both sides can still use it
+    fn open_stream(&mut self, stream_id: u64) -> Result<&mut StreamState, Error> {
+        if self.closed {
+            return Err(Error::ConnectionClosed);
+        }
+        match self.streams.get_mut(&stream_id) {
+            Some(stream) if !stream.is_closed() => Ok(stream),
+            _ => Err(Error::StreamClosed(stream_id)),
+        }
+    }
+
+    /// Applies the frames of an authentic response from the receiver
+    fn apply_response(&mut self, packet: &StreamPacket) {
+        // Since the receiver could encrypt the response, it read our packet and knows our account
+        self.should_send_source_account = false;
+
+        for frame in packet.frames() {
+            match frame {
+                // https://github.com/interledger/rfcs/pull/551 ensures that this won't change
+                Frame::ConnectionAssetDetails(frame) => {
+                    self.destination_asset_details.get_or_insert_with(|| {
+                        (
+                            frame.source_asset_code.to_string(),
+                            frame.source_asset_scale,
+                        )
+                    });
+                }
+                Frame::StreamMaxData(frame) => {
+                    if let Some(stream) = self.streams.get_mut(&frame.stream_id) {
+                        stream.remote_max_offset = Some(
+                            stream
+                                .remote_max_offset
+                                .map_or(frame.max_offset, |max| max.max(frame.max_offset)),
+                        );
+                    }
+                }
+                Frame::StreamData(frame) => {
+                    let stream = match self.streams.get_mut(&frame.stream_id) {
+                        Some(stream) if !stream.is_closed() => stream,
+                        _ => continue,
+                    };
+                    let max_offset = stream.max_offset();
+                    match stream
+                        .incoming
+                        .receive(frame.offset, frame.data, max_offset)
+                    {
+                        Ok(Some(data)) => stream.readable.extend_from_slice(&data),
+                        Ok(None) => {}
+                        Err(()) => warn!(
+                            "Ignoring data the receiver sent beyond the max data of stream {}",
+                            frame.stream_id
+                        ),
+                    }
+                }
+                Frame::StreamClose(frame) => {
+                    if let Some(stream) = self.streams.get_mut(&frame.stream_id) {
+                        if !stream.closed_remotely {
+                            stream.closed_remotely = true;
+                            self.publish(ConnectionEvent::StreamClosed {
+                                stream_id: frame.stream_id,
+                            });
+                        }
+                    }
+                }
+                Frame::ConnectionClose(frame) => {
+                    debug!(
+                        "Receiver closed the connection: {:?} {}",
+                        frame.code, frame.message
+                    );
+                    if !self.closed {
+                        self.closed = true;
+                        self.publish(ConnectionEvent::ConnectionClosed);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The response to a Prepare sent on the connection
+struct PacketResponse {
+    result: Result<u64, Reject>,
+    /// Whether the response came from the receiver, in which case it
+    /// also processed the frames we sent
+    authentic: bool,
+}
+
+struct ConnectionInner<I, A, S> {
+    next: I,
+    from_account: A,
+    destination_account: Address,
+    shared_secret: Bytes,
+    store: S,
+    slippage: f64,
+    state: Mutex<ConnectionState>,
+}
+
+/// A STREAM connection to a receiver, which sends money and data on multiple concurrent streams.
+///
+/// Unlike [send_money](./fn.send_money.html), which sends a fixed amount and closes the
+/// connection, the connection stays open until it is [closed](#method.close) by either side.
+/// The receiver can only send data back in the responses to our packets, so the
+/// streams poll it while they are waiting to receive data.
+pub struct StreamConnection<I, A, S> {
+    inner: Arc<ConnectionInner<I, A, S>>,
+}
+
+impl<I, A, S> Clone for StreamConnection<I, A, S> {
+    fn clone(&self) -> Self {
+        StreamConnection {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<I, A, S> StreamConnection<I, A, S>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    /// Creates a connection to the receiver. No packets are sent before a stream sends
+    /// money or data.
+    ///
+    /// The `slippage` is used to enforce the minimum amount delivered by each packet,
+    /// like in [send_money](./fn.send_money.html). `max_in_flight` is the amount the
+    /// streams of the connection can have in flight at first, in source units. The
+    /// default (AIMD) congestion controller adjusts it as packets are fulfilled and rejected.
+    pub fn new(
+        service: I,
+        from_account: &A,
+        store: S,
+        destination_account: Address,
+        shared_secret: Vec<u8>,
+        slippage: f64,
+        max_in_flight: u64,
+    ) -> Self {
+        Self::with_congestion_controller(
+            service,
+            from_account,
+            store,
+            destination_account,
+            shared_secret,
+            slippage,
+            CongestionStrategy::default().build(max_in_flight),
+        )
+    }
+
+    /// Creates a connection like [new](#method.new), whose streams share a congestion
+    /// controller of the given strategy
+    ///
+    /// # Errors
+    /// If the strategy's parameters are invalid
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_congestion_strategy(
+        service: I,
+        from_account: &A,
+        store: S,
+        destination_account: Address,
+        shared_secret: Vec<u8>,
+        slippage: f64,
+        max_in_flight: u64,
+        congestion_strategy: CongestionStrategy,
+    ) -> Result<Self, Error> {
+        congestion_strategy.validate()?;
+        Ok(Self::with_congestion_controller(
+            service,
+            from_account,
+            store,
+            destination_account,
+            shared_secret,
+            slippage,
+            congestion_strategy.build(max_in_flight),
+        ))
+    }
+
+    /// Creates a connection like [new](#method.new), whose streams share the given
+    /// congestion controller
+    pub fn with_congestion_controller(
+        service: I,
+        from_account: &A,
+        store: S,
+        destination_account: Address,
+        shared_secret: Vec<u8>,
+        slippage: f64,
+        congestion_controller: Box<dyn CongestionController>,
+    ) -> Self {
+        StreamConnection {
+            inner: Arc::new(ConnectionInner {
+                next: service,
+                from_account: from_account.clone(),
+                destination_account,
+                shared_secret: Bytes::from(shared_secret),
+                store,
+                slippage,
+                state: Mutex::new(ConnectionState {
+                    sequence: 1,
+                    // Streams opened by the client have odd IDs
+                    next_stream_id: 1,
+                    streams: BTreeMap::new(),
+                    should_send_source_account: true,
+                    destination_asset_details: None,
+                    congestion_controller,
+                    closed: false,
+                    subscribers: Vec::new(),
+                }),
+            }),
+        }
+    }
+
+    /// Opens a new stream on the connection
+    pub fn open_stream(&self) -> Result<DataAndMoneyStream<I, A, S>, Error> {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.closed {
+            return Err(Error::ConnectionClosed);
+        }
+        let id = state.next_stream_id;
+        state.next_stream_id += 2;
+        state.streams.insert(id, StreamState::new());
+        Ok(DataAndMoneyStream {
+            connection: self.clone(),
+            id,
+        })
+    }
+
+    /// Returns a receiver for the events of the connection from now on
+    pub fn events(&self) -> UnboundedReceiver<ConnectionEvent> {
+        let (sender, receiver) = unbounded();
+        self.inner.state.lock().unwrap().subscribers.push(sender);
+        receiver
+    }
+
+    /// The receiver's asset code and scale, once it told us
+    pub fn destination_asset_details(&self) -> Option<(String, u8)> {
+        self.inner
+            .state
+            .lock()
+            .unwrap()
+            .destination_asset_details
+            .clone()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.state.lock().unwrap().closed
+    }
+
+    /// Closes the connection and tells the receiver. There's no ACK from the
+    /// receiver, so we can't confirm it closed
+    pub async fn close(&self) {
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            if state.closed {
+                return;
+            }
+            state.closed = true;
+        }
+        debug!("Closing connection");
+        self.send_packet(
+            0,
+            0,
+            &[Frame::ConnectionClose(ConnectionCloseFrame {
+                code: ErrorCode::NoError,
+                message: "",
+            })],
+        )
+        .await;
+    }
+
+    /// Sends a Prepare with the given frames, the receive windows of the open
+    /// streams and (until the receiver responds) our address.
+    ///
+    /// If `min_destination_amount` is 0, the Prepare is unfulfillable so no money is at risk
+    async fn send_packet(
+        &self,
+        source_amount: u64,
+        min_destination_amount: u64,
+        frames: &[Frame<'_>],
+    ) -> PacketResponse {
+        let (prepare, sequence) = {
+            let mut state = self.inner.state.lock().unwrap();
+            let sequence = state.sequence;
+            state.sequence += 1;
+
+            let mut all_frames = frames.to_vec();
+            all_frames.extend(state.streams.iter().filter_map(|(stream_id, stream)| {
+                if stream.is_closed() {
+                    None
+                } else {
+                    Some(Frame::StreamMaxData(StreamMaxDataFrame {
+                        stream_id: *stream_id,
+                        max_offset: stream.max_offset(),
+                    }))
+                }
+            }));
+            if state.should_send_source_account {
+                all_frames.push(Frame::ConnectionNewAddress(ConnectionNewAddressFrame {
+                    source_account: self.inner.from_account.ilp_address().clone(),
+                }));
+            }
+            let stream_packet = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Prepare,
+                prepare_amount: min_destination_amount,
+                sequence,
+                frames: &all_frames,
+            }
+            .build();
+            debug!(
+                "Sending packet {} with amount: {} and encrypted STREAM packet: {:?}",
+                sequence, source_amount, stream_packet
+            );
+
+            let data = stream_packet.into_encrypted(&self.inner.shared_secret);
+            let execution_condition = if min_destination_amount > 0 {
+                generate_condition(&self.inner.shared_secret, &data)
+            } else {
+                random_condition()
+            };
+            let prepare = PrepareBuilder {
+                destination: self.inner.destination_account.clone(),
+                amount: source_amount,
+                execution_condition: &execution_condition,
+                expires_at: SystemTime::now() + PACKET_EXPIRY,
+                data: &data[..],
+            }
+            .build();
+            (prepare, sequence)
+        };
+
+        let reply = self
+            .inner
+            .next
+            .clone()
+            .handle_request(IncomingRequest {
+                from: self.inner.from_account.clone(),
+                prepare,
+            })
+            .await;
+
+        let (packet_type, reply_data) = match &reply {
+            Ok(fulfill) => (IlpPacketType::Fulfill, fulfill.data()),
+            Err(reject) => (IlpPacketType::Reject, reject.data()),
+        };
+        let mut state = self.inner.state.lock().unwrap();
+        let claimed_amount = match StreamPacket::from_encrypted(
+            &self.inner.shared_secret,
+            BytesMut::from(reply_data),
+        ) {
+            Ok(packet) if packet.sequence() != sequence => {
+                warn!(
+                    "Discarding replayed STREAM packet (expected sequence {}, but received {})",
+                    sequence,
+                    packet.sequence()
+                );
+                None
+            }
+            // If the receiver claims they sent a Reject but we got a Fulfill, they lied!
+            Ok(packet)
+                if packet.ilp_packet_type() == IlpPacketType::Reject
+                    && packet_type == IlpPacketType::Fulfill =>
+            {
+                warn!("Discarding STREAM packet (received Fulfill, but recipient said they sent a Reject)");
+                None
+            }
+            Ok(packet) => {
+                state.apply_response(&packet);
+                Some(packet.prepare_amount())
+            }
+            Err(_) => None,
+        };
+
+        PacketResponse {
+            // Even if the data was invalid, since it was fulfilled,
+            // we must assume the receiver got at least the minimum
+            result: reply.map(|_| max(min_destination_amount, claimed_amount.unwrap_or(0))),
+            authentic: claimed_amount.is_some(),
+        }
+    }
+}
+
+/// Returns an error if the rejection is final, rather than
+/// one that could be resolved by trying again
+fn check_rejection(reject: &Reject) -> Result<(), Error> {
+    match (reject.code().class(), reject.code()) {
+        (ErrorClass::Temporary, _) => Ok(()),
+        (_, IlpErrorCode::F08_AMOUNT_TOO_LARGE) => Ok(()),
+        (_, IlpErrorCode::F99_APPLICATION_ERROR) => Ok(()),
+        (_, IlpErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT) => Ok(()),
+        _ => Err(Error::UnexpectedRejection(
+            reject.code(),
+            String::from_utf8_lossy(reject.message()).into_owned(),
+        )),
+    }
+}
+
+/// A stream of a [StreamConnection](./struct.StreamConnection.html), which sends money
+/// and data to the receiver and receives the data it sends back.
+///
+/// Clones refer to the same stream and can be used concurrently.
+pub struct DataAndMoneyStream<I, A, S> {
+    connection: StreamConnection<I, A, S>,
+    id: u64,
+}
+
+impl<I, A, S> Clone for DataAndMoneyStream<I, A, S> {
+    fn clone(&self) -> Self {
+        DataAndMoneyStream {
+            connection: self.connection.clone(),
+            id: self.id,
+        }
+    }
+}
+
+impl<I, A, S> DataAndMoneyStream<I, A, S>
+where
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The money fulfilled by the receiver on this stream, in source units,
+    /// and the amount the receiver got, in destination units
+    pub fn total_sent(&self) -> (u64, u64) {
+        let state = self.connection.inner.state.lock().unwrap();
+        state
+            .streams
+            .get(&self.id)
+            .map(|stream| (stream.sent_amount, stream.delivered_amount))
+            .unwrap_or_default()
+    }
+
+    /// Sends the given source amount on the stream and returns the amount the receiver
+    /// got, in destination units.
+    ///
+    /// The streams of a connection share its congestion controller, which limits the
+    /// total amount in flight, so they wait for each other when the limit is reached.
+    pub async fn send_money(&self, source_amount: u64) -> Result<u64, Error> {
+        let inner = &self.connection.inner;
+        let mut remaining = source_amount;
+        let mut delivered = 0;
+        let mut last_fulfill_time = Instant::now();
+
+        while remaining > 0 {
+            if last_fulfill_time.elapsed() >= MAX_TIME_WITHOUT_PROGRESS {
+                return Err(Error::Timeout);
+            }
+
+            let next_packet = {
+                let mut state = inner.state.lock().unwrap();
+                state.open_stream(self.id)?;
+                let destination_asset_details = state.destination_asset_details.clone();
+                let congestion_controller = &mut state.congestion_controller;
+                let amount = min(
+                    min(remaining, congestion_controller.get_amount_left_in_window()),
+                    congestion_controller.get_max_packet_amount(),
+                );
+                // Only one packet is sent until the receiver tells us its asset details
+                let awaiting_asset_details = destination_asset_details.is_none()
+                    && congestion_controller.get_amount_in_flight() > 0;
+                if amount > 0 && !awaiting_asset_details {
+                    congestion_controller.prepare(amount);
+                    // Until we know the receiver's asset details, the packets are unfulfillable
+                    let min_destination_amount = get_rate(
+                        &inner.store,
+                        inner.from_account.asset_scale(),
+                        inner.from_account.asset_code(),
+                        destination_asset_details.as_ref().map(|(_, scale)| *scale),
+                        destination_asset_details
+                            .as_ref()
+                            .map(|(code, _)| &code[..]),
+                        inner.slippage,
+                    )
+                    .and_then(|rate| convert(amount, rate))
+                    .unwrap_or(0);
+                    Some((amount, min_destination_amount))
+                } else {
+                    None
+                }
+            };
+            // Wait for the other packets in flight
+            let (amount, min_destination_amount) = match next_packet {
+                Some(next_packet) => next_packet,
+                None => {
+                    sleep(POLL_INTERVAL).await;
+                    continue;
+                }
+            };
+
+            let sent_at = Instant::now();
+            let response = self
+                .connection
+                .send_packet(
+                    amount,
+                    min_destination_amount,
+                    &[Frame::StreamMoney(StreamMoneyFrame {
+                        stream_id: self.id,
+                        shares: 1,
+                    })],
+                )
+                .await;
+            let round_trip_time = sent_at.elapsed();
+
+            let mut state = inner.state.lock().unwrap();
+            let congestion_controller = &mut state.congestion_controller;
+            match response.result {
+                Ok(delivered_amount) => {
+                    congestion_controller.fulfill(amount, round_trip_time);
+                    if let Some(stream) = state.streams.get_mut(&self.id) {
+                        stream.sent_amount += amount;
+                        stream.delivered_amount += delivered_amount;
+                    }
+                    state.publish(ConnectionEvent::MoneyReceived {
+                        stream_id: self.id,
+                        source_amount: amount,
+                        amount: delivered_amount,
+                    });
+                    remaining -= amount;
+                    delivered += delivered_amount;
+                    last_fulfill_time = Instant::now();
+                }
+                Err(reject) => {
+                    congestion_controller.reject(amount, round_trip_time, &reject);
+                    debug!(
+                        "Prepare with amount {} on stream {} was rejected with code: {}",
+                        amount,
+                        self.id,
+                        reject.code()
+                    );
+                    check_rejection(&reject)?;
+                }
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Sends the data on the stream. Resolves once the receiver acknowledged all of it.
+    ///
+    /// The receiver limits how much data it buffers per stream, so this waits
+    /// for it to read the data it received before sending more.
+    pub async fn send_data(&self, data: &[u8]) -> Result<(), Error> {
+        let inner = &self.connection.inner;
+        let mut sent = 0;
+        let mut last_progress_time = Instant::now();
+
+        while sent < data.len() {
+            if last_progress_time.elapsed() >= MAX_TIME_WITHOUT_PROGRESS {
+                return Err(Error::Timeout);
+            }
+
+            let (offset, len) = {
+                let mut state = inner.state.lock().unwrap();
+                let stream = state.open_stream(self.id)?;
+                let window = stream
+                    .remote_max_offset
+                    .unwrap_or(DEFAULT_MAX_BUFFERED_DATA)
+                    .saturating_sub(stream.write_offset);
+                let len = min(min(data.len() - sent, MAX_DATA_PER_PACKET) as u64, window) as usize;
+                let offset = stream.write_offset;
+                stream.write_offset += len as u64;
+                (offset, len)
+            };
+
+            if len == 0 {
+                // Tell the receiver we are blocked and give it time to read the data
+                let response = self
+                    .connection
+                    .send_packet(
+                        0,
+                        0,
+                        &[Frame::StreamDataBlocked(StreamDataBlockedFrame {
+                            stream_id: self.id,
+                            max_offset: offset + (data.len() - sent) as u64,
+                        })],
+                    )
+                    .await;
+                if let Err(reject) = response.result {
+                    if !response.authentic {
+                        check_rejection(&reject)?;
+                    }
+                }
+                sleep(POLL_INTERVAL).await;
+                continue;
+            }
+
+            // The exact same frame is resent until the receiver acknowledges it
+            let frame = Frame::StreamData(StreamDataFrame {
+                stream_id: self.id,
+                offset,
+                data: &data[sent..sent + len],
+            });
+            let result = loop {
+                let response = self
+                    .connection
+                    .send_packet(0, 0, std::slice::from_ref(&frame))
+                    .await;
+                if response.authentic {
+                    break Ok(());
+                }
+                if let Err(reject) = response.result {
+                    if let Err(err) = check_rejection(&reject) {
+                        break Err(err);
+                    }
+                }
+                if last_progress_time.elapsed() >= MAX_TIME_WITHOUT_PROGRESS {
+                    break Err(Error::Timeout);
+                }
+                sleep(POLL_INTERVAL).await;
+            };
+            if let Err(err) = result {
+                // The data can be sent again at the same offset, unless the stream
+                // reserved data after it for another call in the meantime
+                let mut state = inner.state.lock().unwrap();
+                if let Some(stream) = state.streams.get_mut(&self.id) {
+                    if stream.write_offset == offset + len as u64 {
+                        stream.write_offset = offset;
+                    }
+                }
+                return Err(err);
+            }
+            sent += len;
+            last_progress_time = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Waits for data from the receiver. Returns `None` once the stream or the
+    /// connection is closed and all of the data received on it was read.
+    pub async fn receive(&self) -> Result<Option<Bytes>, Error> {
+        let inner = &self.connection.inner;
+        let mut last_response_time = Instant::now();
+
+        loop {
+            {
+                let mut state = inner.state.lock().unwrap();
+                let closed = state.closed;
+                match state.streams.get_mut(&self.id) {
+                    Some(stream) if !stream.readable.is_empty() => {
+                        return Ok(Some(stream.readable.split().freeze()))
+                    }
+                    Some(stream) if !stream.is_closed() && !closed => {}
+                    _ => return Ok(None),
+                }
+            }
+            if last_response_time.elapsed() >= MAX_TIME_WITHOUT_PROGRESS {
+                return Err(Error::Timeout);
+            }
+
+            // Give the receiver the chance to send us data
+            let response = self.connection.send_packet(0, 0, &[]).await;
+            if response.authentic {
+                last_response_time = Instant::now();
+            } else if let Err(reject) = response.result {
+                check_rejection(&reject)?;
+            }
+
+            let has_data = matches!(
+                inner.state.lock().unwrap().streams.get(&self.id),
+                Some(stream) if !stream.readable.is_empty()
+            );
+            if !has_data {
+                sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+
+    /// Closes the stream and tells the receiver. The data received on it can still be read
+    pub async fn close(&self) {
+        {
+            let mut state = self.connection.inner.state.lock().unwrap();
+            match state.streams.get_mut(&self.id) {
+                Some(stream) if !stream.closed_locally => stream.closed_locally = true,
+                _ => return,
+            }
+            if state.closed {
+                return;
+            }
+        }
+        self.connection
+            .send_packet(
+                0,
+                0,
+                &[Frame::StreamClose(StreamCloseFrame {
+                    stream_id: self.id,
+                    code: ErrorCode::NoError,
+                    message: "",
+                })],
+            )
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::congestion::MaxPacketCongestionController;
+    use crate::test_helpers::{test_receiver, TestAccount, TestStore, ToReceiver};
+    use crate::StreamDataHandler;
+    use async_trait::async_trait;
+    use futures::StreamExt;
+    use interledger_packet::RejectBuilder;
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    struct Uppercase;
+
+    #[async_trait]
+    impl StreamDataHandler for Uppercase {
+        async fn handle_data(&self, _connection: &Address, _stream_id: u64, data: Bytes) -> Bytes {
+            Bytes::from(data.to_ascii_uppercase())
+        }
+    }
+
+    fn account(address: &str) -> TestAccount {
+        TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: Address::from_str(address).unwrap(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            max_packet_amount: None,
+        }
+    }
+
+    fn connect() -> StreamConnection<
+        ToReceiver<impl OutgoingService<TestAccount> + Clone + Send + Sync + 'static>,
+        TestAccount,
+        TestStore,
+    > {
+        let receiver = test_receiver(1000, None, Some(Arc::new(Uppercase)));
+        StreamConnection::new(
+            receiver.service,
+            &account("example.sender"),
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            receiver.destination_account,
+            receiver.shared_secret,
+            0.0,
+            1000,
+        )
+    }
+
+    #[tokio::test]
+    async fn sends_and_receives_data_on_concurrent_streams() {
+        let connection = connect();
+        let first = connection.open_stream().unwrap();
+        let second = connection.open_stream().unwrap();
+        assert_eq!((first.id(), second.id()), (1, 3));
+
+        let (first_result, second_result) =
+            tokio::join!(first.send_data(b"hello"), second.send_data(b"world"));
+        first_result.unwrap();
+        second_result.unwrap();
+        assert_eq!(first.receive().await.unwrap(), Some(Bytes::from("HELLO")));
+        assert_eq!(second.receive().await.unwrap(), Some(Bytes::from("WORLD")));
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_receivers_max_data() {
+        let connection = connect();
+        let stream = connection.open_stream().unwrap();
+
+        // More than the receiver buffers, so it is sent in several packets. Reading the
+        // responses opens the stream's receive window for the rest of the data
+        let data = vec![b'a'; 3 * DEFAULT_MAX_BUFFERED_DATA as usize];
+        let reader = stream.clone();
+        let read = tokio::spawn(async move {
+            let mut received = Vec::new();
+            while received.len() < 3 * DEFAULT_MAX_BUFFERED_DATA as usize {
+                received.extend_from_slice(&reader.receive().await.unwrap().unwrap());
+            }
+            received
+        });
+        stream.send_data(&data).await.unwrap();
+        assert_eq!(read.await.unwrap(), data.to_ascii_uppercase());
+    }
+
+    #[tokio::test]
+    async fn sends_money_and_publishes_events() {
+        let connection = connect();
+        let mut events = connection.events();
+        let first = connection.open_stream().unwrap();
+        let second = connection.open_stream().unwrap();
+
+        let (first_result, second_result) =
+            tokio::join!(first.send_money(100), second.send_money(50));
+        assert_eq!(first_result.unwrap(), 100);
+        assert_eq!(second_result.unwrap(), 50);
+        assert_eq!(first.total_sent(), (100, 100));
+        assert_eq!(
+            connection.destination_asset_details(),
+            Some(("XYZ".to_string(), 9))
+        );
+
+        connection.close().await;
+        drop(connection);
+        drop(first);
+        drop(second);
+        let received: u64 = events
+            .by_ref()
+            .map(|event| match event {
+                ConnectionEvent::MoneyReceived { amount, .. } => amount,
+                _ => 0,
+            })
+            .fold(0, |total, amount| async move { total + amount })
+            .await;
+        assert_eq!(received, 150);
+    }
+
+    #[tokio::test]
+    async fn stops_using_closed_streams_and_connections() {
+        let connection = connect();
+        let stream = connection.open_stream().unwrap();
+        stream.send_data(b"hello").await.unwrap();
+        stream.close().await;
+
+        // The data received before the stream was closed can still be read
+        assert_eq!(stream.receive().await.unwrap(), Some(Bytes::from("HELLO")));
+        assert_eq!(stream.receive().await.unwrap(), None);
+        assert!(matches!(
+            stream.send_data(b"more").await,
+            Err(Error::StreamClosed(1))
+        ));
+
+        connection.close().await;
+        assert!(connection.is_closed());
+        assert!(matches!(
+            connection.open_stream(),
+            Err(Error::ConnectionClosed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn sizes_the_congestion_window_for_the_connection() {
+        let connection = connect();
+        let stream = connection.open_stream().unwrap();
+        // A small first payment does not limit the payments sent after it
+        assert_eq!(stream.send_money(1).await.unwrap(), 1);
+        let state = connection.inner.state.lock().unwrap();
+        assert!(state.congestion_controller.get_amount_left_in_window() >= 1000);
+    }
+
+    #[tokio::test]
+    async fn releases_the_offset_of_data_which_was_not_sent() {
+        let connection = StreamConnection::new(
+            incoming_service_fn(|_: IncomingRequest<TestAccount>| -> IlpResult {
+                Err(RejectBuilder {
+                    code: IlpErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build())
+            }),
+            &account("example.sender"),
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            Address::from_str("example.receiver.connection").unwrap(),
+            vec![0; 32],
+            0.0,
+            1000,
+        );
+        let stream = connection.open_stream().unwrap();
+        assert!(matches!(
+            stream.send_data(b"hello").await,
+            Err(Error::UnexpectedRejection(IlpErrorCode::F02_UNREACHABLE, _))
+        ));
+        let state = connection.inner.state.lock().unwrap();
+        assert_eq!(state.streams[&1].write_offset, 0);
+    }
+
+    #[tokio::test]
+    async fn uses_the_given_congestion_control() {
+        let receiver = test_receiver(1000, None, None);
+        let store = TestStore {
+            route: None,
+            price_1: None,
+            price_2: None,
+        };
+        let result = StreamConnection::with_congestion_strategy(
+            receiver.service.clone(),
+            &account("example.sender"),
+            store.clone(),
+            receiver.destination_account.clone(),
+            receiver.shared_secret.clone(),
+            0.0,
+            1000,
+            CongestionStrategy::Latency {
+                target_latency_ms: 0,
+                increase_amount: None,
+                decrease_factor: 2.0,
+            },
+        );
+        assert!(matches!(result, Err(Error::InvalidCongestionStrategy(_))));
+
+        let connection = StreamConnection::with_congestion_controller(
+            receiver.service,
+            &account("example.sender"),
+            store,
+            receiver.destination_account,
+            receiver.shared_secret,
+            0.0,
+            Box::new(MaxPacketCongestionController::new()),
+        );
+        let stream = connection.open_stream().unwrap();
+        assert_eq!(stream.send_money(500).await.unwrap(), 500);
+    }
+}
@@ -22,6 +22,12 @@ pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
     to_return
 }
 
+/// Checks in constant time that the tag is the HMAC-SHA256 of the message using the provided **secret** key
+pub fn verify_hmac_sha256(key: &[u8], message: &[u8], tag: &[u8]) -> bool {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    hmac::verify(&key, message, tag).is_ok()
+}
+
 /// The fulfillment is generated by HMAC-256'ing the data with a secret key.
 /// The secret key is generated deterministically by HMAC-256'ing the shared secret
 /// and the hardcoded string "ilp_stream_fulfillment"
@@ -0,0 +1,959 @@
+use super::packet::*;
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use interledger_packet::Address;
+use std::cmp::min;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::debug;
+
+/// The number of bytes of each stream the receiver is willing to buffer ahead of the data
+/// it passed to the handler. It also assumes the sender can buffer as much of the data it
+/// responds with, until the sender advertises its own limit with a `StreamMaxData` frame
+pub const DEFAULT_MAX_BUFFERED_DATA: u64 = 16_384;
+/// The state of connections which have not received a packet for this long is dropped
+const CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// The most data sent back in a single Fulfill or Reject, which leaves enough room
+/// for the other frames and the encryption overhead in the 32767 bytes of ILP packet data
+const MAX_RESPONSE_DATA: usize = 16_384;
+/// Data sent back which the sender did not acknowledge for this long is sent again, in case
+/// the Fulfill or Reject which carried it was lost
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(10);
+/// The number of streams the sender can have open on a connection. The receiver advertises
+/// a max stream ID which leaves room for this many of the sender's odd stream IDs, and raises
+/// it whenever the sender closes one
+const MAX_OPEN_STREAMS: u64 = 10;
+/// The most data the handler responded with on a stream which the sender did not acknowledge
+/// yet. Once a stream holds this much, the receiver closes it instead of passing it more data
+const MAX_UNACKED_REPLY_DATA: usize = 65_536;
+
+/// Receives the data sent on STREAM connections and responds to it.
+///
+/// Handlers are passed to
+/// [StreamReceiverService::with_data_handler](./struct.StreamReceiverService.html#method.with_data_handler).
+/// Connections are identified by the ILP address the sender sends its packets to.
+#[async_trait]
+pub trait StreamDataHandler: Send + Sync {
+    /// Called with the next part of the data received on a stream, in order. The calls for
+    /// a stream are made one at a time, even when the sender sends packets concurrently.
+    /// Returns the data to send back to the sender on the same stream, which may be empty.
+    ///
+    /// The returned data is included in the response to the Prepare that carried the
+    /// incoming data and, if it does not fit, in the responses to the next Prepares
+    /// the sender sends on the connection
+    async fn handle_data(&self, connection: &Address, stream_id: u64, data: Bytes) -> Bytes;
+
+    /// Called when the sender closes a stream, or when the receiver closes it because the sender
+    /// does not acknowledge the data sent back on it. Data which was not sent back yet is dropped
+    async fn stream_closed(&self, _connection: &Address, _stream_id: u64) {}
+
+    /// Called when the sender closes the connection or the connection is closed because
+    /// the sender sent more data than it was allowed to
+    async fn connection_closed(&self, _connection: &Address) {}
+}
+
+/// Reassembles the data received on a stream
+#[derive(Default)]
+pub(crate) struct IncomingData {
+    /// All of the data before this offset was returned by `receive`
+    read_offset: u64,
+    /// Data received ahead of `read_offset`, by offset
+    pending: BTreeMap<u64, Bytes>,
+}
+
+impl IncomingData {
+    pub(crate) fn read_offset(&self) -> u64 {
+        self.read_offset
+    }
+
+    /// Buffers the chunk and returns the data which can be read now.
+    /// Returns an error if the chunk ends after `max_offset`
+    pub(crate) fn receive(
+        &mut self,
+        offset: u64,
+        data: &[u8],
+        max_offset: u64,
+    ) -> Result<Option<Bytes>, ()> {
+        let end = offset.saturating_add(data.len() as u64);
+        if end > max_offset {
+            return Err(());
+        }
+        // Data the sender retransmits because it did not get the response
+        // to the packet which carried it the first time
+        if end <= self.read_offset {
+            return Ok(None);
+        }
+        match self.pending.get(&offset) {
+            Some(buffered) if buffered.len() >= data.len() => {}
+            _ => {
+                self.pending.insert(offset, Bytes::copy_from_slice(data));
+            }
+        }
+
+        let mut readable = BytesMut::new();
+        while let Some((&offset, _)) = self.pending.iter().next() {
+            if offset > self.read_offset {
+                break;
+            }
+            let chunk = self.pending.remove(&offset).unwrap();
+            let end = offset + chunk.len() as u64;
+            if end > self.read_offset {
+                readable.extend_from_slice(&chunk[(self.read_offset - offset) as usize..]);
+                self.read_offset = end;
+            }
+        }
+        if readable.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(readable.freeze()))
+        }
+    }
+}
+
+#[derive(Default)]
+struct HandlerTurnsState {
+    /// The number of tickets handed out
+    issued: u64,
+    /// The ticket whose turn it is
+    current: u64,
+    /// Tickets which were dropped before their turn
+    abandoned: HashSet<u64>,
+}
+
+/// Hands out the turns to call the handler for a stream, so the calls for concurrent
+/// packets are made one at a time and in the order their data was reassembled
+#[derive(Default)]
+struct HandlerTurns {
+    state: Mutex<HandlerTurnsState>,
+    notify: Notify,
+}
+
+impl HandlerTurns {
+    fn ticket(self: &Arc<Self>) -> Ticket {
+        let mut state = self.state.lock().unwrap();
+        let number = state.issued;
+        state.issued += 1;
+        Ticket {
+            turns: self.clone(),
+            number,
+        }
+    }
+}
+
+/// A place in the queue of handler calls for a stream. The turn ends when the ticket is dropped
+struct Ticket {
+    turns: Arc<HandlerTurns>,
+    number: u64,
+}
+
+impl Ticket {
+    async fn wait_turn(&self) {
+        loop {
+            // Created before checking, so a turn ending in between still wakes us up
+            let notified = self.turns.notify.notified();
+            if self.turns.state.lock().unwrap().current == self.number {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let mut state = self.turns.state.lock().unwrap();
+        if state.current != self.number {
+            state.abandoned.insert(self.number);
+            return;
+        }
+        state.current += 1;
+        loop {
+            let current = state.current;
+            if !state.abandoned.remove(&current) {
+                break;
+            }
+            state.current += 1;
+        }
+        drop(state);
+        self.turns.notify.notify_waiters();
+    }
+}
+
+#[derive(Default)]
+struct StreamState {
+    /// The data is passed to the handler as soon as it can be read
+    incoming: IncomingData,
+    turns: Arc<HandlerTurns>,
+    /// Data the handler responded with which the sender did not acknowledge yet
+    outgoing: BytesMut,
+    /// The offset of the first byte of `outgoing`
+    acked_offset: u64,
+    /// The offset of the next byte of `outgoing` to send
+    write_offset: u64,
+    /// The most data the sender is willing to receive on this stream, if it told us
+    remote_max_offset: Option<u64>,
+    /// How much the sender can buffer. It is the first max data the sender advertises, because
+    /// it has not read any data then, so any increase after that acknowledges data we sent
+    remote_window: Option<u64>,
+    /// When the oldest data which is not acknowledged was sent
+    unacked_since: Option<Instant>,
+}
+
+impl StreamState {
+    fn max_data(&self, stream_id: u64) -> StreamMaxDataFrame {
+        StreamMaxDataFrame {
+            stream_id,
+            max_offset: self.incoming.read_offset() + DEFAULT_MAX_BUFFERED_DATA,
+        }
+    }
+
+    fn set_remote_max_offset(&mut self, max_offset: u64) {
+        if self.write_offset == 0 && self.remote_window.is_none() {
+            self.remote_window = Some(max_offset);
+        }
+        // The frames of packets which arrive out of order must not lower the limit
+        let max_offset = self
+            .remote_max_offset
+            .map_or(max_offset, |max| max.max(max_offset));
+        self.remote_max_offset = Some(max_offset);
+
+        // The sender read the data up to this offset, so it does not need to be sent again
+        let acked_offset = max_offset
+            .saturating_sub(self.remote_window.unwrap_or(DEFAULT_MAX_BUFFERED_DATA))
+            .min(self.write_offset);
+        if acked_offset > self.acked_offset {
+            let _ = self
+                .outgoing
+                .split_to((acked_offset - self.acked_offset) as usize);
+            self.acked_offset = acked_offset;
+            self.unacked_since = if acked_offset < self.write_offset {
+                Some(Instant::now())
+            } else {
+                None
+            };
+        }
+    }
+}
+
+struct ConnectionState {
+    streams: BTreeMap<u64, StreamState>,
+    /// The highest stream ID the sender is allowed to open
+    max_stream_id: u64,
+    last_packet: Instant,
+}
+
+impl ConnectionState {
+    /// Returns the stream, opening it if the sender is allowed to
+    fn stream(&mut self, stream_id: u64) -> Option<&mut StreamState> {
+        if !self.streams.contains_key(&stream_id)
+            && (stream_id > self.max_stream_id || self.streams.len() as u64 >= MAX_OPEN_STREAMS)
+        {
+            return None;
+        }
+        Some(self.streams.entry(stream_id).or_default())
+    }
+
+    /// Drops the stream and lets the sender open another one in its place
+    fn close_stream(&mut self, stream_id: u64) -> Option<StreamState> {
+        let stream = self.streams.remove(&stream_id)?;
+        self.max_stream_id += 2;
+        Some(stream)
+    }
+}
+
+/// What happened on a connection because of the frames of a single packet
+#[derive(Default)]
+struct Received {
+    /// The data to pass to the handler, by stream, with the turn to do so
+    data: Vec<(u64, Bytes, Ticket)>,
+    closed_streams: Vec<(u64, Ticket)>,
+    connection_closed: bool,
+    /// The turns of the streams which were open when the connection was closed, so the
+    /// handler learns that it is closed after it got all of their data
+    connection_tickets: Vec<Ticket>,
+    max_data: Vec<StreamMaxDataFrame>,
+    max_stream_id: Option<u64>,
+    /// The streams which are closed because too much of the data sent back on them is not
+    /// acknowledged, with the turn to tell the handler
+    backlogged_streams: Vec<(u64, Ticket)>,
+    /// Why the connection is closed, if the sender did something it was not allowed to
+    error: Option<ErrorCode>,
+}
+
+/// The frames to include in the response to a Prepare
+#[derive(Debug, Default)]
+pub(crate) struct DataResponse {
+    max_data: Vec<StreamMaxDataFrame>,
+    max_stream_id: Option<u64>,
+    /// The stream ID, offset and data of each `StreamData` frame
+    data: Vec<(u64, u64, Bytes)>,
+    backlogged_streams: Vec<u64>,
+    error: Option<ErrorCode>,
+}
+
+impl DataResponse {
+    pub(crate) fn frames(&self) -> Vec<Frame<'_>> {
+        let mut frames: Vec<Frame> = self
+            .max_data
+            .iter()
+            .cloned()
+            .map(Frame::StreamMaxData)
+            .collect();
+        if let Some(max_stream_id) = self.max_stream_id {
+            frames.push(Frame::ConnectionMaxStreamId(ConnectionMaxStreamIdFrame {
+                max_stream_id,
+            }));
+        }
+        frames.extend(self.data.iter().map(|(stream_id, offset, data)| {
+            Frame::StreamData(StreamDataFrame {
+                stream_id: *stream_id,
+                offset: *offset,
+                data: &data[..],
+            })
+        }));
+        frames.extend(self.backlogged_streams.iter().map(|stream_id| {
+            Frame::StreamClose(StreamCloseFrame {
+                stream_id: *stream_id,
+                code: ErrorCode::ApplicationError,
+                message: "Too much data sent back on the stream was not acknowledged",
+            })
+        }));
+        if let Some(code) = self.error {
+            let message = match code {
+                ErrorCode::StreamIdError => "Exceeded the connection's max stream ID",
+                _ => "Exceeded the stream's max data",
+            };
+            frames.push(Frame::ConnectionClose(ConnectionCloseFrame {
+                code,
+                message,
+            }));
+        }
+        frames
+    }
+}
+
+/// The state of the connections and streams data is sent on, shared by the clones
+/// of a [StreamReceiverService](./struct.StreamReceiverService.html)
+#[derive(Clone)]
+pub(crate) struct DataConnections {
+    connections: Arc<Mutex<HashMap<Address, ConnectionState>>>,
+    handler: Arc<dyn StreamDataHandler>,
+}
+
+impl DataConnections {
+    pub(crate) fn new(handler: Arc<dyn StreamDataHandler>) -> Self {
+        DataConnections {
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            handler,
+        }
+    }
+
+    /// Passes the data of the packet to the handler and returns the frames to respond with
+    pub(crate) async fn handle_packet(
+        &self,
+        connection: &Address,
+        packet: &StreamPacket,
+    ) -> DataResponse {
+        let received = self.receive(connection, packet);
+
+        for (stream_id, data, ticket) in received.data {
+            ticket.wait_turn().await;
+            let reply = self.handler.handle_data(connection, stream_id, data).await;
+            // Buffered before the turn ends, so the replies are sent in order as well
+            self.buffer_reply(connection, stream_id, &reply);
+        }
+        let backlogged_streams = received
+            .backlogged_streams
+            .iter()
+            .map(|(stream_id, _)| *stream_id)
+            .collect();
+        for (stream_id, ticket) in received
+            .closed_streams
+            .into_iter()
+            .chain(received.backlogged_streams)
+        {
+            ticket.wait_turn().await;
+            self.handler.stream_closed(connection, stream_id).await;
+        }
+        if received.connection_closed || received.error.is_some() {
+            for ticket in received.connection_tickets {
+                ticket.wait_turn().await;
+            }
+            self.handler.connection_closed(connection).await;
+            return DataResponse {
+                error: received.error,
+                ..Default::default()
+            };
+        }
+
+        let mut response = self.respond(connection);
+        response.max_data = received.max_data;
+        response.max_stream_id = received.max_stream_id;
+        response.backlogged_streams = backlogged_streams;
+        response
+    }
+
+    fn receive(&self, connection: &Address, packet: &StreamPacket) -> Received {
+        let mut connections = self.connections.lock().unwrap();
+        let has_data_frames = packet.frames().any(|frame| {
+            matches!(
+                frame,
+                Frame::StreamData(_) | Frame::StreamMaxData(_) | Frame::StreamDataBlocked(_)
+            )
+        });
+        // Only the connections that data is sent on are tracked
+        if !connections.contains_key(connection) {
+            if !has_data_frames {
+                return Received::default();
+            }
+            connections.retain(|_, state| state.last_packet.elapsed() < CONNECTION_IDLE_TIMEOUT);
+            connections.insert(
+                connection.clone(),
+                ConnectionState {
+                    streams: BTreeMap::new(),
+                    max_stream_id: 2 * MAX_OPEN_STREAMS,
+                    last_packet: Instant::now(),
+                },
+            );
+        }
+        let state = connections.get_mut(connection).unwrap();
+        state.last_packet = Instant::now();
+
+        let mut received = Received::default();
+        let mut readable: BTreeMap<u64, (BytesMut, Ticket)> = BTreeMap::new();
+        for frame in packet.frames() {
+            match frame {
+                Frame::StreamData(frame) => {
+                    let stream = match state.stream(frame.stream_id) {
+                        Some(stream) => stream,
+                        None => {
+                            debug!(
+                                "Closing connection {} because stream {} exceeded its max stream ID",
+                                connection, frame.stream_id
+                            );
+                            received.error = Some(ErrorCode::StreamIdError);
+                            break;
+                        }
+                    };
+                    // The handler is not passed more data while the sender does not read
+                    // what it responded with
+                    if stream.outgoing.len() >= MAX_UNACKED_REPLY_DATA {
+                        debug!(
+                            "Closing stream {} of connection {} because the data sent back on it is not acknowledged",
+                            frame.stream_id, connection
+                        );
+                        let stream = state.close_stream(frame.stream_id).unwrap();
+                        received
+                            .backlogged_streams
+                            .push((frame.stream_id, stream.turns.ticket()));
+                        continue;
+                    }
+                    let max_offset = stream.incoming.read_offset() + DEFAULT_MAX_BUFFERED_DATA;
+                    match stream
+                        .incoming
+                        .receive(frame.offset, frame.data, max_offset)
+                    {
+                        Ok(Some(data)) => readable
+                            .entry(frame.stream_id)
+                            .or_insert_with(|| (BytesMut::new(), stream.turns.ticket()))
+                            .0
+                            .extend_from_slice(&data),
+                        Ok(None) => {}
+                        Err(()) => {
+                            debug!(
+                                "Closing connection {} because stream {} exceeded its max data",
+                                connection, frame.stream_id
+                            );
+                            received.error = Some(ErrorCode::FlowControlError);
+                            break;
+                        }
+                    }
+                }
+                Frame::StreamMaxData(frame) => match state.stream(frame.stream_id) {
+                    Some(stream) => stream.set_remote_max_offset(frame.max_offset),
+                    None => {
+                        debug!(
+                            "Closing connection {} because stream {} exceeded its max stream ID",
+                            connection, frame.stream_id
+                        );
+                        received.error = Some(ErrorCode::StreamIdError);
+                        break;
+                    }
+                },
+                Frame::StreamClose(frame) => {
+                    if let Some(stream) = state.close_stream(frame.stream_id) {
+                        received
+                            .closed_streams
+                            .push((frame.stream_id, stream.turns.ticket()));
+                    }
+                }
+                Frame::ConnectionClose(_) => received.connection_closed = true,
+                _ => {}
+            }
+        }
+        if received.error.is_some() || received.connection_closed {
+            if let Some(state) = connections.remove(connection) {
+                received.connection_tickets = state
+                    .streams
+                    .values()
+                    .map(|stream| stream.turns.ticket())
+                    .collect();
+            }
+        }
+
+        received.max_stream_id = connections.get(connection).map(|state| state.max_stream_id);
+
+        // Acknowledge the data by advertising how much more the sender can send
+        for frame in packet.frames() {
+            if let Frame::StreamData(StreamDataFrame { stream_id, .. })
+            | Frame::StreamDataBlocked(StreamDataBlockedFrame { stream_id, .. }) = frame
+            {
+                if let Some(stream) = connections
+                    .get(connection)
+                    .and_then(|state| state.streams.get(&stream_id))
+                {
+                    if !received
+                        .max_data
+                        .iter()
+                        .any(|max_data| max_data.stream_id == stream_id)
+                    {
+                        received.max_data.push(stream.max_data(stream_id));
+                    }
+                }
+            }
+        }
+
+        // The data of streams which are closed by the same packet is still delivered
+        received.data = readable
+            .into_iter()
+            .map(|(stream_id, (data, ticket))| (stream_id, data.freeze(), ticket))
+            .collect();
+        received
+    }
+
+    fn buffer_reply(&self, connection: &Address, stream_id: u64, reply: &[u8]) {
+        if reply.is_empty() {
+            return;
+        }
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(stream) = connections
+            .get_mut(connection)
+            .and_then(|state| state.streams.get_mut(&stream_id))
+        {
+            stream.outgoing.extend_from_slice(reply);
+        }
+    }
+
+    /// Takes as much of the buffered data as the sender can receive. The data is kept
+    /// until the sender acknowledges it, and sent again if it does not do so in time
+    fn respond(&self, connection: &Address) -> DataResponse {
+        let mut connections = self.connections.lock().unwrap();
+        let mut response = DataResponse::default();
+        let state = match connections.get_mut(connection) {
+            Some(state) => state,
+            None => return response,
+        };
+
+        let mut budget = MAX_RESPONSE_DATA;
+        for (stream_id, stream) in state.streams.iter_mut() {
+            if matches!(stream.unacked_since, Some(sent) if sent.elapsed() >= RETRANSMIT_TIMEOUT) {
+                stream.write_offset = stream.acked_offset;
+                stream.unacked_since = None;
+            }
+            let start = (stream.write_offset - stream.acked_offset) as usize;
+            let window = stream
+                .remote_max_offset
+                .unwrap_or(DEFAULT_MAX_BUFFERED_DATA)
+                .saturating_sub(stream.write_offset);
+            let len = min(min(stream.outgoing.len() - start, budget) as u64, window) as usize;
+            if len == 0 {
+                continue;
+            }
+            let data = Bytes::copy_from_slice(&stream.outgoing[start..start + len]);
+            response.data.push((*stream_id, stream.write_offset, data));
+            stream.write_offset += len as u64;
+            stream.unacked_since.get_or_insert_with(Instant::now);
+            budget -= len;
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger_packet::PacketType as IlpPacketType;
+    use std::str::FromStr;
+
+    struct Echo {
+        received: Mutex<Vec<Bytes>>,
+        closed: Mutex<Vec<u64>>,
+    }
+
+    #[async_trait]
+    impl StreamDataHandler for Echo {
+        async fn handle_data(&self, _connection: &Address, _stream_id: u64, data: Bytes) -> Bytes {
+            if data.starts_with(b"slow") {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            self.received.lock().unwrap().push(data.clone());
+            data
+        }
+
+        async fn stream_closed(&self, _connection: &Address, stream_id: u64) {
+            self.closed.lock().unwrap().push(stream_id);
+        }
+    }
+
+    fn connections() -> (DataConnections, Arc<Echo>) {
+        let echo = Arc::new(Echo {
+            received: Mutex::new(Vec::new()),
+            closed: Mutex::new(Vec::new()),
+        });
+        (DataConnections::new(echo.clone()), echo)
+    }
+
+    fn packet(frames: &[Frame]) -> StreamPacket {
+        StreamPacketBuilder {
+            sequence: 1,
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 0,
+            frames,
+        }
+        .build()
+    }
+
+    fn data(stream_id: u64, offset: u64, data: &[u8]) -> Frame<'_> {
+        Frame::StreamData(StreamDataFrame {
+            stream_id,
+            offset,
+            data,
+        })
+    }
+
+    #[tokio::test]
+    async fn responds_with_data_and_max_data() {
+        let (connections, _) = connections();
+        let connection = Address::from_str("example.receiver.connection").unwrap();
+
+        let response = connections
+            .handle_packet(&connection, &packet(&[data(1, 0, b"hello")]))
+            .await;
+        assert_eq!(
+            response.frames(),
+            vec![
+                Frame::StreamMaxData(StreamMaxDataFrame {
+                    stream_id: 1,
+                    max_offset: 5 + DEFAULT_MAX_BUFFERED_DATA,
+                }),
+                Frame::ConnectionMaxStreamId(ConnectionMaxStreamIdFrame {
+                    max_stream_id: 2 * MAX_OPEN_STREAMS,
+                }),
+                data(1, 0, b"hello"),
+            ]
+        );
+
+        // Retransmitted data is acknowledged again but not passed to the handler twice
+        let response = connections
+            .handle_packet(&connection, &packet(&[data(1, 0, b"hello")]))
+            .await;
+        assert_eq!(
+            response.frames(),
+            vec![
+                Frame::StreamMaxData(StreamMaxDataFrame {
+                    stream_id: 1,
+                    max_offset: 5 + DEFAULT_MAX_BUFFERED_DATA,
+                }),
+                Frame::ConnectionMaxStreamId(ConnectionMaxStreamIdFrame {
+                    max_stream_id: 2 * MAX_OPEN_STREAMS,
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reassembles_data_received_out_of_order() {
+        let (connections, _) = connections();
+        let connection = Address::from_str("example.receiver.connection").unwrap();
+
+        let response = connections
+            .handle_packet(&connection, &packet(&[data(1, 5, b" world")]))
+            .await;
+        assert!(response.data.is_empty());
+        assert_eq!(response.max_data[0].max_offset, DEFAULT_MAX_BUFFERED_DATA,);
+
+        let response = connections
+            .handle_packet(&connection, &packet(&[data(1, 0, b"hello")]))
+            .await;
+        assert_eq!(response.data, vec![(1, 0, Bytes::from("hello world"))]);
+    }
+
+    #[tokio::test]
+    async fn respects_the_senders_max_data() {
+        let (connections, _) = connections();
+        let connection = Address::from_str("example.receiver.connection").unwrap();
+
+        let response = connections
+            .handle_packet(
+                &connection,
+                &packet(&[
+                    Frame::StreamMaxData(StreamMaxDataFrame {
+                        stream_id: 1,
+                        max_offset: 3,
+                    }),
+                    data(1, 0, b"hello"),
+                ]),
+            )
+            .await;
+        assert_eq!(response.data, vec![(1, 0, Bytes::from("hel"))]);
+
+        // The rest is sent once the sender raises its limit
+        let response = connections
+            .handle_packet(
+                &connection,
+                &packet(&[Frame::StreamMaxData(StreamMaxDataFrame {
+                    stream_id: 1,
+                    max_offset: 100,
+                })]),
+            )
+            .await;
+        assert_eq!(response.data, vec![(1, 3, Bytes::from("lo"))]);
+    }
+
+    #[tokio::test]
+    async fn closes_the_connection_if_the_sender_exceeds_the_max_data() {
+        let (connections, _) = connections();
+        let connection = Address::from_str("example.receiver.connection").unwrap();
+        let too_much = vec![0; DEFAULT_MAX_BUFFERED_DATA as usize + 1];
+
+        let response = connections
+            .handle_packet(&connection, &packet(&[data(1, 0, &too_much)]))
+            .await;
+        assert_eq!(
+            response.frames(),
+            vec![Frame::ConnectionClose(ConnectionCloseFrame {
+                code: ErrorCode::FlowControlError,
+                message: "Exceeded the stream's max data",
+            })]
+        );
+        assert!(connections.connections.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn closes_the_connection_if_the_sender_exceeds_the_max_stream_id() {
+        let (connections, _) = connections();
+        let connection = Address::from_str("example.receiver.connection").unwrap();
+
+        let response = connections
+            .handle_packet(
+                &connection,
+                &packet(&[Frame::StreamMaxData(StreamMaxDataFrame {
+                    stream_id: 2 * MAX_OPEN_STREAMS + 1,
+                    max_offset: 100,
+                })]),
+            )
+            .await;
+        assert_eq!(
+            response.frames(),
+            vec![Frame::ConnectionClose(ConnectionCloseFrame {
+                code: ErrorCode::StreamIdError,
+                message: "Exceeded the connection's max stream ID",
+            })]
+        );
+        assert!(connections.connections.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn raises_the_max_stream_id_when_streams_are_closed() {
+        let (connections, _) = connections();
+        let connection = Address::from_str("example.receiver.connection").unwrap();
+
+        let response = connections
+            .handle_packet(
+                &connection,
+                &packet(&[
+                    data(1, 0, b"hello"),
+                    Frame::StreamClose(StreamCloseFrame {
+                        stream_id: 1,
+                        code: ErrorCode::NoError,
+                        message: "",
+                    }),
+                ]),
+            )
+            .await;
+        assert_eq!(response.max_stream_id, Some(2 * MAX_OPEN_STREAMS + 2));
+
+        let response = connections
+            .handle_packet(
+                &connection,
+                &packet(&[data(2 * MAX_OPEN_STREAMS + 1, 0, b"hi")]),
+            )
+            .await;
+        assert_eq!(
+            response.data,
+            vec![(2 * MAX_OPEN_STREAMS + 1, 0, Bytes::from("hi"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn closes_streams_whose_replies_are_not_acknowledged() {
+        let (connections, echo) = connections();
+        let connection = Address::from_str("example.receiver.connection").unwrap();
+        let chunk = vec![0; DEFAULT_MAX_BUFFERED_DATA as usize];
+
+        // The sender reads the data it sends but never raises its max data for the replies
+        let mut offset = 0;
+        while offset < MAX_UNACKED_REPLY_DATA as u64 {
+            connections
+                .handle_packet(&connection, &packet(&[data(1, offset, &chunk)]))
+                .await;
+            offset += chunk.len() as u64;
+        }
+        assert_eq!(
+            echo.received.lock().unwrap().len(),
+            offset as usize / chunk.len()
+        );
+
+        let response = connections
+            .handle_packet(&connection, &packet(&[data(1, offset, &chunk)]))
+            .await;
+        assert_eq!(
+            response.frames(),
+            vec![
+                Frame::ConnectionMaxStreamId(ConnectionMaxStreamIdFrame {
+                    max_stream_id: 2 * MAX_OPEN_STREAMS + 2,
+                }),
+                Frame::StreamClose(StreamCloseFrame {
+                    stream_id: 1,
+                    code: ErrorCode::ApplicationError,
+                    message: "Too much data sent back on the stream was not acknowledged",
+                }),
+            ]
+        );
+        assert_eq!(
+            echo.received.lock().unwrap().len(),
+            offset as usize / chunk.len()
+        );
+        assert_eq!(*echo.closed.lock().unwrap(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn notifies_the_handler_of_closed_streams() {
+        let (connections, echo) = connections();
+        let connection = Address::from_str("example.receiver.connection").unwrap();
+
+        let response = connections
+            .handle_packet(
+                &connection,
+                &packet(&[
+                    data(1, 0, b"hello"),
+                    Frame::StreamClose(StreamCloseFrame {
+                        stream_id: 1,
+                        code: ErrorCode::NoError,
+                        message: "",
+                    }),
+                ]),
+            )
+            .await;
+        assert!(response.data.is_empty());
+        assert_eq!(*echo.closed.lock().unwrap(), vec![1]);
+
+        // Connections without data frames are not tracked
+        let response = connections
+            .handle_packet(
+                &Address::from_str("example.receiver.other").unwrap(),
+                &packet(&[Frame::StreamMoney(StreamMoneyFrame {
+                    stream_id: 1,
+                    shares: 1,
+                })]),
+            )
+            .await;
+        assert!(response.frames().is_empty());
+        assert_eq!(connections.connections.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn passes_the_data_of_concurrent_packets_in_order() {
+        let (connections, echo) = connections();
+        let connection = Address::from_str("example.receiver.connection").unwrap();
+
+        let first = packet(&[data(1, 0, b"slow")]);
+        let second = packet(&[data(1, 4, b"fast")]);
+        let (first, second) = tokio::join!(
+            connections.handle_packet(&connection, &first),
+            connections.handle_packet(&connection, &second),
+        );
+        assert_eq!(
+            *echo.received.lock().unwrap(),
+            vec![Bytes::from("slow"), Bytes::from("fast")]
+        );
+        // The replies are sent back in order as well, in whichever response goes out first
+        let mut replies: Vec<_> = first.data.into_iter().chain(second.data).collect();
+        replies.sort_by_key(|(_, offset, _)| *offset);
+        let replies: Vec<u8> = replies
+            .into_iter()
+            .flat_map(|(_, _, data)| data.to_vec())
+            .collect();
+        assert_eq!(replies, b"slowfast");
+    }
+
+    #[tokio::test]
+    async fn resends_data_the_sender_did_not_acknowledge() {
+        let (connections, _) = connections();
+        let connection = Address::from_str("example.receiver.connection").unwrap();
+        let max_data = |max_offset| {
+            Frame::StreamMaxData(StreamMaxDataFrame {
+                stream_id: 1,
+                max_offset,
+            })
+        };
+        let expire_unacked_data = || {
+            let mut connections = connections.connections.lock().unwrap();
+            let stream = connections
+                .get_mut(&connection)
+                .unwrap()
+                .streams
+                .get_mut(&1)
+                .unwrap();
+            stream.unacked_since = Some(Instant::now() - RETRANSMIT_TIMEOUT);
+        };
+
+        let response = connections
+            .handle_packet(
+                &connection,
+                &packet(&[max_data(DEFAULT_MAX_BUFFERED_DATA), data(1, 0, b"hello")]),
+            )
+            .await;
+        assert_eq!(response.data, vec![(1, 0, Bytes::from("hello"))]);
+
+        // The response was lost, so the sender still has not read anything
+        let response = connections
+            .handle_packet(&connection, &packet(&[max_data(DEFAULT_MAX_BUFFERED_DATA)]))
+            .await;
+        assert!(response.data.is_empty());
+        expire_unacked_data();
+        let response = connections
+            .handle_packet(&connection, &packet(&[max_data(DEFAULT_MAX_BUFFERED_DATA)]))
+            .await;
+        assert_eq!(response.data, vec![(1, 0, Bytes::from("hello"))]);
+
+        // Once the sender read the data it is not sent again
+        let response = connections
+            .handle_packet(
+                &connection,
+                &packet(&[max_data(5 + DEFAULT_MAX_BUFFERED_DATA)]),
+            )
+            .await;
+        assert!(response.data.is_empty());
+        expire_unacked_data();
+        let response = connections
+            .handle_packet(
+                &connection,
+                &packet(&[max_data(5 + DEFAULT_MAX_BUFFERED_DATA)]),
+            )
+            .await;
+        assert!(response.data.is_empty());
+    }
+}
@@ -14,6 +14,18 @@ pub enum Error {
         "Error maximum time exceeded: Time since last fulfill exceeded the maximum time limit"
     )]
     Timeout,
+    #[error("Delivering the destination amount would exceed the maximum source amount ({0} delivered for {1})")]
+    MaxSourceAmountExceeded(u64, u64),
+    #[error("Unable to determine the exchange rate to the recipient")]
+    RateProbeFailed,
+    #[error("The quoted destination amount does not fit in a 64-bit amount")]
+    QuoteOverflow,
+    #[error("The STREAM connection is closed")]
+    ConnectionClosed,
+    #[error("Stream {0} is closed")]
+    StreamClosed(u64),
+    #[error("Invalid congestion control parameters: {0}")]
+    InvalidCongestionStrategy(&'static str),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -41,3 +53,15 @@ pub enum StreamPacketError {
     )]
     NonRoundtrippableSaturatingAmount,
 }
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReceiptError {
+    #[error("Unsupported receipt version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("Invalid receipt: {0}")]
+    Oer(#[from] OerError),
+    #[error("Invalid receipt: unexpected trailing bytes")]
+    TrailingBytes,
+    #[error("Invalid receipt: the HMAC does not match the receipt secret")]
+    InvalidHmac,
+}
@@ -6,19 +6,35 @@
 
 /// Stream client
 mod client;
-/// Congestion controller consumed by the [stream client](./client/fn.send_money.html)
+/// Congestion control strategies consumed by the [stream client](./client/fn.send_money.html)
 mod congestion;
+/// Stream connections which send money and data on multiple streams
+mod connection;
 /// Cryptographic utilities for generating fulfillments and encrypting/decrypting STREAM packets
 mod crypto;
+/// Stateful handling of the data sent on STREAM connections, used by the [stream server](./server/struct.StreamReceiverService.html)
+mod data;
 /// Stream errors
 mod error;
 /// Stream Packet implementation, [as specified in the RFC](https://interledger.org/rfcs/0029-stream/#5-packet-and-frame-specification)
 mod packet;
+/// STREAM receipts proving the amount received on a stream, [as specified in the RFC](https://interledger.org/rfcs/0039-stream-receipts/)
+mod receipt;
 /// A stream server implementing an [Outgoing Service](../interledger_service/trait.OutgoingService.html) for receiving STREAM payments from peers
 mod server;
 
-pub use client::{send_money, StreamDelivery};
-pub use error::{Error, StreamPacketError};
+pub use client::{
+    quote, quote_with_congestion_controller, send_money, send_money_fixed_delivery, StreamDelivery,
+    StreamQuote,
+};
+pub use congestion::{
+    AimdCongestionController, CongestionController, CongestionStrategy,
+    LatencyCongestionController, MaxPacketCongestionController,
+};
+pub use connection::{ConnectionEvent, DataAndMoneyStream, StreamConnection};
+pub use data::{StreamDataHandler, DEFAULT_MAX_BUFFERED_DATA};
+pub use error::{Error, ReceiptError, StreamPacketError};
+pub use receipt::{Receipt, ReceiptDetails};
 pub use server::{
     ConnectionGenerator, PaymentNotification, StreamNotificationsStore, StreamReceiverService,
 };
@@ -43,12 +59,16 @@ pub fn fuzz_decrypted_stream_packet(data: &[u8]) {
 pub mod test_helpers {
     use super::*;
     use async_trait::async_trait;
+    use bytes::Bytes;
     use futures::channel::mpsc::UnboundedSender;
     use interledger_errors::{AccountStoreError, AddressStoreError, ExchangeRateStoreError};
-    use interledger_packet::Address;
+    use interledger_packet::{Address, ErrorCode, RejectBuilder};
     use interledger_rates::ExchangeRateStore;
-    use interledger_router::RouterStore;
-    use interledger_service::{Account, AccountStore, AddressStore, Username};
+    use interledger_router::{RouterStore, RoutingTable};
+    use interledger_service::{
+        outgoing_service_fn, Account, AccountStore, AddressStore, IlpResult, IncomingRequest,
+        IncomingService, OutgoingRequest, OutgoingService, Username,
+    };
     use interledger_service_util::MaxPacketAmountAccount;
     use once_cell::sync::Lazy;
     use std::collections::HashMap;
@@ -148,7 +168,7 @@ pub mod test_helpers {
     }
 
     impl RouterStore for TestStore {
-        fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
+        fn routing_table(&self) -> Arc<RoutingTable> {
             Arc::new(
                 vec![(
                     self.route.clone().unwrap().0,
@@ -200,6 +220,89 @@ pub mod test_helpers {
             unimplemented!("Cannot get all exchange rates")
         }
     }
+
+    /// Converts the amounts of the Prepares at the given rate before passing them
+    /// straight to a STREAM receiver
+    #[derive(Clone)]
+    pub struct ToReceiver<O: OutgoingService<TestAccount>> {
+        /// Destination units per 1000 source units
+        pub rate: u64,
+        pub receiver: StreamReceiverService<DummyStore, O, TestAccount>,
+        pub to: TestAccount,
+    }
+
+    #[async_trait]
+    impl<O> IncomingService<TestAccount> for ToReceiver<O>
+    where
+        O: OutgoingService<TestAccount> + Clone + Send + Sync + 'static,
+    {
+        async fn handle_request(&mut self, mut request: IncomingRequest<TestAccount>) -> IlpResult {
+            let amount = request.prepare.amount() * self.rate / 1000;
+            request.prepare.set_amount(amount);
+            self.receiver
+                .send_request(OutgoingRequest {
+                    from: request.from,
+                    to: self.to.clone(),
+                    original_amount: amount,
+                    prepare: request.prepare,
+                })
+                .await
+        }
+    }
+
+    /// A STREAM receiver at `example.receiver` and a connection to it
+    pub struct TestReceiver<O: OutgoingService<TestAccount>> {
+        pub service: ToReceiver<O>,
+        /// The receiver's account, which has the max packet amount the receiver was created with
+        pub account: TestAccount,
+        pub destination_account: Address,
+        pub shared_secret: Vec<u8>,
+    }
+
+    /// Creates a STREAM receiver which gets the Prepares sent to it exchanged at the
+    /// given rate, in destination units per 1000 source units
+    pub fn test_receiver(
+        rate: u64,
+        max_packet_amount: Option<u64>,
+        data_handler: Option<Arc<dyn StreamDataHandler>>,
+    ) -> TestReceiver<impl OutgoingService<TestAccount> + Clone + Send + Sync + 'static> {
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: EXAMPLE_RECEIVER.clone(),
+            max_packet_amount,
+        };
+        let (destination_account, shared_secret) = ConnectionGenerator::new(server_secret.clone())
+            .generate_address_and_secret(&account.ilp_address);
+        // All of the Prepares are sent to the receiver's connections
+        let next = outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> IlpResult {
+            Err(RejectBuilder {
+                code: ErrorCode::F02_UNREACHABLE,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build())
+        });
+        let receiver = match data_handler {
+            Some(handler) => {
+                StreamReceiverService::with_data_handler(server_secret, DummyStore, next, handler)
+            }
+            None => StreamReceiverService::new(server_secret, DummyStore, next),
+        };
+        TestReceiver {
+            service: ToReceiver {
+                rate,
+                receiver,
+                to: account.clone(),
+            },
+            account,
+            destination_account,
+            shared_secret: shared_secret.to_vec(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -269,6 +372,7 @@ mod send_money_to_receiver {
             shared_secret.to_vec(),
             100,
             0.0,
+            CongestionStrategy::default(),
         )
         .await
         .unwrap();
@@ -333,6 +437,7 @@ mod send_money_to_receiver {
             shared_secret.to_vec(),
             1000,
             0.014,
+            CongestionStrategy::default(),
         )
         .await;
 
@@ -0,0 +1,296 @@
+use super::crypto::{hmac_sha256, verify_hmac_sha256};
+use super::error::ReceiptError;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use interledger_packet::{
+    oer::{BufOerExt, MutBufOerExt},
+    Address, OerError,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The version of the receipt format, [as defined in the RFC](https://interledger.org/rfcs/0039-stream-receipts/#specification)
+const RECEIPT_VERSION: u8 = 1;
+/// Length of the HMAC which authenticates a receipt
+const RECEIPT_HMAC_LENGTH: usize = 32;
+/// The received totals of connections which have not received a packet for this long are dropped.
+/// Connections whose totals were dropped get no more receipts, because a receipt for a
+/// smaller total than an earlier one is useless to the verifier
+const RECEIPT_TOTALS_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// The nonce and secret a receiver uses for the receipts of a STREAM connection.
+///
+/// These are provided by the party which verifies the receipts, for example
+/// in the `Receipt-Nonce` and `Receipt-Secret` headers of an SPSP query.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ReceiptDetails {
+    /// Identifies the connection in the receipts it issues
+    pub nonce: [u8; 16],
+    /// Key of the HMAC which authenticates the receipts
+    pub secret: [u8; 32],
+}
+
+/// A proof of the total amount received on a STREAM, [as specified in the RFC](https://interledger.org/rfcs/0039-stream-receipts/)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Receipt {
+    /// The nonce of the connection the money was received on
+    pub nonce: [u8; 16],
+    /// Identifier of the stream the money was received on
+    pub stream_id: u64,
+    /// The total amount received on the stream, in the receiver's units
+    pub total_received: u64,
+}
+
+impl Receipt {
+    /// Serializes the receipt and authenticates it with the given receipt secret
+    pub fn to_bytes(&self, secret: &[u8]) -> Bytes {
+        let mut buffer = BytesMut::with_capacity(60);
+        buffer.put_u8(RECEIPT_VERSION);
+        buffer.put_slice(&self.nonce[..]);
+        buffer.put_var_uint(self.stream_id);
+        buffer.put_u64(self.total_received);
+        let hmac = hmac_sha256(secret, &buffer[..]);
+        buffer.put_slice(&hmac[..]);
+        buffer.freeze()
+    }
+
+    /// Parses a receipt and verifies it was issued with the given receipt secret
+    pub fn verify(bytes: &[u8], secret: &[u8]) -> Result<Self, ReceiptError> {
+        if bytes.len() < RECEIPT_HMAC_LENGTH {
+            return Err(OerError::UnexpectedEof.into());
+        }
+        let (contents, hmac) = bytes.split_at(bytes.len() - RECEIPT_HMAC_LENGTH);
+
+        let mut reader = contents;
+        if reader.len() < 1 + 16 {
+            return Err(OerError::UnexpectedEof.into());
+        }
+        let version = reader.get_u8();
+        if version != RECEIPT_VERSION {
+            return Err(ReceiptError::UnsupportedVersion(version));
+        }
+        let mut nonce = [0; 16];
+        reader.copy_to_slice(&mut nonce[..]);
+        let stream_id = reader.read_var_uint()?;
+        if reader.len() < 8 {
+            return Err(OerError::UnexpectedEof.into());
+        }
+        let total_received = reader.get_u64();
+        if !reader.is_empty() {
+            return Err(ReceiptError::TrailingBytes);
+        }
+
+        if !verify_hmac_sha256(secret, contents, hmac) {
+            return Err(ReceiptError::InvalidHmac);
+        }
+
+        Ok(Receipt {
+            nonce,
+            stream_id,
+            total_received,
+        })
+    }
+}
+
+/// The total amount received on each stream of the connections which get receipts,
+/// shared by the clones of a [StreamReceiverService](./struct.StreamReceiverService.html).
+///
+/// The totals are only known for connections this receiver saw from their first packet.
+/// Packets of other connections (for example ones which started before a restart, on
+/// another node, or whose totals were dropped after being idle) get no receipts at all,
+/// instead of receipts for totals which restart at 0.
+#[derive(Clone, Default)]
+pub(crate) struct ReceiptTotals {
+    connections: Arc<Mutex<HashMap<Address, ConnectionTotals>>>,
+}
+
+struct ConnectionTotals {
+    /// `None` if the connection was not seen from its first packet,
+    /// so the totals received on its streams are unknown
+    streams: Option<HashMap<u64, u64>>,
+    last_packet: Instant,
+}
+
+impl ReceiptTotals {
+    /// Adds the amounts received on each stream in the packet with the given sequence and
+    /// returns the new totals of those streams, or `None` if the connection's totals are unknown
+    fn add(
+        &self,
+        connection: &Address,
+        sequence: u64,
+        amounts: &[(u64, u64)],
+    ) -> Option<Vec<(u64, u64)>> {
+        let mut connections = self.connections.lock().unwrap();
+        if !connections.contains_key(connection) {
+            connections
+                .retain(|_, state| state.last_packet.elapsed() < RECEIPT_TOTALS_IDLE_TIMEOUT);
+        }
+        let state = connections
+            .entry(connection.clone())
+            .or_insert_with(|| ConnectionTotals {
+                streams: if sequence == 1 {
+                    Some(HashMap::new())
+                } else {
+                    None
+                },
+                last_packet: Instant::now(),
+            });
+        state.last_packet = Instant::now();
+
+        let streams = state.streams.as_mut()?;
+        Some(
+            amounts
+                .iter()
+                .map(|(stream_id, amount)| {
+                    let total = streams.entry(*stream_id).or_insert(0);
+                    *total = total.saturating_add(*amount);
+                    (*stream_id, *total)
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Issues the receipts of a single connection
+pub(crate) struct ReceiptIssuer<'a> {
+    pub(crate) details: ReceiptDetails,
+    pub(crate) totals: &'a ReceiptTotals,
+    pub(crate) connection: &'a Address,
+}
+
+impl<'a> ReceiptIssuer<'a> {
+    /// Records the amounts received on each stream in the packet with the given sequence
+    /// and returns the receipts for the new totals of those streams.
+    ///
+    /// Every authentic packet of the connection should be recorded, even if it carries no
+    /// money, so the issuer knows whether it saw the connection from its first packet.
+    pub(crate) fn issue(&self, sequence: u64, amounts: &[(u64, u64)]) -> Vec<(u64, Bytes)> {
+        let totals = match self.totals.add(self.connection, sequence, amounts) {
+            Some(totals) => totals,
+            None => return Vec::new(),
+        };
+        totals
+            .into_iter()
+            .map(|(stream_id, total_received)| {
+                let receipt = Receipt {
+                    nonce: self.details.nonce,
+                    stream_id,
+                    total_received,
+                }
+                .to_bytes(&self.details.secret[..]);
+                (stream_id, receipt)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const SECRET: [u8; 32] = [7; 32];
+
+    fn receipt() -> Receipt {
+        Receipt {
+            nonce: [1; 16],
+            stream_id: 1,
+            total_received: 500,
+        }
+    }
+
+    #[test]
+    fn roundtrips_receipts() {
+        let bytes = receipt().to_bytes(&SECRET[..]);
+        assert_eq!(bytes.len(), 1 + 16 + 2 + 8 + 32);
+        assert_eq!(Receipt::verify(&bytes, &SECRET[..]).unwrap(), receipt());
+    }
+
+    #[test]
+    fn rejects_receipts_with_the_wrong_secret() {
+        let bytes = receipt().to_bytes(&SECRET[..]);
+        assert!(matches!(
+            Receipt::verify(&bytes, &[8; 32][..]),
+            Err(ReceiptError::InvalidHmac)
+        ));
+    }
+
+    #[test]
+    fn rejects_modified_receipts() {
+        let mut bytes = BytesMut::from(&receipt().to_bytes(&SECRET[..])[..]);
+        bytes[20] ^= 1;
+        assert!(matches!(
+            Receipt::verify(&bytes, &SECRET[..]),
+            Err(ReceiptError::InvalidHmac)
+        ));
+
+        bytes[0] = 2;
+        assert!(matches!(
+            Receipt::verify(&bytes, &SECRET[..]),
+            Err(ReceiptError::UnsupportedVersion(2))
+        ));
+
+        assert!(Receipt::verify(&bytes[..40], &SECRET[..]).is_err());
+    }
+
+    fn issuer<'a>(totals: &'a ReceiptTotals, connection: &'a Address) -> ReceiptIssuer<'a> {
+        ReceiptIssuer {
+            details: ReceiptDetails {
+                nonce: [1; 16],
+                secret: SECRET,
+            },
+            totals,
+            connection,
+        }
+    }
+
+    #[test]
+    fn issues_receipts_for_the_total_received_on_each_stream() {
+        let totals = ReceiptTotals::default();
+        let connection = Address::from_str("example.receiver.connection").unwrap();
+        let issuer = issuer(&totals, &connection);
+
+        assert!(issuer.issue(1, &[]).is_empty());
+        assert_eq!(issuer.issue(2, &[(1, 100), (3, 10)]).len(), 2);
+        let receipts = issuer.issue(3, &[(1, 50)]);
+        assert_eq!(receipts.len(), 1);
+        let receipt = Receipt::verify(&receipts[0].1, &SECRET[..]).unwrap();
+        assert_eq!(receipt.stream_id, 1);
+        assert_eq!(receipt.total_received, 150);
+        assert_eq!(receipt.nonce, [1; 16]);
+    }
+
+    #[test]
+    fn issues_no_receipts_for_connections_not_seen_from_the_first_packet() {
+        let totals = ReceiptTotals::default();
+        let connection = Address::from_str("example.receiver.connection").unwrap();
+        let issuer = issuer(&totals, &connection);
+
+        assert!(issuer.issue(5, &[(1, 100)]).is_empty());
+        // A late first packet does not make the earlier amounts known
+        assert!(issuer.issue(1, &[(1, 100)]).is_empty());
+        assert!(issuer.issue(6, &[(1, 100)]).is_empty());
+    }
+
+    #[test]
+    fn issues_no_receipts_once_the_totals_were_dropped() {
+        let totals = ReceiptTotals::default();
+        let connection = Address::from_str("example.receiver.connection").unwrap();
+        let issuer = issuer(&totals, &connection);
+        assert_eq!(issuer.issue(1, &[(1, 100)]).len(), 1);
+
+        // Let the connection go idle, then get a packet of another connection so it is dropped
+        totals
+            .connections
+            .lock()
+            .unwrap()
+            .get_mut(&connection)
+            .unwrap()
+            .last_packet -= RECEIPT_TOTALS_IDLE_TIMEOUT;
+        let other = Address::from_str("example.receiver.other").unwrap();
+        totals.add(&other, 1, &[]);
+        assert!(!totals.connections.lock().unwrap().contains_key(&connection));
+
+        assert!(issuer.issue(2, &[(1, 100)]).is_empty());
+    }
+}
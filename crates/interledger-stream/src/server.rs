@@ -1,5 +1,7 @@
 use super::crypto::*;
+use super::data::{DataConnections, StreamDataHandler};
 use super::packet::*;
+use super::receipt::{ReceiptDetails, ReceiptIssuer, ReceiptTotals};
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 use chrono::{DateTime, Utc};
@@ -11,6 +13,7 @@ use interledger_packet::{
 use interledger_service::{Account, IlpResult, OutgoingRequest, OutgoingService, Username};
 use serde::{Deserialize, Serialize};
 use std::marker::PhantomData;
+use std::sync::Arc;
 use std::time::SystemTime;
 use tokio::sync::broadcast;
 use tracing::debug;
@@ -24,6 +27,10 @@ use uuid::Uuid;
 // running the same STREAM implementation so it doesn't matter what
 // this string is.
 const STREAM_SERVER_SECRET_GENERATOR: &[u8] = b"ilp_stream_shared_secret";
+/// Protocol specific string for generating the key which encrypts the receipt details in tokens
+const RECEIPT_DETAILS_KEY_GENERATOR: &[u8] = b"ilp_stream_receipt_details";
+/// Length of the random part of the tokens
+const TOKEN_LENGTH: usize = 18;
 
 /// A STREAM connection generator that creates `destination_account` and `shared_secret` values
 /// based on a single root secret.
@@ -33,6 +40,7 @@ const STREAM_SERVER_SECRET_GENERATOR: &[u8] = b"ilp_stream_shared_secret";
 #[derive(Clone)]
 pub struct ConnectionGenerator {
     secret_generator: [u8; 32],
+    receipt_details_key: [u8; 32],
 }
 
 impl ConnectionGenerator {
@@ -40,9 +48,11 @@ impl ConnectionGenerator {
         assert_eq!(server_secret.len(), 32, "Server secret must be 32 bytes");
 
         let secret = hmac_sha256(&server_secret[..], STREAM_SERVER_SECRET_GENERATOR);
+        let receipt_details_key = hmac_sha256(&server_secret[..], RECEIPT_DETAILS_KEY_GENERATOR);
 
         ConnectionGenerator {
             secret_generator: secret,
+            receipt_details_key,
         }
     }
 
@@ -51,7 +61,38 @@ impl ConnectionGenerator {
     /// The `destination_account` is generated such that the `shared_secret` can be re-derived
     /// from a Prepare packet's destination and the same server secret.
     pub fn generate_address_and_secret(&self, base_address: &Address) -> (Address, [u8; 32]) {
-        let token = base64::encode_config(&generate_token(), base64::URL_SAFE_NO_PAD);
+        self.generate_address_with_token(base_address, &generate_token()[..])
+    }
+
+    /// Like [`generate_address_and_secret`](#method.generate_address_and_secret), but the
+    /// receiver also sends [STREAM receipts](./struct.Receipt.html) for the money it receives
+    /// on the connection. Receipts are only sent while the receiver knows the totals received
+    /// on the connection, which requires it to have seen the connection's first packet.
+    ///
+    /// The receipt details are encrypted with a key derived from the server secret and
+    /// appended to the token, so they can be recovered from the `destination_account` with
+    /// [`rederive_receipt_details`](#method.rederive_receipt_details).
+    pub fn generate_address_and_secret_with_receipts(
+        &self,
+        base_address: &Address,
+        receipt_details: &ReceiptDetails,
+    ) -> (Address, [u8; 32]) {
+        let mut plaintext = BytesMut::with_capacity(48);
+        plaintext.extend_from_slice(&receipt_details.nonce[..]);
+        plaintext.extend_from_slice(&receipt_details.secret[..]);
+        let encrypted_details = encrypt(&self.receipt_details_key[..], plaintext);
+
+        let mut token = BytesMut::from(&generate_token()[..]);
+        token.unsplit(encrypted_details);
+        self.generate_address_with_token(base_address, &token[..])
+    }
+
+    fn generate_address_with_token(
+        &self,
+        base_address: &Address,
+        token: &[u8],
+    ) -> (Address, [u8; 32]) {
+        let token = base64::encode_config(token, base64::URL_SAFE_NO_PAD);
         // Note the shared secret is generated from the base64-encoded version of the token,
         // rather than from the unencoded bytes
         let shared_secret = hmac_sha256(&self.secret_generator[..], token.as_bytes());
@@ -82,6 +123,35 @@ impl ConnectionGenerator {
         // rather than decoding the base64 first.
         hmac_sha256(&self.secret_generator[..], local_part.as_bytes())
     }
+
+    /// Recover the receipt details from a `destination_account` generated by
+    /// [`generate_address_and_secret_with_receipts`](#method.generate_address_and_secret_with_receipts).
+    ///
+    /// Returns `None` if the connection does not get receipts.
+    pub fn rederive_receipt_details(
+        &self,
+        destination_account: &Address,
+    ) -> Option<ReceiptDetails> {
+        let local_part = destination_account.segments().next_back().unwrap();
+        let token = base64::decode_config(local_part, base64::URL_SAFE_NO_PAD).ok()?;
+        if token.len() <= TOKEN_LENGTH {
+            return None;
+        }
+        let details = decrypt(
+            &self.receipt_details_key[..],
+            BytesMut::from(&token[TOKEN_LENGTH..]),
+        )
+        .ok()?;
+        if details.len() != 48 {
+            return None;
+        }
+
+        let mut nonce = [0; 16];
+        nonce.copy_from_slice(&details[..16]);
+        let mut secret = [0; 32];
+        secret.copy_from_slice(&details[16..]);
+        Some(ReceiptDetails { nonce, secret })
+    }
 }
 
 /// Notification that STREAM fulfilled a packet and received a single Interledger payment, used by Pubsub API consumers
@@ -150,16 +220,19 @@ pub trait StreamNotificationsStore {
 
 /// An OutgoingService that fulfills incoming STREAM packets.
 ///
-/// Note this does **not** maintain STREAM state, but instead fulfills
-/// all incoming packets to collect the money.
+/// Note this does **not** maintain STREAM state for the money it receives, but instead
+/// fulfills all incoming packets to collect the money.
 ///
-/// This does not currently support handling data sent via STREAM.
+/// Data sent via STREAM is ignored, unless the service is created
+/// [with a data handler](#method.with_data_handler).
 #[derive(Clone)]
 pub struct StreamReceiverService<S, O: OutgoingService<A>, A: Account> {
     connection_generator: ConnectionGenerator,
     next: O,
     account_type: PhantomData<A>,
     store: S,
+    data_connections: Option<DataConnections>,
+    receipt_totals: ReceiptTotals,
 }
 
 impl<S, O, A> StreamReceiverService<S, O, A>
@@ -175,6 +248,25 @@ where
             next,
             account_type: PhantomData,
             store,
+            data_connections: None,
+            receipt_totals: ReceiptTotals::default(),
+        }
+    }
+
+    /// Creates a service which also tracks the connections and streams data is sent on.
+    ///
+    /// The data received on each stream is passed to the handler in order and acknowledged
+    /// with `StreamMaxData` frames. The data the handler responds with is sent back in the
+    /// Fulfill or Reject packets.
+    pub fn with_data_handler(
+        server_secret: Bytes,
+        store: S,
+        next: O,
+        handler: Arc<dyn StreamDataHandler>,
+    ) -> Self {
+        StreamReceiverService {
+            data_connections: Some(DataConnections::new(handler)),
+            ..Self::new(server_secret, store, next)
         }
     }
 }
@@ -200,13 +292,36 @@ where
         // The case where the request is bound for this server
         if dest.starts_with(to_address.as_ref()) {
             let shared_secret = self.connection_generator.rederive_secret(&destination);
-            let response = receive_money(
-                &shared_secret,
-                to_address,
-                request.to.asset_code(),
-                request.to.asset_scale(),
-                &request.prepare,
-            );
+            let receipts = self
+                .connection_generator
+                .rederive_receipt_details(&destination)
+                .map(|details| ReceiptIssuer {
+                    details,
+                    totals: &self.receipt_totals,
+                    connection: &destination,
+                });
+            let response = match self.data_connections {
+                Some(ref data_connections) => {
+                    receive_money_and_data(
+                        data_connections,
+                        &shared_secret,
+                        to_address,
+                        request.to.asset_code(),
+                        request.to.asset_scale(),
+                        &request.prepare,
+                        receipts.as_ref(),
+                    )
+                    .await
+                }
+                None => receive_money(
+                    &shared_secret,
+                    to_address,
+                    request.to.asset_code(),
+                    request.to.asset_scale(),
+                    &request.prepare,
+                    receipts.as_ref(),
+                ),
+            };
             match response {
                 Ok(ReceiveOk { fulfill, sequence }) => {
                     self.store
@@ -260,8 +375,6 @@ where
     }
 }
 
-// TODO send asset code and scale back to sender also
-#[allow(clippy::cognitive_complexity)]
 fn receive_money(
     shared_secret: &[u8; 32],
     // Our node's ILP Address ( we are the receiver, so we should return that
@@ -270,26 +383,93 @@ fn receive_money(
     asset_code: &str,
     asset_scale: u8,
     prepare: &Prepare,
+    receipts: Option<&ReceiptIssuer<'_>>,
 ) -> Result<ReceiveOk, ReceiveErr> {
-    // Generate fulfillment
-    let fulfillment = generate_fulfillment(&shared_secret[..], prepare.data());
-    let condition = hash_sha256(&fulfillment);
-    let is_fulfillable = condition == prepare.execution_condition();
+    let stream_packet = decrypt_packet(shared_secret, prepare)?;
+    respond_to_packet(
+        shared_secret,
+        ilp_address,
+        asset_code,
+        asset_scale,
+        prepare,
+        &stream_packet,
+        Vec::new(),
+        receipts,
+    )
+}
 
-    let prepare_amount = prepare.amount();
+/// Like `receive_money`, but also passes the data of the packet to the handler and
+/// includes the data it responds with in the Fulfill or Reject
+async fn receive_money_and_data(
+    data_connections: &DataConnections,
+    shared_secret: &[u8; 32],
+    ilp_address: &Address,
+    asset_code: &str,
+    asset_scale: u8,
+    prepare: &Prepare,
+    receipts: Option<&ReceiptIssuer<'_>>,
+) -> Result<ReceiveOk, ReceiveErr> {
+    let stream_packet = decrypt_packet(shared_secret, prepare)?;
+    let data_response = data_connections
+        .handle_packet(&prepare.destination(), &stream_packet)
+        .await;
+    respond_to_packet(
+        shared_secret,
+        ilp_address,
+        asset_code,
+        asset_scale,
+        prepare,
+        &stream_packet,
+        data_response.frames(),
+        receipts,
+    )
+}
 
+fn decrypt_packet(shared_secret: &[u8; 32], prepare: &Prepare) -> Result<StreamPacket, ReceiveErr> {
     // Creating a copy for the prepare.data() cannot be avoided, as the decryption happens in place
     // while the outer Prepare needs to remain unchanged.
     let copied_data = BytesMut::from(prepare.data());
 
-    let stream_packet = StreamPacket::from_encrypted(shared_secret, copied_data)
-        .map_err(|_| ReceiveErr::InvalidPacket)?;
+    StreamPacket::from_encrypted(shared_secret, copied_data).map_err(|_| ReceiveErr::InvalidPacket)
+}
+
+#[allow(clippy::cognitive_complexity, clippy::too_many_arguments)]
+fn respond_to_packet<'a>(
+    shared_secret: &[u8; 32],
+    ilp_address: &Address,
+    asset_code: &'a str,
+    asset_scale: u8,
+    prepare: &Prepare,
+    stream_packet: &'a StreamPacket,
+    mut response_frames: Vec<Frame<'a>>,
+    receipts: Option<&ReceiptIssuer<'_>>,
+) -> Result<ReceiveOk, ReceiveErr> {
+    // Generate fulfillment
+    let fulfillment = generate_fulfillment(&shared_secret[..], prepare.data());
+    let condition = hash_sha256(&fulfillment);
+    let is_fulfillable = condition == prepare.execution_condition();
+
+    let prepare_amount = prepare.amount();
+    let will_fulfill = is_fulfillable && prepare_amount >= stream_packet.prepare_amount();
 
-    let mut response_frames: Vec<Frame> = Vec::new();
     let mut connection_closed = false;
 
+    // Send the sender our asset code & scale in response to the first packet of the
+    // connection, so it can enforce its minimum destination amounts from then on.
+    // Clients are supposed to send the ConnectionNewAddress frame only until they got a
+    // response, so we send the details again if the first packet's response was lost.
+    let is_first_packet = stream_packet.sequence() == 1
+        || stream_packet
+            .frames()
+            .any(|frame| matches!(frame, Frame::ConnectionNewAddress(_)));
+    if is_first_packet {
+        response_frames.push(Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
+            source_asset_code: asset_code,
+            source_asset_scale: asset_scale,
+        }));
+    }
+
     // Handle STREAM frames
-    // TODO reject if they send data?
     for frame in stream_packet.frames() {
         // Tell the sender the stream can handle lots of money
         if let Frame::StreamMoney(ref frame) = frame {
@@ -301,17 +481,6 @@ fn receive_money(
             }));
         }
 
-        // If we receive a ConnectionNewAddress frame, then send them our asset
-        // code & scale. The client is suppoesd to only send the
-        // ConnectionNewAddress frame once, so we expect that we will only have
-        // to respond with the ConnectionAssetDetails frame only one time.
-        if let Frame::ConnectionNewAddress(_) = frame {
-            response_frames.push(Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
-                source_asset_code: asset_code,
-                source_asset_scale: asset_scale,
-            }));
-        }
-
         // The last packet contains the ConnectionClose frame;
         // if this is the case, return this information to the caller
         // to be included in the payment notification
@@ -320,8 +489,30 @@ fn receive_money(
         }
     }
 
+    // Send a receipt for the new total received on each stream the money is sent on.
+    // Packets we do not fulfill are recorded too, so the issuer knows whether
+    // it saw the connection from its first packet
+    let receipts: Vec<(u64, Bytes)> = match receipts {
+        Some(issuer) => {
+            let amounts = if will_fulfill {
+                stream_amounts(stream_packet, prepare_amount)
+            } else {
+                Vec::new()
+            };
+            issuer.issue(stream_packet.sequence(), &amounts)
+        }
+        None => Vec::new(),
+    };
+    let mut response_frames: Vec<Frame<'_>> = response_frames;
+    response_frames.extend(receipts.iter().map(|(stream_id, receipt)| {
+        Frame::StreamReceipt(StreamReceiptFrame {
+            stream_id: *stream_id,
+            receipt: &receipt[..],
+        })
+    }));
+
     // Return Fulfill or Reject Packet
-    if is_fulfillable && prepare_amount >= stream_packet.prepare_amount() {
+    if will_fulfill {
         let response_packet = StreamPacketBuilder {
             sequence: stream_packet.sequence(),
             ilp_packet_type: IlpPacketType::Fulfill,
@@ -382,6 +573,37 @@ fn receive_money(
     }
 }
 
+/// Splits the amount of the packet between the streams it is sent on, in proportion to
+/// their shares. The last stream gets what is left after rounding down the other amounts
+fn stream_amounts(stream_packet: &StreamPacket, amount: u64) -> Vec<(u64, u64)> {
+    let shares: Vec<(u64, u64)> = stream_packet
+        .frames()
+        .filter_map(|frame| match frame {
+            Frame::StreamMoney(frame) => Some((frame.stream_id, frame.shares)),
+            _ => None,
+        })
+        .collect();
+    let total_shares: u128 = shares.iter().map(|(_, shares)| u128::from(*shares)).sum();
+    if total_shares == 0 {
+        return Vec::new();
+    }
+
+    let mut remaining = amount;
+    shares
+        .iter()
+        .enumerate()
+        .map(|(i, (stream_id, stream_shares))| {
+            let stream_amount = if i == shares.len() - 1 {
+                remaining
+            } else {
+                (u128::from(amount) * u128::from(*stream_shares) / total_shares) as u64
+            };
+            remaining -= stream_amount;
+            (*stream_id, stream_amount)
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod connection_generator {
     use super::*;
@@ -404,6 +626,38 @@ mod connection_generator {
             connection_generator.rederive_secret(&destination_account),
             shared_secret
         );
+        assert_eq!(
+            connection_generator.rederive_receipt_details(&destination_account),
+            None
+        );
+    }
+
+    #[test]
+    fn encodes_receipt_details_in_address() {
+        let receiver_address = Address::from_str("example.receiver").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[9; 32][..]));
+        let receipt_details = ReceiptDetails {
+            nonce: [1; 16],
+            secret: [2; 32],
+        };
+        let (destination_account, shared_secret) = connection_generator
+            .generate_address_and_secret_with_receipts(&receiver_address, &receipt_details);
+
+        assert_eq!(
+            connection_generator.rederive_secret(&destination_account),
+            shared_secret
+        );
+        assert_eq!(
+            connection_generator.rederive_receipt_details(&destination_account),
+            Some(receipt_details)
+        );
+
+        // The details can only be recovered with the same server secret
+        let other_generator = ConnectionGenerator::new(Bytes::from(&[8; 32][..]));
+        assert_eq!(
+            other_generator.rederive_receipt_details(&destination_account),
+            None
+        );
     }
 }
 
@@ -452,10 +706,135 @@ mod receiving_money {
         .build();
 
         let shared_secret = connection_generator.rederive_secret(&prepare.destination());
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare, None);
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn sends_asset_details_in_response_to_first_packet() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret);
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+
+        let asset_details = |sequence: u64| {
+            let data = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Prepare,
+                prepare_amount: 0,
+                sequence,
+                frames: &[Frame::StreamMoney(StreamMoneyFrame {
+                    stream_id: 1,
+                    shares: 1,
+                })],
+            }
+            .build()
+            .into_encrypted(&shared_secret[..]);
+            let prepare = PrepareBuilder {
+                destination: destination_account.clone(),
+                amount: 100,
+                expires_at: UNIX_EPOCH,
+                data: &data[..],
+                execution_condition: &generate_condition(&shared_secret[..], &data),
+            }
+            .build();
+            let fulfill = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare, None)
+                .unwrap()
+                .fulfill;
+            let response =
+                StreamPacket::from_encrypted(&shared_secret, BytesMut::from(fulfill.data()))
+                    .unwrap();
+            response.frames().find_map(|frame| match frame {
+                Frame::ConnectionAssetDetails(frame) => Some((
+                    frame.source_asset_code.to_string(),
+                    frame.source_asset_scale,
+                )),
+                _ => None,
+            })
+        };
+
+        assert_eq!(asset_details(1), Some(("ABC".to_string(), 9)));
+        assert_eq!(asset_details(2), None);
+    }
+
+    #[test]
+    fn sends_receipts_for_total_received_on_each_stream() {
+        use crate::receipt::Receipt;
+
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let receipt_details = ReceiptDetails {
+            nonce: [3; 16],
+            secret: [4; 32],
+        };
+        let (destination_account, shared_secret) = connection_generator
+            .generate_address_and_secret_with_receipts(&ilp_address, &receipt_details);
+        let totals = ReceiptTotals::default();
+
+        let receipts = |sequence: u64, amount: u64| {
+            let data = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Prepare,
+                prepare_amount: 0,
+                sequence,
+                frames: &[
+                    Frame::StreamMoney(StreamMoneyFrame {
+                        stream_id: 1,
+                        shares: 1,
+                    }),
+                    Frame::StreamMoney(StreamMoneyFrame {
+                        stream_id: 3,
+                        shares: 3,
+                    }),
+                ],
+            }
+            .build()
+            .into_encrypted(&shared_secret[..]);
+            let prepare = PrepareBuilder {
+                destination: destination_account.clone(),
+                amount,
+                expires_at: UNIX_EPOCH,
+                data: &data[..],
+                execution_condition: &generate_condition(&shared_secret[..], &data),
+            }
+            .build();
+            let issuer = ReceiptIssuer {
+                details: connection_generator
+                    .rederive_receipt_details(&prepare.destination())
+                    .unwrap(),
+                totals: &totals,
+                connection: &destination_account,
+            };
+            let fulfill = receive_money(
+                &shared_secret,
+                &ilp_address,
+                "ABC",
+                9,
+                &prepare,
+                Some(&issuer),
+            )
+            .unwrap()
+            .fulfill;
+            let response =
+                StreamPacket::from_encrypted(&shared_secret, BytesMut::from(fulfill.data()))
+                    .unwrap();
+            response
+                .frames()
+                .filter_map(|frame| match frame {
+                    Frame::StreamReceipt(frame) => {
+                        let receipt = Receipt::verify(frame.receipt, &[4; 32]).unwrap();
+                        assert_eq!(receipt.stream_id, frame.stream_id);
+                        assert_eq!(receipt.nonce, [3; 16]);
+                        Some((receipt.stream_id, receipt.total_received))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(receipts(1, 100), vec![(1, 25), (3, 75)]);
+        assert_eq!(receipts(2, 10), vec![(1, 27), (3, 83)]);
+    }
+
     #[test]
     fn fulfills_valid_packet_without_connection_tag() {
         let ilp_address = Address::from_str("example.destination").unwrap();
@@ -477,7 +856,7 @@ mod receiving_money {
         .build();
 
         let shared_secret = connection_generator.rederive_secret(&prepare.destination());
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare, None);
         assert!(result.is_ok());
     }
 
@@ -503,7 +882,7 @@ mod receiving_money {
         .build();
 
         let shared_secret = connection_generator.rederive_secret(&prepare.destination());
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare, None);
         assert!(result.is_err());
     }
 
@@ -539,7 +918,7 @@ mod receiving_money {
         .build();
 
         let shared_secret = connection_generator.rederive_secret(&prepare.destination());
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare, None);
         assert!(result.is_err());
     }
 
@@ -557,7 +936,7 @@ mod receiving_money {
             &hex!("b7d09d2e16e6f83c55b60e42fcd7c2b8ed49624a1df73c59b383dbe2e8690309")[..],
             "did not regenerate the same shared secret",
         );
-        let fulfill = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare)
+        let fulfill = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare, None)
             .expect("Receiver should be able to generate the fulfillment")
             .fulfill;
         assert_eq!(
@@ -629,6 +1008,98 @@ mod stream_receiver_service {
         assert!(result.is_ok());
     }
 
+    struct Uppercase;
+
+    #[async_trait]
+    impl StreamDataHandler for Uppercase {
+        async fn handle_data(&self, _connection: &Address, _stream_id: u64, data: Bytes) -> Bytes {
+            Bytes::from(data.to_ascii_uppercase())
+        }
+    }
+
+    #[tokio::test]
+    async fn responds_with_data() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+        let stream_packet = StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 0,
+            sequence: 1,
+            frames: &[Frame::StreamData(StreamDataFrame {
+                stream_id: 1,
+                offset: 0,
+                data: b"hello",
+            })],
+        }
+        .build();
+        let data = stream_packet.into_encrypted(&shared_secret[..]);
+        let execution_condition = generate_condition(&shared_secret[..], &data);
+
+        let prepare = PrepareBuilder {
+            destination: destination_account,
+            amount: 0,
+            expires_at: UNIX_EPOCH,
+            data: &data[..],
+            execution_condition: &execution_condition,
+        }
+        .build();
+
+        let mut service = StreamReceiverService::with_data_handler(
+            server_secret.clone(),
+            DummyStore,
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> IlpResult {
+                panic!("shouldn't get here")
+            }),
+            Arc::new(Uppercase),
+        );
+
+        let fulfill = service
+            .send_request(OutgoingRequest {
+                from: TestAccount {
+                    id: Uuid::new_v4(),
+                    ilp_address: Address::from_str("example.sender").unwrap(),
+                    asset_code: "XYZ".to_string(),
+                    asset_scale: 9,
+                    max_packet_amount: None,
+                },
+                to: TestAccount {
+                    id: Uuid::new_v4(),
+                    ilp_address: ilp_address.clone(),
+                    asset_code: "XYZ".to_string(),
+                    asset_scale: 9,
+                    max_packet_amount: None,
+                },
+                original_amount: prepare.amount(),
+                prepare,
+            })
+            .await
+            .unwrap();
+        let response =
+            StreamPacket::from_encrypted(&shared_secret, BytesMut::from(fulfill.data())).unwrap();
+        assert_eq!(
+            response.frames().collect::<Vec<_>>(),
+            vec![
+                Frame::StreamMaxData(StreamMaxDataFrame {
+                    stream_id: 1,
+                    max_offset: 5 + crate::DEFAULT_MAX_BUFFERED_DATA,
+                }),
+                Frame::ConnectionMaxStreamId(ConnectionMaxStreamIdFrame { max_stream_id: 20 }),
+                Frame::StreamData(StreamDataFrame {
+                    stream_id: 1,
+                    offset: 0,
+                    data: b"HELLO",
+                }),
+                Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
+                    source_asset_code: "XYZ",
+                    source_asset_scale: 9,
+                }),
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn rejects_invalid_packets() {
         let ilp_address = Address::from_str("example.destination").unwrap();